@@ -0,0 +1,152 @@
+/* echo what's typed at a HiFive board's console, driven by interrupts
+ *
+ * This is firmware for real hardware, built with riscv-rt as its own crate
+ * in examples/riscv rather than as one of this crate's examples, for either
+ * the HiFive1 Rev B (FE310-G002) or the HiFive Unmatched (FU740-C000):
+ *
+ *   cd examples/riscv
+ *   cargo build --release --features fe310 --target riscv32imac-unknown-none-elf
+ *   cargo build --release --features fu740 --target riscv64gc-unknown-none-elf
+ *
+ * The HiFive1 build runs in M-mode from flash, after the board's bootloader:
+ * flash it with the J-Link, eg with JLinkExe's loadfile. The Unmatched
+ * build runs in S-mode from RAM under OpenSBI: load it from U-Boot with
+ * bootelf. U-Boot runs it on whichever hart it's on, and the PLIC context
+ * below assumes that's hart 1; use maxcpus or adjust CONTEXT otherwise.
+ *
+ * The board preset programs the console's baud rate, then the UART's
+ * interrupt is routed through the PLIC. Received bytes are moved into a
+ * ring buffer by the interrupt handler, and the main loop sleeps until
+ * there are some, then queues them to be sent back, with CR expanded to
+ * CRLF, for the interrupt handler to feed to the tx FIFO.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+#![no_std]
+#![no_main]
+
+use panic_halt as _;
+use riscv_rt::entry;
+use mmio_sifive_uart::UART;
+use mmio_sifive_uart::board::Board;
+use mmio_sifive_uart::buffered::Buffers;
+use mmio_sifive_uart::plic::{Plic, InterruptController};
+
+#[cfg(all(feature = "fe310", feature = "fu740"))]
+compile_error!("pick one board: fe310 or fu740");
+
+/* HiFive1 Rev B: a single hart in M-mode, context 0 */
+#[cfg(feature = "fe310")]
+const BOARD: Board = Board::hifive1_revb();
+#[cfg(feature = "fe310")]
+const CONTEXT: usize = 0;
+
+/* HiFive Unmatched: hart 1 in S-mode, context 2 */
+#[cfg(feature = "fu740")]
+const BOARD: Board = Board::hifive_unmatched();
+#[cfg(feature = "fu740")]
+const CONTEXT: usize = 2;
+
+/* both chips have their PLIC here */
+const PLIC_BASE: usize = 0x0c00_0000;
+const PRIORITY: u32 = 1;
+
+/* reached from the interrupt handler as well as main() */
+static CONSOLE: UART = UART::attach_with_variant(BOARD.console.base, BOARD.soc.variant());
+static BUFFERS: Buffers<64, 256> = Buffers::new();
+static PLIC: Plic = Plic::new(PLIC_BASE);
+
+#[entry]
+fn main() -> !
+{
+    /* set up the console's controller and baud rate. CONSOLE is attached to
+       the same registers, and is used from here on */
+    if BOARD.console().is_err()
+    {
+        loop { riscv::asm::wfi(); }
+    }
+    let _ = CONSOLE.send_str("\r\nmmio_sifive_uart echo on ");
+    let _ = CONSOLE.send_str(BOARD.name);
+    let _ = CONSOLE.send_str("\r\n");
+
+    PLIC.set_threshold(CONTEXT, 0);
+    CONSOLE.route_irq(&PLIC, BOARD.console.irq, PRIORITY, CONTEXT);
+    let (mut tx, mut rx) = match CONSOLE.split(&BUFFERS)
+    {
+        Ok(halves) => halves,
+        Err(_) => loop { riscv::asm::wfi(); }
+    };
+
+    unsafe
+    {
+        enable_external_interrupts();
+        riscv::interrupt::enable();
+    }
+
+    loop
+    {
+        /* sleep with interrupts masked so one arriving after the check still
+           wakes the hart, and is taken once they're unmasked */
+        unsafe { riscv::interrupt::disable() };
+        if rx.is_empty()
+        {
+            riscv::asm::wfi();
+        }
+        unsafe { riscv::interrupt::enable() };
+
+        while let Some(byte) = rx.read_byte()
+        {
+            let echo: &[u8] = match byte
+            {
+                b'\r' => b"\r\n",
+                _ => &[byte]
+            };
+
+            /* wait for room rather than drop output if the sender gets ahead */
+            let mut queued = 0;
+            while queued < echo.len()
+            {
+                queued += tx.write(&echo[queued..]);
+            }
+        }
+    }
+}
+
+/* claim and service the console's interrupt. nothing else is routed to this
+   context, but complete anything unexpected so it can't wedge the PLIC */
+fn external_interrupt()
+{
+    if let Some(other) = CONSOLE.service_plic(&PLIC, CONTEXT, BOARD.console.irq, &BUFFERS)
+    {
+        PLIC.complete(CONTEXT, other);
+    }
+}
+
+#[cfg(feature = "fe310")]
+unsafe fn enable_external_interrupts()
+{
+    riscv::register::mie::set_mext();
+}
+
+#[cfg(feature = "fe310")]
+#[export_name = "MachineExternal"]
+extern "C" fn machine_external()
+{
+    external_interrupt();
+}
+
+#[cfg(feature = "fu740")]
+unsafe fn enable_external_interrupts()
+{
+    riscv::register::sie::set_sext();
+}
+
+#[cfg(feature = "fu740")]
+#[export_name = "SupervisorExternal"]
+extern "C" fn supervisor_external()
+{
+    external_interrupt();
+}
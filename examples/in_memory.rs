@@ -0,0 +1,34 @@
+/* the examples given for UART::in_memory(), compiled and run on the host
+ *
+ *   cargo run --example in_memory --features std
+ *
+ * The UART's registers are simulated in memory, so what it receives is
+ * supplied by the program and what it sends can be checked, without any
+ * hardware. Each assertion here matches one in the driver's comments.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use mmio_sifive_uart::{UART, Fault};
+
+fn main()
+{
+    /* from the memory module's header */
+    let (regs, uart) = UART::in_memory();
+    regs.receive(b"hi");
+    assert_eq!(uart.read_byte_blocking(), b'h');
+    uart.send_byte_blocking(b'!');
+    assert_eq!(regs.sent(), b"!");
+
+    /* from UART::in_memory() */
+    let (regs, uart) = UART::in_memory();
+    assert!(matches!(uart.read_byte(), Err(Fault::DataNotReady)));
+    regs.receive(b"hi");
+    assert!(matches!(uart.read_byte(), Ok(b'h')));
+    uart.send_str("ok").unwrap();
+    assert_eq!(regs.sent(), b"ok");
+
+    println!("the in_memory() examples run as documented");
+}
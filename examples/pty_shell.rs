@@ -0,0 +1,151 @@
+/* run the debug shell on a simulated UART bridged to a pseudo-terminal
+ *
+ *   cargo run --example pty_shell --features std,shell
+ *
+ * prints the path of the terminal end, eg /dev/pts/3, for picocom or screen
+ * to open, and runs the shell there until its exit command. The shell edits
+ * lines with Readline, so the arrow keys, history and control keys all work
+ * as they would over a real serial line.
+ *
+ * With --check, it opens the terminal itself, types some commands with a
+ * few editing keys in, and checks the replies, exiting with a non-zero
+ * status if they're wrong, so it can be run as an end-to-end test.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::mpsc;
+use std::time::Duration;
+use mmio_sifive_uart::{UART, Fault};
+use mmio_sifive_uart::host::HostBus;
+use mmio_sifive_uart::shell::{Command, Shell};
+
+/* what --check types: a backspace, an unknown command, history recalled
+   with the up arrow, and a line edited after moving to its start */
+const KEYS: &[u8] = b"echo hx\x7fi there\rnope\r\x1b[A\x1b[A\r\
+                      cho moved\x01e\rexit\r";
+
+/* the replies --check expects, in order */
+const REPLIES: &[&str] = &["\r\nhi there\r\n", "unknown command: nope", "\r\nhi there\r\n",
+                           "\r\nmoved\r\n", "bye\r\n"];
+
+/* how long --check waits for the shell to finish */
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn echo(uart: &UART, args: &[&str]) -> Result<(), Fault>
+{
+    uart.send_str(&args.join(" ")).map_err(|partial| partial.fault)?;
+    uart.send_str("\r\n").map(|_| ()).map_err(|partial| partial.fault)
+}
+
+static COMMANDS: &[Command] = &[
+    Command { name: "echo", help: "echo <args>: print args", handler: echo },
+    Command { name: "exit", help: "exit: leave the shell", handler: |_, _| Err(Fault::Cancelled) }
+];
+
+/* type KEYS at the terminal and send back everything the shell prints up to its
+   farewell */
+fn drive(path: PathBuf, output: mpsc::Sender<Vec<u8>>)
+{
+    let mut terminal = OpenOptions::new().read(true).write(true).open(path).expect("can't open terminal");
+    terminal.write_all(KEYS).expect("can't type at terminal");
+
+    let mut seen = Vec::new();
+    let mut buf = [0u8; 256];
+    while !seen.ends_with(b"bye\r\n")
+    {
+        match terminal.read(&mut buf)
+        {
+            Ok(0) | Err(_) => break,
+            Ok(count) => seen.extend_from_slice(&buf[..count])
+        }
+    }
+    let _ = output.send(seen);
+}
+
+/* return true if replies appear in output in order */
+fn replies_in_order(output: &str, replies: &[&str]) -> bool
+{
+    let mut rest = output;
+    for reply in replies
+    {
+        match rest.find(reply)
+        {
+            Some(at) => rest = &rest[at + reply.len()..],
+            None => return false
+        }
+    }
+    true
+}
+
+fn main()
+{
+    let check = std::env::args().any(|arg| arg == "--check");
+
+    let (bus, path) = match HostBus::pty()
+    {
+        Ok(pty) => pty,
+        Err(e) =>
+        {
+            eprintln!("can't create a pseudo-terminal: {}", e);
+            exit(1)
+        }
+    };
+    let uart = bus.into_uart();
+
+    let (sender, output) = mpsc::channel();
+    match check
+    {
+        true => { let path = path.clone(); std::thread::spawn(move || drive(path, sender)); },
+        false => println!("shell running on {}, type exit to leave", path.display())
+    }
+
+    /* Readline waits forever for each key, so a shell stuck by a bug would never
+       return: --check gives up on it from here instead */
+    let shell = std::thread::spawn(move ||
+    {
+        let mut shell: Shell<80, 8> = Shell::new("uart> ", COMMANDS);
+        let result = shell.run(&uart);
+        let _ = uart.send_str("bye\r\n");
+        result
+    });
+
+    if !check
+    {
+        match shell.join()
+        {
+            Ok(Ok(())) => exit(0),
+            _ =>
+            {
+                eprintln!("shell on {} failed", path.display());
+                exit(1)
+            }
+        }
+    }
+
+    let seen = match output.recv_timeout(CHECK_TIMEOUT)
+    {
+        Ok(seen) => String::from_utf8_lossy(&seen).into_owned(),
+        Err(_) =>
+        {
+            eprintln!("shell on {} didn't finish", path.display());
+            exit(1)
+        }
+    };
+
+    match replies_in_order(&seen, REPLIES)
+    {
+        true => println!("shell on {} replied as expected", path.display()),
+        false =>
+        {
+            eprintln!("unexpected replies from shell on {}: {:?}", path.display(), seen);
+            exit(1)
+        }
+    }
+}
@@ -0,0 +1,25 @@
+/* put the selected board's memory map where riscv-rt's link.x will find it
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main()
+{
+    let memory = match env::var_os("CARGO_FEATURE_FU740")
+    {
+        Some(_) => "memory-fu740.x",
+        None => "memory-fe310.x"
+    };
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy(memory, out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed={}", memory);
+    println!("cargo:rerun-if-changed=build.rs");
+}
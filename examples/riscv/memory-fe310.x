@@ -0,0 +1,13 @@
+/* HiFive1 Rev B: code in flash after the board's 64KiB bootloader, data in the FE310-G002's 16KiB DTIM */
+MEMORY
+{
+    FLASH : ORIGIN = 0x20010000, LENGTH = 4032K
+    RAM : ORIGIN = 0x80000000, LENGTH = 16K
+}
+
+REGION_ALIAS("REGION_TEXT", FLASH);
+REGION_ALIAS("REGION_RODATA", FLASH);
+REGION_ALIAS("REGION_DATA", RAM);
+REGION_ALIAS("REGION_BSS", RAM);
+REGION_ALIAS("REGION_HEAP", RAM);
+REGION_ALIAS("REGION_STACK", RAM);
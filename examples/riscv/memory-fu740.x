@@ -0,0 +1,12 @@
+/* HiFive Unmatched: everything in DDR, clear of OpenSBI and U-Boot, for bootelf to load */
+MEMORY
+{
+    RAM : ORIGIN = 0x90000000, LENGTH = 16M
+}
+
+REGION_ALIAS("REGION_TEXT", RAM);
+REGION_ALIAS("REGION_RODATA", RAM);
+REGION_ALIAS("REGION_DATA", RAM);
+REGION_ALIAS("REGION_BSS", RAM);
+REGION_ALIAS("REGION_HEAP", RAM);
+REGION_ALIAS("REGION_STACK", RAM);
@@ -0,0 +1,145 @@
+/* test code written against embedded-io with a mock port, then run it on a UART
+ *
+ *   cargo run --example substitute --features std,embedded-io
+ *
+ * handshake() only knows it has something that implements embedded_io's Read
+ * and Write, so it can be checked against a Mock, which fails if the bytes
+ * written aren't the ones expected, and supplies the bytes to be read. Mock is
+ * shaped like embedded-hal-mock's: a list of expected transactions, and done()
+ * to check they all happened. Any mock of the traits can stand in for it.
+ * The same code is then run on a UART, here simulated by HostBus with the
+ * reply already waiting, though on hardware it would be &UART all the same.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use std::process::exit;
+use embedded_io::{ErrorType, Read, Write};
+use mmio_sifive_uart::host::HostBus;
+
+/* ask the modem at the other end if it's there, and return true if it says so */
+fn handshake<P: Read + Write>(port: &mut P) -> Result<bool, P::Error>
+{
+    port.write_all(b"AT\r")?;
+    port.flush()?;
+
+    let mut reply = [0u8; 16];
+    let mut len = 0;
+    while len < reply.len() && !reply[..len].ends_with(b"\n")
+    {
+        len += port.read(&mut reply[len..])?;
+    }
+    Ok(&reply[..len] == b"OK\r\n")
+}
+
+/* an exchange the port under test should see */
+enum Transaction
+{
+    Write(&'static [u8]),
+    Read(&'static [u8])
+}
+
+/* a port that checks what's written, and answers reads, from a script of
+   transactions. panics as soon as the code under test strays from it */
+struct Mock
+{
+    expected: Vec<Transaction>,
+    next: usize,
+    done: usize     /* bytes of the next transaction already consumed */
+}
+
+impl Mock
+{
+    fn new(expected: Vec<Transaction>) -> Self
+    {
+        Mock { expected, next: 0, done: 0 }
+    }
+
+    /* panic unless every transaction has happened */
+    fn done(&self)
+    {
+        assert_eq!(self.next, self.expected.len(), "transactions left over");
+    }
+
+    /* move on to the next transaction once this one's bytes are used up */
+    fn advance(&mut self, count: usize, len: usize)
+    {
+        self.done += count;
+        if self.done == len
+        {
+            self.next += 1;
+            self.done = 0;
+        }
+    }
+}
+
+impl ErrorType for Mock
+{
+    type Error = embedded_io::ErrorKind;
+}
+
+impl Read for Mock
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>
+    {
+        let bytes = match self.expected.get(self.next)
+        {
+            Some(Transaction::Read(bytes)) => &bytes[self.done..],
+            _ => panic!("unexpected read")
+        };
+
+        let count = bytes.len().min(buf.len());
+        buf[..count].copy_from_slice(&bytes[..count]);
+        self.advance(count, self.done + bytes.len());
+        Ok(count)
+    }
+}
+
+impl Write for Mock
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error>
+    {
+        let bytes = match self.expected.get(self.next)
+        {
+            Some(Transaction::Write(bytes)) => &bytes[self.done..],
+            _ => panic!("unexpected write of {:?}", buf)
+        };
+
+        let count = bytes.len().min(buf.len());
+        assert_eq!(&buf[..count], &bytes[..count], "wrong bytes written");
+        self.advance(count, self.done + bytes.len());
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error>
+    {
+        Ok(())
+    }
+}
+
+fn main()
+{
+    /* the tests: a modem that answers, and one that doesn't understand */
+    let mut mock = Mock::new(vec![Transaction::Write(b"AT\r"), Transaction::Read(b"OK\r\n")]);
+    assert_eq!(handshake(&mut mock), Ok(true));
+    mock.done();
+
+    let mut mock = Mock::new(vec![Transaction::Write(b"AT\r"), Transaction::Read(b"ERROR\r\n")]);
+    assert_eq!(handshake(&mut mock), Ok(false));
+    mock.done();
+    println!("handshake passes its tests against the mock");
+
+    /* and the real thing, with the modem's reply queued up to be received */
+    let uart = HostBus::new(&b"OK\r\n"[..], std::io::sink()).into_uart();
+    match handshake(&mut &uart)
+    {
+        Ok(true) => println!("handshake succeeds on a UART"),
+        result =>
+        {
+            eprintln!("handshake failed on a UART: {:?}", result);
+            exit(1)
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/* feed arbitrary terminal input to the key parser
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mmio_sifive_uart::ansi::{Key, KeyParser};
+
+fuzz_target!(|data: &[u8]|
+{
+    let mut keys = KeyParser::new();
+    for &byte in data
+    {
+        let _ = keys.feed(byte);
+    }
+
+    /* however it was left, a plain character gets the parser back to normal */
+    let mut recovered = false;
+    for _ in 0..64
+    {
+        if keys.feed(b'x') == Some(Key::Char(b'x')) && !keys.is_pending()
+        {
+            recovered = true;
+            break
+        }
+    }
+    assert!(recovered, "key parser stuck in an escape sequence");
+});
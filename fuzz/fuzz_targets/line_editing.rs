@@ -0,0 +1,89 @@
+/* type arbitrary input at Readline and LineDiscipline
+ *
+ * A simulated controller delivers the input through RXDATA and discards
+ * what's echoed. Both must return every line, however it was edited, within
+ * their buffers, and give up once the input runs out rather than spin.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+#![no_main]
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use libfuzzer_sys::fuzz_target;
+use mmio_sifive_uart::{UART, Polls};
+use mmio_sifive_uart::bus::{self, Bus};
+use mmio_sifive_uart::readline::Readline;
+use mmio_sifive_uart::discipline::{LineDiscipline, InputMode};
+
+const LINE: usize = 32;
+
+/* a controller whose rx FIFO holds the fuzzer's input */
+struct Terminal(Mutex<VecDeque<u8>>);
+
+impl Bus for Terminal
+{
+    fn read(&self, reg: usize) -> u32
+    {
+        match reg
+        {
+            bus::RXDATA => self.0.lock().unwrap().pop_front().map(|b| b as u32).unwrap_or(bus::FIFO_FLAG),
+            _ => 0
+        }
+    }
+
+    fn write(&self, _: usize, _: u32) {}
+}
+
+/* a bus must live for the rest of the program, so one is reused for every run */
+static TERMINAL: Terminal = Terminal(Mutex::new(VecDeque::new()));
+
+fn type_in(data: &[u8])
+{
+    let mut input = TERMINAL.0.lock().unwrap();
+    input.clear();
+    input.extend(data);
+}
+
+fuzz_target!(|data: &[u8]|
+{
+    let uart = UART::attach_bus(&TERMINAL);
+
+    type_in(data);
+    let mut readline: Readline<LINE, 4> = Readline::new();
+    while let Ok(line) = readline.read_line_timeout(&uart, &mut Polls::new(1))
+    {
+        assert!(line.len() <= LINE);
+    }
+    for back in 0..6
+    {
+        assert!(readline.history(back).map_or(0, |line| line.len()) <= LINE);
+    }
+
+    /* the first byte picks the discipline's mode and echo */
+    let (options, rest) = match data.split_first()
+    {
+        Some((&options, rest)) => (options, rest),
+        None => return
+    };
+    type_in(rest);
+    let mode = match options & 1
+    {
+        0 => InputMode::Cooked,
+        _ => InputMode::Raw
+    };
+    let mut discipline: LineDiscipline<LINE> = LineDiscipline::new(mode);
+    discipline.set_echo(options & 2 != 0);
+
+    let mut buf = [0u8; LINE];
+    let mut read = 0;
+    while let Ok(count) = discipline.read_timeout(&uart, &mut buf, &mut Polls::new(1))
+    {
+        assert!(count > 0 && count <= LINE);
+        read += count;
+        assert!(read <= rest.len(), "more read than typed");
+    }
+});
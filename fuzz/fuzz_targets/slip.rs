@@ -0,0 +1,46 @@
+/* feed arbitrary bytes to the SLIP decoder, and check frames survive a round trip
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mmio_sifive_uart::slip::{Encoder, Decoder};
+
+/* small enough that long frames overflow it */
+const FRAME_MAX: usize = 64;
+
+fuzz_target!(|data: &[u8]|
+{
+    /* line noise: whatever arrives, frames fit the buffer and nothing panics */
+    let mut buf = [0u8; FRAME_MAX];
+    let mut decoder = Decoder::new(&mut buf);
+    for &byte in data
+    {
+        if let Ok(Some(len)) = decoder.push(byte)
+        {
+            assert!(len > 0 && len <= FRAME_MAX);
+            assert_eq!(decoder.frame().len(), len);
+        }
+    }
+
+    /* the input as a frame decodes back to itself */
+    let mut buf = vec![0u8; data.len()];
+    let mut decoder = Decoder::new(&mut buf);
+    let mut decoded = None;
+    for byte in Encoder::new(data)
+    {
+        if let Some(len) = decoder.push(byte).expect("encoded frame rejected")
+        {
+            decoded = Some(len);
+        }
+    }
+    match decoded
+    {
+        Some(_) => assert_eq!(decoder.frame(), data),
+        None => assert!(data.is_empty())
+    }
+});
@@ -0,0 +1,57 @@
+/* C interface to the mmio_sifive_uart driver, built with its ffi feature.
+ * Regenerate with: cbindgen --config cbindgen.toml --output include/sifive_uart.h
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+#ifndef SIFIVE_UART_H
+#define SIFIVE_UART_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* number of UARTs that can be initialized */
+#define SIFIVE_UART_MAX 4
+
+/* size in bytes of each UART's rx and tx software FIFOs */
+#define SIFIVE_UART_RX_BUFFER 256
+#define SIFIVE_UART_TX_BUFFER 256
+
+/* negative error codes returned on failure */
+#define SIFIVE_UART_ESIZETOOSMALL   -1
+#define SIFIVE_UART_ETXNOTEMPTY     -2
+#define SIFIVE_UART_EDATANOTREADY   -3
+#define SIFIVE_UART_EFRAMETOOLONG   -4
+#define SIFIVE_UART_EBADESCAPE      -5
+#define SIFIVE_UART_EBADFRAME       -6
+#define SIFIVE_UART_ECANCELLED      -7
+#define SIFIVE_UART_ETOOMANYERRORS  -8
+#define SIFIVE_UART_EBADDEVICETREE  -9
+#define SIFIVE_UART_EREGISTRYFULL   -10
+#define SIFIVE_UART_ENOSUCHDEVICE   -11
+#define SIFIVE_UART_EALREADYCLAIMED -12
+#define SIFIVE_UART_EBADADDRESS     -13
+#define SIFIVE_UART_ETIMEDOUT       -14
+#define SIFIVE_UART_EOUTOFRANGE     -15
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+int sifive_uart_init(unsigned int index, uintptr_t base, uintptr_t size);
+int sifive_uart_set_baud(unsigned int index, uint32_t baud, uint32_t bus_freq);
+int sifive_uart_putc(unsigned int index, uint8_t byte);
+int sifive_uart_getc(unsigned int index);
+
+int sifive_uart_buffered_start(unsigned int index);
+void sifive_uart_irq(unsigned int index);
+intptr_t sifive_uart_buffered_write(unsigned int index, const uint8_t *data, uintptr_t len);
+intptr_t sifive_uart_buffered_read(unsigned int index, uint8_t *buf, uintptr_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SIFIVE_UART_H */
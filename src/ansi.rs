@@ -0,0 +1,215 @@
+/* turn terminal input into key presses
+ *
+ * Terminals send cursor and editing keys as ANSI escape sequences, eg up
+ * is ESC [ A, and may wrap pasted text in ESC [ 200 ~ and ESC [ 201 ~ when
+ * bracketed paste is turned on. KeyParser is fed received bytes one at a
+ * time and hands back a Key whenever one is complete, eg:
+ *
+ *   let mut keys = KeyParser::new();
+ *   for byte in uart.bytes()
+ *   {
+ *       match keys.feed(byte?)
+ *       {
+ *           Some(Key::Up) => ...,
+ *           Some(Key::Char(c)) => ...,
+ *           _ => ()
+ *       }
+ *   }
+ *
+ * CR, LF and CRLF are each a single Enter. ESC followed by anything other
+ * than the start of a sequence is taken to be alt held with that key.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key
+{
+    Char(u8),       /* a printable character, or a byte of a UTF-8 sequence */
+    Control(u8),    /* a control key without a Key of its own, eg Control(b'c') for ctrl-C */
+    Alt(u8),        /* a byte received straight after ESC */
+    Enter,
+    Tab,
+    Backspace,      /* backspace or DEL, which terminals send for the same key */
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    PasteStart,     /* what follows was pasted rather than typed */
+    PasteEnd,
+    Unknown         /* a well-formed escape sequence that isn't recognized */
+}
+
+/* where the parser is in an escape sequence */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State
+{
+    Ground,
+    Escape,             /* ESC received */
+    Csi(u32, bool),     /* ESC [ then the first numeric parameter so far, and whether more followed */
+    Ss3                 /* ESC O */
+}
+
+pub struct KeyParser
+{
+    state: State,
+    after_cr: bool      /* the last byte was CR, so an LF now is part of the same Enter */
+}
+
+impl Default for KeyParser
+{
+    fn default() -> Self
+    {
+        KeyParser::new()
+    }
+}
+
+impl KeyParser
+{
+    pub const fn new() -> Self
+    {
+        KeyParser { state: State::Ground, after_cr: false }
+    }
+
+    /* true if part of an escape sequence has been received */
+    pub fn is_pending(&self) -> bool
+    {
+        self.state != State::Ground
+    }
+
+    /* process a received byte, returning the key it completes, if any */
+    pub fn feed(&mut self, byte: u8) -> Option<Key>
+    {
+        let after_cr = self.after_cr;
+        self.after_cr = false;
+
+        match self.state
+        {
+            State::Ground => match byte
+            {
+                ESC =>
+                {
+                    self.state = State::Escape;
+                    None
+                },
+                b'\n' if after_cr => None,
+                b'\r' | b'\n' =>
+                {
+                    self.after_cr = byte == b'\r';
+                    Some(Key::Enter)
+                },
+                b'\t' => Some(Key::Tab),
+                0x08 | 0x7f => Some(Key::Backspace),
+                0x00..=0x1f => Some(Key::Control(byte | 0x60)),
+                _ => Some(Key::Char(byte))
+            },
+
+            State::Escape =>
+            {
+                self.state = match byte
+                {
+                    b'[' => State::Csi(0, false),
+                    b'O' => State::Ss3,
+                    _ => State::Ground
+                };
+                match self.state
+                {
+                    State::Ground => Some(Key::Alt(byte)),
+                    _ => None
+                }
+            },
+
+            State::Csi(param, more) => match byte
+            {
+                b'0'..=b'9' if !more =>
+                {
+                    self.state = State::Csi(param.saturating_mul(10).saturating_add((byte - b'0') as u32), false);
+                    None
+                },
+
+                /* parameters after the first, eg the modifiers in ESC [ 1 ; 5 C, are ignored */
+                b'0'..=b'9' | b';' =>
+                {
+                    self.state = State::Csi(param, true);
+                    None
+                },
+
+                /* anything that isn't a final byte ends the sequence unrecognized */
+                _ =>
+                {
+                    self.state = State::Ground;
+                    Some(match (byte, param)
+                    {
+                        (b'~', 1) | (b'~', 7) => Key::Home,
+                        (b'~', 2) => Key::Insert,
+                        (b'~', 3) => Key::Delete,
+                        (b'~', 4) | (b'~', 8) => Key::End,
+                        (b'~', 5) => Key::PageUp,
+                        (b'~', 6) => Key::PageDown,
+                        (b'~', 200) => Key::PasteStart,
+                        (b'~', 201) => Key::PasteEnd,
+                        _ => cursor_key(byte)
+                    })
+                }
+            },
+
+            State::Ss3 =>
+            {
+                self.state = State::Ground;
+                Some(cursor_key(byte))
+            }
+        }
+    }
+}
+
+/* the key for the last byte of a cursor key's sequence */
+fn cursor_key(byte: u8) -> Key
+{
+    match byte
+    {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        _ => Key::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<Key>
+    {
+        let mut parser = KeyParser::new();
+        input.iter().filter_map(|&byte| parser.feed(byte)).collect()
+    }
+
+    #[test]
+    fn recognizes_keys()
+    {
+        assert_eq!(parse(b"a\x1b[A\x1bOF\x1b[3~\x1b[1;5C\x1b[200~x\x1b[201~"),
+                   [Key::Char(b'a'), Key::Up, Key::End, Key::Delete, Key::Right,
+                    Key::PasteStart, Key::Char(b'x'), Key::PasteEnd]);
+        assert_eq!(parse(b"\r\n\n\r\x7f\x03\tq\x1bz\x1b[99~\x1b[Z"),
+                   [Key::Enter, Key::Enter, Key::Enter, Key::Backspace, Key::Control(b'c'),
+                    Key::Tab, Key::Char(b'q'), Key::Alt(b'z'), Key::Unknown, Key::Unknown]);
+
+        let mut parser = KeyParser::new();
+        parser.feed(0x1b);
+        assert!(parser.is_pending());
+    }
+}
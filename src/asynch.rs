@@ -0,0 +1,679 @@
+/* async reads and writes, woken by the UART's interrupts
+ *
+ * AsyncUart pairs a UART with a set of Buffers filled and drained by the
+ * UART's interrupt handler, which must call UART::handle_interrupt(). Tasks
+ * awaiting data or buffer space sleep until the handler wakes them, rather
+ * than polling the hardware. With the embedded-io-async feature, AsyncUart
+ * implements that crate's Read and Write traits so Embassy-style firmware
+ * can use it with the usual ecosystem of async I/O helpers.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::future::{Future, poll_fn};
+use core::pin::pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use super::{UART, Fault, WatermarkLevel};
+use super::buffered::Buffers;
+
+/* an async timer, supplied by the executor or HAL, used to bound waits */
+pub trait Timer
+{
+    /* return a future that completes once duration has passed */
+    fn after(&mut self, duration: Duration) -> impl Future<Output = ()>;
+}
+
+/* holds a line of up to N bytes as it arrives. it's kept by the caller, not
+   inside the read_line() future, so a cancelled read loses nothing */
+pub struct LineBuffer<const N: usize>
+{
+    bytes: [u8; N],
+    len: usize,
+    complete: bool,     /* a whole line is held, and is discarded on the next read */
+    after_cr: bool,     /* the last byte was a CR, so a following LF ends no line */
+    overflowed: bool    /* the line outgrew the buffer, so drop bytes up to its end */
+}
+
+impl<const N: usize> Default for LineBuffer<N>
+{
+    fn default() -> Self
+    {
+        LineBuffer::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N>
+{
+    pub const fn new() -> Self
+    {
+        LineBuffer { bytes: [0; N], len: 0, complete: false, after_cr: false, overflowed: false }
+    }
+
+    /* the line so far, without its terminator */
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        &self.bytes[..self.len]
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    /* forget the line so far, and anything about the bytes before it */
+    pub fn clear(&mut self)
+    {
+        self.len = 0;
+        self.complete = false;
+        self.after_cr = false;
+        self.overflowed = false;
+    }
+
+    /* add a received byte, returning true if it completes the line. once a
+       line overflows, the rest of it is dropped, up to and including its
+       terminator, so it can't be mistaken for the next line */
+    fn push(&mut self, byte: u8) -> Result<bool, Fault>
+    {
+        /* a CR ending the previous line is remembered, as a LF may follow it */
+        if self.complete
+        {
+            self.len = 0;
+            self.complete = false;
+        }
+
+        let after_cr = self.after_cr;
+        self.after_cr = byte == b'\r';
+        match byte
+        {
+            b'\n' if after_cr => Ok(false),
+            b'\r' | b'\n' if self.overflowed =>
+            {
+                self.overflowed = false;
+                Ok(false)
+            },
+            b'\r' | b'\n' =>
+            {
+                self.complete = true;
+                Ok(true)
+            },
+            _ if self.overflowed => Ok(false),
+            _ if self.len == N =>
+            {
+                self.len = 0;
+                self.overflowed = true;
+                Err(Fault::FrameTooLong)
+            },
+            _ =>
+            {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+                Ok(false)
+            }
+        }
+    }
+}
+
+pub struct AsyncUart<'a, const RX: usize, const TX: usize>
+{
+    uart: &'a UART,
+    buffers: &'a Buffers<RX, TX>,
+    flushing: Option<WatermarkLevel>    /* tx watermark to put back once a flush completes */
+}
+
+impl<'a, const RX: usize, const TX: usize> AsyncUart<'a, RX, TX>
+{
+    /* set up the UART to interrupt as soon as a byte arrives. the buffers must
+       be the same ones passed to handle_interrupt() by the interrupt handler,
+       and can only be used by one driver; AlreadyClaimed is returned if they
+       already have been */
+    pub fn new(uart: &'a UART, buffers: &'a Buffers<RX, TX>) -> Result<Self, Fault>
+    {
+        buffers.claim()?;
+        uart.set_rx_watermark(WatermarkLevel::L0);
+        uart.enable_rx_watermark_irq(true);
+        Ok(AsyncUart { uart, buffers, flushing: None })
+    }
+
+    /* wait for at least one byte to arrive, then return as many as are waiting,
+       up to the size of buf. cancel-safe: if the future is dropped before it
+       completes, no data is lost */
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        if buf.is_empty()
+        {
+            return Ok(0)
+        }
+
+        poll_fn(|cx| self.poll_read(cx, buf)).await
+    }
+
+    /* copy bytes already in the rx buffer into buf, returning how many. returns
+       immediately, with zero if nothing's waiting. the hardware FIFO is left to
+       the interrupt handler, which moves its contents into the rx buffer */
+    pub fn read_available(&mut self, buf: &mut [u8]) -> usize
+    {
+        self.buffers.rx.pop_into(buf)
+    }
+
+    /* add received bytes to line until a CR, LF or CRLF ends it, then return the
+       line without its terminator. cancel-safe: a cancelled read leaves what's
+       been received so far in line, and the next call carries on from there.
+       a line too long for the buffer is discarded and FrameTooLong returned */
+    pub async fn read_line<'b, const N: usize>(&mut self, line: &'b mut LineBuffer<N>) -> Result<&'b [u8], Fault>
+    {
+        let mut byte = [0u8];
+        poll_fn(|cx| loop
+        {
+            match self.poll_read(cx, &mut byte)
+            {
+                Poll::Ready(Ok(_)) => match line.push(byte[0])
+                {
+                    Ok(true) => return Poll::Ready(Ok(())),
+                    Ok(false) => (),
+                    Err(e) => return Poll::Ready(Err(e))
+                },
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending
+            }
+        }).await?;
+
+        Ok(line.as_bytes())
+    }
+
+    /* wait for at least one byte to arrive, then return received bytes in
+       place, without copying them out of the rx buffer. the bytes stay in the
+       buffer until consume() is called. cancel-safe */
+    pub async fn fill_buf(&mut self) -> Result<&[u8], Fault>
+    {
+        poll_fn(|cx| self.poll_fill_buf(cx)).await?;
+
+        /* this driver has claimed the buffers, and the slice borrows it mutably,
+           so nothing else can pop until it's dropped */
+        Ok(unsafe { self.buffers.rx.peek() })
+    }
+
+    /* discard up to count bytes previously returned by fill_buf() */
+    pub fn consume(&mut self, count: usize)
+    {
+        self.buffers.rx.consume(count);
+    }
+
+    /* wait up to duration, as measured by timer, for a byte to arrive, failing
+       with TimedOut if none does. cancel-safe: a byte is only removed from the
+       rx buffer if it's returned */
+    pub async fn read_byte_timeout<T: Timer>(&mut self, timer: &mut T, duration: Duration) -> Result<u8, Fault>
+    {
+        let mut timeout = pin!(timer.after(duration));
+        let mut byte = [0u8];
+        poll_fn(|cx|
+        {
+            if let Poll::Ready(result) = self.poll_read(cx, &mut byte)
+            {
+                return Poll::Ready(result.map(|_| byte[0]))
+            }
+
+            timeout.as_mut().poll(cx).map(|_| Err(Fault::TimedOut))
+        }).await
+    }
+
+    /* wait for room in the tx buffer, then queue as much of buf as fits,
+       returning how many bytes were queued. cancel-safe: if the future is
+       dropped before it completes, nothing was queued */
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Fault>
+    {
+        if buf.is_empty()
+        {
+            return Ok(0)
+        }
+
+        poll_fn(|cx| self.poll_write(cx, buf)).await
+    }
+
+    /* queue all of buf, sleeping whenever the tx buffer is full until the tx
+       watermark interrupt has moved bytes into the hardware. not cancel-safe:
+       if the future is dropped, an unknown amount of buf will have been queued */
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Fault>
+    {
+        while !buf.is_empty()
+        {
+            let queued = self.write(buf).await?;
+            buf = &buf[queued..];
+        }
+
+        Ok(())
+    }
+
+    /* wait until every queued byte has left the hardware tx FIFO for the
+       shifter, so the last byte is on the wire. the tx watermark interrupt
+       wakes the task when the FIFO empties, with the watermark set to 1 until
+       the flush completes, when it's put back. a flush dropped before then
+       leaves it at 1 until the next one completes */
+    pub async fn flush(&mut self) -> Result<(), Fault>
+    {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /* the building blocks of read(), write() and flush(), for executors and
+       runtimes that poll by hand rather than through a trait ecosystem. each
+       either completes immediately or returns Pending, having arranged for
+       cx's waker to be woken by the interrupt handler when it's worth polling
+       again */
+
+    /* copy waiting bytes into buf, returning how many. buf must not be empty */
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, Fault>>
+    {
+        /* register before the second check so a byte that lands in between still wakes us */
+        let count = match self.buffers.rx.pop_into(buf)
+        {
+            0 =>
+            {
+                self.buffers.rx_waker.register(cx.waker());
+                self.buffers.rx.pop_into(buf)
+            },
+            count => count
+        };
+
+        match count
+        {
+            0 => Poll::Pending,
+            count => Poll::Ready(Ok(count))
+        }
+    }
+
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Fault>>
+    {
+        if !self.buffers.rx.is_empty()
+        {
+            return Poll::Ready(Ok(()))
+        }
+
+        self.buffers.rx_waker.register(cx.waker());
+        match self.buffers.rx.is_empty()
+        {
+            true => Poll::Pending,
+            false => Poll::Ready(Ok(()))
+        }
+    }
+
+    /* queue as much of buf as fits in the tx buffer, returning how many bytes.
+       buf must not be empty */
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Fault>>
+    {
+        let count = match self.uart.queue_tx(self.buffers, buf)
+        {
+            0 =>
+            {
+                self.buffers.tx_waker.register(cx.waker());
+                self.uart.queue_tx(self.buffers, buf)
+            },
+            count => count
+        };
+
+        match count
+        {
+            0 => Poll::Pending,
+            count => Poll::Ready(Ok(count))
+        }
+    }
+
+    /* complete once every queued byte has left the hardware tx FIFO */
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Fault>>
+    {
+        /* the tx watermark must be 1 before the hardware FIFO can be checked */
+        if self.flushing.is_none()
+        {
+            self.flushing = Some(self.uart.tx_watermark());
+            self.uart.set_tx_watermark(WatermarkLevel::L1);
+        }
+
+        let done = || self.buffers.tx.is_empty() && self.uart.is_tx_drained();
+        if !done()
+        {
+            self.buffers.tx_waker.register(cx.waker());
+            self.uart.start_drain(self.buffers);
+            if !done()
+            {
+                return Poll::Pending
+            }
+        }
+
+        if let Some(level) = self.flushing.take()
+        {
+            self.uart.set_tx_watermark(level);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+mod io
+{
+    use super::AsyncUart;
+    use crate::Fault;
+    use embedded_io_async::{BufRead, ErrorType, Read, Write};
+
+    impl<const RX: usize, const TX: usize> ErrorType for AsyncUart<'_, RX, TX>
+    {
+        type Error = Fault;
+    }
+
+    impl<const RX: usize, const TX: usize> Read for AsyncUart<'_, RX, TX>
+    {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Fault>
+        {
+            AsyncUart::read(self, buf).await
+        }
+    }
+
+    impl<const RX: usize, const TX: usize> BufRead for AsyncUart<'_, RX, TX>
+    {
+        async fn fill_buf(&mut self) -> Result<&[u8], Fault>
+        {
+            AsyncUart::fill_buf(self).await
+        }
+
+        fn consume(&mut self, amt: usize)
+        {
+            AsyncUart::consume(self, amt)
+        }
+    }
+
+    impl<const RX: usize, const TX: usize> Write for AsyncUart<'_, RX, TX>
+    {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Fault>
+        {
+            AsyncUart::write(self, buf).await
+        }
+
+        async fn write_all(&mut self, buf: &[u8]) -> Result<(), Fault>
+        {
+            AsyncUart::write_all(self, buf).await
+        }
+
+        async fn flush(&mut self) -> Result<(), Fault>
+        {
+            AsyncUart::flush(self).await
+        }
+    }
+}
+
+/* received bytes as a never-ending stream */
+#[cfg(feature = "futures")]
+impl<const RX: usize, const TX: usize> futures_core::Stream for AsyncUart<'_, RX, TX>
+{
+    type Item = u8;
+
+    fn poll_next(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<u8>>
+    {
+        let mut byte = [0u8];
+        self.get_mut().poll_read(cx, &mut byte).map(|_| Some(byte[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use core::task::Waker;
+    use std::sync::atomic::Ordering;
+    use crate::tests::FakeRegisters;
+    use crate::waker::tests::counting_waker;
+    use crate::{REG_IE, REG_IE_RXWM, REG_IE_TXWM, REG_IP, REG_IP_TXWM, REG_RXCTRL, REG_RXDATA, REG_RXDATA_EMPTY, REG_TXCTRL, REG_TXDATA};
+
+    #[test]
+    fn read_sleeps_until_interrupt()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        assert!(matches!(AsyncUart::new(&uart, &buffers), Err(Fault::AlreadyClaimed)));
+        assert_ne!(regs.get(REG_IE) & REG_IE_RXWM, 0);
+        assert_eq!(regs.get(REG_RXCTRL) >> 16 & 7, 0);
+
+        let (counter, waker) = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0u8; 4];
+        {
+            let mut read = pin!(serial.read(&mut buf));
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+
+            regs.receive(b'z');
+            uart.handle_interrupt(&buffers);
+            regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+            assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+            assert!(matches!(read.as_mut().poll(&mut cx), Poll::Ready(Ok(4))));
+        }
+        assert_eq!(&buf, b"zzzz");
+    }
+
+    #[test]
+    fn write_and_flush()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 4> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let queued = match pin!(serial.write(b"abcdef")).poll(&mut cx)
+        {
+            Poll::Ready(Ok(n)) => n,
+            _ => panic!("write should complete immediately")
+        };
+        assert_eq!(queued, 4);
+        regs.set(REG_IP, 0);
+        uart.set_tx_watermark(WatermarkLevel::L3);
+
+        {
+            let mut flush = pin!(serial.flush());
+            assert!(flush.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(regs.get(REG_TXCTRL) >> 16 & 7, 1);
+            uart.handle_interrupt(&buffers);
+            assert_eq!(regs.get(REG_TXDATA), b'd' as u32);
+
+            /* the software FIFO is empty but the hardware's hasn't drained */
+            assert!(flush.as_mut().poll(&mut cx).is_pending());
+            assert_ne!(regs.get(REG_IE) & REG_IE_TXWM, 0);
+
+            regs.set(REG_IP, REG_IP_TXWM);
+            uart.handle_interrupt(&buffers);
+            assert_eq!(regs.get(REG_IE) & REG_IE_TXWM, 0);
+            assert!(flush.as_mut().poll(&mut cx).is_ready());
+        }
+        assert_eq!(uart.tx_watermark(), WatermarkLevel::L3);
+    }
+
+    #[test]
+    fn reads_available_without_waiting()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(serial.read_available(&mut buf), 0);
+
+        buffers.rx.push_from(b"ok");
+        assert_eq!(serial.read_available(&mut buf), 2);
+        assert_eq!(&buf[..2], b"ok");
+    }
+
+    #[test]
+    fn buffered_reads_in_place()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+
+        assert!(pin!(serial.fill_buf()).poll(&mut cx).is_pending());
+
+        regs.receive(b'r');
+        uart.handle_interrupt(&buffers);
+        match pin!(serial.fill_buf()).poll(&mut cx)
+        {
+            Poll::Ready(Ok(bytes)) => assert_eq!(bytes, b"rrrrrrrr"),
+            _ => panic!("fill_buf should return the received bytes")
+        }
+
+        serial.consume(5);
+        assert_eq!(buffers.rx.len(), 3);
+    }
+
+    #[test]
+    fn line_survives_cancellation()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut line: LineBuffer<8> = LineBuffer::new();
+
+        buffers.rx.push_from(b"ls");
+        assert!(pin!(serial.read_line(&mut line)).poll(&mut cx).is_pending());
+        assert_eq!(line.as_bytes(), b"ls");
+
+        buffers.rx.push_from(b" -l\r\nnext\n");
+        match pin!(serial.read_line(&mut line)).poll(&mut cx)
+        {
+            Poll::Ready(Ok(text)) => assert_eq!(text, b"ls -l"),
+            _ => panic!("line should be complete")
+        }
+        match pin!(serial.read_line(&mut line)).poll(&mut cx)
+        {
+            Poll::Ready(Ok(text)) => assert_eq!(text, b"next"),
+            _ => panic!("CRLF should end only one line")
+        }
+
+        /* the rest of an overlong line isn't taken for the next one */
+        buffers.rx.push_from(b"0123456789\r\nok\n");
+        assert!(matches!(pin!(serial.read_line(&mut line)).poll(&mut cx), Poll::Ready(Err(Fault::FrameTooLong))));
+        match pin!(serial.read_line(&mut line)).poll(&mut cx)
+        {
+            Poll::Ready(Ok(text)) => assert_eq!(text, b"ok"),
+            _ => panic!("the line after an overlong one should be read whole")
+        }
+
+        /* clearing forgets a CR, so the LF after it ends an empty line */
+        buffers.rx.push_from(b"\r");
+        assert!(matches!(pin!(serial.read_line(&mut line)).poll(&mut cx), Poll::Ready(Ok(_))));
+        line.clear();
+        buffers.rx.push_from(b"\n");
+        match pin!(serial.read_line(&mut line)).poll(&mut cx)
+        {
+            Poll::Ready(Ok(text)) => assert_eq!(text, b""),
+            _ => panic!("a LF after clear() should end a line")
+        };
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn stream_yields_bytes()
+    {
+        use futures_core::Stream;
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let mut stream = core::pin::Pin::new(&mut serial);
+        assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+        buffers.rx.push_from(b"hi");
+        assert_eq!(stream.as_mut().poll_next(&mut cx), Poll::Ready(Some(b'h')));
+        assert_eq!(stream.as_mut().poll_next(&mut cx), Poll::Ready(Some(b'i')));
+    }
+
+    #[test]
+    fn polls_by_hand()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<4, 2> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let (counter, waker) = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(matches!(serial.poll_write(&mut cx, b"abc"), Poll::Ready(Ok(2))));
+        assert!(serial.poll_write(&mut cx, b"c").is_pending());
+        uart.handle_interrupt(&buffers);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(matches!(serial.poll_write(&mut cx, b"c"), Poll::Ready(Ok(1))));
+
+        let mut buf = [0u8; 4];
+        assert!(serial.poll_read(&mut cx, &mut buf).is_pending());
+    }
+
+    #[test]
+    fn write_all_sleeps_between_refills()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<4, 4> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let (counter, waker) = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut write = pin!(serial.write_all(b"0123456789"));
+        let mut interrupts = 0;
+        while write.as_mut().poll(&mut cx).is_pending()
+        {
+            uart.handle_interrupt(&buffers);
+            interrupts += 1;
+        }
+        assert_eq!(interrupts, 2);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        assert_eq!(buffers.tx.len(), 2);
+    }
+
+    /* a timer that expires after being polled a set number of times */
+    struct Polls(usize);
+
+    impl Timer for Polls
+    {
+        fn after(&mut self, _: Duration) -> impl Future<Output = ()>
+        {
+            let mut remaining = self.0;
+            poll_fn(move |_| match remaining
+            {
+                0 => Poll::Ready(()),
+                _ => { remaining -= 1; Poll::Pending }
+            })
+        }
+    }
+
+    #[test]
+    fn read_byte_times_out()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut serial = AsyncUart::new(&uart, &buffers).unwrap();
+        let mut timer = Polls(1);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        {
+            let mut read = pin!(serial.read_byte_timeout(&mut timer, Duration::from_secs(0)));
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+            assert!(matches!(read.as_mut().poll(&mut cx), Poll::Ready(Err(Fault::TimedOut))));
+        }
+
+        regs.receive(b'q');
+        uart.handle_interrupt(&buffers);
+        let mut read = pin!(serial.read_byte_timeout(&mut timer, Duration::from_secs(0)));
+        assert!(matches!(read.as_mut().poll(&mut cx), Poll::Ready(Ok(b'q'))));
+    }
+}
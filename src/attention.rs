@@ -0,0 +1,121 @@
+/* spot a magic sequence in received data, as a break would be used
+ *
+ * The controller can't detect a line break, so there's no way for someone
+ * at the terminal to get the attention of firmware that's busy or hung,
+ * as sysrq does on a Linux serial console. Attention instead watches
+ * received bytes for a chosen sequence, such as the modem-style +++ or
+ * screen's ctrl-A x, optionally typed within a time limit so the sequence
+ * turning up in ordinary text is less likely to set it off, eg:
+ *
+ *   let mut attention = Attention::new(Attention::PLUSES).within(CLOCK_HZ);
+ *   ...
+ *   if attention.feed(byte, clock.now())
+ *   {
+ *       enter_debugger();
+ *   }
+ *
+ * Bytes are only watched: they still belong to whoever reads them.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attention
+{
+    sequence: &'static [u8],
+    window: u64,        /* most ticks allowed from the first byte of the sequence to the last */
+    matched: usize,     /* bytes of the sequence received so far */
+    started: u64        /* when the first of them arrived */
+}
+
+impl Attention
+{
+    pub const PLUSES: &'static [u8] = b"+++";
+    pub const CTRL_A_X: &'static [u8] = b"\x01x";
+
+    /* watch for sequence, however long it takes to arrive. an empty sequence
+       never matches */
+    pub const fn new(sequence: &'static [u8]) -> Self
+    {
+        Attention { sequence, window: u64::MAX, matched: 0, started: 0 }
+    }
+
+    /* only match if the whole sequence arrives within window ticks of whatever
+       clock is passed to feed() */
+    pub const fn within(mut self, window: u64) -> Self
+    {
+        self.window = window;
+        self
+    }
+
+    /* watch a received byte, arriving at tick now, and return true if it
+       completes the sequence. callers without a clock can pass 0 */
+    pub fn feed(&mut self, byte: u8, now: u64) -> bool
+    {
+        if self.matched > 0 && now.wrapping_sub(self.started) > self.window
+        {
+            self.matched = 0;
+        }
+
+        if self.sequence.get(self.matched) != Some(&byte)
+        {
+            /* the byte may yet start the sequence afresh */
+            self.matched = 0;
+            if self.sequence.first() != Some(&byte)
+            {
+                return false
+            }
+        }
+
+        if self.matched == 0
+        {
+            self.started = now;
+        }
+
+        self.matched += 1;
+        if self.matched < self.sequence.len()
+        {
+            return false
+        }
+
+        self.matched = 0;
+        true
+    }
+
+    /* forget any partial match */
+    pub fn reset(&mut self)
+    {
+        self.matched = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn matches(attention: &mut Attention, input: &[(u8, u64)]) -> Vec<usize>
+    {
+        input.iter().enumerate().filter(|(_, &(byte, now))| attention.feed(byte, now)).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn spots_sequences()
+    {
+        let mut attention = Attention::new(Attention::CTRL_A_X);
+        let input: Vec<(u8, u64)> = b"ax\x01\x01x\x01y\x01x".iter().map(|&b| (b, 0)).collect();
+        assert_eq!(matches(&mut attention, &input), [4, 8]);
+
+        assert!(!Attention::new(b"").feed(b'+', 0));
+    }
+
+    #[test]
+    fn sequence_must_arrive_in_time()
+    {
+        let mut attention = Attention::new(Attention::PLUSES).within(100);
+        assert_eq!(matches(&mut attention, &[(b'+', 0), (b'+', 50), (b'+', 101), (b'+', 150), (b'+', 201)]), [4]);
+        assert!(matches(&mut attention, &[(b'+', 300), (b'+', 310), (b'x', 320), (b'+', 330)]).is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+/* find the baud rate the host is using
+ *
+ * A bootloader that doesn't know what rate the terminal on the other end
+ * is set to can ask the user to press Enter and listen for it. The
+ * controller doesn't expose the rx line itself, so Autobaud can't time
+ * the edges of the incoming bits. Instead it tries each candidate rate in
+ * turn, programming the closest divisor and listening for a while, timed
+ * with a caller-provided clock, for the expected character. A character
+ * sent at a different rate arrives as something else, or nothing, eg:
+ *
+ *   let rate = Autobaud::new(mcycle, CPU_HZ).detect(&uart, BUS_HZ)?;
+ *
+ * Rates are tried fastest first, as the slower the rate, the more likely
+ * a mismatched character happens to look right. Anything else received
+ * while listening is discarded.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, REG_DIV, REG_DIV_MASK};
+use super::math;
+use super::timeout::Clock;
+
+/* rates terminals commonly use, fastest first */
+pub const STANDARD_RATES: [u32; 8] = [115200, 57600, 38400, 19200, 9600, 4800, 2400, 1200];
+
+/* full passes through the rates made by default before giving up */
+const DEFAULT_ROUNDS: usize = 8;
+
+pub struct Autobaud<'r, C: Clock>
+{
+    clock: C,
+    window: u64,        /* ticks to listen at each rate */
+    expect: u8,         /* character the host is asked to send */
+    rates: &'r [u32],   /* rates to try, in order */
+    rounds: usize       /* passes through rates before giving up */
+}
+
+impl<C: Clock> Autobaud<'static, C>
+{
+    /* listen for CR at each of the STANDARD_RATES, for a quarter of a second
+       each, measured by clock, which runs at tick_hz */
+    pub fn new(clock: C, tick_hz: u64) -> Self
+    {
+        Autobaud { clock, window: tick_hz / 4, expect: b'\r', rates: &STANDARD_RATES, rounds: DEFAULT_ROUNDS }
+    }
+}
+
+impl<'r, C: Clock> Autobaud<'r, C>
+{
+    /* listen for byte rather than CR */
+    pub fn expecting(self, byte: u8) -> Self
+    {
+        Autobaud { expect: byte, ..self }
+    }
+
+    /* try rates, in order, rather than the STANDARD_RATES */
+    pub fn with_rates<'s>(self, rates: &'s [u32]) -> Autobaud<'s, C>
+    {
+        Autobaud { clock: self.clock, window: self.window, expect: self.expect, rates, rounds: self.rounds }
+    }
+
+    /* set the ticks to listen at each rate. this should be long enough for the
+       user to press a key, or for several characters if the host repeats it */
+    pub fn with_window(self, ticks: u64) -> Self
+    {
+        Autobaud { window: ticks, ..self }
+    }
+
+    /* set the number of passes through the rates before giving up */
+    pub fn with_rounds(self, rounds: usize) -> Self
+    {
+        Autobaud { rounds, ..self }
+    }
+
+    /* find the rate the expected character is arriving at, given the UART's bus
+       frequency in Hz. the UART is left set to that rate, which is returned.
+       rates the divisor can't reach are skipped. fails with TimedOut, with the
+       original divisor restored, if the character isn't recognized in time */
+    pub fn detect(&mut self, uart: &UART, bus_freq: u32) -> Result<u32, Fault>
+    {
+        let original = uart.read_reg(REG_DIV);
+
+        for _ in 0..self.rounds
+        {
+            for &rate in self.rates
+            {
+                /* skipped up front, so the checked feature has nothing to panic over */
+                match math::divisor(rate, bus_freq, uart.variant.divisor_offset)
+                {
+                    Some(divisor) if divisor <= REG_DIV_MASK => uart.set_baud(rate, bus_freq),
+                    _ => continue
+                }
+
+                /* throw away anything received at the previous rate */
+                while uart.read_byte().is_ok() {}
+
+                if self.listen(uart)
+                {
+                    return Ok(rate)
+                }
+            }
+        }
+
+        uart.write_reg(REG_DIV, original);
+        Err(Fault::TimedOut)
+    }
+
+    /* return true if the expected character arrives within the window. gives
+       up early on anything else, as the rate must be wrong */
+    fn listen(&mut self, uart: &UART) -> bool
+    {
+        let started = self.clock.now();
+        while self.clock.now().wrapping_sub(started) < self.window
+        {
+            match uart.read_byte()
+            {
+                Ok(byte) => return byte == self.expect,
+                Err(_) => uart.relax()
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+    use crate::bus::{self, Bus};
+
+    /* a host sending CR at 9600 baud. the UART receives it intact only when
+       set to that rate, and garbage at other rates */
+    struct Host
+    {
+        div: AtomicU32,
+        time: AtomicU64
+    }
+
+    impl Bus for Host
+    {
+        fn read(&self, reg: usize) -> u32
+        {
+            match reg
+            {
+                bus::DIV => self.div.load(Ordering::Relaxed),
+                bus::RXDATA if self.time.fetch_add(1, Ordering::Relaxed) % 50 == 49 =>
+                {
+                    match self.div.load(Ordering::Relaxed)
+                    {
+                        1040 => b'\r' as u32,
+                        div if div > 1040 => bus::FIFO_FLAG,
+                        _ => 0x80
+                    }
+                },
+                bus::RXDATA => bus::FIFO_FLAG,
+                bus::RXCTRL => crate::REG_RXCTRL_RXEN,
+                _ => 0
+            }
+        }
+
+        fn write(&self, reg: usize, val: u32)
+        {
+            if reg == bus::DIV
+            {
+                self.div.store(val, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_the_rate()
+    {
+        let host: &'static Host = Box::leak(Box::new(Host { div: AtomicU32::new(7), time: AtomicU64::new(0) }));
+        let uart = UART::on_bus(host);
+        let clock = || host.time.load(Ordering::Relaxed);
+
+        let mut autobaud = Autobaud::new(clock, 400);
+        assert!(matches!(autobaud.detect(&uart, 10_000_000), Ok(9600)));
+        assert_eq!(host.div.load(Ordering::Relaxed), 1040);
+
+        let rates = [57600, 19200];
+        let mut autobaud = Autobaud::new(clock, 400).with_rates(&rates).with_rounds(2);
+        assert!(matches!(autobaud.detect(&uart, 10_000_000), Err(Fault::TimedOut)));
+        assert_eq!(host.div.load(Ordering::Relaxed), 1040);
+    }
+
+    /* 100 baud needs a divisor too big for the register at this bus frequency,
+       which must be passed over without tripping the checked feature */
+    #[test]
+    fn skips_unreachable_rates()
+    {
+        let host: &'static Host = Box::leak(Box::new(Host { div: AtomicU32::new(7), time: AtomicU64::new(0) }));
+        let uart = UART::on_bus(host);
+        let clock = || host.time.load(Ordering::Relaxed);
+
+        let rates = [100, 9600];
+        let mut autobaud = Autobaud::new(clock, 400).with_rates(&rates);
+        assert!(matches!(autobaud.detect(&uart, 10_000_000), Ok(9600)));
+    }
+}
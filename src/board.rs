@@ -0,0 +1,135 @@
+/* presets for SiFive's development boards
+ *
+ * Each preset bundles the board's chip, the clock its boot firmware leaves
+ * driving the UARTs, and which UART is wired to the board's USB serial
+ * console and at what baud rate, so that
+ *   Board::hifive_unmatched().console()
+ * returns a ready-to-use console.
+ *
+ * QEMU's sifive_u and sifive_e machines are included too. QEMU's UART model
+ * doesn't emulate baud timing, so firmware that detects it's running under
+ * emulation can pick the matching profile and the divisor will be left alone.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::math;
+use super::soc::{Soc, UartInfo, FU540_C000, FU740_C000, FE310_G002};
+
+/* baud rate used by the boards' stock firmware */
+const CONSOLE_BAUD: u32 = 115_200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board
+{
+    pub name: &'static str,
+    pub soc: Soc,
+    pub clock: u32,         /* frequency in Hz of the clock driving the UARTs */
+    pub console: UartInfo,  /* UART connected to the USB serial console */
+    pub baud: u32,          /* console baud rate */
+    pub emulated: bool      /* running under QEMU: don't program the divisor */
+}
+
+impl Board
+{
+    /* HiFive Unmatched: FU740-C000, console on UART0 */
+    pub const fn hifive_unmatched() -> Self
+    {
+        Board::with_console("HiFive Unmatched", FU740_C000, FU740_C000.uart0)
+    }
+
+    /* HiFive Unleashed: FU540-C000, console on UART0 */
+    pub const fn hifive_unleashed() -> Self
+    {
+        Board::with_console("HiFive Unleashed", FU540_C000, FU540_C000.uart0)
+    }
+
+    /* HiFive1 Rev B: FE310-G002, console on UART0 */
+    pub const fn hifive1_revb() -> Self
+    {
+        Board::with_console("HiFive1 Rev B", FE310_G002, FE310_G002.uart0)
+    }
+
+    /* QEMU's sifive_u machine, which models the FU540-C000. its PRCI comes out
+       of reset with coreclk at 1GHz, so the advertised tlclk matches silicon */
+    pub const fn qemu_sifive_u() -> Self
+    {
+        Board::with_console("QEMU sifive_u", FU540_C000, FU540_C000.uart0).emulated()
+    }
+
+    /* QEMU's sifive_e machine, which models the FE310 */
+    pub const fn qemu_sifive_e() -> Self
+    {
+        Board::with_console("QEMU sifive_e", FE310_G002, FE310_G002.uart0).emulated()
+    }
+
+    const fn with_console(name: &'static str, soc: Soc, console: UartInfo) -> Self
+    {
+        Board { name, soc, clock: soc.clock, console, baud: CONSOLE_BAUD, emulated: false }
+    }
+
+    const fn emulated(self) -> Self
+    {
+        Board { emulated: true, ..self }
+    }
+
+    /* use a different UART clock, eg if the PLLs have been reprogrammed */
+    pub const fn with_clock(self, clock: u32) -> Self
+    {
+        Board { clock, ..self }
+    }
+
+    /* use a different console baud rate */
+    pub const fn with_baud(self, baud: u32) -> Self
+    {
+        Board { baud, ..self }
+    }
+
+    /* return the divisor that will be programmed for the console's baud rate,
+       or None if it's skipped because the board is emulated */
+    pub fn divisor(&self) -> Option<u32>
+    {
+        match self.emulated
+        {
+            true => None,
+            false => Some(math::clamped_divisor(self.baud, self.clock, self.soc.variant().divisor_offset))
+        }
+    }
+
+    /* create the board's console UART and set its baud rate */
+    pub fn console(&self) -> Result<UART, Fault>
+    {
+        self.uart(&self.console)
+    }
+
+    /* create one of the board's UARTs and set it to the console's baud rate,
+       unless the board is emulated */
+    pub fn uart(&self, info: &UartInfo) -> Result<UART, Fault>
+    {
+        let uart = UART::from_soc_info(&self.soc, info)?;
+        if !self.emulated
+        {
+            uart.set_baud(self.baud, self.clock);
+        }
+        Ok(uart)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn divisor_matches_set_baud()
+    {
+        /* SiFive's controller divides by the register's value plus one */
+        let board = Board::hifive_unmatched().with_clock(115_200 * 100);
+        assert_eq!(board.divisor(), Some(99));
+        assert_eq!(board.with_baud(0).divisor(), Some(crate::REG_DIV_MASK));
+        assert_eq!(Board::qemu_sifive_u().divisor(), None);
+    }
+}
@@ -0,0 +1,618 @@
+/* interrupt-driven software FIFOs in front of the controller's own
+ *
+ * The hardware FIFOs are only eight bytes deep. Buffers extends them with
+ * ring buffers in RAM: the UART's interrupt handler moves received bytes
+ * from the hardware into the rx ring, and bytes waiting in the tx ring
+ * into the hardware, then wakes any task waiting on either.
+ *
+ * Buffers is intended to live in a static so the interrupt handler can
+ * reach it, eg:
+ *   static BUFFERS: Buffers<64, 256> = Buffers::new();
+ *   ...
+ *   fn uart_irq() { UART.handle_interrupt(&BUFFERS); }
+ *
+ * Input can be rate limited, to protect consoles and parsers from a wedged
+ * or hostile sender streaming at full line rate. The limit is a bucket of
+ * up to burst bytes, topped up by per_tick bytes each time tick() is called,
+ * eg from a timer interrupt. Bytes arriving while it's empty are read from
+ * the hardware, so the rx FIFO doesn't back up, then dropped and counted.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::{UART, Fault, REG_TXDATA, REG_IE, REG_IP};
+use super::ring::RingBuffer;
+use super::waker::AtomicWaker;
+use super::stats::IrqCounters;
+use super::handler::UartEventHandler;
+
+/* rx and tx software FIFOs holding up to RX and TX bytes respectively */
+pub struct Buffers<const RX: usize, const TX: usize>
+{
+    pub(crate) rx: RingBuffer<RX>,
+    pub(crate) tx: RingBuffer<TX>,
+    pub(crate) rx_waker: AtomicWaker,   /* woken when bytes arrive */
+    pub(crate) tx_waker: AtomicWaker,   /* woken when bytes are handed to the hardware */
+    pub(crate) draining: AtomicBool,    /* a flush is waiting for the hardware tx FIFO to empty */
+    claimed: AtomicBool,                /* handed out to a driver, or as Tx and Rx halves */
+    echo: AtomicBool,                   /* send received printable bytes straight back */
+    overruns: AtomicUsize,              /* received bytes dropped because the rx ring was full */
+    limited: AtomicBool,                /* input is rate limited */
+    per_tick: AtomicUsize,              /* bytes added to the budget by each tick() */
+    burst: AtomicUsize,                 /* most bytes the budget can hold */
+    budget: AtomicUsize,                /* bytes that can be received before the next tick() */
+    throttled: AtomicUsize,             /* received bytes dropped because the budget ran out */
+    pub(crate) irqs: IrqCounters        /* interrupts handled, and how long they took */
+}
+
+impl<const RX: usize, const TX: usize> Default for Buffers<RX, TX>
+{
+    fn default() -> Self
+    {
+        Buffers::new()
+    }
+}
+
+impl<const RX: usize, const TX: usize> Buffers<RX, TX>
+{
+    pub const fn new() -> Self
+    {
+        Buffers
+        {
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            rx_waker: AtomicWaker::new(),
+            tx_waker: AtomicWaker::new(),
+            draining: AtomicBool::new(false),
+            claimed: AtomicBool::new(false),
+            echo: AtomicBool::new(false),
+            overruns: AtomicUsize::new(0),
+            limited: AtomicBool::new(false),
+            per_tick: AtomicUsize::new(0),
+            burst: AtomicUsize::new(0),
+            budget: AtomicUsize::new(0),
+            throttled: AtomicUsize::new(0),
+            irqs: IrqCounters::new()
+        }
+    }
+
+    /* claim the buffers for the one driver, or pair of halves, that reads and
+       queues bytes through them, so their rings only ever have one consumer of
+       received bytes and one producer of bytes to send, and their wakers one
+       task each. a set of buffers can only be claimed once; after that,
+       AlreadyClaimed is returned */
+    pub(crate) fn claim(&self) -> Result<(), Fault>
+    {
+        match self.claimed.swap(true, Ordering::AcqRel)
+        {
+            true => Err(Fault::AlreadyClaimed),
+            false => Ok(())
+        }
+    }
+
+    /* turn echo on or off. while it's on, the interrupt handler sends each
+       received printable byte straight back, and CR as CRLF, as an interactive
+       console expects. it's off by default, and must stay off for binary
+       protocols. echoed bytes go to the hardware FIFO ahead of any queued for
+       transmission, and are dropped if it's full */
+    pub fn set_echo(&self, echo: bool)
+    {
+        self.echo.store(echo, Ordering::Relaxed);
+    }
+
+    pub fn echo(&self) -> bool
+    {
+        self.echo.load(Ordering::Relaxed)
+    }
+
+    /* number of received bytes dropped so far because the rx ring was full */
+    pub fn overruns(&self) -> usize
+    {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /* accept at most burst bytes at once, and per_tick more for each call to
+       tick(). the budget starts full */
+    pub fn set_rate_limit(&self, per_tick: usize, burst: usize)
+    {
+        self.per_tick.store(per_tick, Ordering::Relaxed);
+        self.burst.store(burst, Ordering::Relaxed);
+        self.budget.store(burst, Ordering::Relaxed);
+        self.limited.store(true, Ordering::Release);
+    }
+
+    /* accept input as fast as it arrives, which is the default */
+    pub fn clear_rate_limit(&self)
+    {
+        self.limited.store(false, Ordering::Release);
+    }
+
+    /* top up the rate limit's budget. call this at a steady rate */
+    pub fn tick(&self)
+    {
+        let per_tick = self.per_tick.load(Ordering::Relaxed);
+        let burst = self.burst.load(Ordering::Relaxed);
+        let _ = self.budget.fetch_update(Ordering::AcqRel, Ordering::Acquire,
+                                         |budget| Some(budget.saturating_add(per_tick).min(burst)));
+    }
+
+    /* number of received bytes dropped so far by the rate limit */
+    pub fn throttled(&self) -> usize
+    {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /* return true if a received byte is within the rate limit, using up some of
+       the budget, or false if it should be dropped */
+    fn within_rate_limit(&self) -> bool
+    {
+        if !self.limited.load(Ordering::Acquire)
+        {
+            return true
+        }
+
+        match self.budget.fetch_update(Ordering::AcqRel, Ordering::Acquire, |budget| budget.checked_sub(1))
+        {
+            Ok(_) => true,
+            Err(_) =>
+            {
+                self.throttled.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+}
+
+impl UART
+{
+    /* call from the UART's interrupt handler to move data between the hardware
+       and software FIFOs. does a bounded amount of work: at most one hardware
+       FIFO's worth in each direction. the watermark interrupts are level
+       triggered, so anything left over raises another interrupt. the tx
+       watermark interrupt is disabled once there's nothing left to send, and
+       re-enabled when more bytes are queued. if a flush is waiting, it stays
+       enabled until the hardware FIFO has drained too. an interrupt with
+       nothing enabled pending is counted as spurious in the stats */
+    pub fn handle_interrupt<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>)
+    {
+        self.handle_interrupt_with(buffers, &mut ());
+    }
+
+    /* handle_interrupt(), telling handler about bytes received and room freed
+       in the tx ring, and about spurious interrupts */
+    pub fn handle_interrupt_with<H, const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>, handler: &mut H)
+        where H: UartEventHandler
+    {
+        buffers.irqs.interrupts.fetch_add(1, Ordering::Relaxed);
+
+        /* the IE and IP registers share a layout */
+        if self.read_reg(REG_IP) & self.read_reg(REG_IE) == 0
+        {
+            buffers.irqs.spurious.fetch_add(1, Ordering::Relaxed);
+            handler.spurious_irq();
+        }
+
+        let received = self.pump_rx(buffers);
+        if received > 0
+        {
+            buffers.rx_waker.wake();
+            handler.rx_ready(buffers.rx.len());
+        }
+
+        let sent = self.pump_tx(buffers) > 0;
+
+        let mut drained = false;
+        if buffers.tx.is_empty()
+        {
+            match buffers.draining.load(Ordering::Acquire)
+            {
+                true if self.is_tx_drained() =>
+                {
+                    buffers.draining.store(false, Ordering::Release);
+                    self.enable_tx_watermark_irq(false);
+                    drained = true;
+                },
+                true => (),
+                false => self.enable_tx_watermark_irq(false)
+            }
+
+            /* queue_tx() or flush() on another hart may have just queued bytes or
+               started a drain, and enabled the irq before it was disabled above.
+               look again so that their work isn't left stranded */
+            if !buffers.tx.is_empty() || buffers.draining.load(Ordering::Acquire)
+            {
+                self.enable_tx_watermark_irq(true);
+            }
+        }
+
+        if sent || drained
+        {
+            buffers.tx_waker.wake();
+        }
+        if sent
+        {
+            handler.tx_space(buffers.tx.free());
+        }
+    }
+
+    /* move up to a hardware FIFO's worth of received bytes into the rx ring,
+       echoing them if that's on, and return how many were added to the ring */
+    pub(crate) fn pump_rx<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>) -> usize
+    {
+        let echo = buffers.echo();
+        let mut received = 0;
+        for _ in 0..self.variant.fifo_depth
+        {
+            match self.take_rx_byte()
+            {
+                Some(_) if !buffers.within_rate_limit() => (),
+                Some(byte) =>
+                {
+                    match buffers.rx.push(byte)
+                    {
+                        true => received += 1,
+                        false => { buffers.overruns.fetch_add(1, Ordering::Relaxed); }
+                    }
+                    if echo
+                    {
+                        self.echo_byte(byte);
+                    }
+                },
+                None => break
+            }
+        }
+
+        received
+    }
+
+    /* move queued bytes into the hardware tx FIFO until it's full or there are
+       none left, returning how many were moved */
+    pub(crate) fn pump_tx<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>) -> usize
+    {
+        let mut sent = 0;
+        for _ in 0..self.variant.fifo_depth
+        {
+            if self.is_tx_full()
+            {
+                break
+            }
+
+            match buffers.tx.pop()
+            {
+                Some(byte) =>
+                {
+                    self.write_reg(REG_TXDATA, byte as u32);
+                    sent += 1;
+                },
+                None => break
+            }
+        }
+
+        sent
+    }
+
+    /* send a received byte back if it's printable, expanding CR to CRLF */
+    fn echo_byte(&self, byte: u8)
+    {
+        let echoed: &[u8] = match byte
+        {
+            b'\r' => b"\r\n",
+            0x20..=0x7e => &[byte],
+            _ => &[]
+        };
+
+        for &byte in echoed
+        {
+            if self.is_tx_full()
+            {
+                break
+            }
+            self.write_reg(REG_TXDATA, byte as u32);
+        }
+    }
+
+    /* interrupt once the software and hardware tx FIFOs are both empty. the tx
+       watermark must be 1, its default, as that's the level at which the
+       watermark interrupt signals an empty hardware FIFO */
+    pub(crate) fn start_drain<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>)
+    {
+        buffers.draining.store(true, Ordering::Release);
+        self.enable_tx_watermark_irq(true);
+    }
+
+    /* queue bytes for the interrupt handler to transmit, returning how many fit */
+    pub(crate) fn queue_tx<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>, data: &[u8]) -> usize
+    {
+        let queued = buffers.tx.push_from(data);
+
+        /* the irq fires as soon as it's enabled if the hardware FIFO is below its
+           watermark, so this kicks off transmission if it had stalled */
+        if queued > 0
+        {
+            self.enable_tx_watermark_irq(true);
+        }
+
+        queued
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::{FakeRegisters, Script, Fifos};
+    use crate::{IrqPending, WatermarkLevel, REG_IE_TXWM, REG_IP_RXWM, REG_RXDATA, REG_RXDATA_EMPTY};
+
+    #[test]
+    fn interrupt_moves_data_both_ways()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<4, 16> = Buffers::new();
+
+        /* the fake never empties, so the handler reads one FIFO's worth */
+        regs.receive(b'k');
+        uart.handle_interrupt(&buffers);
+        assert_eq!(buffers.rx.len(), 4);
+        assert_eq!(buffers.overruns(), 4);
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+
+        assert_eq!(uart.queue_tx(&buffers, b"0123456789"), 10);
+        assert_ne!(regs.get(REG_IE) & REG_IE_TXWM, 0);
+
+        uart.handle_interrupt(&buffers);
+        assert_eq!(regs.get(REG_TXDATA), b'7' as u32);
+        assert_ne!(regs.get(REG_IE) & REG_IE_TXWM, 0);
+
+        uart.handle_interrupt(&buffers);
+        assert_eq!(regs.get(REG_TXDATA), b'9' as u32);
+        assert_eq!(regs.get(REG_IE) & REG_IE_TXWM, 0);
+    }
+
+    #[test]
+    fn echoes_printable_bytes()
+    {
+        let (script, uart) = Script::uart(b"ok\r\x1b\x00");
+        let buffers: Buffers<8, 8> = Buffers::new();
+        uart.handle_interrupt(&buffers);
+        assert!(script.sent().is_empty());
+
+        script.receive(b"ok\r\x1b\x00");
+        buffers.set_echo(true);
+        uart.handle_interrupt(&buffers);
+        assert_eq!(script.sent(), b"ok\r\n");
+        assert_eq!(buffers.rx.len(), 8);
+    }
+
+    #[test]
+    fn rate_limit_drops_floods()
+    {
+        let (script, uart) = Script::uart(b"0123456789");
+        let buffers: Buffers<16, 8> = Buffers::new();
+        buffers.set_rate_limit(2, 3);
+
+        uart.handle_interrupt(&buffers);
+        assert_eq!(buffers.rx.len(), 3);
+        assert_eq!(buffers.throttled(), 5);
+
+        buffers.tick();
+        uart.handle_interrupt(&buffers);
+        assert_eq!(buffers.rx.len(), 5);
+        assert_eq!(buffers.throttled(), 5);
+
+        /* the budget can't grow beyond the burst */
+        for _ in 0..10
+        {
+            buffers.tick();
+        }
+        script.receive(b"abcdef");
+        uart.handle_interrupt(&buffers);
+        assert_eq!(buffers.rx.len(), 8);
+        assert_eq!(buffers.throttled(), 8);
+
+        buffers.clear_rate_limit();
+        script.receive(b"xyz");
+        uart.handle_interrupt(&buffers);
+        assert_eq!(buffers.rx.len(), 11);
+    }
+
+    #[test]
+    fn counts_spurious_interrupts()
+    {
+        struct Phantoms(usize);
+
+        impl UartEventHandler for Phantoms
+        {
+            fn spurious_irq(&mut self) { self.0 += 1; }
+        }
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<4, 4> = Buffers::new();
+        let mut phantoms = Phantoms(0);
+
+        uart.handle_interrupt_with(&buffers, &mut phantoms);
+        regs.set(REG_IP, REG_IP_RXWM);
+        uart.handle_interrupt_with(&buffers, &mut phantoms);
+        uart.enable_rx_watermark_irq(true);
+        uart.handle_interrupt(&buffers);
+        assert_eq!(phantoms.0, 2);
+        assert_eq!((buffers.stats().interrupts, buffers.stats().spurious), (3, 2));
+    }
+
+    /* what the handler heard, in order */
+    #[derive(Default)]
+    struct Heard
+    {
+        rx: Vec<usize>,
+        tx: Vec<usize>
+    }
+
+    impl UartEventHandler for Heard
+    {
+        fn rx_ready(&mut self, available: usize) { self.rx.push(available); }
+        fn tx_space(&mut self, free: usize) { self.tx.push(free); }
+    }
+
+    /* the interrupt is level-sensitive: the line stays raised while any enabled
+       source is pending */
+    fn raised(uart: &UART) -> bool
+    {
+        uart.read_reg(REG_IP) & uart.read_reg(REG_IE) != 0
+    }
+
+    /* take interrupts for as long as the line is raised, as the PLIC would, and
+       return how many were taken */
+    fn service<const RX: usize, const TX: usize>(uart: &UART, buffers: &Buffers<RX, TX>, heard: &mut Heard) -> usize
+    {
+        let mut taken = 0;
+        while raised(uart)
+        {
+            assert!(taken < 8, "interrupt line stuck raised");
+            uart.handle_interrupt_with(buffers, heard);
+            taken += 1;
+        }
+        taken
+    }
+
+    #[test]
+    fn rx_watermark_crossings()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut heard = Heard::default();
+        uart.set_rx_watermark(WatermarkLevel::L3);
+
+        /* pending only once the FIFO holds more than the watermark */
+        fifos.arrive(b"abcd");
+        fifos.shift(3);
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+        fifos.shift(1);
+        assert_eq!(uart.ip(), IrqPending::BOTH);
+
+        /* pending but masked doesn't raise the line, and unmasking does */
+        assert_eq!(service(&uart, &buffers, &mut heard), 0);
+        uart.enable_rx_watermark_irq(true);
+        assert!(raised(&uart));
+        assert_eq!(service(&uart, &buffers, &mut heard), 1);
+        assert_eq!(heard.rx, [4]);
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+
+        /* falling back to the watermark clears it without the handler */
+        fifos.arrive(b"efghi");
+        fifos.shift(5);
+        assert!(raised(&uart));
+        for _ in 0..2
+        {
+            uart.read_byte().unwrap();
+        }
+        assert!(!raised(&uart));
+        fifos.arrive(b"j");
+        fifos.shift(1);
+        assert_eq!(service(&uart, &buffers, &mut heard), 1);
+        assert_eq!(heard.rx, [4, 8]);
+        assert_eq!(buffers.stats().spurious, 0);
+    }
+
+    #[test]
+    fn tx_watermark_crossings()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut heard = Heard::default();
+        uart.set_tx_watermark(WatermarkLevel::L2);
+
+        /* an empty FIFO is below the watermark, but stays quiet until there's
+           something to send */
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+        assert!(!raised(&uart));
+        assert_eq!(uart.queue_tx(&buffers, b"0123456789abcdef"), 16);
+        assert_eq!(service(&uart, &buffers, &mut heard), 1);
+        assert_eq!(fifos.levels(), (8, 0));
+        assert_eq!(heard.tx, [8]);
+
+        /* pending only once the FIFO holds fewer than the watermark */
+        fifos.shift(6);
+        assert!(!raised(&uart));
+        fifos.shift(1);
+        assert!(raised(&uart));
+        assert_eq!(service(&uart, &buffers, &mut heard), 1);
+        assert_eq!(fifos.levels(), (8, 0));
+        assert_eq!(heard.tx, [8, 15]);
+
+        /* the last bytes empty the ring, so the source is masked while still
+           pending, rather than left to raise the line again */
+        fifos.shift(7);
+        assert_eq!(service(&uart, &buffers, &mut heard), 1);
+        assert_eq!(uart.ip(), IrqPending::NONE);
+        fifos.shift(8);
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+        assert!(!raised(&uart));
+        assert_eq!(fifos.sent(), b"0123456789abcdef");
+        assert_eq!(heard.tx, [8, 15, 16]);
+    }
+
+    #[test]
+    fn random_traffic_keeps_line_quiet()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let buffers: Buffers<64, 32> = Buffers::new();
+        let mut heard = Heard::default();
+        uart.set_rx_watermark(WatermarkLevel::L3);
+        uart.enable_rx_watermark_irq(true);
+
+        let mut seed: u32 = 0x5eed;
+        let mut random = |range: u32| { seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345); (seed >> 16) % range };
+        let (mut arrived, mut queued, mut received) = (Vec::new(), Vec::new(), Vec::new());
+        let mut next = 0u8;
+
+        for _ in 0..2000
+        {
+            match random(4)
+            {
+                0 => for _ in 0..=random(4)
+                {
+                    fifos.arrive(&[next]);
+                    arrived.push(next);
+                    next = next.wrapping_add(1);
+                },
+                1 => fifos.shift(1 + random(4) as usize),
+                2 =>
+                {
+                    let data: Vec<u8> = (0..random(12) as u8).map(|i| next.wrapping_add(i)).collect();
+                    let count = uart.queue_tx(&buffers, &data);
+                    queued.extend_from_slice(&data[..count]);
+                },
+                _ =>
+                {
+                    let mut buf = [0u8; 64];
+                    let count = buffers.rx.pop_into(&mut buf);
+                    received.extend_from_slice(&buf[..count]);
+                }
+            }
+
+            /* after every step the handler leaves nothing enabled and pending */
+            service(&uart, &buffers, &mut heard);
+            assert!(!raised(&uart));
+        }
+
+        /* let the line run until both directions go quiet */
+        for _ in 0..64
+        {
+            fifos.shift(4);
+            service(&uart, &buffers, &mut heard);
+            let mut buf = [0u8; 64];
+            let count = buffers.rx.pop_into(&mut buf);
+            received.extend_from_slice(&buf[..count]);
+        }
+
+        assert_eq!(fifos.overruns() + buffers.overruns(), 0);
+        assert_eq!(received, arrived);
+        assert_eq!(fifos.sent(), queued);
+        assert_eq!(heard.rx.iter().filter(|&&available| available == 0).count(), 0);
+        assert_eq!(buffers.stats().spurious, 0);
+    }
+}
@@ -0,0 +1,80 @@
+/* reaching the controller's registers through something other than MMIO
+ *
+ * Normally the driver reads and writes the registers directly at their
+ * memory-mapped address. A UART created with UART::on_bus() instead sends
+ * every register access to a Bus, which can simulate the controller, eg
+ * to bridge it to a host terminal or to test code built on the driver.
+ * Accesses are identified by their byte offset from the base of the
+ * register block, one of the constants below.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Variant};
+
+pub const TXDATA: usize = super::REG_TXDATA;
+pub const RXDATA: usize = super::REG_RXDATA;
+pub const TXCTRL: usize = super::REG_TXCTRL;
+pub const RXCTRL: usize = super::REG_RXCTRL;
+pub const IE: usize = super::REG_IE;
+pub const IP: usize = super::REG_IP;
+pub const DIV: usize = super::REG_DIV;
+
+/* TXDATA reads with this set when the tx FIFO is full, and RXDATA when the rx FIFO is empty */
+pub const FIFO_FLAG: u32 = super::REG_TXDATA_FULL;
+
+/* something that behaves like the controller's registers. it's shared by
+   every user of the UART, including interrupt handlers, so must be Sync */
+pub trait Bus: Sync
+{
+    fn read(&self, reg: usize) -> u32;
+    fn write(&self, reg: usize, val: u32);
+}
+
+impl UART
+{
+    /* create and initialize a standard UART, as new() does, whose registers are
+       reached through bus */
+    pub fn on_bus(bus: &'static dyn Bus) -> Self
+    {
+        let uart = UART::attach_bus(bus);
+        uart.init();
+        uart
+    }
+
+    /* like attach() but for registers reached through bus */
+    pub const fn attach_bus(bus: &'static dyn Bus) -> Self
+    {
+        let mut uart = UART::attach_with_variant(0, Variant::SIFIVE);
+        uart.bus = Some(bus);
+        uart
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::sync::Mutex;
+
+    /* records register writes */
+    struct Log(Mutex<Vec<(usize, u32)>>);
+
+    impl Bus for Log
+    {
+        fn read(&self, _: usize) -> u32 { 0 }
+        fn write(&self, reg: usize, val: u32) { self.0.lock().unwrap().push((reg, val)) }
+    }
+
+    #[test]
+    fn accesses_go_to_bus()
+    {
+        let log: &'static Log = Box::leak(Box::new(Log(Mutex::new(Vec::new()))));
+        let uart = UART::on_bus(log);
+        uart.send_byte(b'b').unwrap();
+        assert_eq!(log.0.lock().unwrap().last(), Some(&(TXDATA, b'b' as u32)));
+        assert_eq!(log.0.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,119 @@
+/* validation of the values callers hand the driver
+ *
+ * Every watermark, divisor or baud rate a caller chooses is checked here
+ * before it reaches a register. A value the controller can't hold is
+ * returned as OutOfRange by the try_ methods. With the checked feature
+ * enabled, it instead panics in debug builds, so misuse is caught loudly
+ * during development while release builds stay free of panics. The
+ * infallible setters don't go through these checks: they clamp the value,
+ * as they always have, whatever the features.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, WatermarkLevel, REG_DIV, REG_DIV_MASK};
+use super::math;
+
+/* fail with OutOfRange, or panic in a checked debug build, unless ok */
+#[inline]
+fn ensure(ok: bool, what: &'static str) -> Result<(), Fault>
+{
+    match ok
+    {
+        true => Ok(()),
+        false if cfg!(all(feature = "checked", debug_assertions)) => panic!("mmio_sifive_uart: {} out of range", what),
+        false => Err(Fault::OutOfRange)
+    }
+}
+
+impl UART
+{
+    /* like set_tx_watermark() but fails rather than clamp a level the controller can't hold */
+    pub fn try_set_tx_watermark(&self, level: WatermarkLevel) -> Result<(), Fault>
+    {
+        ensure(level <= self.variant.watermark_max(), "tx watermark")?;
+        self.set_tx_watermark(level);
+        Ok(())
+    }
+
+    /* like set_rx_watermark() but fails rather than clamp a level the controller can't hold */
+    pub fn try_set_rx_watermark(&self, level: WatermarkLevel) -> Result<(), Fault>
+    {
+        ensure(level <= self.variant.watermark_max(), "rx watermark")?;
+        self.set_rx_watermark(level);
+        Ok(())
+    }
+
+    /* write the baud rate divisor directly, failing if it doesn't fit the register */
+    pub fn try_set_divisor(&self, divisor: u32) -> Result<(), Fault>
+    {
+        ensure(divisor & !REG_DIV_MASK == 0, "divisor")?;
+        self.check_tx_quiet();
+        self.write_reg(REG_DIV, divisor);
+        Ok(())
+    }
+
+    /* like set_baud() but fails if baud is zero, too slow for the divisor
+       register at this bus frequency, or too fast for the bus to reach */
+    pub fn try_set_baud(&self, baud: u32, bus_freq: u32) -> Result<(), Fault>
+    {
+        match math::divisor(baud, bus_freq, self.variant.divisor_offset)
+        {
+            Some(divisor) => self.try_set_divisor(divisor),
+            None => ensure(false, "baud rate")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::{Variant, REG_TOTAL_SIZE};
+
+    #[test]
+    fn accepts_values_in_range()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.try_set_tx_watermark(WatermarkLevel::L7).unwrap();
+        uart.try_set_rx_watermark(WatermarkLevel::L0).unwrap();
+        uart.try_set_baud(115200, 500_000_000).unwrap();
+        assert_eq!((uart.tx_watermark(), uart.rx_watermark()), (WatermarkLevel::L7, WatermarkLevel::L0));
+        assert_eq!(regs.get(REG_DIV), 4339);
+    }
+
+    #[test]
+    #[cfg_attr(all(feature = "checked", debug_assertions), should_panic(expected = "out of range"))]
+    fn rejects_values_out_of_range()
+    {
+        /* a clone with half the FIFO can't take the higher levels */
+        let regs = FakeRegisters::new();
+        let shallow = Variant { fifo_depth: 4, ..Variant::SIFIVE };
+        let uart = UART::new_with_variant(regs.base(), REG_TOTAL_SIZE, shallow).unwrap();
+        assert!(matches!(uart.try_set_rx_watermark(WatermarkLevel::L4), Err(Fault::OutOfRange)));
+        assert!(matches!(uart.try_set_baud(0, 500_000_000), Err(Fault::OutOfRange)));
+        assert!(matches!(uart.try_set_baud(300, 500_000_000), Err(Fault::OutOfRange)));
+
+        /* faster than the bus, which would otherwise get divisor 0 and run at bus_freq */
+        assert!(matches!(uart.try_set_baud(115200, 1000), Err(Fault::OutOfRange)));
+        assert!(matches!(uart.try_set_baud(115200, 115200), Err(Fault::OutOfRange)));
+    }
+
+    /* the infallible setters clamp instead, checked or not */
+    #[test]
+    #[cfg_attr(all(feature = "strict", debug_assertions), should_panic(expected = "out of range"))]
+    fn setters_clamp()
+    {
+        let regs = FakeRegisters::new();
+        let shallow = Variant { fifo_depth: 4, ..Variant::SIFIVE };
+        let uart = UART::new_with_variant(regs.base(), REG_TOTAL_SIZE, shallow).unwrap();
+        uart.set_rx_watermark(WatermarkLevel::L6);
+        uart.set_baud(300, 500_000_000);
+        assert_eq!(uart.rx_watermark(), WatermarkLevel::L3);
+        assert_eq!(regs.get(REG_DIV), REG_DIV_MASK);
+    }
+}
@@ -0,0 +1,408 @@
+/* COBS (consistent overhead byte stuffing) framing on top of the UART
+ *
+ * Each frame is encoded so it contains no zero bytes, and is terminated
+ * by a single zero byte. This makes resynchronizing after line noise
+ * trivial: wait for the next zero. Overhead is at most one byte per 254.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::timeout::{Timeout, Forever};
+
+/* byte that marks the end of every frame */
+pub const DELIMITER: u8 = 0;
+
+/* longest run of non-zero bytes a single code byte can describe */
+const MAX_RUN: usize = 254;
+
+#[derive(Clone, Copy)]
+enum EncodeState
+{
+    Code,   /* emit the code byte for the next block */
+    Copy,   /* copying the current block's data bytes */
+    End,    /* emit the frame delimiter */
+    Done
+}
+
+/* iterate over the bytes of a COBS-encoded frame, including its delimiter */
+pub struct Encoder<'a>
+{
+    data: &'a [u8],
+    pos: usize,         /* next input byte to encode */
+    left: usize,        /* data bytes remaining in the current block */
+    run: usize,         /* length of the current block's data */
+    state: EncodeState
+}
+
+impl<'a> Encoder<'a>
+{
+    pub fn new(data: &'a [u8]) -> Self
+    {
+        Encoder { data, pos: 0, left: 0, run: 0, state: EncodeState::Code }
+    }
+}
+
+impl Iterator for Encoder<'_>
+{
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8>
+    {
+        loop
+        {
+            match self.state
+            {
+                EncodeState::Code =>
+                {
+                    self.run = self.data[self.pos..].iter()
+                                .take(MAX_RUN).take_while(|&&b| b != 0).count();
+                    self.left = self.run;
+                    self.state = EncodeState::Copy;
+                    return Some(self.run as u8 + 1)
+                },
+                EncodeState::Copy if self.left > 0 =>
+                {
+                    let byte = self.data[self.pos];
+                    self.pos += 1;
+                    self.left -= 1;
+                    return Some(byte)
+                },
+                EncodeState::Copy =>
+                {
+                    /* a short block ends with a zero, which is implied by
+                       the code byte, or with the end of the data */
+                    if self.pos < self.data.len()
+                    {
+                        if self.run < MAX_RUN
+                        {
+                            self.pos += 1;
+                        }
+                        self.state = EncodeState::Code;
+                    }
+                    else
+                    {
+                        self.state = EncodeState::End;
+                    }
+                },
+                EncodeState::End =>
+                {
+                    self.state = EncodeState::Done;
+                    return Some(DELIMITER)
+                },
+                EncodeState::Done => return None
+            }
+        }
+    }
+}
+
+/* reassemble COBS frames one byte at a time into a caller-supplied buffer */
+pub struct Decoder<'a>
+{
+    buf: &'a mut [u8],
+    len: usize,
+    code: u8,           /* code byte of the current block */
+    left: usize,        /* data bytes remaining in the current block */
+    pending_zero: bool, /* previous block implied a zero, if more data follows */
+    started: bool,      /* at least one code byte seen */
+    discard: bool,      /* frame is broken: skip bytes until the next delimiter */
+    complete: bool      /* buf[..len] holds a finished frame */
+}
+
+impl<'a> Decoder<'a>
+{
+    pub fn new(buf: &'a mut [u8]) -> Self
+    {
+        Decoder
+        {
+            buf, len: 0, code: 0, left: 0, pending_zero: false,
+            started: false, discard: false, complete: false
+        }
+    }
+
+    /* feed a received byte into the decoder. returns the length of the frame
+       when its delimiter arrives, which can then be fetched with frame().
+       a frame that overflows the buffer or is truncated is reported once
+       and then dropped up to the next delimiter */
+    pub fn push(&mut self, byte: u8) -> Result<Option<usize>, Fault>
+    {
+        if self.complete
+        {
+            self.reset();
+        }
+
+        if byte == DELIMITER
+        {
+            let (broken, truncated, started) = (self.discard, self.left > 0, self.started);
+            let len = self.len;
+            self.reset();
+
+            if broken || !started
+            {
+                return Ok(None)
+            }
+
+            if truncated
+            {
+                return Err(Fault::BadFrame)
+            }
+
+            self.len = len;
+            self.complete = true;
+            return Ok(Some(len))
+        }
+
+        if self.discard
+        {
+            return Ok(None)
+        }
+
+        if self.left == 0
+        {
+            /* start of a new block */
+            if self.pending_zero
+            {
+                self.store(0)?;
+            }
+
+            self.started = true;
+            self.code = byte;
+            self.left = byte as usize - 1;
+            self.pending_zero = self.left == 0 && byte as usize <= MAX_RUN;
+            return Ok(None)
+        }
+
+        self.store(byte)?;
+        self.left -= 1;
+        self.pending_zero = self.left == 0 && (self.code as usize) <= MAX_RUN;
+        Ok(None)
+    }
+
+    /* return the most recently completed frame, or an empty slice if a frame
+       is still being received */
+    pub fn frame(&self) -> &[u8]
+    {
+        match self.complete
+        {
+            true => &self.buf[..self.len],
+            false => &[]
+        }
+    }
+
+    /* abandon the frame in progress and wait for the next one */
+    pub fn reset(&mut self)
+    {
+        self.len = 0;
+        self.code = 0;
+        self.left = 0;
+        self.pending_zero = false;
+        self.started = false;
+        self.discard = false;
+        self.complete = false;
+    }
+
+    fn store(&mut self, byte: u8) -> Result<(), Fault>
+    {
+        if self.len >= self.buf.len()
+        {
+            self.reset();
+            self.discard = true;
+            return Err(Fault::FrameTooLong)
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl UART
+{
+    /* transmit the given data as a single zero-delimited COBS frame */
+    pub fn send_frame(&self, data: &[u8]) -> Result<(), Fault>
+    {
+        for byte in Encoder::new(data)
+        {
+            self.send_byte(byte)?;
+        }
+
+        Ok(())
+    }
+
+    /* receive a COBS frame into buf, returning the decoded frame's length.
+       gives up with DataNotReady if nothing at all arrives, but once a byte
+       has, waits as long as it takes for the rest of the frame, however slow
+       the line. use read_frame_timeout() to bound that too, or a Decoder
+       directly to receive frames across multiple calls */
+    pub fn read_frame(&self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        let first = self.wait_for_byte()?;
+        self.read_rest_of_frame(buf, first, &mut Forever)
+    }
+
+    /* receive a COBS frame into buf, returning the decoded frame's length,
+       waiting for each byte until timeout expires. a Deadline bounds the
+       whole frame. gives up with DataNotReady, discarding any partial frame */
+    pub fn read_frame_timeout<T: Timeout>(&self, buf: &mut [u8], timeout: &mut T) -> Result<usize, Fault>
+    {
+        let first = self.read_byte_timeout(timeout)?;
+        self.read_rest_of_frame(buf, first, timeout)
+    }
+
+    /* decode a frame starting with first, waiting for each later byte until
+       timeout expires. a frame too long for buf is read and dropped up to its
+       delimiter before the fault is returned, so the next read starts with the
+       frame after it rather than partway through this one */
+    fn read_rest_of_frame<T: Timeout>(&self, buf: &mut [u8], first: u8, timeout: &mut T) -> Result<usize, Fault>
+    {
+        let mut decoder = Decoder::new(buf);
+        let mut byte = first;
+        loop
+        {
+            match decoder.push(byte)
+            {
+                Ok(Some(len)) => return Ok(len),
+                Ok(None) => (),
+                Err(fault) if byte == DELIMITER => return Err(fault),
+                Err(fault) => loop
+                {
+                    match self.read_byte_timeout(timeout)
+                    {
+                        Ok(DELIMITER) | Err(_) => return Err(fault),
+                        Ok(_) => ()
+                    }
+                }
+            }
+            byte = self.read_byte_timeout(timeout)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::{Fifos, Script};
+    use crate::timeout::Polls;
+    use crate::LOOP_MAX;
+
+    fn encode(data: &[u8]) -> Vec<u8>
+    {
+        Encoder::new(data).collect()
+    }
+
+    fn decode(encoded: &[u8]) -> Vec<u8>
+    {
+        let mut buf = [0u8; 600];
+        let mut decoder = Decoder::new(&mut buf);
+        for &byte in encoded
+        {
+            if decoder.push(byte).unwrap().is_some()
+            {
+                return decoder.frame().to_vec()
+            }
+        }
+        panic!("frame not terminated");
+    }
+
+    #[test]
+    fn known_encodings()
+    {
+        assert_eq!(encode(&[]), [1, 0]);
+        assert_eq!(encode(&[0]), [1, 1, 0]);
+        assert_eq!(encode(&[0, 0]), [1, 1, 1, 0]);
+        assert_eq!(encode(&[0x11, 0x22, 0, 0x33]), [3, 0x11, 0x22, 2, 0x33, 0]);
+        assert_eq!(encode(&[0x11, 0, 0, 0]), [2, 0x11, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn long_runs()
+    {
+        let run: Vec<u8> = (1..=254).collect();
+        let mut expected = vec![0xff];
+        expected.extend(&run);
+        expected.push(0);
+        assert_eq!(encode(&run), expected);
+
+        let run: Vec<u8> = (1..=255).collect();
+        let mut expected = vec![0xff];
+        expected.extend(1..=254);
+        expected.extend([2, 0xff, 0]);
+        assert_eq!(encode(&run), expected);
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let cases: [Vec<u8>; 5] =
+        [
+            vec![],
+            vec![0; 10],
+            (0..=255).collect(),
+            (0..520).map(|i| (i % 7) as u8).collect(),
+            (0..520).map(|i| (i % 255) as u8 + 1).collect()
+        ];
+
+        for case in cases.iter()
+        {
+            assert_eq!(&decode(&encode(case)), case);
+        }
+    }
+
+    #[test]
+    fn truncated_and_oversized_frames()
+    {
+        let mut buf = [0u8; 2];
+        let mut decoder = Decoder::new(&mut buf);
+        decoder.push(4).unwrap();
+        decoder.push(1).unwrap();
+        assert!(matches!(decoder.push(DELIMITER), Err(Fault::BadFrame)));
+
+        decoder.push(4).unwrap();
+        decoder.push(1).unwrap();
+        decoder.push(2).unwrap();
+        assert!(matches!(decoder.push(3), Err(Fault::FrameTooLong)));
+        assert_eq!(decoder.push(DELIMITER).unwrap(), None);
+
+        decoder.push(2).unwrap();
+        decoder.push(9).unwrap();
+        assert_eq!(decoder.push(DELIMITER).unwrap(), Some(1));
+        assert_eq!(decoder.frame(), &[9]);
+    }
+
+    /* bytes trickling in far slower than LOOP_MAX polls apart */
+    #[test]
+    fn waits_for_slow_frames()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let mut buf = [0u8; 8];
+        assert!(matches!(uart.read_frame(&mut buf), Err(Fault::DataNotReady)));
+
+        fifos.arrive(&encode(b"s\0w"));
+        fifos.shift(1);
+        fifos.set_pace(LOOP_MAX * 3);
+        assert_eq!(uart.read_frame(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"s\0w");
+
+        fifos.arrive(&encode(b"s\0w"));
+        assert!(matches!(uart.read_frame_timeout(&mut buf, &mut Polls::new(LOOP_MAX)), Err(Fault::DataNotReady)));
+        assert_eq!(uart.read_frame_timeout(&mut buf, &mut Polls::new(LOOP_MAX * 4)).unwrap(), 3);
+    }
+
+    #[test]
+    fn resyncs_after_broken_frames()
+    {
+        let mut input = encode(b"far too long");
+        input.extend([4, 1, DELIMITER]);
+        input.extend(encode(b"o\0k"));
+        let (_, uart) = Script::uart(&input);
+
+        let mut buf = [0u8; 4];
+        assert!(matches!(uart.read_frame(&mut buf), Err(Fault::FrameTooLong)));
+        assert!(matches!(uart.read_frame(&mut buf), Err(Fault::BadFrame)));
+        assert_eq!(uart.read_frame(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"o\0k");
+    }
+}
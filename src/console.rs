@@ -0,0 +1,182 @@
+/* consoles that aren't tied to a particular transport
+ *
+ * Logging and shell code written against ConsoleBackend rather than UART
+ * can later be moved to another transport, such as RTT, semihosting or a
+ * different serial controller, by implementing the trait for it. UART and
+ * Mux channels implement it here. The trait is object-safe, so a console
+ * can be picked at boot and kept as a &dyn ConsoleBackend. Console adds
+ * core::fmt::Write on top, turning LF into CRLF for terminals, eg:
+ *
+ *   let mut console = Console::new(&uart);
+ *   writeln!(console, "hart {} up", hartid)?;
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, LOOP_MAX};
+use super::mux::Channel;
+
+pub trait ConsoleBackend
+{
+    /* send bytes from data, returning how many were accepted. may accept fewer
+       than all of them. fails if none could be */
+    fn write(&self, data: &[u8]) -> Result<usize, Fault>;
+
+    /* wait for everything written to be sent. by default there's nothing to wait for */
+    fn flush(&self) -> Result<(), Fault>
+    {
+        Ok(())
+    }
+
+    /* copy received bytes into buf, returning how many without waiting for more.
+       by default, backends are output-only and never receive anything */
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        Ok(0)
+    }
+}
+
+impl<B: ConsoleBackend + ?Sized> ConsoleBackend for &B
+{
+    fn write(&self, data: &[u8]) -> Result<usize, Fault>
+    {
+        (**self).write(data)
+    }
+
+    fn flush(&self) -> Result<(), Fault>
+    {
+        (**self).flush()
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        (**self).read(buf)
+    }
+}
+
+impl ConsoleBackend for UART
+{
+    fn write(&self, data: &[u8]) -> Result<usize, Fault>
+    {
+        match self.send_bytes(data)
+        {
+            Ok(sent) => Ok(sent),
+            Err(partial) if partial.sent > 0 => Ok(partial.sent),
+            Err(partial) => Err(partial.fault)
+        }
+    }
+
+    /* gives up with TxNotEmpty after checking LOOP_MAX times */
+    fn flush(&self) -> Result<(), Fault>
+    {
+        for _ in 0..LOOP_MAX
+        {
+            if self.is_tx_idle()
+            {
+                return Ok(())
+            }
+            self.relax();
+        }
+
+        Err(Fault::TxNotEmpty)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        Ok(self.read_available(buf))
+    }
+}
+
+impl<const CHANNELS: usize, const RX: usize> ConsoleBackend for Channel<'_, '_, CHANNELS, RX>
+{
+    fn write(&self, data: &[u8]) -> Result<usize, Fault>
+    {
+        Channel::write(self, data).map(|_| data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        Ok(Channel::read(self, buf))
+    }
+}
+
+/* formatted output to any backend, with LF sent as CRLF */
+pub struct Console<B: ConsoleBackend>
+{
+    backend: B
+}
+
+impl<B: ConsoleBackend> Console<B>
+{
+    pub const fn new(backend: B) -> Self
+    {
+        Console { backend }
+    }
+
+    pub fn backend(&self) -> &B
+    {
+        &self.backend
+    }
+
+    /* send all of data, retrying until the backend has accepted it */
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), Fault>
+    {
+        while !data.is_empty()
+        {
+            let count = self.backend.write(data)?;
+            data = &data[count..];
+        }
+
+        Ok(())
+    }
+}
+
+impl<B: ConsoleBackend> core::fmt::Write for Console<B>
+{
+    fn write_str(&mut self, string: &str) -> core::fmt::Result
+    {
+        for (i, line) in string.split('\n').enumerate()
+        {
+            if i > 0
+            {
+                self.write_all(b"\r\n").map_err(|_| core::fmt::Error)?;
+            }
+            self.write_all(line.as_bytes()).map_err(|_| core::fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use core::fmt::Write;
+    use crate::tests::Script;
+    use crate::mux::{Mux, FRAME_MAX};
+
+    #[test]
+    fn backends_are_interchangeable()
+    {
+        let (script, uart) = Script::uart(b"in");
+        let backend: &dyn ConsoleBackend = &uart;
+        let mut console = Console::new(backend);
+        writeln!(console, "{} up", 1).unwrap();
+        assert_eq!(script.sent(), b"1 up\r\n");
+
+        let mut buf = [0u8; 4];
+        assert_eq!(console.backend().read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"in");
+
+        let mut frame = [0u8; FRAME_MAX];
+        let mux: Mux<1, 16> = Mux::new(&uart, &mut frame);
+        let mut console = Console::new(mux.channel(0));
+        write!(console, "x").unwrap();
+        script.receive(&script.sent());
+        assert_eq!(ConsoleBackend::read(console.backend(), &mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'x');
+    }
+}
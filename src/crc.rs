@@ -0,0 +1,73 @@
+/* checksums used by the crate's transfer protocols
+ *
+ * Computed bit-by-bit rather than with lookup tables to keep the
+ * crate small: serial links are slow enough that speed doesn't matter.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+/* CRC-16/XMODEM: polynomial 0x1021, initial value 0, no reflection.
+   pass 0 as crc to start a new checksum, or a previous result to continue it */
+pub fn crc16(crc: u16, data: &[u8]) -> u16
+{
+    let mut crc = crc;
+    for &byte in data
+    {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8
+        {
+            crc = match crc & 0x8000
+            {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021
+            };
+        }
+    }
+
+    crc
+}
+
+/* CRC-32/ISO-HDLC, as used by zlib and Ethernet: reflected polynomial
+   0xedb88320, initial value and final XOR 0xffffffff. pass 0 as crc to
+   start a new checksum, or a previous result to continue it */
+pub fn crc32(crc: u32, data: &[u8]) -> u32
+{
+    let mut crc = !crc;
+    for &byte in data
+    {
+        crc ^= byte as u32;
+        for _ in 0..8
+        {
+            crc = match crc & 1
+            {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xedb8_8320
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn crc16_check_value()
+    {
+        /* standard check value for CRC-16/XMODEM */
+        assert_eq!(crc16(0, b"123456789"), 0x31c3);
+        assert_eq!(crc16(crc16(0, b"1234"), b"56789"), 0x31c3);
+    }
+
+    #[test]
+    fn crc32_check_value()
+    {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+}
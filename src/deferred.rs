@@ -0,0 +1,208 @@
+/* interrupt handling split into top and bottom halves
+ *
+ * Kernels usually want as little as possible done in the interrupt
+ * handler itself. ack_and_buffer() is the top half: it takes what's in the
+ * rx FIFO into the buffers, quietens the interrupt, and notes what happened
+ * in a WorkQueue. process() is the bottom half, called later from thread
+ * context, eg a kernel worker, to refill the tx FIFO, re-arm the
+ * interrupt if there's more to send, and report the work found, eg:
+ *
+ *   static BUFFERS: Buffers<64, 256> = Buffers::new();
+ *   static WORK: WorkQueue = WorkQueue::new();
+ *
+ *   fn uart_irq() { UART.ack_and_buffer(&BUFFERS, &WORK); schedule_worker(); }
+ *
+ *   fn worker()
+ *   {
+ *       let work = UART.process(&BUFFERS, &WORK);
+ *       if work.rx_ready { wake_readers(); }
+ *   }
+ *
+ * Events noted by several interrupts before process() runs are merged.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::{UART, REG_IE, REG_IE_TXWM};
+use super::buffered::Buffers;
+use super::handler::UartEventHandler;
+
+const WORK_RX_READY: usize = 1 << 0;
+const WORK_TX_SPACE: usize = 1 << 1;
+
+/* what the top half found for the bottom half to deal with */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Work
+{
+    pub rx_ready: bool,     /* received bytes were added to the rx buffer */
+    pub tx_space: bool      /* the tx FIFO dropped below its watermark */
+}
+
+impl Work
+{
+    /* return true if there's anything to do */
+    pub fn any(&self) -> bool
+    {
+        self.rx_ready || self.tx_space
+    }
+}
+
+/* events passed from the top half to the bottom half */
+pub struct WorkQueue
+{
+    events: AtomicUsize
+}
+
+impl Default for WorkQueue
+{
+    fn default() -> Self
+    {
+        WorkQueue::new()
+    }
+}
+
+impl WorkQueue
+{
+    pub const fn new() -> Self
+    {
+        WorkQueue { events: AtomicUsize::new(0) }
+    }
+
+    /* return the work waiting for process(), without taking it */
+    pub fn peek(&self) -> Work
+    {
+        WorkQueue::decode(self.events.load(Ordering::Acquire))
+    }
+
+    fn post(&self, events: usize)
+    {
+        self.events.fetch_or(events, Ordering::AcqRel);
+    }
+
+    fn take(&self) -> Work
+    {
+        WorkQueue::decode(self.events.swap(0, Ordering::AcqRel))
+    }
+
+    fn decode(events: usize) -> Work
+    {
+        Work { rx_ready: events & WORK_RX_READY != 0, tx_space: events & WORK_TX_SPACE != 0 }
+    }
+}
+
+impl UART
+{
+    /* top half, for the interrupt handler. empties the rx FIFO into buffers and,
+       if the tx watermark interrupt is enabled and pending, masks it until process() has
+       refilled the tx FIFO. both quieten the level-triggered interrupt. bounded
+       time: at most one hardware FIFO's worth of reads */
+    pub fn ack_and_buffer<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>, queue: &WorkQueue)
+    {
+        let mut events = 0;
+        if self.pump_rx(buffers) > 0
+        {
+            events |= WORK_RX_READY;
+        }
+
+        /* the pending bit follows the FIFO level even while masked */
+        if self.ip().txwm && self.read_reg(REG_IE) & REG_IE_TXWM != 0
+        {
+            self.enable_tx_watermark_irq(false);
+            events |= WORK_TX_SPACE;
+        }
+
+        if events != 0
+        {
+            queue.post(events);
+        }
+    }
+
+    /* bottom half, for thread context. moves queued bytes into the tx FIFO, and
+       unmasks the tx watermark interrupt if any are left, then returns the work
+       the top half noted since the last call */
+    pub fn process<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>, queue: &WorkQueue) -> Work
+    {
+        let work = queue.take();
+        if work.tx_space
+        {
+            self.pump_tx(buffers);
+        }
+
+        if work.rx_ready
+        {
+            buffers.rx_waker.wake();
+        }
+
+        if work.tx_space
+        {
+            buffers.tx_waker.wake();
+            if !buffers.tx.is_empty()
+            {
+                self.enable_tx_watermark_irq(true);
+            }
+        }
+
+        work
+    }
+
+    /* process(), then tell handler about the work found */
+    pub fn process_with<H, const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>, queue: &WorkQueue,
+                                                             handler: &mut H) -> Work
+        where H: UartEventHandler
+    {
+        let work = self.process(buffers, queue);
+        if work.rx_ready
+        {
+            handler.rx_ready(buffers.rx.len());
+        }
+        if work.tx_space
+        {
+            handler.tx_space(buffers.tx.free());
+        }
+        work
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+    use crate::{REG_IP, REG_IP_TXWM};
+
+    #[test]
+    fn halves_hand_over_work()
+    {
+        let (script, uart) = Script::uart(b"in");
+        let buffers: Buffers<8, 16> = Buffers::new();
+        let queue = WorkQueue::new();
+        assert_eq!(uart.queue_tx(&buffers, b"0123456789"), 10);
+
+        /* the script's IP holds whatever is written to it */
+        uart.ack_and_buffer(&buffers, &queue);
+        assert_eq!(queue.peek(), Work { rx_ready: true, tx_space: false });
+        assert_eq!(uart.process(&buffers, &queue), Work { rx_ready: true, tx_space: false });
+        assert!(!queue.peek().any());
+        assert_eq!(buffers.rx.len(), 2);
+        assert!(script.sent().is_empty());
+
+        uart.write_reg(REG_IP, REG_IP_TXWM);
+        uart.ack_and_buffer(&buffers, &queue);
+        assert_eq!(uart.read_reg(REG_IE) & REG_IE_TXWM, 0);
+        assert_eq!(uart.process(&buffers, &queue), Work { rx_ready: false, tx_space: true });
+        assert_eq!(script.sent(), b"01234567");
+        assert_ne!(uart.read_reg(REG_IE) & REG_IE_TXWM, 0);
+
+        uart.ack_and_buffer(&buffers, &queue);
+        uart.process(&buffers, &queue);
+        assert_eq!(script.sent(), b"89");
+        assert_eq!(uart.read_reg(REG_IE) & REG_IE_TXWM, 0);
+
+        /* masked, so nothing to do */
+        uart.ack_and_buffer(&buffers, &queue);
+        assert!(!queue.peek().any());
+    }
+}
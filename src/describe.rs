@@ -0,0 +1,167 @@
+/* describing a UART's state in logs
+ *
+ * Debug and Display read the controller's registers to report how it's
+ * set up, but never RXDATA: reading that dequeues a received byte, so
+ * logging the driver could otherwise eat incoming characters. Config holds
+ * the same information, and displays it as a one-line summary for boot
+ * banners, eg: 115200 8-N-1, div=4339, irq rx@6
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::fmt;
+use super::{UART, WatermarkLevel, REG_TXCTRL, REG_RXCTRL, REG_IE, REG_DIV, REG_DIV_MASK,
+            REG_TXCTRL_TXEN, REG_TXCTRL_NSTOP, REG_RXCTRL_RXEN, REG_IE_TXWM, REG_IE_RXWM};
+use super::math;
+
+/* a UART's configuration, gathered from registers that can be read without side effects */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config
+{
+    pub baud: Option<u32>,  /* derived from the divisor, if the bus frequency is known */
+    pub stop_bits: u32,
+    pub divisor: u32,
+    pub tx_enabled: bool,
+    pub rx_enabled: bool,
+    pub tx_watermark: WatermarkLevel,
+    pub rx_watermark: WatermarkLevel,
+    pub tx_irq: bool,
+    pub rx_irq: bool
+}
+
+impl UART
+{
+    /* read back the controller's configuration */
+    pub fn config(&self) -> Config
+    {
+        let txctrl = self.read_reg(REG_TXCTRL);
+        let rxctrl = self.read_reg(REG_RXCTRL);
+        let ie = self.read_reg(REG_IE);
+        Config
+        {
+            baud: None,
+            stop_bits: match txctrl & REG_TXCTRL_NSTOP { 0 => 1, _ => 2 },
+            divisor: self.read_reg(REG_DIV) & REG_DIV_MASK,
+            tx_enabled: txctrl & REG_TXCTRL_TXEN != 0,
+            rx_enabled: rxctrl & REG_RXCTRL_RXEN != 0,
+            tx_watermark: self.tx_watermark(),
+            rx_watermark: self.rx_watermark(),
+            tx_irq: ie & REG_IE_TXWM != 0,
+            rx_irq: ie & REG_IE_RXWM != 0
+        }
+    }
+
+    /* like config() but also work out the baud rate from the bus frequency in Hz */
+    pub fn config_at(&self, bus_freq: u32) -> Config
+    {
+        let config = self.config();
+        let baud = math::baud_from_divisor(config.divisor, bus_freq, self.variant.divisor_offset);
+        Config { baud, ..config }
+    }
+}
+
+/* eg: 115200 8-N-1, div=4339, irq rx@6 tx@1 */
+impl fmt::Display for Config
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        if let Some(baud) = self.baud
+        {
+            write!(f, "{} ", baud)?;
+        }
+
+        write!(f, "8-N-{}, div={}, irq", self.stop_bits, self.divisor)?;
+        if self.rx_irq
+        {
+            write!(f, " rx@{}", self.rx_watermark)?;
+        }
+        if self.tx_irq
+        {
+            write!(f, " tx@{}", self.tx_watermark)?;
+        }
+        if !self.rx_irq && !self.tx_irq
+        {
+            write!(f, " none")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str
+{
+    match enabled
+    {
+        true => "on",
+        false => "off"
+    }
+}
+
+impl fmt::Debug for UART
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let state = self.config();
+        f.debug_struct("UART")
+            .field("base_addr", &format_args!("{:#x}", self.base_addr))
+            .field("variant", &self.variant.name)
+            .field("tx_enabled", &state.tx_enabled)
+            .field("rx_enabled", &state.rx_enabled)
+            .field("tx_watermark", &state.tx_watermark)
+            .field("rx_watermark", &state.rx_watermark)
+            .field("tx_irq", &state.tx_irq)
+            .field("rx_irq", &state.rx_irq)
+            .field("divisor", &state.divisor)
+            .finish()
+    }
+}
+
+/* eg: SiFive UART at 0x10010000: tx on (irq off, watermark 1), rx on (irq on, watermark 6), divisor 4339 */
+impl fmt::Display for UART
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let state = self.config();
+        write!(f, "{} at {:#x}: tx {} (irq {}, watermark {}), rx {} (irq {}, watermark {}), divisor {}",
+               self.variant.name, self.base_addr,
+               on_off(state.tx_enabled), on_off(state.tx_irq), state.tx_watermark,
+               on_off(state.rx_enabled), on_off(state.rx_irq), state.rx_watermark,
+               state.divisor)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::tests::FakeRegisters;
+
+    #[test]
+    fn describing_leaves_rx_fifo_alone()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.set_baud(115_200, 115_200 * 4340);
+        uart.enable_rx_watermark_irq(true);
+        regs.receive(b'!');
+
+        let text = format!("{}", uart);
+        assert!(text.ends_with(": tx on (irq off, watermark 1), rx on (irq on, watermark 6), divisor 4339"));
+        assert!(format!("{:?}", uart).contains("divisor: 4339"));
+        assert_eq!(uart.read_byte().unwrap(), b'!');
+    }
+
+    #[test]
+    fn config_summary()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.set_baud(115_200, 500_000_000);
+        assert_eq!(format!("{}", uart.config()), "8-N-1, div=4339, irq none");
+
+        uart.enable_rx_watermark_irq(true);
+        uart.enable_tx_watermark_irq(true);
+        assert_eq!(format!("{}", uart.config_at(500_000_000)), "115207 8-N-1, div=4339, irq rx@6 tx@1");
+    }
+}
@@ -0,0 +1,242 @@
+/* raw and cooked input, switchable at runtime
+ *
+ * LineDiscipline sits between the rx FIFO and a reader, much as a Unix
+ * terminal's line discipline does, so one port can carry both a binary
+ * protocol and a person typing at it. In raw mode bytes pass through
+ * untouched. In cooked mode input is collected into lines of up to N bytes
+ * and only handed over once complete: CR is mapped to LF, backspace and DEL
+ * rub out the last character, ctrl-U the line, and other control
+ * characters apart from tab are dropped. Cooked input can also be echoed.
+ *
+ *   let mut input: LineDiscipline<128> = LineDiscipline::new(InputMode::Cooked);
+ *   let len = input.read_timeout(&uart, &mut buf, &mut Forever)?;
+ *   ...
+ *   input.set_mode(InputMode::Raw);
+ *   xmodem::Receiver::new().receive(&uart, &mut sink)?;
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::timeout::Timeout;
+
+const BS: u8 = 0x08;
+const DEL: u8 = 0x7f;
+const CTRL_U: u8 = 0x15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode
+{
+    Raw,    /* every byte passes through as received */
+    Cooked  /* edited lines, each ending in LF */
+}
+
+pub struct LineDiscipline<const N: usize>
+{
+    mode: InputMode,
+    echo: bool,
+    line: [u8; N],
+    len: usize,     /* bytes in line */
+    ready: usize,   /* bytes at the start of line that may be read */
+    taken: usize    /* bytes of those already read */
+}
+
+impl<const N: usize> LineDiscipline<N>
+{
+    pub const fn new(mode: InputMode) -> Self
+    {
+        LineDiscipline { mode, echo: false, line: [0; N], len: 0, ready: 0, taken: 0 }
+    }
+
+    pub fn mode(&self) -> InputMode
+    {
+        self.mode
+    }
+
+    /* switch mode. a partly typed line is handed over as it is on switching
+       to raw mode, so no input is lost */
+    pub fn set_mode(&mut self, mode: InputMode)
+    {
+        if mode == InputMode::Raw
+        {
+            self.ready = self.len;
+        }
+        self.mode = mode;
+    }
+
+    /* echo cooked input back as it's typed. raw input is never echoed */
+    pub fn set_echo(&mut self, echo: bool)
+    {
+        self.echo = echo;
+    }
+
+    /* copy input that's ready into buf, returning how many bytes. returns
+       immediately, with zero if nothing's ready. in cooked mode no more than
+       one line is returned per call */
+    pub fn read(&mut self, uart: &UART, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        if buf.is_empty() || self.ready > 0
+        {
+            return Ok(self.take(buf))
+        }
+
+        match self.mode
+        {
+            InputMode::Raw => Ok(uart.read_available(buf)),
+            InputMode::Cooked =>
+            {
+                while self.ready == 0
+                {
+                    match uart.read_byte()
+                    {
+                        Ok(byte) => self.cook(uart, byte)?,
+                        Err(_) => break
+                    }
+                }
+                Ok(self.take(buf))
+            }
+        }
+    }
+
+    /* like read() but waits until timeout expires for input to become ready,
+       failing with DataNotReady if none does. in cooked mode, each key typed
+       restarts the wait */
+    pub fn read_timeout<T: Timeout>(&mut self, uart: &UART, buf: &mut [u8], timeout: &mut T) -> Result<usize, Fault>
+    {
+        loop
+        {
+            match self.read(uart, buf)?
+            {
+                0 if !buf.is_empty() => (),
+                count => return Ok(count)
+            }
+
+            let byte = uart.read_byte_timeout(timeout)?;
+            match self.mode
+            {
+                InputMode::Raw =>
+                {
+                    buf[0] = byte;
+                    return Ok(1 + uart.read_available(&mut buf[1..]))
+                },
+                InputMode::Cooked => self.cook(uart, byte)?
+            }
+        }
+    }
+
+    /* add a received byte to the line being typed */
+    fn cook(&mut self, uart: &UART, byte: u8) -> Result<(), Fault>
+    {
+        match byte
+        {
+            b'\r' | b'\n' =>
+            {
+                self.line[self.len] = b'\n';
+                self.len += 1;
+                self.ready = self.len;
+                self.echo(uart, b"\r\n")
+            },
+            BS | DEL if self.len > 0 =>
+            {
+                self.len -= 1;
+                self.echo(uart, b"\x08 \x08")
+            },
+            CTRL_U =>
+            {
+                while self.len > 0
+                {
+                    self.len -= 1;
+                    self.echo(uart, b"\x08 \x08")?;
+                }
+                Ok(())
+            },
+            b'\t' | 0x20..=0x7e | 0x80..=0xff =>
+            {
+                self.line[self.len] = byte;
+                self.len += 1;
+
+                /* a line that fills the buffer is handed over as it is */
+                if self.len == N
+                {
+                    self.ready = self.len;
+                }
+                self.echo(uart, &[byte])
+            },
+            _ => Ok(())
+        }
+    }
+
+    /* copy ready bytes into buf, dropping them from the line once all are read */
+    fn take(&mut self, buf: &mut [u8]) -> usize
+    {
+        let count = buf.len().min(self.ready - self.taken);
+        buf[..count].copy_from_slice(&self.line[self.taken..self.taken + count]);
+        self.taken += count;
+
+        if self.taken == self.ready
+        {
+            self.line.copy_within(self.ready..self.len, 0);
+            self.len -= self.ready;
+            self.ready = 0;
+            self.taken = 0;
+        }
+        count
+    }
+
+    fn echo(&self, uart: &UART, bytes: &[u8]) -> Result<(), Fault>
+    {
+        match self.echo
+        {
+            true => uart.send_bytes(bytes).map(|_| ()).map_err(|partial| partial.fault),
+            false => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Polls;
+    use crate::tests::Script;
+
+    #[test]
+    fn cooks_lines()
+    {
+        let (script, uart) = Script::uart(b"lx\x7fs\x01\r\x00pwd\n");
+        let mut input: LineDiscipline<16> = LineDiscipline::new(InputMode::Cooked);
+        input.set_echo(true);
+
+        let mut buf = [0u8; 16];
+        assert_eq!(input.read(&uart, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ls\n");
+        assert_eq!(script.sent(), b"lx\x08 \x08s\r\n");
+
+        /* a line is handed over in pieces if buf is too small */
+        let mut small = [0u8; 2];
+        assert_eq!(input.read(&uart, &mut small).unwrap(), 2);
+        assert_eq!(input.read(&uart, &mut small).unwrap(), 2);
+        assert_eq!(&small, b"d\n");
+        assert_eq!(input.read(&uart, &mut small).unwrap(), 0);
+    }
+
+    #[test]
+    fn switches_to_raw()
+    {
+        let (script, uart) = Script::uart(b"par");
+        let mut input: LineDiscipline<16> = LineDiscipline::new(InputMode::Cooked);
+        let mut buf = [0u8; 16];
+        assert_eq!(input.read(&uart, &mut buf).unwrap(), 0);
+        assert!(matches!(input.read_timeout(&uart, &mut buf, &mut Polls::new(2)), Err(Fault::DataNotReady)));
+
+        /* the partial line is kept, and raw bytes follow it unaltered */
+        input.set_mode(InputMode::Raw);
+        script.receive(b"\r\x00\x7f");
+        assert_eq!(input.read(&uart, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"par");
+        assert_eq!(input.read_timeout(&uart, &mut buf, &mut Polls::new(2)).unwrap(), 3);
+        assert_eq!(&buf[..3], b"\r\x00\x7f");
+    }
+}
@@ -0,0 +1,278 @@
+/* chunked image download with CRC32 checks and resume
+ *
+ * XMODEM's 128 and 1024-byte blocks, each waiting for an ACK, make loading
+ * a multi-megabyte kernel slow. This protocol lets the receiver ask for
+ * chunks as big as its buffer, up to 64KiB, checks each with a CRC32, and
+ * can pick up where an interrupted download left off. Little-endian
+ * integers throughout. The receiver drives the transfer by asking for the
+ * data at an offset:
+ *
+ *   'R', offset (u32), largest chunk it can take (u16)
+ *
+ * and the sender replies with a chunk starting there:
+ *
+ *   'D', offset (u32), image length (u32), chunk length (u16), data,
+ *   CRC32 of everything after the 'D' up to and including the data (u32)
+ *
+ * A chunk that's corrupt or doesn't arrive is asked for again. Once it has
+ * the whole image, the receiver sends 'F' and the image length (u32). The
+ * sender is easy to script: read seven bytes, seek, reply, repeat.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, Polls};
+use super::crc::crc32;
+
+const REQUEST: u8 = b'R';
+const DATA: u8 = b'D';
+const FINISHED: u8 = b'F';
+
+/* bytes of a chunk's header after the 'D' */
+const HEADER: usize = 4 + 4 + 2;
+
+/* as for XMODEM: roughly a second or so of polling on a typical core, and
+   ten retries */
+const DEFAULT_TIMEOUT: usize = 1_000_000;
+const DEFAULT_RETRIES: usize = 10;
+
+/* receives each verified chunk and the offset in the image it belongs at.
+   return an error to abandon the download */
+pub trait Sink
+{
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Fault>;
+}
+
+impl<F> Sink for F where F: FnMut(u32, &[u8]) -> Result<(), Fault>
+{
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Fault>
+    {
+        self(offset, data)
+    }
+}
+
+pub struct Download<'a>
+{
+    buf: &'a mut [u8],  /* each chunk is received here before it's checked */
+    offset: u32,        /* bytes of the image received so far */
+    timeout: usize,     /* polls to wait for each byte */
+    retries: usize      /* consecutive bad or missing chunks tolerated */
+}
+
+impl<'a> Download<'a>
+{
+    /* download into chunks of up to buf's length, or 64KiB, whichever is less */
+    pub fn new(buf: &'a mut [u8]) -> Self
+    {
+        Download { buf, offset: 0, timeout: DEFAULT_TIMEOUT, retries: DEFAULT_RETRIES }
+    }
+
+    /* start from offset, eg the offset() of an earlier, interrupted download */
+    pub fn resume_from(self, offset: u32) -> Self
+    {
+        Download { offset, ..self }
+    }
+
+    /* set the number of polls to wait for each byte before timing out */
+    pub fn with_timeout(self, polls: usize) -> Self
+    {
+        Download { timeout: polls, ..self }
+    }
+
+    /* set the number of consecutive bad or missing chunks tolerated */
+    pub fn with_retries(self, retries: usize) -> Self
+    {
+        Download { retries, ..self }
+    }
+
+    /* bytes of the image received so far */
+    pub fn offset(&self) -> u32
+    {
+        self.offset
+    }
+
+    /* download the rest of the image, writing each chunk to sink, and return the
+       image's length. fails with TooManyErrors if the link is too unreliable,
+       in which case calling again carries on from offset(), and with
+       FrameTooLong straight away if buf is empty, as no chunk would fit */
+    pub fn receive<S: Sink>(&mut self, uart: &UART, sink: &mut S) -> Result<u32, Fault>
+    {
+        let max = self.buf.len().min(u16::MAX as usize) as u16;
+        let mut errors = 0;
+
+        if max == 0
+        {
+            return Err(Fault::FrameTooLong)
+        }
+
+        loop
+        {
+            if errors > self.retries
+            {
+                return Err(Fault::TooManyErrors)
+            }
+
+            let mut request = [REQUEST, 0, 0, 0, 0, 0, 0];
+            request[1..5].copy_from_slice(&self.offset.to_le_bytes());
+            request[5..].copy_from_slice(&max.to_le_bytes());
+            uart.send_bytes(&request).map_err(|partial| partial.fault)?;
+
+            match self.read_chunk(uart)
+            {
+                /* a resend of an earlier chunk is ignored, and an empty one
+                   before the end is no progress, so counts as bad */
+                Ok((offset, total, len)) if offset == self.offset && (len > 0 || offset >= total) =>
+                {
+                    sink.write(offset, &self.buf[..len])?;
+                    self.offset += len as u32;
+                    errors = 0;
+
+                    if self.offset >= total
+                    {
+                        let mut finished = [FINISHED, 0, 0, 0, 0];
+                        finished[1..].copy_from_slice(&total.to_le_bytes());
+                        uart.send_bytes(&finished).map_err(|partial| partial.fault)?;
+                        return Ok(total)
+                    }
+                },
+                _ => errors += 1
+            }
+        }
+    }
+
+    /* wait for a chunk and return its offset, the image's length and the chunk's
+       length once its data is in buf and has passed its CRC check */
+    fn read_chunk(&mut self, uart: &UART) -> Result<(u32, u32, usize), Fault>
+    {
+        let mut polls = Polls::new(self.timeout);
+
+        /* skip anything before the chunk, such as the tail of a corrupt one */
+        while uart.read_byte_timeout(&mut polls)? != DATA {}
+
+        let mut header = [0u8; HEADER];
+        uart.read_exact_timeout(&mut header, &mut polls)?;
+        let offset = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let total = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let len = u16::from_le_bytes([header[8], header[9]]) as usize;
+
+        /* a chunk running past the end of the image is as bad as an oversized one */
+        if len > self.buf.len() || (offset as u64 + len as u64) > total as u64
+        {
+            return Err(Fault::FrameTooLong)
+        }
+
+        let data = &mut self.buf[..len];
+        uart.read_exact_timeout(data, &mut polls)?;
+        let mut crc = [0u8; 4];
+        uart.read_exact_timeout(&mut crc, &mut polls)?;
+
+        match crc32(crc32(0, &header), data) == u32::from_le_bytes(crc)
+        {
+            true => Ok((offset, total, len)),
+            false => Err(Fault::BadFrame)
+        }
+    }
+}
+
+impl UART
+{
+    /* download a whole image into sink, in chunks of up to buf's length, and
+       return its length */
+    pub fn download<S: Sink>(&self, buf: &mut [u8], sink: &mut S) -> Result<u32, Fault>
+    {
+        Download::new(buf).receive(self, sink)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    /* what a sender replies with for offset */
+    fn chunk(image: &[u8], offset: usize, max: usize) -> Vec<u8>
+    {
+        let data = &image[offset..image.len().min(offset + max)];
+        let mut body = vec![];
+        body.extend((offset as u32).to_le_bytes());
+        body.extend((image.len() as u32).to_le_bytes());
+        body.extend((data.len() as u16).to_le_bytes());
+        body.extend(data);
+
+        let mut chunk = vec![DATA];
+        chunk.extend(&body);
+        chunk.extend(crc32(0, &body).to_le_bytes());
+        chunk
+    }
+
+    #[test]
+    fn downloads_in_chunks()
+    {
+        let image = b"a kernel image of some size";
+        let mut input = chunk(image, 0, 16);
+
+        /* a corrupted chunk is asked for again, and a stale one ignored */
+        let mut corrupt = chunk(image, 16, 16);
+        corrupt[20] ^= 1;
+        input.extend(corrupt);
+        input.extend(chunk(image, 0, 16));
+        input.extend(chunk(image, 16, 16));
+
+        let (script, uart) = Script::uart(&input);
+        let mut received = vec![];
+        let mut buf = [0u8; 16];
+        let mut download = Download::new(&mut buf).with_timeout(10);
+        let len = download.receive(&uart, &mut |offset, data: &[u8]|
+        {
+            assert_eq!(offset as usize, received.len());
+            received.extend_from_slice(data);
+            Ok(())
+        });
+
+        assert!(matches!(len, Ok(27)));
+        assert_eq!(received, image);
+        assert_eq!(script.sent(), [&b"R\x00\x00\x00\x00\x10\x00"[..], b"R\x10\x00\x00\x00\x10\x00", b"R\x10\x00\x00\x00\x10\x00",
+                                   b"R\x10\x00\x00\x00\x10\x00", b"F\x1b\x00\x00\x00"].concat());
+    }
+
+    #[test]
+    fn resumes_after_giving_up()
+    {
+        let image = [0x5a; 40];
+        let (script, uart) = Script::uart(&chunk(&image, 0, 32));
+        let mut buf = [0u8; 32];
+        let mut download = Download::new(&mut buf).with_timeout(10).with_retries(1);
+        let mut sink = |_, _: &[u8]| Ok(());
+        assert!(matches!(download.receive(&uart, &mut sink), Err(Fault::TooManyErrors)));
+        assert_eq!(download.offset(), 32);
+
+        script.receive(&chunk(&image, 32, 32));
+        assert!(matches!(download.receive(&uart, &mut sink), Ok(40)));
+    }
+
+    #[test]
+    fn empty_chunks_are_no_progress()
+    {
+        let image = b"an image";
+        let mut input = chunk(image, 0, 0).repeat(3);
+        input.extend(chunk(image, 0, 8));
+        let (_, uart) = Script::uart(&input);
+        let mut buf = [0u8; 8];
+        let mut download = Download::new(&mut buf).with_timeout(10).with_retries(2);
+        let mut sink = |_, _: &[u8]| Ok(());
+        assert!(matches!(download.receive(&uart, &mut sink), Err(Fault::TooManyErrors)));
+        assert_eq!(download.offset(), 0);
+
+        /* but an empty image is complete as soon as it's announced */
+        let (_, uart) = Script::uart(&chunk(b"", 0, 8));
+        assert!(matches!(Download::new(&mut buf).receive(&uart, &mut sink), Ok(0)));
+
+        /* and with nowhere to put a chunk, there's no point asking for one */
+        let (script, uart) = Script::uart(&input);
+        assert!(matches!(Download::new(&mut []).receive(&uart, &mut sink), Err(Fault::FrameTooLong)));
+        assert!(script.sent().is_empty());
+    }
+}
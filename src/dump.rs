@@ -0,0 +1,219 @@
+/* stream binary data out of the UART as printable text
+ *
+ * Hex and Base64 encoders that convert data as it's written, carrying
+ * at most two bytes between writes, so crash dumps and flash contents
+ * can be sent without an intermediate buffer. Output is split into
+ * CRLF-terminated lines that host tools such as `xxd -r -p` and
+ * `base64 -d` accept as-is.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_PAD: u8 = b'=';
+
+/* output line lengths, in characters */
+const HEX_LINE: usize = 64;
+const BASE64_LINE: usize = 76;
+
+/* tracks the output column and breaks lines when they get too long */
+struct Lines<O>
+{
+    out: O,
+    column: usize,
+    width: usize
+}
+
+impl<O: FnMut(u8) -> Result<(), Fault>> Lines<O>
+{
+    fn put(&mut self, c: u8) -> Result<(), Fault>
+    {
+        if self.column == self.width
+        {
+            self.newline()?;
+        }
+
+        self.column += 1;
+        (self.out)(c)
+    }
+
+    fn newline(&mut self) -> Result<(), Fault>
+    {
+        self.column = 0;
+        (self.out)(b'\r')?;
+        (self.out)(b'\n')
+    }
+
+    /* end the final line, if anything is on it */
+    fn finish(&mut self) -> Result<(), Fault>
+    {
+        match self.column
+        {
+            0 => Ok(()),
+            _ => self.newline()
+        }
+    }
+}
+
+/* hex encoder, writing each character to the given output */
+pub struct Hex<O>
+{
+    lines: Lines<O>
+}
+
+impl<O: FnMut(u8) -> Result<(), Fault>> Hex<O>
+{
+    pub fn new(out: O) -> Self
+    {
+        Hex { lines: Lines { out, column: 0, width: HEX_LINE } }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Fault>
+    {
+        for &byte in data
+        {
+            self.lines.put(HEX_DIGITS[(byte >> 4) as usize])?;
+            self.lines.put(HEX_DIGITS[(byte & 0xf) as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /* terminate the output */
+    pub fn finish(mut self) -> Result<(), Fault>
+    {
+        self.lines.finish()
+    }
+}
+
+/* Base64 encoder, writing each character to the given output */
+pub struct Base64<O>
+{
+    lines: Lines<O>,
+    carry: [u8; 2],     /* bytes waiting for a complete group of three */
+    carried: usize
+}
+
+impl<O: FnMut(u8) -> Result<(), Fault>> Base64<O>
+{
+    pub fn new(out: O) -> Self
+    {
+        Base64 { lines: Lines { out, column: 0, width: BASE64_LINE }, carry: [0; 2], carried: 0 }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Fault>
+    {
+        for &byte in data
+        {
+            if self.carried < self.carry.len()
+            {
+                self.carry[self.carried] = byte;
+                self.carried += 1;
+                continue
+            }
+
+            self.carried = 0;
+            self.group([self.carry[0], self.carry[1], byte], 4)?;
+        }
+
+        Ok(())
+    }
+
+    /* encode any remaining bytes with padding, and terminate the output */
+    pub fn finish(mut self) -> Result<(), Fault>
+    {
+        match self.carried
+        {
+            1 => self.group([self.carry[0], 0, 0], 2)?,
+            2 => self.group([self.carry[0], self.carry[1], 0], 3)?,
+            _ => ()
+        }
+
+        self.lines.finish()
+    }
+
+    /* output the first count characters encoding the group, padded to four */
+    fn group(&mut self, group: [u8; 3], count: usize) -> Result<(), Fault>
+    {
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for i in 0..4
+        {
+            let c = match i < count
+            {
+                true => BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize],
+                false => BASE64_PAD
+            };
+            self.lines.put(c)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl UART
+{
+    /* return a hex encoder that transmits its output on this UART */
+    pub fn hex_writer(&self) -> Hex<impl FnMut(u8) -> Result<(), Fault> + '_>
+    {
+        Hex::new(move |c| self.send_byte(c))
+    }
+
+    /* return a Base64 encoder that transmits its output on this UART */
+    pub fn base64_writer(&self) -> Base64<impl FnMut(u8) -> Result<(), Fault> + '_>
+    {
+        Base64::new(move |c| self.send_byte(c))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn base64(chunks: &[&[u8]]) -> String
+    {
+        let mut text = vec![];
+        let mut encoder = Base64::new(|c| { text.push(c); Ok(()) });
+        for chunk in chunks
+        {
+            encoder.write(chunk).unwrap();
+        }
+        encoder.finish().unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn base64_known_values()
+    {
+        assert_eq!(base64(&[]), "");
+        assert_eq!(base64(&[b"f"]), "Zg==\r\n");
+        assert_eq!(base64(&[b"fo"]), "Zm8=\r\n");
+        assert_eq!(base64(&[b"foo"]), "Zm9v\r\n");
+        assert_eq!(base64(&[b"f", b"oob", b"ar"]), "Zm9vYmFy\r\n");
+    }
+
+    #[test]
+    fn base64_wraps_lines()
+    {
+        let text = base64(&[&[0u8; 60]]);
+        assert_eq!(text, format!("{}\r\n{}\r\n", "A".repeat(BASE64_LINE), "A".repeat(4)));
+    }
+
+    #[test]
+    fn hex_encoding()
+    {
+        let mut text = vec![];
+        let mut encoder = Hex::new(|c| { text.push(c); Ok(()) });
+        encoder.write(&[0x00, 0x7f]).unwrap();
+        encoder.write(&[0xa5; 31]).unwrap();
+        encoder.finish().unwrap();
+
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text, format!("007f{}\r\na5\r\n", "a5".repeat(30)));
+    }
+}
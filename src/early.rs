@@ -0,0 +1,151 @@
+/* transmit-only console for the first instructions of M-mode firmware
+ *
+ * At reset, firmware may be running from an address other than the one it
+ * was linked at, before its data has been copied or zeroed and before any
+ * relocations have been applied. Only code that reaches memory
+ * PC-relatively, or through addresses it's handed, is safe to run then.
+ * EarlyUart is written to that constraint:
+ *
+ *  - it's built in a const fn from the base address and touches no statics
+ *  - it never goes through a function pointer, trait object or the Bus,
+ *    whose addresses would need relocating, and has no yield or watchdog hooks
+ *  - it doesn't use core::fmt, lookup tables or anything that can panic;
+ *    numbers are printed with plain arithmetic
+ *  - waits for the transmitter are bounded, so a missing or unclocked UART
+ *    drops bytes rather than hanging boot
+ *
+ * Once the firmware is relocated, switch to a UART created by new().
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::ptr::{read_volatile, write_volatile};
+use super::{REG_TXDATA, REG_TXCTRL, REG_DIV, REG_TXCTRL_TXEN, REG_TXDATA_FULL, LOOP_MAX};
+
+/* a UART's transmitter, reached directly at its base address */
+#[derive(Clone, Copy)]
+pub struct EarlyUart
+{
+    base_addr: usize
+}
+
+impl EarlyUart
+{
+    /* use the controller at base_addr without touching it */
+    pub const fn attach(base_addr: usize) -> Self
+    {
+        EarlyUart { base_addr }
+    }
+
+    /* enable the transmitter with one stop bit, and set the baud rate divisor
+       if one's given. leave divisor as None to keep whatever an earlier boot
+       stage set up */
+    pub fn init(&self, divisor: Option<u32>)
+    {
+        if let Some(divisor) = divisor
+        {
+            self.write_reg(REG_DIV, divisor);
+        }
+        self.write_reg(REG_TXCTRL, REG_TXCTRL_TXEN);
+    }
+
+    /* send a byte, giving up after a bounded wait for space in the tx FIFO */
+    pub fn putc(&self, byte: u8)
+    {
+        let mut polls = 0;
+        while self.read_reg(REG_TXDATA) & REG_TXDATA_FULL != 0
+        {
+            polls += 1;
+            if polls >= LOOP_MAX
+            {
+                return
+            }
+        }
+        self.write_reg(REG_TXDATA, byte as u32);
+    }
+
+    pub fn puts(&self, bytes: &[u8])
+    {
+        for &byte in bytes
+        {
+            self.putc(byte);
+        }
+    }
+
+    /* send value as 0x followed by 16 lowercase hex digits */
+    pub fn put_hex(&self, value: u64)
+    {
+        self.puts(b"0x");
+        let mut shift = 64;
+        while shift > 0
+        {
+            shift -= 4;
+            let nybble = ((value >> shift) & 0xf) as u8;
+            self.putc(match nybble
+            {
+                0..=9 => b'0' + nybble,
+                _ => b'a' + nybble - 10
+            });
+        }
+    }
+
+    /* send value in decimal, without leading zeroes */
+    pub fn put_dec(&self, value: u64)
+    {
+        let mut divisor = 1;
+        while value / divisor >= 10
+        {
+            divisor *= 10;
+        }
+
+        while divisor > 0
+        {
+            self.putc(b'0' + ((value / divisor) % 10) as u8);
+            divisor /= 10;
+        }
+    }
+
+    fn write_reg(&self, reg: usize, val: u32)
+    {
+        /* assumes reg is within MMIO area's range */
+        unsafe { write_volatile((self.base_addr + reg) as *mut u32, val) }
+    }
+
+    fn read_reg(&self, reg: usize) -> u32
+    {
+        /* assumes reg is within MMIO area's range */
+        unsafe { read_volatile((self.base_addr + reg) as *const u32) }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+
+    #[test]
+    fn prints_numbers()
+    {
+        let regs = FakeRegisters::new();
+        let early = EarlyUart::attach(regs.base());
+        early.init(Some(4340));
+        assert_eq!(regs.get(REG_DIV), 4340);
+        assert_eq!(regs.get(REG_TXCTRL), REG_TXCTRL_TXEN);
+
+        /* the fake holds only the last byte written */
+        early.put_hex(0x8000_0000_dead_beef);
+        assert_eq!(regs.get(REG_TXDATA), b'f' as u32);
+        early.put_dec(18446744073709551615);
+        assert_eq!(regs.get(REG_TXDATA), b'5' as u32);
+        early.put_dec(0);
+        assert_eq!(regs.get(REG_TXDATA), b'0' as u32);
+
+        /* a full transmitter drops the byte after a bounded wait */
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+        early.putc(b'x');
+        assert_eq!(regs.get(REG_TXDATA), REG_TXDATA_FULL);
+    }
+}
@@ -0,0 +1,220 @@
+/* line editing for interactive prompts
+ *
+ * LineEditor fills a caller's buffer with a line typed at a terminal,
+ * handling the keys people expect to work: backspace and DEL rub out the
+ * last character, ctrl-U the whole line, and CR, LF or CRLF end it. With
+ * echo on, what's typed is sent back so it appears on the terminal, eg:
+ *
+ *   let mut editor = LineEditor::new(true);
+ *   let mut line = [0u8; 64];
+ *   uart.send_str("=> ")?;
+ *   let len = editor.read_line(&uart, &mut line)?;
+ *
+ * For boot menus, prompt() sends the prompt and reads an echoed line in
+ * one call, giving up if nothing is typed in time so the menu can fall
+ * through to its default, eg:
+ *
+ *   let mut timeout = Ticks::new(MCycle, 5 * CORE_HZ);
+ *   match uart.prompt("boot> ", &mut line, &mut timeout)
+ *   {
+ *       Some(choice) => ...,
+ *       None => ... /* carry on with the default */
+ *   }
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::timeout::{Timeout, Forever};
+
+const BS: u8 = 0x08;
+const DEL: u8 = 0x7f;
+const CTRL_U: u8 = 0x15;
+const BEL: u8 = 0x07;
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+
+/* state carried between lines and between calls that time out */
+pub struct LineEditor
+{
+    echo: bool,
+    len: usize,     /* bytes of the current line in the caller's buffer */
+    after_cr: bool  /* the last line ended with CR, so a following LF is part of it */
+}
+
+impl LineEditor
+{
+    pub const fn new(echo: bool) -> Self
+    {
+        LineEditor { echo, len: 0, after_cr: false }
+    }
+
+    pub fn set_echo(&mut self, echo: bool)
+    {
+        self.echo = echo;
+    }
+
+    /* read a line into buf, waiting as long as it takes, and return its
+       length, not counting the line ending */
+    pub fn read_line(&mut self, uart: &UART, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        self.read_line_timeout(uart, buf, &mut Forever)
+    }
+
+    /* like read_line() but waits until timeout expires for each key, failing
+       with DataNotReady if one doesn't arrive. what's been typed so far stays
+       in buf: call again with the same buf to carry on editing the line */
+    pub fn read_line_timeout<T: Timeout>(&mut self, uart: &UART, buf: &mut [u8], timeout: &mut T) -> Result<usize, Fault>
+    {
+        loop
+        {
+            let byte = uart.read_byte_timeout(timeout)?;
+            if let Some(len) = self.key(uart, buf, byte)?
+            {
+                return Ok(len)
+            }
+        }
+    }
+
+    /* act on one received byte, returning the line's length if it's complete */
+    fn key(&mut self, uart: &UART, buf: &mut [u8], byte: u8) -> Result<Option<usize>, Fault>
+    {
+        let after_cr = self.after_cr;
+        self.after_cr = false;
+
+        match byte
+        {
+            LF if after_cr => (),
+
+            CR | LF =>
+            {
+                self.after_cr = byte == CR;
+                self.echo(uart, b"\r\n")?;
+                let len = self.len;
+                self.len = 0;
+                return Ok(Some(len))
+            },
+
+            BS | DEL if self.len > 0 =>
+            {
+                self.len -= 1;
+                self.echo(uart, b"\x08 \x08")?;
+            },
+
+            CTRL_U =>
+            {
+                while self.len > 0
+                {
+                    self.len -= 1;
+                    self.echo(uart, b"\x08 \x08")?;
+                }
+            },
+
+            /* other control characters are ignored */
+            0..=0x1f | DEL => (),
+
+            _ if self.len < buf.len() =>
+            {
+                buf[self.len] = byte;
+                self.len += 1;
+                self.echo(uart, &[byte])?;
+            },
+
+            /* no room: ring the terminal's bell rather than drop the key silently */
+            _ => self.echo(uart, &[BEL])?
+        }
+
+        Ok(None)
+    }
+
+    fn echo(&self, uart: &UART, bytes: &[u8]) -> Result<(), Fault>
+    {
+        match self.echo
+        {
+            true => uart.send_bytes(bytes).map(|_| ()).map_err(|partial| partial.fault),
+            false => Ok(())
+        }
+    }
+}
+
+impl UART
+{
+    /* send text, then read an echoed line into buf and return it, not counting
+       the line ending. the timeout restarts with each key, so Ticks gives up
+       after that long without a key, and a Deadline bounds the whole prompt.
+       returns None if the timeout expires, the UART fails, or what was typed
+       isn't valid UTF-8 */
+    pub fn prompt<'b, T: Timeout>(&self, text: &str, buf: &'b mut [u8], timeout: &mut T) -> Option<&'b str>
+    {
+        self.send_str(text).ok()?;
+
+        let mut editor = LineEditor::new(true);
+        match editor.read_line_timeout(self, buf, timeout)
+        {
+            Ok(len) => core::str::from_utf8(&buf[..len]).ok(),
+            Err(_) =>
+            {
+                /* leave the prompt's line so whatever's sent next starts afresh */
+                let _ = self.send_bytes(b"\r\n");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Polls;
+    use crate::tests::Script;
+
+    #[test]
+    fn edits_and_echoes()
+    {
+        let (script, uart) = Script::uart(b"lsx\x08\x7f -l\r\nab\x15cd\n");
+        let mut editor = LineEditor::new(true);
+        let mut line = [0u8; 8];
+
+        assert_eq!(editor.read_line(&uart, &mut line).unwrap(), 4);
+        assert_eq!(&line[..4], b"l -l");
+        assert_eq!(script.sent(), b"lsx\x08 \x08\x08 \x08 -l\r\n");
+
+        /* the LF of CRLF doesn't end an empty line */
+        assert_eq!(editor.read_line(&uart, &mut line).unwrap(), 2);
+        assert_eq!(&line[..2], b"cd");
+    }
+
+    #[test]
+    fn full_buffer_and_timeouts()
+    {
+        let (script, uart) = Script::uart(b"abc");
+        let mut editor = LineEditor::new(false);
+        let mut line = [0u8; 2];
+
+        assert!(matches!(editor.read_line_timeout(&uart, &mut line, &mut Polls::new(3)), Err(Fault::DataNotReady)));
+        assert!(script.sent().is_empty());
+
+        /* the line carries on where it left off */
+        script.receive(b"\r");
+        editor.set_echo(true);
+        assert_eq!(editor.read_line(&uart, &mut line).unwrap(), 2);
+        assert_eq!(&line, b"ab");
+        assert_eq!(script.sent(), b"\r\n");
+    }
+
+    #[test]
+    fn prompt_falls_through_on_silence()
+    {
+        let (script, uart) = Script::uart(b"2\r");
+        let mut line = [0u8; 8];
+        assert_eq!(uart.prompt("boot> ", &mut line, &mut Polls::new(3)), Some("2"));
+        assert_eq!(script.sent(), b"boot> 2\r\n");
+
+        script.receive(b"1");
+        assert_eq!(uart.prompt("boot> ", &mut line, &mut Polls::new(3)), None);
+        assert_eq!(script.sent(), b"boot> 1\r\n");
+    }
+}
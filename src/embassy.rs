@@ -0,0 +1,181 @@
+/* Embassy-style interrupt binding for the buffered async driver
+ *
+ * Embassy HALs tie a driver to its interrupt at compile time: the
+ * bind_interrupts! macro generates the interrupt handler and a zero-sized
+ * struct proving the binding exists, which the driver's constructor takes
+ * as an argument. This does the same for AsyncUart, eg:
+ *
+ *   mmio_sifive_uart::bind_interrupts!(struct Irqs { UART0 => InterruptHandler<64, 256>; });
+ *   ...
+ *   let mut serial = AsyncUart::bind(uart, Irqs)?;
+ *   serial.write(b"hello\n").await?;
+ *
+ * The generated handler is an extern "C" function named after the
+ * interrupt, UART0 in this case, for the platform's interrupt controller
+ * code to call when that source is claimed.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+use super::{UART, Fault};
+use super::asynch::AsyncUart;
+use super::buffered::Buffers;
+
+const UNBOUND: u8 = 0;  /* no UART has been handed over yet */
+const BINDING: u8 = 1;  /* a UART is being stored */
+const BOUND: u8 = 2;    /* the interrupt handler may use the UART */
+
+/* the UART and buffers serviced by a bound interrupt. this lives in a static
+   generated by bind_interrupts! */
+pub struct InterruptHandler<const RX: usize, const TX: usize>
+{
+    state: AtomicU8,
+    uart: UnsafeCell<MaybeUninit<UART>>,
+    buffers: Buffers<RX, TX>
+}
+
+/* the UART is written once, before state becomes BOUND, and only read after */
+unsafe impl<const RX: usize, const TX: usize> Sync for InterruptHandler<RX, TX> {}
+
+impl<const RX: usize, const TX: usize> Default for InterruptHandler<RX, TX>
+{
+    fn default() -> Self
+    {
+        InterruptHandler::new()
+    }
+}
+
+impl<const RX: usize, const TX: usize> InterruptHandler<RX, TX>
+{
+    pub const fn new() -> Self
+    {
+        InterruptHandler
+        {
+            state: AtomicU8::new(UNBOUND),
+            uart: UnsafeCell::new(MaybeUninit::uninit()),
+            buffers: Buffers::new()
+        }
+    }
+
+    /* service the UART's interrupt. does nothing until a UART has been bound */
+    pub fn on_interrupt(&self)
+    {
+        if let Some(uart) = self.uart()
+        {
+            uart.handle_interrupt(&self.buffers);
+        }
+    }
+
+    /* the UART this handler services, if one has been bound */
+    pub fn uart(&self) -> Option<&UART>
+    {
+        match self.state.load(Ordering::Acquire)
+        {
+            BOUND => Some(unsafe { (*self.uart.get()).assume_init_ref() }),
+            _ => None
+        }
+    }
+
+    /* take ownership of the UART and return a driver using it and this
+       handler's buffers. a handler can only be bound once; after that,
+       AlreadyClaimed is returned */
+    fn bind(&'static self, uart: UART) -> Result<AsyncUart<'static, RX, TX>, Fault>
+    {
+        if self.state.compare_exchange(UNBOUND, BINDING, Ordering::Acquire, Ordering::Relaxed).is_err()
+        {
+            return Err(Fault::AlreadyClaimed)
+        }
+
+        let uart = unsafe { (*self.uart.get()).write(uart) };
+        self.state.store(BOUND, Ordering::Release);
+        AsyncUart::new(uart, &self.buffers)
+    }
+}
+
+/* implemented by bind_interrupts! for the struct it generates, proving an
+   interrupt handler for RX and TX byte buffers has been installed.
+
+   safety: handler() must return the same handler each time, and that
+   handler's on_interrupt() must be called whenever the UART interrupts */
+pub unsafe trait Binding<const RX: usize, const TX: usize>: Copy + 'static
+{
+    fn handler() -> &'static InterruptHandler<RX, TX>;
+}
+
+impl<const RX: usize, const TX: usize> AsyncUart<'static, RX, TX>
+{
+    /* hand the UART over to the interrupt handler bound by bind_interrupts!
+       and return a driver for it. fails with AlreadyClaimed if a UART has
+       already been bound to the handler */
+    pub fn bind<B: Binding<RX, TX>>(uart: UART, _irqs: B) -> Result<Self, Fault>
+    {
+        B::handler().bind(uart)
+    }
+}
+
+/* generate an interrupt handler for a buffered UART, and a struct proving it's
+   bound. the sizes are the rx and tx software FIFO sizes in bytes */
+#[macro_export]
+macro_rules! bind_interrupts
+{
+    ($(#[$attr:meta])* $vis:vis struct $name:ident { $irq:ident => InterruptHandler<$rx:tt, $tx:tt>; }) =>
+    {
+        #[derive(Clone, Copy)]
+        $(#[$attr])*
+        $vis struct $name;
+
+        unsafe impl $crate::embassy::Binding<$rx, $tx> for $name
+        {
+            fn handler() -> &'static $crate::embassy::InterruptHandler<$rx, $tx>
+            {
+                static HANDLER: $crate::embassy::InterruptHandler<$rx, $tx> = $crate::embassy::InterruptHandler::new();
+                &HANDLER
+            }
+        }
+
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        unsafe extern "C" fn $irq()
+        {
+            <$name as $crate::embassy::Binding<$rx, $tx>>::handler().on_interrupt();
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use crate::tests::FakeRegisters;
+    use crate::{REG_RXDATA, REG_RXDATA_EMPTY};
+
+    crate::bind_interrupts!(struct Irqs { TEST_UART_IRQ => InterruptHandler<8, 8>; });
+
+    #[test]
+    fn bound_interrupt_feeds_driver()
+    {
+        /* the handler is a static, so the fake registers must outlive the test */
+        let regs: &'static FakeRegisters = Box::leak(Box::new(FakeRegisters::new()));
+        unsafe { TEST_UART_IRQ() };
+
+        let mut serial = AsyncUart::bind(regs.uart(), Irqs).unwrap();
+        assert!(matches!(AsyncUart::bind(regs.uart(), Irqs), Err(Fault::AlreadyClaimed)));
+
+        regs.receive(b'e');
+        unsafe { TEST_UART_IRQ() };
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+
+        let mut buf = [0u8; 2];
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(matches!(pin!(serial.read(&mut buf)).poll(&mut cx), Poll::Ready(Ok(2))));
+        assert_eq!(&buf, b"ee");
+    }
+}
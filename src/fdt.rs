@@ -0,0 +1,462 @@
+/* find SiFive UARTs in a flattened device tree (FDT) blob
+ *
+ * This is a minimal, allocation-free walker of the DTB structure block:
+ * just enough to locate nodes compatible with "sifive,uart0" and decode
+ * their reg, interrupts, clock-frequency and current-speed properties,
+ * which is what a RISC-V kernel handed a DTB by OpenSBI needs to bring up
+ * its console. Enabled with the fdt feature.
+ *
+ * Format reference: Devicetree Specification, chapter 5.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+
+/* string in a node's compatible property that identifies this controller */
+pub const COMPATIBLE: &str = "sifive,uart0";
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+
+/* structure block tokens */
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/* cell sizes assumed when a parent node doesn't specify them */
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+/* deepest node nesting we'll follow */
+const MAX_DEPTH: usize = 16;
+
+/* a UART described by the device tree */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UartNode
+{
+    pub base: u64,          /* physical address of the registers, from reg */
+    pub size: u64,          /* size of the MMIO region, from reg */
+    pub irq: Option<u32>,   /* interrupt number, from interrupts */
+    pub clock: Option<u32>, /* input clock in Hz, from clock-frequency */
+    pub baud: Option<u32>   /* configured baud rate, from current-speed */
+}
+
+/* what we know about each node on the path from the root to the current node */
+#[derive(Clone, Copy)]
+struct Frame
+{
+    address_cells: u32, /* cell sizes of this node's children's reg properties */
+    size_cells: u32,
+    uart: bool,         /* node is compatible with this driver */
+    disabled: bool,     /* node's status is something other than okay */
+    node: UartNode
+}
+
+impl Frame
+{
+    fn new() -> Self
+    {
+        Frame
+        {
+            address_cells: DEFAULT_ADDRESS_CELLS, size_cells: DEFAULT_SIZE_CELLS,
+            uart: false, disabled: false, node: UartNode::default()
+        }
+    }
+}
+
+/* iterator over the enabled, compatible UARTs in a device tree */
+pub struct Probe<'a>
+{
+    structs: &'a [u8],
+    strings: &'a [u8],
+    offset: usize,
+    depth: usize,
+    stack: [Frame; MAX_DEPTH],
+    finished: bool
+}
+
+/* parse the header of the given device tree blob and return an iterator
+   over the UARTs it describes, or fail with BadDeviceTree */
+pub fn probe(dtb: &[u8]) -> Result<Probe<'_>, Fault>
+{
+    if dtb.len() < HEADER_SIZE || read_u32(dtb, 0) != Some(FDT_MAGIC)
+    {
+        return Err(Fault::BadDeviceTree)
+    }
+
+    let field = |index: usize| read_u32(dtb, index * 4).map(|v| v as usize).ok_or(Fault::BadDeviceTree);
+    let total = field(1)?;
+    let (structs_off, strings_off) = (field(2)?, field(3)?);
+    let (strings_size, structs_size) = (field(8)?, field(9)?);
+
+    let dtb = dtb.get(..total).ok_or(Fault::BadDeviceTree)?;
+    let structs = span(dtb, structs_off, structs_size).ok_or(Fault::BadDeviceTree)?;
+    let strings = span(dtb, strings_off, strings_size).ok_or(Fault::BadDeviceTree)?;
+
+    Ok(Probe { structs, strings, offset: 0, depth: 0, stack: [Frame::new(); MAX_DEPTH], finished: false })
+}
+
+impl Iterator for Probe<'_>
+{
+    type Item = Result<UartNode, Fault>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        while !self.finished
+        {
+            match self.step()
+            {
+                Ok(Some(node)) => return Some(Ok(node)),
+                Ok(None) => (),
+                Err(e) =>
+                {
+                    self.finished = true;
+                    return Some(Err(e))
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Probe<'_>
+{
+    /* process one token, returning a UART node when one is complete */
+    fn step(&mut self) -> Result<Option<UartNode>, Fault>
+    {
+        let token = self.u32()?;
+        match token
+        {
+            FDT_BEGIN_NODE =>
+            {
+                /* skip the node's name */
+                let name = self.structs.get(self.offset..).ok_or(Fault::BadDeviceTree)?;
+                let len = name.iter().position(|&b| b == 0).ok_or(Fault::BadDeviceTree)?;
+                self.offset = align(self.offset + len + 1).ok_or(Fault::BadDeviceTree)?;
+
+                if self.depth >= MAX_DEPTH
+                {
+                    return Err(Fault::BadDeviceTree)
+                }
+                self.stack[self.depth] = Frame::new();
+                self.depth += 1;
+            },
+
+            FDT_END_NODE =>
+            {
+                if self.depth == 0
+                {
+                    return Err(Fault::BadDeviceTree)
+                }
+                self.depth -= 1;
+
+                let frame = self.stack[self.depth];
+                if frame.uart && !frame.disabled
+                {
+                    return Ok(Some(frame.node))
+                }
+            },
+
+            FDT_PROP =>
+            {
+                let len = self.u32()? as usize;
+                let name_offset = self.u32()? as usize;
+                let value = span(self.structs, self.offset, len).ok_or(Fault::BadDeviceTree)?;
+                self.offset = align(self.offset + len).ok_or(Fault::BadDeviceTree)?;
+
+                let name = self.strings.get(name_offset..).ok_or(Fault::BadDeviceTree)?;
+                let name = &name[..name.iter().position(|&b| b == 0).ok_or(Fault::BadDeviceTree)?];
+                self.property(name, value)?;
+            },
+
+            FDT_NOP => (),
+            FDT_END => self.finished = true,
+            _ => return Err(Fault::BadDeviceTree)
+        }
+
+        Ok(None)
+    }
+
+    /* note anything useful in a property of the current node */
+    fn property(&mut self, name: &[u8], value: &[u8]) -> Result<(), Fault>
+    {
+        if self.depth == 0
+        {
+            return Err(Fault::BadDeviceTree)
+        }
+
+        /* a node's reg is sized by its parent's cell counts */
+        let (address_cells, size_cells) = match self.depth
+        {
+            1 => (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS),
+            d => (self.stack[d - 2].address_cells, self.stack[d - 2].size_cells)
+        };
+        let frame = &mut self.stack[self.depth - 1];
+
+        match name
+        {
+            b"#address-cells" => frame.address_cells = cells(value, 0, 1).ok_or(Fault::BadDeviceTree)? as u32,
+            b"#size-cells" => frame.size_cells = cells(value, 0, 1).ok_or(Fault::BadDeviceTree)? as u32,
+            b"compatible" => frame.uart = value.split(|&b| b == 0).any(|s| s == COMPATIBLE.as_bytes()),
+            b"status" => frame.disabled = !matches!(value, b"okay\0" | b"ok\0"),
+            b"reg" =>
+            {
+                let (a, s) = (address_cells as usize, size_cells as usize);
+                frame.node.base = cells(value, 0, a).ok_or(Fault::BadDeviceTree)?;
+                frame.node.size = cells(value, a, s).ok_or(Fault::BadDeviceTree)?;
+            },
+            b"interrupts" => frame.node.irq = cells(value, 0, 1).map(|v| v as u32),
+            b"clock-frequency" =>
+            {
+                /* one cell, or two for a 64-bit rate, which must still fit a u32 */
+                let clock = match value.len()
+                {
+                    4 | 8 => cells(value, 0, value.len() / 4),
+                    _ => None
+                };
+                frame.node.clock = Some(clock.filter(|&v| v <= u32::MAX as u64).ok_or(Fault::BadDeviceTree)? as u32);
+            },
+            b"current-speed" => frame.node.baud = cells(value, 0, 1).map(|v| v as u32),
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    fn u32(&mut self) -> Result<u32, Fault>
+    {
+        let value = read_u32(self.structs, self.offset).ok_or(Fault::BadDeviceTree)?;
+        self.offset += 4;
+        Ok(value)
+    }
+}
+
+/* combine count big-endian cells, starting at cell index first, into one value */
+fn cells(value: &[u8], first: usize, count: usize) -> Option<u64>
+{
+    if count > 2
+    {
+        return None
+    }
+
+    (first..first + count).try_fold(0u64, |acc, i| Some(acc << 32 | read_u32(value, i * 4)? as u64))
+}
+
+/* the len bytes of data from start, or None if they run past its end. the
+   blob's offsets and sizes are untrusted, so their sum mustn't overflow */
+fn span(data: &[u8], start: usize, len: usize) -> Option<&[u8]>
+{
+    data.get(start..start.checked_add(len)?)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32>
+{
+    let bytes = span(data, offset, 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/* structure block items are padded to 32-bit boundaries */
+fn align(offset: usize) -> Option<usize>
+{
+    Some(offset.checked_add(3)? & !3)
+}
+
+impl UART
+{
+    /* create a UART object from a device tree node. if the node gives the
+       input clock, the baud rate is set to the node's current-speed or, if
+       that's missing, to the given default */
+    pub fn from_fdt_node(node: &UartNode, default_baud: u32) -> Result<Self, Fault>
+    {
+        let uart = UART::from_phys(node.base, node.size)?;
+
+        if let Some(clock) = node.clock
+        {
+            uart.set_baud(node.baud.unwrap_or(default_baud), clock);
+        }
+
+        Ok(uart)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /* just enough of a DTB compiler to build test trees */
+    struct Builder
+    {
+        structs: Vec<u8>,
+        strings: Vec<u8>
+    }
+
+    impl Builder
+    {
+        fn new() -> Self
+        {
+            Builder { structs: vec![], strings: vec![] }
+        }
+
+        fn token(&mut self, token: u32) -> &mut Self
+        {
+            self.structs.extend(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self)
+        {
+            while self.structs.len() & 3 != 0
+            {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self
+        {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self
+        {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self
+        {
+            let offset = self.strings.len() as u32;
+            self.strings.extend(name.as_bytes());
+            self.strings.push(0);
+            self.token(FDT_PROP).token(value.len() as u32).token(offset);
+            self.structs.extend(value);
+            self.pad();
+            self
+        }
+
+        fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Self
+        {
+            let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.prop(name, &value)
+        }
+
+        fn finish(&mut self) -> Vec<u8>
+        {
+            self.token(FDT_END);
+            let structs_off = HEADER_SIZE + 16; /* header plus an empty reservation map */
+            let strings_off = structs_off + self.structs.len();
+            let total = strings_off + self.strings.len();
+            let header = [FDT_MAGIC, total as u32, structs_off as u32, strings_off as u32,
+                          HEADER_SIZE as u32, 17, 16, 0, self.strings.len() as u32, self.structs.len() as u32];
+
+            let mut dtb: Vec<u8> = header.iter().flat_map(|f| f.to_be_bytes()).collect();
+            dtb.extend(&[0; 16]);
+            dtb.extend(&self.structs);
+            dtb.extend(&self.strings);
+            dtb
+        }
+    }
+
+    #[test]
+    fn finds_enabled_uarts()
+    {
+        let dtb = Builder::new()
+            .begin("")
+                .cells("#address-cells", &[2]).cells("#size-cells", &[2])
+                .begin("soc")
+                    .cells("#address-cells", &[2]).cells("#size-cells", &[2])
+                    .begin("serial@10010000")
+                        .prop("compatible", b"sifive,fu740-c000-uart\0sifive,uart0\0")
+                        .cells("reg", &[0, 0x1001_0000, 0, 0x1000])
+                        .cells("interrupts", &[39])
+                        .cells("clock-frequency", &[130_000_000])
+                        .cells("current-speed", &[115_200])
+                    .end()
+                    .begin("serial@10011000")
+                        .prop("compatible", b"sifive,uart0\0")
+                        .cells("reg", &[0, 0x1001_1000, 0, 0x1000])
+                        .prop("status", b"disabled\0")
+                    .end()
+                    .begin("ethernet@10090000")
+                        .prop("compatible", b"sifive,fu540-c000-gem\0")
+                        .cells("reg", &[0, 0x1009_0000, 0, 0x2000])
+                    .end()
+                .end()
+            .end()
+            .finish();
+
+        let nodes: Vec<UartNode> = probe(&dtb).unwrap().map(|n| n.unwrap()).collect();
+        assert_eq!(nodes, [UartNode
+        {
+            base: 0x1001_0000, size: 0x1000, irq: Some(39), clock: Some(130_000_000), baud: Some(115_200)
+        }]);
+    }
+
+    #[test]
+    fn honors_parent_cell_sizes()
+    {
+        let dtb = Builder::new()
+            .begin("")
+                .cells("#address-cells", &[1]).cells("#size-cells", &[1])
+                .begin("serial@10013000")
+                    .prop("compatible", b"sifive,uart0\0")
+                    .cells("reg", &[0x1001_3000, 0x1000])
+                .end()
+            .end()
+            .finish();
+
+        let node = probe(&dtb).unwrap().next().unwrap().unwrap();
+        assert_eq!((node.base, node.size, node.irq, node.clock), (0x1001_3000, 0x1000, None, None));
+    }
+
+    #[test]
+    fn checks_clock_frequency_size()
+    {
+        let probe_clock = |value: &[u8]|
+        {
+            let dtb = Builder::new()
+                .begin("")
+                    .begin("serial@10010000")
+                        .prop("compatible", b"sifive,uart0\0")
+                        .cells("reg", &[0, 0x1001_0000, 0, 0x1000])
+                        .prop("clock-frequency", value)
+                    .end()
+                .end()
+                .finish();
+            probe(&dtb).unwrap().next().unwrap().map(|node| node.clock)
+        };
+
+        assert!(matches!(probe_clock(&[0, 0, 0, 10]), Ok(Some(10))));
+        assert!(matches!(probe_clock(&[0, 0, 0, 0, 0, 0, 0, 10]), Ok(Some(10))));
+        for value in [&[][..], &[0, 10], &[0; 12], &[0, 0, 0, 1, 0, 0, 0, 0]]
+        {
+            assert!(matches!(probe_clock(value), Err(Fault::BadDeviceTree)));
+        }
+    }
+
+    #[test]
+    fn rejects_garbage()
+    {
+        assert!(matches!(probe(&[0; 64]), Err(Fault::BadDeviceTree)));
+
+        let mut dtb = Builder::new().begin("").end().finish();
+        let structs_off = HEADER_SIZE + 16;
+        dtb[structs_off + 3] = 0x7f; /* unknown token */
+        assert!(matches!(probe(&dtb).unwrap().next(), Some(Err(Fault::BadDeviceTree))));
+
+        /* offsets and sizes reaching past the end of the address space */
+        assert_eq!(span(&dtb, usize::MAX, 4), None);
+        assert_eq!(align(usize::MAX - 1), None);
+        dtb[36..40].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(probe(&dtb), Err(Fault::BadDeviceTree)));
+    }
+}
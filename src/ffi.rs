@@ -0,0 +1,266 @@
+/* C bindings, so mixed C and Rust firmware can share one driver
+ *
+ * UARTs are identified by a small index into a fixed table, filled in
+ * by sifive_uart_init(), so C code needn't know the size or layout of a
+ * UART. Each entry has its own software FIFOs for the buffered calls,
+ * which are serviced by calling sifive_uart_irq() from the UART's
+ * interrupt handler. Calls returning int give zero or a received byte on
+ * success, and a negative SIFIVE_UART_E* code on failure.
+ *
+ * The matching header is include/sifive_uart.h. It can be regenerated
+ * with: cbindgen --config cbindgen.toml --output include/sifive_uart.h
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::UnsafeCell;
+use core::ffi::{c_int, c_uint};
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+use super::{UART, Fault};
+use super::buffered::Buffers;
+
+/* number of UARTs that can be initialized */
+pub const SIFIVE_UART_MAX: usize = 4;
+
+/* size in bytes of each UART's rx and tx software FIFOs */
+pub const SIFIVE_UART_RX_BUFFER: usize = 256;
+pub const SIFIVE_UART_TX_BUFFER: usize = 256;
+
+const EMPTY: u8 = 0;    /* slot not yet initialized */
+const FILLING: u8 = 1;  /* sifive_uart_init() is storing a UART */
+const READY: u8 = 2;    /* slot may be used */
+
+struct Slot
+{
+    state: AtomicU8,
+    uart: UnsafeCell<MaybeUninit<UART>>,
+    buffers: Buffers<SIFIVE_UART_RX_BUFFER, SIFIVE_UART_TX_BUFFER>
+}
+
+/* the UART is written once, before state becomes READY, and only read after */
+unsafe impl Sync for Slot {}
+
+static SLOTS: [Slot; SIFIVE_UART_MAX] = [const
+{
+    Slot { state: AtomicU8::new(EMPTY), uart: UnsafeCell::new(MaybeUninit::uninit()), buffers: Buffers::new() }
+}; SIFIVE_UART_MAX];
+
+/* the initialized slot at index, or NoSuchDevice */
+fn slot(index: c_uint) -> Result<(&'static UART, &'static Slot), Fault>
+{
+    let slot = SLOTS.get(index as usize).ok_or(Fault::NoSuchDevice)?;
+    match slot.state.load(Ordering::Acquire)
+    {
+        READY => Ok((unsafe { (*slot.uart.get()).assume_init_ref() }, slot)),
+        _ => Err(Fault::NoSuchDevice)
+    }
+}
+
+/* the negative error code returned to C for a fault. these values are part
+   of the C interface and must match the header */
+fn code(fault: Fault) -> c_int
+{
+    match fault
+    {
+        Fault::SizeTooSmall => -1,
+        Fault::TxNotEmpty => -2,
+        Fault::DataNotReady => -3,
+        Fault::FrameTooLong => -4,
+        Fault::BadEscape => -5,
+        Fault::BadFrame => -6,
+        Fault::Cancelled => -7,
+        Fault::TooManyErrors => -8,
+        Fault::BadDeviceTree => -9,
+        Fault::RegistryFull => -10,
+        Fault::NoSuchDevice => -11,
+        Fault::AlreadyClaimed => -12,
+        Fault::BadAddress => -13,
+        Fault::TimedOut => -14,
+        Fault::OutOfRange => -15
+    }
+}
+
+fn status(result: Result<(), Fault>) -> c_int
+{
+    match result
+    {
+        Ok(()) => 0,
+        Err(fault) => code(fault)
+    }
+}
+
+fn count(result: Result<usize, Fault>) -> isize
+{
+    match result
+    {
+        Ok(count) => count as isize,
+        Err(fault) => code(fault) as isize
+    }
+}
+
+/* initialize the standard UART at base, whose MMIO area is size bytes, as
+   UART::new() does, and store it at index. returns SIFIVE_UART_EALREADYCLAIMED
+   if index is already in use */
+#[no_mangle]
+pub extern "C" fn sifive_uart_init(index: c_uint, base: usize, size: usize) -> c_int
+{
+    let slot = match SLOTS.get(index as usize)
+    {
+        Some(slot) => slot,
+        None => return code(Fault::NoSuchDevice)
+    };
+
+    if slot.state.compare_exchange(EMPTY, FILLING, Ordering::Acquire, Ordering::Relaxed).is_err()
+    {
+        return code(Fault::AlreadyClaimed)
+    }
+
+    match UART::new(base, size)
+    {
+        Ok(uart) =>
+        {
+            unsafe { (*slot.uart.get()).write(uart) };
+            slot.state.store(READY, Ordering::Release);
+            0
+        },
+        Err(fault) =>
+        {
+            slot.state.store(EMPTY, Ordering::Release);
+            code(fault)
+        }
+    }
+}
+
+/* set the baud rate from the frequency of the bus clocking the UART */
+#[no_mangle]
+pub extern "C" fn sifive_uart_set_baud(index: c_uint, baud: u32, bus_freq: u32) -> c_int
+{
+    status(slot(index).map(|(uart, _)| uart.set_baud(baud, bus_freq)))
+}
+
+/* send a byte, waiting a bounded time for room in the tx FIFO */
+#[no_mangle]
+pub extern "C" fn sifive_uart_putc(index: c_uint, byte: u8) -> c_int
+{
+    status(slot(index).and_then(|(uart, _)| uart.send_byte(byte)))
+}
+
+/* return a received byte, or SIFIVE_UART_EDATANOTREADY if there isn't one */
+#[no_mangle]
+pub extern "C" fn sifive_uart_getc(index: c_uint) -> c_int
+{
+    match slot(index).and_then(|(uart, _)| uart.read_byte())
+    {
+        Ok(byte) => byte as c_int,
+        Err(fault) => code(fault)
+    }
+}
+
+/* start the buffered calls: enable the rx watermark interrupt so that
+   sifive_uart_irq() collects received bytes */
+#[no_mangle]
+pub extern "C" fn sifive_uart_buffered_start(index: c_uint) -> c_int
+{
+    status(slot(index).map(|(uart, _)| uart.enable_rx_watermark_irq(true)))
+}
+
+/* call from the UART's interrupt handler to service its software FIFOs */
+#[no_mangle]
+pub extern "C" fn sifive_uart_irq(index: c_uint)
+{
+    if let Ok((uart, slot)) = slot(index)
+    {
+        uart.handle_interrupt(&slot.buffers);
+    }
+}
+
+/* turn a pointer and length from C into a slice. C may pass any pointer,
+   including null, with a zero length, but a slice's must never be null */
+unsafe fn slice<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Fault>
+{
+    match (len, data.is_null())
+    {
+        (0, _) => Ok(&[]),
+        (_, true) => Err(Fault::BadAddress),
+        (_, false) => Ok(core::slice::from_raw_parts(data, len))
+    }
+}
+
+unsafe fn slice_mut<'a>(data: *mut u8, len: usize) -> Result<&'a mut [u8], Fault>
+{
+    match (len, data.is_null())
+    {
+        (0, _) => Ok(&mut []),
+        (_, true) => Err(Fault::BadAddress),
+        (_, false) => Ok(core::slice::from_raw_parts_mut(data, len))
+    }
+}
+
+/* queue up to len bytes from data for transmission by the interrupt
+   handler. returns how many were queued, or a negative error code:
+   SIFIVE_UART_EBADADDRESS if data is null and len isn't zero.
+
+   safety: data must point to len readable bytes */
+#[no_mangle]
+pub unsafe extern "C" fn sifive_uart_buffered_write(index: c_uint, data: *const u8, len: usize) -> isize
+{
+    count(slot(index).and_then(|(uart, slot)| Ok(uart.queue_tx(&slot.buffers, slice(data, len)?))))
+}
+
+/* copy up to len received bytes into buf. returns how many were copied,
+   which is zero if nothing has arrived, or a negative error code:
+   SIFIVE_UART_EBADADDRESS if buf is null and len isn't zero.
+
+   safety: buf must point to len writable bytes */
+#[no_mangle]
+pub unsafe extern "C" fn sifive_uart_buffered_read(index: c_uint, buf: *mut u8, len: usize) -> isize
+{
+    count(slot(index).and_then(|(_, slot)| Ok(slot.buffers.rx.pop_into(slice_mut(buf, len)?))))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::{REG_TOTAL_SIZE, REG_TXDATA, REG_RXDATA, REG_RXDATA_EMPTY, REG_IE, REG_IE_RXWM};
+
+    #[test]
+    fn c_calls()
+    {
+        /* the table is a static, so the fake registers must outlive the test */
+        let regs: &'static FakeRegisters = Box::leak(Box::new(FakeRegisters::new()));
+        assert_eq!(sifive_uart_putc(0, b'x'), -11);
+        assert_eq!(sifive_uart_init(0, regs.base(), 4), -1);
+        assert_eq!(sifive_uart_init(0, regs.base(), REG_TOTAL_SIZE), 0);
+        assert_eq!(sifive_uart_init(0, regs.base(), REG_TOTAL_SIZE), -12);
+        assert_eq!(sifive_uart_init(SIFIVE_UART_MAX as c_uint, regs.base(), REG_TOTAL_SIZE), -11);
+
+        assert_eq!(sifive_uart_putc(0, b'c'), 0);
+        assert_eq!(regs.get(REG_TXDATA), b'c' as u32);
+        assert_eq!(sifive_uart_getc(0), -3);
+        regs.receive(b'r');
+        assert_eq!(sifive_uart_getc(0), b'r' as c_int);
+
+        assert_eq!(sifive_uart_buffered_start(0), 0);
+        assert_ne!(regs.get(REG_IE) & REG_IE_RXWM, 0);
+        sifive_uart_irq(0);
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+
+        let mut buf = [0u8; 16];
+        assert_eq!(unsafe { sifive_uart_buffered_read(0, buf.as_mut_ptr(), buf.len()) }, 8);
+        assert_eq!(&buf[..2], b"rr");
+        assert_eq!(unsafe { sifive_uart_buffered_write(0, b"ok".as_ptr(), 2) }, 2);
+        sifive_uart_irq(0);
+        assert_eq!(regs.get(REG_TXDATA), b'k' as u32);
+
+        /* C may pass null with nothing to copy, but not with something */
+        assert_eq!(unsafe { sifive_uart_buffered_write(0, core::ptr::null(), 0) }, 0);
+        assert_eq!(unsafe { sifive_uart_buffered_read(0, core::ptr::null_mut(), 0) }, 0);
+        assert_eq!(unsafe { sifive_uart_buffered_write(0, core::ptr::null(), 2) }, -13);
+        assert_eq!(unsafe { sifive_uart_buffered_read(0, core::ptr::null_mut(), 2) }, -13);
+    }
+}
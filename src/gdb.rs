@@ -0,0 +1,134 @@
+/* transport for a GDB remote serial protocol stub running over the UART
+ *
+ * Shaped after the connection traits used by gdbstub and similar crates:
+ * blocking reads and writes, a non-blocking peek, and detection of the
+ * interrupt character GDB sends when the user hits Ctrl-C while the
+ * target is running.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+
+/* sent by GDB, outside of a packet, to stop the target */
+pub const INTERRUPT: u8 = 0x03;
+
+pub struct GdbTransport<'a>
+{
+    uart: &'a UART,
+    peeked: Option<u8> /* byte read from the UART but not yet consumed */
+}
+
+impl<'a> GdbTransport<'a>
+{
+    pub fn new(uart: &'a UART) -> Self
+    {
+        GdbTransport { uart, peeked: None }
+    }
+
+    /* transmit a byte */
+    pub fn write(&mut self, byte: u8) -> Result<(), Fault>
+    {
+        self.uart.send_byte(byte)
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Fault>
+    {
+        for &byte in data
+        {
+            self.write(byte)?;
+        }
+
+        Ok(())
+    }
+
+    /* bytes are handed straight to the hardware, so there's nothing to flush */
+    pub fn flush(&mut self) -> Result<(), Fault>
+    {
+        Ok(())
+    }
+
+    /* wait as long as it takes for the next byte from the debugger */
+    pub fn read(&mut self) -> Result<u8, Fault>
+    {
+        loop
+        {
+            if let Some(byte) = self.peek()?
+            {
+                self.peeked = None;
+                return Ok(byte)
+            }
+
+            self.uart.relax();
+        }
+    }
+
+    /* return the next byte from the debugger, if one has arrived, without consuming it */
+    pub fn peek(&mut self) -> Result<Option<u8>, Fault>
+    {
+        if self.peeked.is_none()
+        {
+            self.peeked = self.uart.read_byte().ok();
+        }
+
+        Ok(self.peeked)
+    }
+
+    /* call periodically while the target runs: returns true if the debugger
+       has asked to stop it, consuming the interrupt character. any other
+       byte is left to be read as normal */
+    pub fn poll_interrupt(&mut self) -> bool
+    {
+        match self.peek()
+        {
+            Ok(Some(INTERRUPT)) =>
+            {
+                self.peeked = None;
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::{REG_TXDATA, REG_RXDATA, REG_RXDATA_EMPTY};
+
+    #[test]
+    fn interrupt_detection()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut gdb = GdbTransport::new(&uart);
+
+        assert!(!gdb.poll_interrupt());
+
+        regs.receive(INTERRUPT);
+        assert!(gdb.poll_interrupt());
+
+        /* a packet byte is kept for the stub to read */
+        regs.receive(b'$');
+        assert!(!gdb.poll_interrupt());
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+        assert_eq!(gdb.peek().unwrap(), Some(b'$'));
+        assert_eq!(gdb.read().unwrap(), b'$');
+        assert_eq!(gdb.peek().unwrap(), None);
+    }
+
+    #[test]
+    fn writes_reach_the_hardware()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut gdb = GdbTransport::new(&uart);
+
+        gdb.write_all(b"$OK#9a").unwrap();
+        assert_eq!(regs.get(REG_TXDATA), b'a' as u32);
+    }
+}
@@ -0,0 +1,694 @@
+/* emulate the controller for a guest
+ *
+ * A hypervisor that traps a guest's loads and stores to a UART's MMIO
+ * range needs to know what each one means. GuestAccess classifies a
+ * trapped access by register, and unpacks the fields of writes, using
+ * the same register layout the driver itself uses, eg:
+ *
+ *   match GuestAccess::write(offset, width, value)?
+ *   {
+ *       GuestAccess::WriteTxData(byte) => host_console.put(byte),
+ *       GuestAccess::WriteIe { txwm, rxwm } => ...,
+ *       ...
+ *   }
+ *
+ * Registers are 32 bits wide. Accesses must be to the start of a register,
+ * and may be 1, 2 or 4 bytes wide: narrower writes are zero-extended.
+ *
+ * VirtualUart goes further and emulates the whole controller, with its
+ * FIFOs, FULL and EMPTY flags and watermark interrupts, so a guest can be
+ * given a UART its own driver, including this one, will accept. The guest's
+ * bytes go to, and its input comes from, a Host the hypervisor provides,
+ * which is also told when the interrupt line changes, eg:
+ *
+ *   let mut uart = VirtualUart::new(console);
+ *   ...
+ *   match write
+ *   {
+ *       true => uart.write(offset, width, value)?,
+ *       false => guest.set_reg(rd, uart.read(offset, width)?)
+ *   }
+ *   ...
+ *   uart.service();     // when the host console has input or room for output
+ *
+ * A VirtualUart's state, including bytes still in its FIFOs, can be saved
+ * with snapshot() and put back with restore(), on the same machine or,
+ * via Snapshot::to_bytes() and from_bytes(), another one, so a guest can be
+ * checkpointed or migrated without losing characters in flight.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{Fault, REG_TOTAL_SIZE, REG_TXDATA, REG_RXDATA, REG_TXCTRL, REG_RXCTRL, REG_IE, REG_IP, REG_DIV,
+            REG_IE_TXWM, REG_IE_RXWM, REG_TXCTRL_TXEN, REG_TXCTRL_NSTOP, REG_TXCTRL_TXCNT_SHIFT,
+            REG_RXCTRL_RXEN, REG_RXCTRL_RXCNT_SHIFT, REG_CNT_FIELD, REG_DIV_MASK, REG_IP_TXWM, REG_IP_RXWM,
+            REG_TXDATA_FULL, REG_RXDATA_EMPTY, IrqPending, UART};
+
+/* entries in each of the emulated tx and rx FIFOs, as on SiFive's controller */
+const FIFO_DEPTH: usize = UART::FIFO_DEPTH as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestAccess
+{
+    ReadTxData,                 /* the guest is checking whether the tx FIFO is full */
+    WriteTxData(u8),            /* the guest is sending a byte */
+    ReadRxData,                 /* the guest is taking a received byte */
+    ReadTxCtrl,
+    WriteTxCtrl { enable: bool, two_stop_bits: bool, watermark: u32 },
+    ReadRxCtrl,
+    WriteRxCtrl { enable: bool, watermark: u32 },
+    ReadIe,
+    WriteIe { txwm: bool, rxwm: bool },
+    ReadIp,
+    ReadDiv,
+    WriteDiv(u16),
+    Ignored                     /* a write to a read-only register, which has no effect */
+}
+
+impl GuestAccess
+{
+    /* classify a load of width bytes from offset into the register block */
+    pub fn read(offset: usize, width: usize) -> Result<GuestAccess, Fault>
+    {
+        Ok(match GuestAccess::register(offset, width)?
+        {
+            REG_TXDATA => GuestAccess::ReadTxData,
+            REG_RXDATA => GuestAccess::ReadRxData,
+            REG_TXCTRL => GuestAccess::ReadTxCtrl,
+            REG_RXCTRL => GuestAccess::ReadRxCtrl,
+            REG_IE => GuestAccess::ReadIe,
+            REG_IP => GuestAccess::ReadIp,
+            _ => GuestAccess::ReadDiv
+        })
+    }
+
+    /* classify a store of value, width bytes wide, to offset into the register
+       block. reserved bits of value are ignored, as the hardware does */
+    pub fn write(offset: usize, width: usize, value: u32) -> Result<GuestAccess, Fault>
+    {
+        let value = match width
+        {
+            1 => value & 0xff,
+            2 => value & 0xffff,
+            _ => value
+        };
+
+        Ok(match GuestAccess::register(offset, width)?
+        {
+            REG_TXDATA => GuestAccess::WriteTxData(value as u8),
+            REG_TXCTRL => GuestAccess::WriteTxCtrl
+            {
+                enable: value & REG_TXCTRL_TXEN != 0,
+                two_stop_bits: value & REG_TXCTRL_NSTOP != 0,
+                watermark: value >> REG_TXCTRL_TXCNT_SHIFT & REG_CNT_FIELD
+            },
+            REG_RXCTRL => GuestAccess::WriteRxCtrl
+            {
+                enable: value & REG_RXCTRL_RXEN != 0,
+                watermark: value >> REG_RXCTRL_RXCNT_SHIFT & REG_CNT_FIELD
+            },
+            REG_IE => GuestAccess::WriteIe { txwm: value & REG_IE_TXWM != 0, rxwm: value & REG_IE_RXWM != 0 },
+            REG_DIV => GuestAccess::WriteDiv((value & REG_DIV_MASK) as u16),
+            _ => GuestAccess::Ignored
+        })
+    }
+
+    /* return the register an access is to. fails with BadAddress if offset is
+       outside the register block or not at the start of a register, and
+       OutOfRange if width isn't 1, 2 or 4 */
+    fn register(offset: usize, width: usize) -> Result<usize, Fault>
+    {
+        if offset >= REG_TOTAL_SIZE || !offset.is_multiple_of(4)
+        {
+            return Err(Fault::BadAddress)
+        }
+
+        match width
+        {
+            1 | 2 | 4 => Ok(offset),
+            _ => Err(Fault::OutOfRange)
+        }
+    }
+}
+
+/* the hypervisor's side of a VirtualUart */
+pub trait Host
+{
+    /* take a byte the guest has sent. return false if it can't be taken yet,
+       leaving it, and everything after it, in the tx FIFO */
+    fn transmit(&mut self, byte: u8) -> bool;
+
+    /* return the next byte for the guest to receive, if there is one */
+    fn receive(&mut self) -> Option<u8>;
+
+    /* the guest's interrupt line has been raised or lowered */
+    fn interrupt(&mut self, _raised: bool) {}
+}
+
+/* a FIFO of up to FIFO_DEPTH bytes */
+#[derive(Debug, Clone, Copy, Default)]
+struct Fifo
+{
+    data: [u8; FIFO_DEPTH],
+    head: usize,
+    len: usize
+}
+
+impl Fifo
+{
+    fn is_full(&self) -> bool
+    {
+        self.len == FIFO_DEPTH
+    }
+
+    fn push(&mut self, byte: u8) -> bool
+    {
+        if self.is_full()
+        {
+            return false
+        }
+        self.data[(self.head + self.len) % FIFO_DEPTH] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn peek(&self) -> Option<u8>
+    {
+        match self.len
+        {
+            0 => None,
+            _ => Some(self.data[self.head])
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8>
+    {
+        let byte = self.peek()?;
+        self.head = (self.head + 1) % FIFO_DEPTH;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/* bytes in a serialized Snapshot */
+pub const SNAPSHOT_SIZE: usize = 1 + 4 * 4 + 2 * (1 + FIFO_DEPTH) + 1;
+
+/* format of serialized snapshots, bumped whenever it changes */
+const SNAPSHOT_VERSION: u8 = 1;
+
+/* a VirtualUart's registers, FIFO contents and interrupt line */
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot
+{
+    txctrl: u32,
+    rxctrl: u32,
+    ie: u32,
+    div: u32,
+    tx: Fifo,
+    rx: Fifo,
+    irq: bool
+}
+
+impl Snapshot
+{
+    /* serialize as a version byte, then txctrl, rxctrl, ie and div as little
+       endian words, then the count and bytes of the tx and rx FIFOs, oldest
+       first, and finally the interrupt line */
+    pub fn to_bytes(&self) -> [u8; SNAPSHOT_SIZE]
+    {
+        let mut bytes = [0u8; SNAPSHOT_SIZE];
+        bytes[0] = SNAPSHOT_VERSION;
+        for (i, reg) in [self.txctrl, self.rxctrl, self.ie, self.div].iter().enumerate()
+        {
+            bytes[1 + i * 4..5 + i * 4].copy_from_slice(&reg.to_le_bytes());
+        }
+
+        let mut pos = 17;
+        for fifo in [&self.tx, &self.rx]
+        {
+            bytes[pos] = fifo.len as u8;
+            for i in 0..fifo.len
+            {
+                bytes[pos + 1 + i] = fifo.data[(fifo.head + i) % FIFO_DEPTH];
+            }
+            pos += 1 + FIFO_DEPTH;
+        }
+
+        bytes[pos] = self.irq as u8;
+        bytes
+    }
+
+    /* deserialize a snapshot made by to_bytes(). fails with BadFrame if bytes
+       is the wrong length, from another version, or inconsistent */
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, Fault>
+    {
+        if bytes.len() != SNAPSHOT_SIZE || bytes[0] != SNAPSHOT_VERSION
+        {
+            return Err(Fault::BadFrame)
+        }
+
+        let word = |i: usize| u32::from_le_bytes([bytes[1 + i * 4], bytes[2 + i * 4], bytes[3 + i * 4], bytes[4 + i * 4]]);
+        let fifo = |pos: usize|
+        {
+            let len = bytes[pos] as usize;
+            if len > FIFO_DEPTH
+            {
+                return Err(Fault::BadFrame)
+            }
+
+            let mut fifo = Fifo { len, ..Fifo::default() };
+            fifo.data.copy_from_slice(&bytes[pos + 1..pos + 1 + FIFO_DEPTH]);
+            Ok(fifo)
+        };
+
+        Ok(Snapshot
+        {
+            txctrl: word(0),
+            rxctrl: word(1),
+            ie: word(2),
+            div: word(3),
+            tx: fifo(17)?,
+            rx: fifo(18 + FIFO_DEPTH)?,
+            irq: match bytes[SNAPSHOT_SIZE - 1]
+            {
+                0 => false,
+                1 => true,
+                _ => return Err(Fault::BadFrame)
+            }
+        })
+    }
+}
+
+/* an emulated controller, reset with everything disabled */
+pub struct VirtualUart<H: Host>
+{
+    host: H,
+    tx_enable: bool,
+    two_stop_bits: bool,
+    tx_watermark: u32,
+    rx_enable: bool,
+    rx_watermark: u32,
+    ie: IrqPending,
+    div: u16,
+    tx: Fifo,
+    rx: Fifo,
+    irq: bool       /* level last reported to the host */
+}
+
+impl<H: Host> VirtualUart<H>
+{
+    pub fn new(host: H) -> Self
+    {
+        VirtualUart
+        {
+            host,
+            tx_enable: false,
+            two_stop_bits: false,
+            tx_watermark: 0,
+            rx_enable: false,
+            rx_watermark: 0,
+            ie: IrqPending::NONE,
+            div: 0,
+            tx: Fifo::default(),
+            rx: Fifo::default(),
+            irq: false
+        }
+    }
+
+    pub fn host(&self) -> &H
+    {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut H
+    {
+        &mut self.host
+    }
+
+    /* carry out a guest's load, returning the value it reads. narrow loads
+       get the low bits of the register */
+    pub fn read(&mut self, offset: usize, width: usize) -> Result<u32, Fault>
+    {
+        let value = match GuestAccess::read(offset, width)?
+        {
+            GuestAccess::ReadTxData => match self.tx.is_full()
+            {
+                true => REG_TXDATA_FULL,
+                false => 0
+            },
+            GuestAccess::ReadRxData => match self.rx.pop()
+            {
+                Some(byte) => byte as u32,
+                None => REG_RXDATA_EMPTY
+            },
+            GuestAccess::ReadTxCtrl => self.txctrl(),
+            GuestAccess::ReadRxCtrl => self.rxctrl(),
+            GuestAccess::ReadIe => VirtualUart::<H>::irq_bits(self.ie),
+            GuestAccess::ReadIp => VirtualUart::<H>::irq_bits(self.pending()),
+            _ => self.div as u32
+        };
+
+        self.service();
+        Ok(match width
+        {
+            1 => value & 0xff,
+            2 => value & 0xffff,
+            _ => value
+        })
+    }
+
+    /* carry out a guest's store. a byte written while the tx FIFO is full is
+       lost, as it would be on the hardware */
+    pub fn write(&mut self, offset: usize, width: usize, value: u32) -> Result<(), Fault>
+    {
+        match GuestAccess::write(offset, width, value)?
+        {
+            GuestAccess::WriteTxData(byte) =>
+            {
+                self.tx.push(byte);
+            },
+            GuestAccess::WriteTxCtrl { enable, two_stop_bits, watermark } =>
+            {
+                self.tx_enable = enable;
+                self.two_stop_bits = two_stop_bits;
+                self.tx_watermark = watermark;
+            },
+            GuestAccess::WriteRxCtrl { enable, watermark } =>
+            {
+                self.rx_enable = enable;
+                self.rx_watermark = watermark;
+            },
+            GuestAccess::WriteIe { txwm, rxwm } => self.ie = IrqPending { txwm, rxwm },
+            GuestAccess::WriteDiv(div) => self.div = div,
+            _ => ()
+        }
+
+        self.service();
+        Ok(())
+    }
+
+    /* move bytes between the FIFOs and the host, and update the interrupt line.
+       call this whenever the host has new input or room for more output */
+    pub fn service(&mut self)
+    {
+        while self.tx_enable
+        {
+            match self.tx.peek()
+            {
+                Some(byte) if self.host.transmit(byte) => { self.tx.pop(); },
+                _ => break
+            }
+        }
+
+        while self.rx_enable && !self.rx.is_full()
+        {
+            match self.host.receive()
+            {
+                Some(byte) => { self.rx.push(byte); },
+                None => break
+            }
+        }
+
+        let irq = self.irq_raised();
+        if irq != self.irq
+        {
+            self.irq = irq;
+            self.host.interrupt(irq);
+        }
+    }
+
+    /* capture the controller's state, for restore() */
+    pub fn snapshot(&self) -> Snapshot
+    {
+        Snapshot
+        {
+            txctrl: self.txctrl(),
+            rxctrl: self.rxctrl(),
+            ie: VirtualUart::<H>::irq_bits(self.ie),
+            div: self.div as u32,
+            tx: self.tx,
+            rx: self.rx,
+            irq: self.irq
+        }
+    }
+
+    /* put back the state captured by snapshot(), and tell the host the level of
+       the interrupt line. nothing moves between the FIFOs and the host until
+       the next access or service() */
+    pub fn restore(&mut self, snapshot: &Snapshot)
+    {
+        for (reg, value) in [(REG_TXCTRL, snapshot.txctrl), (REG_RXCTRL, snapshot.rxctrl),
+                             (REG_IE, snapshot.ie), (REG_DIV, snapshot.div)]
+        {
+            match GuestAccess::write(reg, 4, value)
+            {
+                Ok(GuestAccess::WriteTxCtrl { enable, two_stop_bits, watermark }) =>
+                {
+                    self.tx_enable = enable;
+                    self.two_stop_bits = two_stop_bits;
+                    self.tx_watermark = watermark;
+                },
+                Ok(GuestAccess::WriteRxCtrl { enable, watermark }) =>
+                {
+                    self.rx_enable = enable;
+                    self.rx_watermark = watermark;
+                },
+                Ok(GuestAccess::WriteIe { txwm, rxwm }) => self.ie = IrqPending { txwm, rxwm },
+                Ok(GuestAccess::WriteDiv(div)) => self.div = div,
+                _ => ()
+            }
+        }
+
+        self.tx = snapshot.tx;
+        self.rx = snapshot.rx;
+        self.irq = snapshot.irq;
+        self.host.interrupt(self.irq);
+    }
+
+    /* interrupts the guest would see pending in IP, whether enabled or not */
+    pub fn pending(&self) -> IrqPending
+    {
+        IrqPending
+        {
+            txwm: (self.tx.len as u32) < self.tx_watermark,
+            rxwm: (self.rx.len as u32) > self.rx_watermark
+        }
+    }
+
+    /* return true if an enabled interrupt is pending, raising the guest's interrupt line */
+    pub fn irq_raised(&self) -> bool
+    {
+        let pending = self.pending();
+        (pending.txwm && self.ie.txwm) || (pending.rxwm && self.ie.rxwm)
+    }
+
+    /* the baud rate divisor the guest has set, for hosts that want to honor it */
+    pub fn divisor(&self) -> u16
+    {
+        self.div
+    }
+
+    fn txctrl(&self) -> u32
+    {
+        let mut value = self.tx_watermark << REG_TXCTRL_TXCNT_SHIFT;
+        if self.tx_enable
+        {
+            value |= REG_TXCTRL_TXEN;
+        }
+        if self.two_stop_bits
+        {
+            value |= REG_TXCTRL_NSTOP;
+        }
+        value
+    }
+
+    fn rxctrl(&self) -> u32
+    {
+        match self.rx_enable
+        {
+            true => self.rx_watermark << REG_RXCTRL_RXCNT_SHIFT | REG_RXCTRL_RXEN,
+            false => self.rx_watermark << REG_RXCTRL_RXCNT_SHIFT
+        }
+    }
+
+    fn irq_bits(irqs: IrqPending) -> u32
+    {
+        let mut bits = 0;
+        if irqs.txwm
+        {
+            bits |= REG_IP_TXWM;
+        }
+        if irqs.rxwm
+        {
+            bits |= REG_IP_RXWM;
+        }
+        bits
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use crate::UART;
+    use crate::bus::Bus;
+
+    /* a host console that takes up to room bytes of output */
+    #[derive(Default)]
+    struct Console
+    {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+        room: usize,
+        irqs: Vec<bool>
+    }
+
+    impl Host for Console
+    {
+        fn transmit(&mut self, byte: u8) -> bool
+        {
+            match self.room
+            {
+                0 => false,
+                _ => { self.room -= 1; self.output.push(byte); true }
+            }
+        }
+
+        fn receive(&mut self) -> Option<u8>
+        {
+            self.input.pop_front()
+        }
+
+        fn interrupt(&mut self, raised: bool)
+        {
+            self.irqs.push(raised);
+        }
+    }
+
+    /* lets this crate's driver run as the guest */
+    struct Guest(Mutex<VirtualUart<Console>>);
+
+    impl Bus for Guest
+    {
+        fn read(&self, reg: usize) -> u32
+        {
+            self.0.lock().unwrap().read(reg, 4).unwrap()
+        }
+
+        fn write(&self, reg: usize, val: u32)
+        {
+            self.0.lock().unwrap().write(reg, 4, val).unwrap()
+        }
+    }
+
+    #[test]
+    fn classifies_accesses()
+    {
+        assert_eq!(GuestAccess::write(0, 1, 0x141).unwrap(), GuestAccess::WriteTxData(0x41));
+        assert_eq!(GuestAccess::read(4, 4).unwrap(), GuestAccess::ReadRxData);
+        assert_eq!(GuestAccess::write(8, 4, 0x0003_0003).unwrap(),
+                   GuestAccess::WriteTxCtrl { enable: true, two_stop_bits: true, watermark: 3 });
+        assert_eq!(GuestAccess::write(12, 4, 0x0006_0000).unwrap(), GuestAccess::WriteRxCtrl { enable: false, watermark: 6 });
+        assert_eq!(GuestAccess::write(16, 4, 0xffff_fffe).unwrap(), GuestAccess::WriteIe { txwm: false, rxwm: true });
+        assert_eq!(GuestAccess::write(20, 4, 3).unwrap(), GuestAccess::Ignored);
+        assert_eq!(GuestAccess::write(24, 4, 0x1_0010).unwrap(), GuestAccess::WriteDiv(0x10));
+        assert_eq!(GuestAccess::read(24, 2).unwrap(), GuestAccess::ReadDiv);
+    }
+
+    #[test]
+    fn rejects_bad_accesses()
+    {
+        assert!(matches!(GuestAccess::read(28, 4), Err(Fault::BadAddress)));
+        assert!(matches!(GuestAccess::read(2, 1), Err(Fault::BadAddress)));
+        assert!(matches!(GuestAccess::write(0, 8, 0), Err(Fault::OutOfRange)));
+    }
+
+    #[test]
+    fn driver_runs_on_virtual_uart()
+    {
+        let console = Console { input: b"hello".iter().copied().collect(), room: 100, ..Console::default() };
+        let guest: &'static Guest = Box::leak(Box::new(Guest(Mutex::new(VirtualUart::new(console)))));
+        let uart = UART::on_bus(guest);
+
+        uart.send_bytes(b"hi").unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(uart.read_available(&mut buf), 5);
+        assert_eq!(&buf[..5], b"hello");
+
+        let mut virt = guest.0.lock().unwrap();
+        assert_eq!(virt.host().output, b"hi");
+        assert_eq!(virt.read(REG_TXCTRL, 4).unwrap(), 1 << REG_TXCTRL_TXCNT_SHIFT | REG_TXCTRL_TXEN);
+    }
+
+    #[test]
+    fn fifos_and_watermarks()
+    {
+        let mut virt = VirtualUart::new(Console::default());
+        virt.write(REG_TXCTRL, 4, 2 << REG_TXCTRL_TXCNT_SHIFT).unwrap();
+        virt.write(REG_RXCTRL, 4, 1 << REG_RXCTRL_RXCNT_SHIFT | REG_RXCTRL_RXEN).unwrap();
+        virt.write(REG_IE, 4, REG_IE_TXWM | REG_IE_RXWM).unwrap();
+        assert_eq!(virt.host().irqs, [true]);
+
+        /* transmitter disabled, so the tx FIFO fills up */
+        for byte in 0..10
+        {
+            virt.write(REG_TXDATA, 4, byte).unwrap();
+        }
+        assert_eq!(virt.read(REG_TXDATA, 4).unwrap(), REG_TXDATA_FULL);
+        assert_eq!(virt.read(REG_IP, 4).unwrap(), 0);
+        assert_eq!(virt.host().irqs, [true, false]);
+
+        /* host only has room for some of it */
+        virt.host_mut().room = 7;
+        virt.write(REG_TXCTRL, 4, 2 << REG_TXCTRL_TXCNT_SHIFT | REG_TXCTRL_TXEN).unwrap();
+        assert_eq!(virt.host().output, [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(virt.read(REG_IP, 4).unwrap(), REG_IP_TXWM);
+
+        /* input arrives */
+        virt.host_mut().input.extend(b"ab");
+        virt.service();
+        assert_eq!(virt.read(REG_IP, 4).unwrap(), REG_IP_TXWM | REG_IP_RXWM);
+        assert_eq!(virt.read(REG_RXDATA, 1).unwrap(), b'a' as u32);
+        assert_eq!(virt.read(REG_RXDATA, 4).unwrap(), b'b' as u32);
+        assert_eq!(virt.read(REG_RXDATA, 4).unwrap(), REG_RXDATA_EMPTY);
+    }
+
+    #[test]
+    fn snapshots_survive_migration()
+    {
+        let mut virt = VirtualUart::new(Console { input: b"0123456789".iter().copied().collect(), ..Console::default() });
+        virt.write(REG_RXCTRL, 4, 3 << REG_RXCTRL_RXCNT_SHIFT | REG_RXCTRL_RXEN).unwrap();
+        virt.write(REG_IE, 4, REG_IE_RXWM).unwrap();
+        virt.write(REG_DIV, 4, 0x87).unwrap();
+        assert_eq!(virt.read(REG_RXDATA, 4).unwrap(), b'0' as u32);
+        for &byte in b"tx"
+        {
+            virt.write(REG_TXDATA, 4, byte as u32).unwrap();
+        }
+
+        let bytes = virt.snapshot().to_bytes();
+        let mut copy = VirtualUart::new(Console { room: 10, ..Console::default() });
+        copy.restore(&Snapshot::from_bytes(&bytes).unwrap());
+        assert_eq!(copy.snapshot().to_bytes(), bytes);
+        assert_eq!(copy.host().irqs, [true]);
+        assert_eq!(copy.divisor(), 0x87);
+
+        for &byte in b"12345678"
+        {
+            assert_eq!(copy.read(REG_RXDATA, 4).unwrap(), byte as u32);
+        }
+        assert_eq!(copy.host().irqs, [true, false]);
+
+        copy.write(REG_TXCTRL, 4, REG_TXCTRL_TXEN).unwrap();
+        assert_eq!(copy.host().output, b"tx");
+
+        assert!(matches!(Snapshot::from_bytes(&bytes[1..]), Err(Fault::BadFrame)));
+        let mut bad = bytes;
+        bad[17] = 9;
+        assert!(matches!(Snapshot::from_bytes(&bad), Err(Fault::BadFrame)));
+    }
+}
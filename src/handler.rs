@@ -0,0 +1,39 @@
+/* reacting to UART events without closures or allocation
+ *
+ * Polled::poll_with(), UART::process_with() and
+ * UART::handle_interrupt_with() report what they did to a
+ * UartEventHandler: a trait implemented on the caller's own type, whose
+ * methods take &mut self so the handler can keep state, such as a line
+ * being assembled or a count of bytes, without a dyn Fn, a static or a
+ * heap, eg:
+ *
+ *   struct Shell { line: [u8; 80], len: usize }
+ *
+ *   impl UartEventHandler for Shell
+ *   {
+ *       fn rx_ready(&mut self, available: usize) { ... }
+ *   }
+ *
+ *   port.poll_with(&mut shell);
+ *
+ * Every method does nothing by default, so handlers only implement the
+ * events they care about, and () is a handler that ignores them all.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+pub trait UartEventHandler
+{
+    /* received bytes were added to the rx buffer, which now holds available bytes */
+    fn rx_ready(&mut self, _available: usize) {}
+
+    /* bytes were handed to the controller, leaving room for free bytes in the tx buffer */
+    fn tx_space(&mut self, _free: usize) {}
+
+    /* the interrupt handler ran with no enabled interrupt pending */
+    fn spurious_irq(&mut self) {}
+}
+
+impl UartEventHandler for () {}
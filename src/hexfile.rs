@@ -0,0 +1,329 @@
+/* receive firmware images sent as Intel HEX or Motorola S-records
+ *
+ * Both formats are plain text, one record per line, each carrying an
+ * address, some data and a checksum, so an image can be sent from a host
+ * with nothing more than cat firmware.hex > /dev/ttyUSB0. Loader is fed
+ * received bytes one at a time, checks each record, and hands the data to
+ * a caller-supplied sink along with the address it's to be written to.
+ * The format is recognized from each record's first character, : for
+ * Intel HEX and S for S-records.
+ *
+ * Nothing paces the sender, so the sink must keep up with the line or the
+ * rx FIFO will overflow. Buffers' interrupt-driven rx ring, or a slower
+ * baud rate, gives a sink time to program flash.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+
+/* longest record: the count, up to four bytes of address, a type byte for
+   Intel HEX, 255 data bytes and the checksum */
+const RECORD_MAX: usize = 1 + 4 + 1 + 255 + 1;
+
+/* receives the data of each valid record, in the order sent. return an error
+   to abandon the image */
+pub trait Sink
+{
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Fault>;
+}
+
+impl<F> Sink for F where F: FnMut(u32, &[u8]) -> Result<(), Fault>
+{
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Fault>
+    {
+        self(address, data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format
+{
+    IntelHex,
+    Srec(u8)    /* the record type, from the digit after the S */
+}
+
+pub struct Loader<S: Sink>
+{
+    sink: S,
+    format: Option<Format>,     /* the record being received, if any */
+    record: [u8; RECORD_MAX],   /* its bytes so far, decoded from hex */
+    len: usize,
+    high_nibble: Option<u8>,    /* first digit of a byte */
+    base: u32,                  /* added to Intel HEX addresses by extended address records */
+    entry: Option<u32>,         /* start address given by the image, if any */
+    skip: bool,                 /* ignoring the rest of a malformed line */
+    done: bool
+}
+
+impl<S: Sink> Loader<S>
+{
+    pub fn new(sink: S) -> Self
+    {
+        Loader
+        {
+            sink,
+            format: None,
+            record: [0; RECORD_MAX],
+            len: 0,
+            high_nibble: None,
+            base: 0,
+            entry: None,
+            skip: false,
+            done: false
+        }
+    }
+
+    /* process a received byte. returns true once the image's end record has
+       been received. fails with BadFrame if a record is malformed or its
+       checksum is wrong, or with whatever the sink returns. after a failure,
+       loading carries on from the next record */
+    pub fn push(&mut self, byte: u8) -> Result<bool, Fault>
+    {
+        if self.done
+        {
+            return Ok(true)
+        }
+
+        if self.skip
+        {
+            self.skip = !matches!(byte, b'\r' | b'\n');
+            return Ok(false)
+        }
+
+        let format = match self.format
+        {
+            Some(format) => format,
+            None =>
+            {
+                self.format = match byte
+                {
+                    b':' => Some(Format::IntelHex),
+                    b'S' => Some(Format::Srec(0xff)),
+                    b'\r' | b'\n' | b' ' | b'\t' => None,
+                    _ => return self.abandon()
+                };
+                self.len = 0;
+                self.high_nibble = None;
+                return Ok(false)
+            }
+        };
+
+        if let Format::Srec(0xff) = format
+        {
+            match byte
+            {
+                b'0'..=b'9' => self.format = Some(Format::Srec(byte - b'0')),
+                _ => return self.abandon()
+            }
+            return Ok(false)
+        }
+
+        match byte
+        {
+            b'\r' | b'\n' =>
+            {
+                self.format = None;
+                if self.high_nibble.is_some()
+                {
+                    return Err(Fault::BadFrame)
+                }
+
+                match format
+                {
+                    Format::IntelHex => self.intel_hex()?,
+                    Format::Srec(kind) => self.srec(kind)?
+                }
+                Ok(self.done)
+            },
+
+            _ =>
+            {
+                let digit = match (byte as char).to_digit(16)
+                {
+                    Some(digit) => digit as u8,
+                    None => return self.abandon()
+                };
+
+                match self.high_nibble.take()
+                {
+                    None => self.high_nibble = Some(digit),
+                    Some(_) if self.len == RECORD_MAX => return self.abandon(),
+                    Some(high) =>
+                    {
+                        self.record[self.len] = high << 4 | digit;
+                        self.len += 1;
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /* the start address given by the image, once its records have been received */
+    pub fn entry(&self) -> Option<u32>
+    {
+        self.entry
+    }
+
+    /* start on a new image */
+    pub fn reset(&mut self)
+    {
+        self.format = None;
+        self.base = 0;
+        self.entry = None;
+        self.skip = false;
+        self.done = false;
+    }
+
+    /* return the sink */
+    pub fn release(self) -> S
+    {
+        self.sink
+    }
+
+    /* skip the rest of a malformed line */
+    fn abandon(&mut self) -> Result<bool, Fault>
+    {
+        self.format = None;
+        self.skip = true;
+        Err(Fault::BadFrame)
+    }
+
+    /* act on a complete Intel HEX record: count, 16-bit address, type, data, checksum */
+    fn intel_hex(&mut self) -> Result<(), Fault>
+    {
+        let record = &self.record[..self.len];
+        if record.len() < 5 || record.len() != record[0] as usize + 5 ||
+           record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0
+        {
+            return Err(Fault::BadFrame)
+        }
+
+        let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let data = &record[4..record.len() - 1];
+        let value = data.iter().fold(0u32, |value, &byte| value << 8 | byte as u32);
+
+        match (record[3], data.len())
+        {
+            (0x00, _) => self.sink.write(self.base.wrapping_add(address), data)?,
+            (0x01, 0) => self.done = true,
+            (0x02, 2) => self.base = value << 4,
+            (0x03, 4) => self.entry = Some((value >> 16 << 4).wrapping_add(value & 0xffff)),
+            (0x04, 2) => self.base = value << 16,
+            (0x05, 4) => self.entry = Some(value),
+            _ => return Err(Fault::BadFrame)
+        }
+
+        Ok(())
+    }
+
+    /* act on a complete S-record: count, address, data, checksum */
+    fn srec(&mut self, kind: u8) -> Result<(), Fault>
+    {
+        let record = &self.record[..self.len];
+        let address_len = match kind
+        {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(Fault::BadFrame)
+        };
+
+        if record.len() < 2 + address_len || record.len() != record[0] as usize + 1 ||
+           record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xff
+        {
+            return Err(Fault::BadFrame)
+        }
+
+        let address = record[1..=address_len].iter().fold(0u32, |value, &byte| value << 8 | byte as u32);
+        let data = &record[1 + address_len..record.len() - 1];
+
+        match kind
+        {
+            1..=3 => self.sink.write(address, data)?,
+            7..=9 =>
+            {
+                self.entry = Some(address);
+                self.done = true;
+            },
+
+            /* headers and record counts */
+            _ => ()
+        }
+
+        Ok(())
+    }
+}
+
+impl UART
+{
+    /* receive an Intel HEX or S-record image, waiting as long as it takes for
+       each byte, and write its data to sink. returns the image's start address,
+       if it gives one. stops at the first bad record */
+    pub fn load_hex<S: Sink>(&self, sink: S) -> Result<Option<u32>, Fault>
+    {
+        let mut loader = Loader::new(sink);
+        loop
+        {
+            if loader.push(self.read_byte_blocking())?
+            {
+                return Ok(loader.entry())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    type Chunks = Vec<(u32, Vec<u8>)>;
+
+    fn load(image: &[u8]) -> (Chunks, Result<Option<u32>, Fault>)
+    {
+        let (_, uart) = Script::uart(image);
+        let mut chunks = vec![];
+        let entry = uart.load_hex(|address, data: &[u8]| { chunks.push((address, data.to_vec())); Ok(()) });
+        (chunks, entry)
+    }
+
+    #[test]
+    fn loads_intel_hex()
+    {
+        let (chunks, entry) = load(b":020000040800F2\r\n:0400100001020304E2\r\n:0400000508000101ED\r\n:00000001FF\r\n");
+        assert_eq!(chunks, [(0x0800_0010, vec![1, 2, 3, 4])]);
+        assert!(matches!(entry, Ok(Some(0x0800_0101))));
+
+        /* a bad checksum */
+        let (chunks, entry) = load(b":0400100001020304E3\n");
+        assert!(chunks.is_empty());
+        assert!(matches!(entry, Err(Fault::BadFrame)));
+    }
+
+    #[test]
+    fn loads_srecords()
+    {
+        let (chunks, entry) = load(b"S00600004844521B\nS1070010DEADBEEFB0\nS5030001FB\nS9030010EC\n");
+        assert_eq!(chunks, [(0x0010, vec![0xde, 0xad, 0xbe, 0xef])]);
+        assert!(matches!(entry, Ok(Some(0x0010))));
+    }
+
+    #[test]
+    fn carries_on_after_a_bad_record()
+    {
+        let mut chunks = vec![];
+        let results: Vec<_> =
+        {
+            let mut loader = Loader::new(|address, data: &[u8]| { chunks.push((address, data.to_vec())); Ok(()) });
+            b"x\n:01000000zz\n:0100000042BD\n:00000001FF\n".iter().map(|&byte| loader.push(byte)).collect()
+        };
+        assert_eq!(results.iter().filter(|result| result.is_err()).count(), 2);
+        assert!(matches!(results.last(), Some(Ok(true))));
+        assert_eq!(chunks, [(0, vec![0x42])]);
+    }
+}
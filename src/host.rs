@@ -0,0 +1,224 @@
+/* simulate a UART on the host, bridged to a pseudo-terminal or TCP socket
+ *
+ * HostBus stands in for the controller's registers: bytes the driver
+ * transmits are written to a host stream, and bytes read from the stream
+ * are delivered through RXDATA. This lets interactive code built on the
+ * crate, such as a shell or an XMODEM transfer, be tried out from a
+ * terminal emulator or netcat without any hardware, eg:
+ *
+ *   let (bus, path) = HostBus::pty()?;
+ *   println!("connect to {}", path.display());
+ *   let uart = bus.into_uart();
+ *
+ * The simulated transmitter never fills up, and the interrupt pending
+ * register is worked out from the watermarks and the number of bytes
+ * waiting to be read. Nothing raises interrupts, so code using the
+ * buffered drivers must call handle_interrupt() itself.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use super::bus::Bus;
+use super::memory::State;
+use super::UART;
+
+/* a simulated controller connected to a host stream */
+pub struct HostBus
+{
+    state: Arc<Mutex<State>>,
+    output: Mutex<Box<dyn Write + Send>>,
+    _slave: Option<File>    /* keeps a pty usable while nothing else has it open */
+}
+
+impl HostBus
+{
+    /* transmit to output and receive from input. input is read by a background
+       thread until it reaches end of file or fails */
+    pub fn new<R, W>(input: R, output: W) -> Self
+        where R: Read + Send + 'static, W: Write + Send + 'static
+    {
+        let state = Arc::new(Mutex::new(State::default()));
+        let receiver = state.clone();
+        std::thread::spawn(move || HostBus::receive(input, &receiver));
+
+        HostBus { state, output: Mutex::new(Box::new(output)), _slave: None }
+    }
+
+    /* connect to a TCP server, eg one started with nc -l */
+    pub fn tcp_connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self>
+    {
+        let stream = TcpStream::connect(addr)?;
+        HostBus::tcp(stream)
+    }
+
+    /* wait for a single TCP client, eg nc or telnet, to connect */
+    pub fn tcp_accept<A: ToSocketAddrs>(addr: A) -> io::Result<Self>
+    {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        HostBus::tcp(stream)
+    }
+
+    fn tcp(stream: TcpStream) -> io::Result<Self>
+    {
+        stream.set_nodelay(true)?;
+        Ok(HostBus::new(stream.try_clone()?, stream))
+    }
+
+    /* create a pseudo-terminal and return it with the path of its terminal
+       end, eg /dev/pts/3, for a program such as screen or picocom to open.
+       the terminal starts in raw mode so bytes pass through unaltered */
+    pub fn pty() -> io::Result<(Self, PathBuf)>
+    {
+        let master = unsafe
+        {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0
+            {
+                return Err(io::Error::last_os_error())
+            }
+            File::from_raw_fd(fd)
+        };
+
+        let path = unsafe
+        {
+            if libc::grantpt(master.as_raw_fd()) != 0 || libc::unlockpt(master.as_raw_fd()) != 0
+            {
+                return Err(io::Error::last_os_error())
+            }
+
+            let name = libc::ptsname(master.as_raw_fd());
+            if name.is_null()
+            {
+                return Err(io::Error::last_os_error())
+            }
+            PathBuf::from(std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned())
+        };
+
+        /* reading the master fails once the last user of the terminal end
+           closes it, so hold it open for as long as the bus exists */
+        let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+        unsafe
+        {
+            let mut termios = core::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0
+            {
+                return Err(io::Error::last_os_error())
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0
+            {
+                return Err(io::Error::last_os_error())
+            }
+        }
+
+        let mut bus = HostBus::new(master.try_clone()?, master);
+        bus._slave = Some(slave);
+        Ok((bus, path))
+    }
+
+    /* create a standard UART, initialized as new() does, on this bus. the bus
+       is leaked, as the UART may be used for the rest of the program */
+    pub fn into_uart(self) -> UART
+    {
+        UART::on_bus(Box::leak(Box::new(self)))
+    }
+
+    /* the background thread's loop, queuing received bytes */
+    fn receive<R: Read>(mut input: R, state: &Mutex<State>)
+    {
+        let mut buf = [0u8; 256];
+        loop
+        {
+            match input.read(&mut buf)
+            {
+                Ok(0) => break,
+                Ok(count) => state.lock().unwrap().rx.extend(&buf[..count]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => break
+            }
+        }
+    }
+}
+
+impl Bus for HostBus
+{
+    fn read(&self, reg: usize) -> u32
+    {
+        self.state.lock().unwrap().read(reg)
+    }
+
+    fn write(&self, reg: usize, val: u32)
+    {
+        let sent = self.state.lock().unwrap().write(reg, val);
+        if let Some(byte) = sent
+        {
+            /* the register interface can't report errors, so a byte for a
+               disconnected peer is dropped, as it would be on a real line */
+            let mut output = self.output.lock().unwrap();
+            let _ = output.write_all(&[byte]).and_then(|_| output.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::time::Duration;
+
+    /* poll for a received byte, giving the background thread time to deliver it */
+    fn receive(uart: &UART) -> u8
+    {
+        for _ in 0..1000
+        {
+            if let Ok(byte) = uart.read_byte()
+            {
+                return byte
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("nothing received");
+    }
+
+    #[test]
+    fn bridges_tcp()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uart = HostBus::tcp_connect(listener.local_addr().unwrap()).unwrap().into_uart();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        uart.send_str("hi").unwrap();
+        let mut buf = [0u8; 2];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+
+        peer.write_all(b"yo").unwrap();
+        assert_eq!(receive(&uart), b'y');
+        assert_eq!(receive(&uart), b'o');
+        assert!(uart.read_byte().is_err());
+    }
+
+    #[test]
+    fn bridges_pty()
+    {
+        let (bus, path) = HostBus::pty().unwrap();
+        let uart = bus.into_uart();
+        let mut terminal = OpenOptions::new().read(true).write(true).open(path).unwrap();
+
+        terminal.write_all(b"\r").unwrap();
+        assert_eq!(receive(&uart), b'\r');
+
+        uart.send_byte(b'\n').unwrap();
+        let mut buf = [0u8; 1];
+        terminal.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"\n");
+    }
+}
@@ -0,0 +1,149 @@
+/* frame boundaries marked by silence on the line
+ *
+ * Some protocols, such as Modbus RTU, have no delimiters: a frame ends
+ * when the line has been idle for a few character times. IdleDetector
+ * measures the gaps between bytes taken from a split UART's receive half
+ * against a caller-provided clock, and reports a gap longer than the one
+ * it was given as an Idle event after the bytes before it, eg:
+ *
+ *   let mut idle = IdleDetector::new(mtime, 19200, MTIME_HZ, 4);
+ *   loop
+ *   {
+ *       match rx.poll_event(&mut idle)
+ *       {
+ *           Some(RxEvent::Data(byte)) => frame.push(byte),
+ *           Some(RxEvent::Idle) => handle_frame(&frame),
+ *           None => ()
+ *       }
+ *   }
+ *
+ * Gaps are timed from when bytes are taken from the buffers, not when they
+ * arrive, so poll at least once per character time for accurate results.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::UART;
+use super::split::Rx;
+use super::timeout::Clock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RxEvent
+{
+    Data(u8),   /* a byte was received */
+    Idle        /* the line has gone quiet since the last byte */
+}
+
+pub struct IdleDetector<C: Clock>
+{
+    clock: C,
+    gap: u64,       /* ticks of silence that end a frame */
+    last: u64,      /* when the last byte was seen */
+    active: bool    /* bytes have been seen since the last Idle */
+}
+
+impl<C: Clock> IdleDetector<C>
+{
+    /* treat chars character times of silence at baud as a frame boundary, where
+       clock runs at tick_hz. for Modbus RTU's 3.5 character times, use 4 */
+    pub fn new(clock: C, baud: u32, tick_hz: u64, chars: u32) -> Self
+    {
+        let bits = UART::BITS_PER_CHAR as u64 * chars as u64;
+        let gap = (tick_hz * bits).div_ceil(baud.max(1) as u64);
+        IdleDetector::with_gap(clock, gap)
+    }
+
+    /* treat gap ticks of silence as a frame boundary */
+    pub fn with_gap(clock: C, gap: u64) -> Self
+    {
+        IdleDetector { clock, gap, last: 0, active: false }
+    }
+
+    /* ticks of silence that end a frame */
+    pub fn gap(&self) -> u64
+    {
+        self.gap
+    }
+
+    /* forget any frame in progress, so no Idle is reported until more bytes arrive */
+    pub fn reset(&mut self)
+    {
+        self.active = false;
+    }
+
+    /* return the clock */
+    pub fn release(self) -> C
+    {
+        self.clock
+    }
+}
+
+impl<const RX: usize, const TX: usize> Rx<'_, RX, TX>
+{
+    /* take the next received byte, or report that the line has gone idle after
+       the last one. returns None if nothing has happened. Idle is reported once
+       per gap, and only after at least one byte */
+    pub fn poll_event<C: Clock>(&mut self, idle: &mut IdleDetector<C>) -> Option<RxEvent>
+    {
+        let now = idle.clock.now();
+        if let Some(byte) = self.read_byte()
+        {
+            idle.last = now;
+            idle.active = true;
+            return Some(RxEvent::Data(byte))
+        }
+
+        match idle.active && now.wrapping_sub(idle.last) >= idle.gap
+        {
+            true =>
+            {
+                idle.active = false;
+                Some(RxEvent::Idle)
+            },
+            false => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use core::cell::Cell;
+    use crate::buffered::Buffers;
+    use crate::split::on_uart_interrupt;
+    use crate::tests::Script;
+
+    #[test]
+    fn gaps_delimit_frames()
+    {
+        assert_eq!(IdleDetector::new(|| 0, 9600, 1_000_000, 4).gap(), 4167);
+
+        let (script, uart) = Script::uart(b"ab");
+        let buffers: Buffers<8, 8> = Buffers::new();
+        let (_, mut rx) = uart.split(&buffers).unwrap();
+        let time = Cell::new(0);
+        let mut idle = IdleDetector::with_gap(|| time.get(), 10);
+
+        assert_eq!(rx.poll_event(&mut idle), None);
+        on_uart_interrupt(&uart, &buffers);
+        assert_eq!(rx.poll_event(&mut idle), Some(RxEvent::Data(b'a')));
+        time.set(9);
+        assert_eq!(rx.poll_event(&mut idle), Some(RxEvent::Data(b'b')));
+        time.set(18);
+        assert_eq!(rx.poll_event(&mut idle), None);
+        time.set(19);
+        assert_eq!(rx.poll_event(&mut idle), Some(RxEvent::Idle));
+        time.set(100);
+        assert_eq!(rx.poll_event(&mut idle), None);
+
+        script.receive(b"c");
+        on_uart_interrupt(&uart, &buffers);
+        assert_eq!(rx.poll_event(&mut idle), Some(RxEvent::Data(b'c')));
+        idle.reset();
+        time.set(200);
+        assert_eq!(rx.poll_event(&mut idle), None);
+    }
+}
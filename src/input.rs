@@ -0,0 +1,124 @@
+/* reading runs of received bytes
+ *
+ * Most of these take whatever the rx FIFO holds at the time, without waiting
+ * for more to arrive, for event loops that poll the UART among other work.
+ * bytes() instead iterates over bytes as they arrive, eg:
+ *   for byte in uart.bytes() { ... }
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::mem::MaybeUninit;
+use super::{UART, Fault};
+use super::timeout::{Timeout, Forever};
+
+/* an iterator over received bytes, waiting for each as long as its timeout allows */
+pub struct Bytes<'a, T: Timeout>
+{
+    uart: &'a UART,
+    timeout: T
+}
+
+impl<T: Timeout> Iterator for Bytes<'_, T>
+{
+    type Item = Result<u8, Fault>;
+
+    /* never ends: a byte that doesn't arrive in time is returned as an error */
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        Some(self.uart.read_byte_timeout(&mut self.timeout))
+    }
+}
+
+impl UART
+{
+    /* iterate over received bytes, waiting as long as it takes for each */
+    pub fn bytes(&self) -> Bytes<'_, Forever>
+    {
+        self.bytes_timeout(Forever)
+    }
+
+    /* iterate over received bytes, waiting until timeout expires for each,
+       eg bytes_timeout(Polls::new(1000)). a byte that times out is returned
+       as DataNotReady */
+    pub fn bytes_timeout<T: Timeout>(&self, timeout: T) -> Bytes<'_, T>
+    {
+        Bytes { uart: self, timeout }
+    }
+
+    /* move bytes waiting in the rx FIFO into buf, stopping when either runs
+       out, and return the bytes read. buf needn't be initialized first */
+    pub fn read_into_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>]) -> &'b [u8]
+    {
+        let mut count = 0;
+        for slot in buf.iter_mut()
+        {
+            match self.read_byte()
+            {
+                Ok(byte) => slot.write(byte),
+                Err(_) => break
+            };
+            count += 1;
+        }
+
+        /* the first count bytes have been written */
+        unsafe { core::slice::from_raw_parts(buf.as_ptr() as *const u8, count) }
+    }
+
+    /* copy bytes waiting in the rx FIFO into buf, returning how many. returns
+       immediately, with zero if nothing's waiting */
+    pub fn read_available(&self, buf: &mut [u8]) -> usize
+    {
+        /* only initialized bytes are written, so treating buf as uninit is sound */
+        let uninit = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        self.read_into_uninit(uninit).len()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+
+    #[test]
+    fn reads_into_uninitialized_memory()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut buf = [MaybeUninit::uninit(); 4];
+        assert_eq!(uart.read_into_uninit(&mut buf), b"");
+
+        regs.receive(b'u');
+        assert_eq!(uart.read_into_uninit(&mut buf), b"uuuu");
+    }
+
+    #[test]
+    fn iterates_over_received_bytes()
+    {
+        use crate::Polls;
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        assert!(matches!(uart.bytes_timeout(Polls::new(2)).next(), Some(Err(Fault::DataNotReady))));
+
+        regs.receive(b'i');
+        let received: Result<Vec<u8>, Fault> = uart.bytes().take(3).collect();
+        assert_eq!(received.unwrap(), b"iii");
+    }
+
+    #[test]
+    fn reads_only_what_is_waiting()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut buf = [0u8; 3];
+        assert_eq!(uart.read_available(&mut buf), 0);
+
+        regs.receive(b'a');
+        assert_eq!(uart.read_available(&mut buf), 3);
+        assert_eq!(&buf, b"aaa");
+    }
+}
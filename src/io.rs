@@ -0,0 +1,167 @@
+/* blocking reads and writes through the embedded-io traits
+ *
+ * With the embedded-io feature, &UART implements embedded_io's Read, Write,
+ * ReadReady and WriteReady, as std implements Read and Write for &File, so a
+ * UART shared as a static can be used too. Protocol code written against
+ * those traits, rather than UART, can then be tested on the host by handing
+ * it a mock port instead, eg from embedded-hal-mock, which checks the bytes
+ * written and supplies the bytes read:
+ *
+ *   fn handshake<P: Read + Write>(port: &mut P) -> Result<bool, P::Error>
+ *
+ *   handshake(&mut &uart)?;             // on the hardware
+ *   handshake(&mut mock)?;              // in a test, with the same code
+ *
+ * examples/substitute.rs shows the pattern in full. Reads and writes wait as
+ * long as it takes for the first byte, as the traits require, using the
+ * UART's yield and watchdog hooks, then move as many more as are ready.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+use super::{UART, Fault, REG_TXDATA};
+
+impl embedded_io::Error for Fault
+{
+    fn kind(&self) -> ErrorKind
+    {
+        match self
+        {
+            Fault::TxNotEmpty | Fault::DataNotReady | Fault::TimedOut => ErrorKind::TimedOut,
+            Fault::FrameTooLong => ErrorKind::OutOfMemory,
+            Fault::BadEscape | Fault::BadFrame | Fault::BadDeviceTree => ErrorKind::InvalidData,
+            Fault::SizeTooSmall | Fault::BadAddress | Fault::OutOfRange => ErrorKind::InvalidInput,
+            Fault::NoSuchDevice => ErrorKind::NotFound,
+            Fault::AlreadyClaimed => ErrorKind::AddrInUse,
+            Fault::Cancelled => ErrorKind::Interrupted,
+            _ => ErrorKind::Other
+        }
+    }
+}
+
+impl ErrorType for &UART
+{
+    type Error = Fault;
+}
+
+impl Read for &UART
+{
+    /* wait for a byte, then take any others already in the rx FIFO */
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        match buf.split_first_mut()
+        {
+            Some((first, rest)) =>
+            {
+                *first = self.read_byte_blocking();
+                Ok(1 + self.read_available(rest))
+            },
+            None => Ok(0)
+        }
+    }
+}
+
+impl ReadReady for &UART
+{
+    fn read_ready(&mut self) -> Result<bool, Fault>
+    {
+        Ok(self.is_rx_ready())
+    }
+}
+
+impl Write for &UART
+{
+    /* wait for room for a byte, then queue as many more as fit in the tx FIFO */
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Fault>
+    {
+        let (first, rest) = match buf.split_first()
+        {
+            Some(split) => split,
+            None => return Ok(0)
+        };
+
+        self.send_byte_blocking(*first);
+        let mut sent = 1;
+        for byte in rest
+        {
+            if self.is_tx_full()
+            {
+                break
+            }
+
+            self.write_reg(REG_TXDATA, *byte as u32);
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /* wait for the tx FIFO to empty */
+    fn flush(&mut self) -> Result<(), Fault>
+    {
+        while !self.is_tx_idle()
+        {
+            self.relax();
+        }
+
+        Ok(())
+    }
+}
+
+impl WriteReady for &UART
+{
+    fn write_ready(&mut self) -> Result<bool, Fault>
+    {
+        Ok(!self.is_tx_full())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Fifos;
+
+    #[test]
+    fn moves_what_fits()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let mut port = &uart;
+        assert!(matches!(port.write(b"0123456789"), Ok(8)));
+        assert!(matches!(port.write_ready(), Ok(false)));
+        fifos.shift(3);
+        assert!(matches!(port.write(b"89ab"), Ok(3)));
+        assert_eq!(fifos.sent(), b"012");
+
+        fifos.arrive(b"hi");
+        fifos.shift(2);
+        assert!(matches!(port.read_ready(), Ok(true)));
+        let mut buf = [0; 4];
+        assert!(matches!(port.read(&mut buf), Ok(2)));
+        assert_eq!(&buf[..2], b"hi");
+        assert!(matches!(port.read(&mut []), Ok(0)));
+    }
+
+    /* protocol code written against the traits runs unchanged on the UART */
+    #[test]
+    fn drives_generic_code()
+    {
+        fn ask<P: Read + Write>(port: &mut P, question: &[u8]) -> Result<u8, P::Error>
+        {
+            port.write_all(question)?;
+            port.flush()?;
+            let mut answer = [0];
+            port.read(&mut answer)?;
+            Ok(answer[0])
+        }
+
+        let (fifos, uart) = Fifos::uart();
+        fifos.arrive(b"y");
+        fifos.set_pace(4);
+        assert!(matches!(ask(&mut &uart, b"ok?"), Ok(b'y')));
+        assert_eq!(fifos.sent(), b"ok?");
+    }
+}
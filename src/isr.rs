@@ -0,0 +1,134 @@
+/* the part of the driver that's safe to use in interrupt handlers
+ *
+ * Interrupt handlers mustn't wait on the hardware: most of UART's methods
+ * poll until the controller is ready, up to LOOP_MAX times or forever, and
+ * may call yield and watchdog hooks that expect thread context. IsrHandle
+ * offers only the methods that never wait, each doing a small fixed number
+ * of register accesses, so code holding one can't block by mistake, eg:
+ *
+ *   fn uart_irq()
+ *   {
+ *       let isr = UART.isr_handle();
+ *       isr.service(&BUFFERS);
+ *   }
+ *
+ * The cost of each method is given in register accesses alongside it.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, IrqPending, REG_TXDATA, REG_IE, REG_IE_TXWM, REG_IE_RXWM};
+use super::buffered::Buffers;
+
+/* bounded-time access to a UART */
+#[derive(Clone, Copy)]
+pub struct IsrHandle<'a>
+{
+    uart: &'a UART
+}
+
+impl UART
+{
+    /* return a handle offering only what's safe in an interrupt handler */
+    pub fn isr_handle(&self) -> IsrHandle<'_>
+    {
+        IsrHandle { uart: self }
+    }
+}
+
+impl IsrHandle<'_>
+{
+    /* send byte if there's room in the tx FIFO, returning false if there isn't.
+       two accesses */
+    pub fn try_send_byte(&self, byte: u8) -> bool
+    {
+        match self.uart.is_tx_full()
+        {
+            true => false,
+            false =>
+            {
+                self.uart.write_reg(REG_TXDATA, byte as u32);
+                true
+            }
+        }
+    }
+
+    /* take a received byte, if there is one. one access */
+    pub fn try_read_byte(&self) -> Option<u8>
+    {
+        self.uart.take_rx_byte()
+    }
+
+    /* return which interrupts are pending. one access */
+    pub fn ip(&self) -> IrqPending
+    {
+        self.uart.ip()
+    }
+
+    /* return which interrupts are enabled. one access */
+    pub fn ie(&self) -> IrqPending
+    {
+        let ie = self.uart.read_reg(REG_IE);
+        IrqPending { txwm: ie & REG_IE_TXWM != 0, rxwm: ie & REG_IE_RXWM != 0 }
+    }
+
+    /* enable exactly the interrupts in irqs, eg IrqPending::NONE to mask them all.
+       one access */
+    pub fn set_ie(&self, irqs: IrqPending)
+    {
+        let mut ie = 0;
+        if irqs.txwm
+        {
+            ie |= REG_IE_TXWM;
+        }
+        if irqs.rxwm
+        {
+            ie |= REG_IE_RXWM;
+        }
+        self.uart.write_reg(REG_IE, ie);
+    }
+
+    /* move bytes between the controller and buffers, as UART::handle_interrupt().
+       at most about four accesses per byte of hardware FIFO depth */
+    pub fn service<const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>)
+    {
+        self.uart.handle_interrupt(buffers);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::{REG_TXDATA_FULL, REG_RXCTRL, REG_RXDATA, REG_RXDATA_EMPTY};
+
+    #[test]
+    fn never_waits()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let isr = uart.isr_handle();
+
+        assert!(isr.try_send_byte(b'i'));
+        assert_eq!(regs.get(REG_TXDATA), b'i' as u32);
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+        assert!(!isr.try_send_byte(b'j'));
+
+        assert_eq!(isr.try_read_byte(), None);
+        regs.receive(b'r');
+        assert_eq!(isr.try_read_byte(), Some(b'r'));
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+
+        /* finding nothing with the receiver off isn't misuse here, even when strict */
+        regs.set(REG_RXCTRL, 0);
+        assert_eq!(isr.try_read_byte(), None);
+
+        isr.set_ie(IrqPending::RXWM);
+        assert_eq!(isr.ie(), IrqPending::RXWM);
+        isr.set_ie(IrqPending::NONE);
+        assert_eq!(regs.get(REG_IE), 0);
+    }
+}
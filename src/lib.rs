@@ -15,10 +15,93 @@
  */
 
 /* we're on our own here */
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![allow(dead_code)]
+#![allow(clippy::erasing_op, clippy::identity_op)] /* keep register offsets readable */
+#![allow(clippy::missing_safety_doc)] /* safety requirements are spelled out in each function's comments */
 
 use core::ptr::{write_volatile, read_volatile};
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use serial::SerialController;
+pub use variant::{Variant, Quirks};
+pub use watermark::{WatermarkLevel, FIFO_DEPTH};
+pub use bus::Bus;
+pub use describe::Config;
+pub use timeout::{Timeout, Polls, Forever, Clock, Ticks, Deadline};
+
+pub mod serial;
+pub mod console;
+pub mod bus;
+pub mod early;
+mod ring;
+pub mod waker;
+pub mod buffered;
+pub mod stats;
+pub mod asynch;
+pub mod split;
+pub mod idle;
+pub mod autobaud;
+pub mod poll;
+pub mod isr;
+pub mod mask;
+pub mod deferred;
+pub mod handler;
+pub mod rtos;
+pub mod variant;
+pub mod watermark;
+pub mod tuning;
+pub mod math;
+pub mod checked;
+pub mod strict;
+pub mod timeout;
+pub mod output;
+pub mod printf;
+pub mod log;
+pub mod telemetry;
+pub mod pager;
+pub mod input;
+pub mod utf8;
+pub mod edit;
+pub mod readline;
+pub mod discipline;
+pub mod ansi;
+pub mod attention;
+pub mod describe;
+pub mod slip;
+pub mod cobs;
+pub mod mux;
+pub mod crc;
+pub mod xmodem;
+pub mod hexfile;
+pub mod download;
+pub mod gdb;
+pub mod packet;
+pub mod dump;
+pub mod soc;
+pub mod board;
+pub mod registry;
+pub mod guest;
+pub mod replay;
+#[cfg(feature = "fdt")]
+pub mod fdt;
+#[cfg(feature = "embedded-io")]
+pub mod io;
+#[cfg(feature = "embassy")]
+pub mod embassy;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "shell")]
+pub mod shell;
+#[cfg(feature = "plic")]
+pub mod plic;
+#[cfg(any(test, feature = "std"))]
+pub mod memory;
+#[cfg(all(feature = "std", unix))]
+pub mod mapped;
+#[cfg(all(feature = "std", unix))]
+pub mod host;
 
 const REG_TOTAL_SIZE: usize = 7 * 4; /* 7 x 32-bit registers */
 
@@ -34,13 +117,27 @@ const REG_DIV:    usize = 6 * 4; /* baud rate divisor */
 /* individual control bits */
 const REG_IE_TXWM:      u32 = 1 << 0;  /* transmit watermark interrupt enable */
 const REG_IE_RXWM:      u32 = 1 << 1;  /* receive watermark interrupt enable */
+const REG_IP_TXWM:      u32 = 1 << 0;  /* transmit watermark interrupt pending */
+const REG_IP_RXWM:      u32 = 1 << 1;  /* receive watermark interrupt pending */
 const REG_TXCTRL_TXEN:  u32 = 1 << 0;  /* transmit enable */
-const REG_TXCTRL_TXCNT: u32 = 1 << 16; /* tx FIFO irq watermark level of 1 */
+const REG_TXCTRL_NSTOP: u32 = 1 << 1;  /* two stop bits rather than one */
+const REG_TXCTRL_TXCNT_SHIFT: u32 = 16; /* position of tx FIFO irq watermark level */
 const REG_RXCTRL_RXEN:  u32 = 1 << 0;  /* receive enable */
-const REG_RXCTRL_RXCNT: u32 = 6 << 16; /* rx FIFO irq watermark level of 6 */
+const REG_RXCTRL_RXCNT_SHIFT: u32 = 16; /* position of rx FIFO irq watermark level */
+const REG_CNT_FIELD:    u32 = 0x7;     /* txcnt and rxcnt field, before shifting */
 const REG_TXDATA_FULL:  u32 = 1 << 31;
 const REG_RXDATA_EMPTY: u32 = 1 << 31;
 
+/* bits that are implemented in each register. the rest are reserved and read as zero */
+const REG_TXCTRL_MASK:  u32 = 0x0007_0003; /* txcnt, nstop, txen */
+const REG_RXCTRL_MASK:  u32 = 0x0007_0001; /* rxcnt, rxen */
+const REG_IE_MASK:      u32 = REG_IE_TXWM | REG_IE_RXWM;
+const REG_DIV_MASK:     u32 = 0x0000_ffff;
+
+/* FIFO irq watermark levels set up by new() */
+const DEFAULT_TX_WATERMARK: WatermarkLevel = WatermarkLevel::L1;
+const DEFAULT_RX_WATERMARK: WatermarkLevel = WatermarkLevel::L6;
+
 /* to avoid infinite loops, give up checking
    for a byte to arrive or for a byte to be
    transmitted after this many check iterations */
@@ -52,45 +149,179 @@ pub enum Fault
 {
     SizeTooSmall,   /* the size of the MMIO area is unexpectedly small */
     TxNotEmpty,     /* gave up waiting to transmit */
-    DataNotReady    /* gave up waiting to send */
+    DataNotReady,   /* gave up waiting to send */
+    FrameTooLong,   /* received frame doesn't fit in the supplied buffer */
+    BadEscape,      /* received frame contains an invalid escape sequence */
+    BadFrame,       /* received frame is malformed or truncated */
+    Cancelled,      /* the other side aborted the transfer */
+    TooManyErrors,  /* gave up after repeated timeouts or corrupted data */
+    BadDeviceTree,  /* device tree blob is malformed or describes an unusable UART */
+    RegistryFull,   /* no room left to declare another UART */
+    NoSuchDevice,   /* no UART has been declared at that index */
+    AlreadyClaimed, /* that UART is already in use */
+    BadAddress,     /* physical address can't be reached by this CPU */
+    TimedOut,       /* nothing arrived within the time allowed */
+    OutOfRange      /* value doesn't fit the register or isn't supported by the controller */
+}
+
+/* interrupts the controller is raising, as reported by its IP register */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IrqPending
+{
+    pub txwm: bool, /* fewer bytes than the tx watermark are waiting to be sent */
+    pub rxwm: bool  /* more bytes than the rx watermark are waiting to be read */
+}
+
+impl IrqPending
+{
+    pub const NONE: IrqPending = IrqPending { txwm: false, rxwm: false };
+    pub const TXWM: IrqPending = IrqPending { txwm: true, rxwm: false };
+    pub const RXWM: IrqPending = IrqPending { txwm: false, rxwm: true };
+    pub const BOTH: IrqPending = IrqPending { txwm: true, rxwm: true };
+
+    /* return true if any interrupt is pending */
+    pub fn any(&self) -> bool
+    {
+        self.txwm || self.rxwm
+    }
+}
+
+/* controller configuration captured by suspend() for resume() to restore */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedState
+{
+    txctrl: u32,
+    rxctrl: u32,
+    ie: u32,
+    div: u32
 }
 
-#[derive(Debug)]
 pub struct UART
 {
-    base_addr: usize
+    base_addr: usize,
+    variant: Variant,
+    bus: Option<&'static dyn Bus>,      /* reach the registers through this rather than MMIO */
+    yield_hook: Option<fn()>,           /* called while busy-waiting on the hardware */
+    watchdog: Option<(fn(), usize)>,    /* hook called every so many steps of blocking operations */
+    steps: AtomicUsize                  /* blocking steps since the watchdog hook was last called */
 }
 
 impl UART
 {
+    /* entries in each of the tx and rx FIFOs of SiFive's controller. a variant
+       may have fewer, see fifo_depth() */
+    pub const FIFO_DEPTH: u32 = FIFO_DEPTH;
+
+    /* the largest value the baud rate divisor register holds, giving the slowest rate */
+    pub const MAX_DIVISOR: u32 = REG_DIV_MASK;
+
+    /* the controller's 32-bit registers, from TXDATA to DIV */
+    pub const REGISTER_COUNT: usize = REG_TOTAL_SIZE / 4;
+
+    /* bits on the line per byte sent as 8-n-1: a start bit, eight data bits and a stop bit */
+    pub const BITS_PER_CHAR: u32 = 10;
+
     /* create and initialize a standard 8-n-1 UART object, or fail with a reason code.
     this used the previously configured baud rate, which is derived from the
     CPU core speed. the baud should be set separately */
     pub fn new(base_addr: usize, size: usize) -> Result<Self, Fault>
+    {
+        UART::new_with_variant(base_addr, size, Variant::SIFIVE)
+    }
+
+    /* like new() but for a controller that clones SiFive's register layout with
+       the given deviations, eg Variant::K210_UARTHS */
+    pub fn new_with_variant(base_addr: usize, size: usize, variant: Variant) -> Result<Self, Fault>
     {
         /* give up if the available MMIO area is smaller than the register area we need */
         if REG_TOTAL_SIZE > size { return Err(Fault::SizeTooSmall) }
 
-        let uart = UART { base_addr };
+        let uart = UART::attach_with_variant(base_addr, variant);
+        uart.init();
+        Ok(uart)
+    }
 
+    /* set up the controller for standard 8-n-1 operation */
+    fn init(&self)
+    {
         /* enable transmission, one stop bit, set tx irq watermark.
            when the number of bytes to transmit drops below the
            watermark, raise an irq (if enabled) */
-        uart.write_reg(REG_TXCTRL, REG_TXCTRL_TXCNT | REG_TXCTRL_TXEN);
+        let txcnt = self.variant.clamp_watermark(DEFAULT_TX_WATERMARK).get() << REG_TXCTRL_TXCNT_SHIFT;
+        self.write_reg(REG_TXCTRL, txcnt | REG_TXCTRL_TXEN);
 
         /* enable receive, set rx irq watermark.
            when the number of received bytes goes above the
            watermark, raise an irq (if enabled) */
-        uart.write_reg(REG_RXCTRL, REG_RXCTRL_RXCNT | REG_RXCTRL_RXEN);
+        let rxcnt = self.variant.clamp_watermark(DEFAULT_RX_WATERMARK).get() << REG_RXCTRL_RXCNT_SHIFT;
+        self.write_reg(REG_RXCTRL, rxcnt | REG_RXCTRL_RXEN);
+    }
 
-        Ok(uart)
+    /* create a UART object for the controller at base_addr without touching its
+       registers, keeping whatever configuration the firmware or an earlier boot
+       stage left, such as OpenSBI's or U-Boot's console setup. new() rewrites
+       the control registers, which can glitch a character being sent */
+    pub const fn attach(base_addr: usize) -> Self
+    {
+        UART::attach_with_variant(base_addr, Variant::SIFIVE)
+    }
+
+    /* like attach() but for a controller that clones SiFive's register layout */
+    pub const fn attach_with_variant(base_addr: usize, variant: Variant) -> Self
+    {
+        UART { base_addr, variant, bus: None, yield_hook: None, watchdog: None, steps: AtomicUsize::new(0) }
+    }
+
+    /* like new() but takes a 64-bit physical address and size, as found in device
+       trees and firmware tables, even on 32-bit cores. fails with BadAddress
+       if the registers can't be reached through a usize address, in which case the
+       caller must map them somewhere that can and use new() with that address */
+    pub fn from_phys(base_addr: u64, size: u64) -> Result<Self, Fault>
+    {
+        let end = base_addr.checked_add(REG_TOTAL_SIZE as u64).ok_or(Fault::BadAddress)?;
+        if end - 1 > usize::MAX as u64
+        {
+            return Err(Fault::BadAddress)
+        }
+
+        /* a size too big for usize is certainly big enough */
+        let size = usize::try_from(size).unwrap_or(usize::MAX);
+        UART::new(base_addr as usize, size)
+    }
+
+    /* return true if there appears to be a SiFive UART at base_addr. this checks
+       the reserved bits of the control registers read as zero, and that only
+       the implemented bits of the divisor register can be written. the divisor
+       is restored afterwards. it's a sanity check, not proof: only use it on
+       addresses where something benign is expected to be mapped */
+    pub fn probe(base_addr: usize) -> bool
+    {
+        let uart = UART::attach(base_addr);
+
+        let reserved_clear =
+            uart.read_reg(REG_TXCTRL) & !REG_TXCTRL_MASK == 0 &&
+            uart.read_reg(REG_RXCTRL) & !REG_RXCTRL_MASK == 0 &&
+            uart.read_reg(REG_IE) & !REG_IE_MASK == 0 &&
+            uart.read_reg(REG_IP) & !REG_IE_MASK == 0;
+
+        if !reserved_clear
+        {
+            return false
+        }
+
+        let div = uart.read_reg(REG_DIV);
+        uart.write_reg(REG_DIV, !0);
+        let readback = uart.read_reg(REG_DIV);
+        uart.write_reg(REG_DIV, div);
+
+        readback == REG_DIV_MASK
     }
 
     /* enable or disable the tx watermark irqs */
     pub fn enable_tx_watermark_irq(&self, enable: bool)
     {
         let flags = self.read_reg(REG_IE);
-        if enable == true
+        if enable
         {
             self.write_reg(REG_IE, flags | REG_IE_TXWM);
         }
@@ -104,7 +335,7 @@ impl UART
     pub fn enable_rx_watermark_irq(&self, enable: bool)
     {
         let flags = self.read_reg(REG_IE);
-        if enable == true
+        if enable
         {
             self.write_reg(REG_IE, flags | REG_IE_RXWM);
         }
@@ -114,11 +345,90 @@ impl UART
         }
     }
 
+    /* set the tx FIFO irq watermark. when enabled, the tx watermark irq is raised
+       while fewer than level bytes are waiting to be transmitted. level is limited
+       to what the controller supports */
+    pub fn set_tx_watermark(&self, level: WatermarkLevel)
+    {
+        strict::check(level <= self.variant.watermark_max(), "tx watermark out of range");
+        let level = self.variant.clamp_watermark(level);
+        let ctrl = self.read_reg(REG_TXCTRL);
+        self.write_reg(REG_TXCTRL, math::with_watermark(ctrl, REG_TXCTRL_TXCNT_SHIFT, level.get()));
+    }
+
+    /* set the rx FIFO irq watermark. when enabled, the rx watermark irq is raised
+       while more than level bytes are waiting to be read. level is limited to
+       what the controller supports */
+    pub fn set_rx_watermark(&self, level: WatermarkLevel)
+    {
+        strict::check(level <= self.variant.watermark_max(), "rx watermark out of range");
+        let level = self.variant.clamp_watermark(level);
+        let ctrl = self.read_reg(REG_RXCTRL);
+        self.write_reg(REG_RXCTRL, math::with_watermark(ctrl, REG_RXCTRL_RXCNT_SHIFT, level.get()));
+    }
+
+    /* return the tx FIFO irq watermark level */
+    pub fn tx_watermark(&self) -> WatermarkLevel
+    {
+        WatermarkLevel::saturating(math::watermark(self.read_reg(REG_TXCTRL), REG_TXCTRL_TXCNT_SHIFT))
+    }
+
+    /* return the rx FIFO irq watermark level */
+    pub fn rx_watermark(&self) -> WatermarkLevel
+    {
+        WatermarkLevel::saturating(math::watermark(self.read_reg(REG_RXCTRL), REG_RXCTRL_RXCNT_SHIFT))
+    }
+
     /* set the divisor for the required baud given the bus frequency.
-       baud and bus_freq are both in Hz */
+       baud and bus_freq are both in Hz. a baud rate too slow for the
+       divisor register, or zero, gets the slowest rate possible, and one
+       faster than the bus clock allows gets the fastest */
     pub fn set_baud(&self, baud: u32, bus_freq: u32)
     {
-        self.write_reg(REG_DIV, bus_freq / baud);
+        let divisor = math::clamped_divisor(baud, bus_freq, self.variant.divisor_offset);
+        self.check_tx_quiet();
+        self.write_reg(REG_DIV, divisor);
+    }
+
+    /* like set_baud() but given the frequency of the UART clock's source, eg
+       coreclk on the FU540, allowing for the HALF_RATE_CLOCK quirk */
+    pub fn set_baud_from_source(&self, baud: u32, source_freq: u32)
+    {
+        let bus_freq = match self.variant.quirks.contains(Quirks::HALF_RATE_CLOCK)
+        {
+            true => source_freq / 2,
+            false => source_freq
+        };
+        self.set_baud(baud, bus_freq);
+    }
+
+    /* return the address this object uses to reach the controller's registers */
+    pub fn base_addr(&self) -> usize
+    {
+        self.base_addr
+    }
+
+    /* point this object at a new mapping of the same controller, eg after the MMU
+       is enabled and the registers move from their physical to a virtual address.
+       nothing is written to the controller, so the console carries on as it was.
+       unsafe because the caller must ensure new_base maps the same registers and
+       that nothing is still using the old mapping */
+    pub unsafe fn set_base(&mut self, new_base: usize)
+    {
+        self.base_addr = new_base;
+    }
+
+    /* like set_base() but consumes this object and returns the rebased one */
+    pub unsafe fn rebind(mut self, new_base: usize) -> Self
+    {
+        self.set_base(new_base);
+        self
+    }
+
+    /* return the variant of the controller this object drives */
+    pub fn variant(&self) -> Variant
+    {
+        self.variant
     }
 
     /* return size of this controller's MMIO space in bytes */
@@ -127,69 +437,872 @@ impl UART
         REG_TOTAL_SIZE
     }
 
+    /* return the number of entries in each of this controller's FIFOs */
+    pub fn fifo_depth(&self) -> u32
+    {
+        self.variant.fifo_depth
+    }
+
+    /* return the largest divisor this controller can be set to */
+    pub fn max_divisor(&self) -> u32
+    {
+        UART::MAX_DIVISOR
+    }
+
+    /* return the number of 32-bit registers this controller has */
+    pub fn register_count(&self) -> usize
+    {
+        UART::REGISTER_COUNT
+    }
+
+    /* return the slowest baud rate this controller can run at from bus_freq in Hz */
+    pub fn min_baud(&self, bus_freq: u32) -> u32
+    {
+        math::baud_from_divisor(UART::MAX_DIVISOR, bus_freq, self.variant.divisor_offset).unwrap_or(0)
+    }
+
     /* centralize reading and writing of registers to these unsafe functions */
     fn write_reg(&self, reg: usize, val: u32)
     {
-        /* assumes reg is within MMIO area's range */
-        unsafe { write_volatile((self.base_addr + reg) as *mut u32, val) }
+        match self.bus
+        {
+            Some(bus) => bus.write(reg, val),
+
+            /* assumes reg is within MMIO area's range */
+            None => unsafe { write_volatile((self.base_addr + reg) as *mut u32, val) }
+        }
     }
 
     fn read_reg(&self, reg: usize) -> u32
     {
-        /* assumes reg is within MMIO area's range */
-        unsafe { read_volatile((self.base_addr + reg) as *const u32) }
+        match self.bus
+        {
+            Some(bus) => bus.read(reg),
+
+            /* assumes reg is within MMIO area's range */
+            None => unsafe { read_volatile((self.base_addr + reg) as *const u32) }
+        }
     }
 
+    /* send a byte, checking up to LOOP_MAX times for room in the tx FIFO */
     pub fn send_byte(&self, to_send: u8) -> Result<(), Fault>
     {
+        self.send_byte_timeout(to_send, &mut Polls::new(LOOP_MAX))
+    }
 
-        let mut result = Err(Fault::TxNotEmpty);
-        let mut attempts_remaining = LOOP_MAX;
+    pub fn read_byte(&self) -> Result<u8, Fault>
+    {
+        match self.take_rx_byte()
+        {
+            Some(byte) => Ok(byte),
+            None =>
+            {
+                self.check_rx_enabled();
+                Err(Fault::DataNotReady)
+            }
+        }
+    }
 
-        while attempts_remaining > 0
+    /* take a received byte, if there is one, in a single access and without
+       the strict feature's checks, for interrupt handlers */
+    pub(crate) fn take_rx_byte(&self) -> Option<u8>
+    {
+        /* reading RXDATA dequeues the byte it returns, so only read it once */
+        let val = self.read_reg(REG_RXDATA);
+        match val & REG_RXDATA_EMPTY
         {
-            (!self.is_transmit_full()).then(||
+            0 => Some((val & 0xff) as u8),
+            _ => None
+        }
+    }
+
+    /* send a byte, waiting as long as it takes for room in the tx FIFO. for
+       callers such as panic handlers that mustn't drop output */
+    pub fn send_byte_blocking(&self, to_send: u8)
+    {
+        self.step();
+        while self.is_tx_full()
+        {
+            self.relax();
+        }
+
+        self.write_reg(REG_TXDATA, to_send as u32);
+    }
+
+    /* read a byte, waiting as long as it takes for one to arrive */
+    pub fn read_byte_blocking(&self) -> u8
+    {
+        self.step();
+        loop
+        {
+            if let Ok(byte) = self.read_byte()
             {
-                self.write_reg(REG_TXDATA, to_send as u32);
-                result = Ok(());
-                attempts_remaining = 0;
-            });
+                return byte
+            }
+
+            self.relax();
         }
+    }
 
-        result
+    /* call hook, rather than spinning, each time a blocking operation finds the
+       hardware isn't ready, so an RTOS or scheduler can run something else
+       while this UART waits. None restores spinning */
+    pub fn set_yield_hook(&mut self, hook: Option<fn()>)
+    {
+        self.yield_hook = hook;
     }
 
-    pub fn read_byte(&self) -> Result<u8, Fault>
+    /* call hook once every interval steps of blocking operations, so long transfers
+       such as large writes and XMODEM downloads can feed a hardware watchdog.
+       each byte sent or received is a step, as is each check of the hardware while
+       waiting for it. an interval of zero is treated as one. None removes the hook */
+    pub fn set_watchdog_hook(&mut self, hook: Option<fn()>, interval: usize)
+    {
+        self.watchdog = hook.map(|hook| (hook, interval.max(1)));
+        self.steps.store(0, Ordering::Relaxed);
+    }
+
+    /* count a step of a blocking operation for the watchdog hook */
+    pub(crate) fn step(&self)
     {
-        if !self.is_data_empty()
+        if let Some((hook, interval)) = self.watchdog
         {
-            return Ok((self.read_reg(REG_RXDATA) & 0xff) as u8)
+            if self.steps.fetch_add(1, Ordering::Relaxed) + 1 >= interval
+            {
+                self.steps.store(0, Ordering::Relaxed);
+                hook();
+            }
         }
+    }
 
-        Err(Fault::DataNotReady)
+    /* pause inside a busy-wait loop */
+    pub(crate) fn relax(&self)
+    {
+        self.step();
+        match self.yield_hook
+        {
+            Some(hook) => hook(),
+            None => core::hint::spin_loop()
+        }
     }
 
-    /* return true if data can't be sent */
-    fn is_transmit_full(&self) -> bool
+    /* wait for queued bytes to be sent, then disable the transmitter, receiver
+       and both interrupts, leaving the controller inert. for handing the UART
+       over to another OS: nothing more can be sent or received until it's set
+       up again. the baud rate divisor is left alone */
+    pub fn shutdown(&mut self)
     {
-        let val = self.read_reg(REG_TXDATA);
-        return val & REG_TXDATA_FULL != 0
+        let txctrl = self.read_reg(REG_TXCTRL);
+        self.write_reg(REG_IE, 0);
+
+        /* a disabled transmitter would never drain */
+        if txctrl & REG_TXCTRL_TXEN != 0
+        {
+            while !self.is_tx_idle()
+            {
+                self.relax();
+            }
+
+            /* an empty FIFO only means the last byte has moved to the shifter, and
+               clearing txen would cut it off. so give it a character time: a start
+               bit, eight data bits and the stop bits, each lasting div + offset bus
+               cycles. a register read takes at least one bus cycle */
+            let bits = match txctrl & REG_TXCTRL_NSTOP { 0 => 10, _ => 11 };
+            let div = self.read_reg(REG_DIV) & REG_DIV_MASK;
+            for _ in 0..bits * (div + self.variant.divisor_offset)
+            {
+                self.read_reg(REG_DIV);
+            }
+        }
+
+        self.write_reg(REG_TXCTRL, self.read_reg(REG_TXCTRL) & !REG_TXCTRL_TXEN);
+        self.write_reg(REG_RXCTRL, self.read_reg(REG_RXCTRL) & !REG_RXCTRL_RXEN);
     }
 
-    /* return false if data is ready to be read */
-    fn is_data_empty(&self) -> bool
+    /* put the controller back to its power-on state: transmitter, receiver and
+       interrupts disabled, watermarks and stop bits zeroed, and the rx FIFO
+       emptied. the divisor is left alone: its reset value isn't fixed but picked
+       per chip to give 115200 baud from the expected bus clock, so call set_baud()
+       afterwards. bytes already in the tx FIFO can't be discarded */
+    pub fn reset(&self)
     {
-        let val = self.read_reg(REG_RXDATA);
-        return val & REG_RXDATA_EMPTY != 0
+        self.write_reg(REG_IE, 0);
+        self.write_reg(REG_TXCTRL, 0);
+        self.write_reg(REG_RXCTRL, 0);
+
+        /* the receiver is off now, so empty the rx FIFO directly rather than
+           with read_byte(), which strict builds would take for a mistake */
+        for _ in 0..self.variant.fifo_depth
+        {
+            if self.read_reg(REG_RXDATA) & REG_RXDATA_EMPTY != 0
+            {
+                break
+            }
+        }
+    }
+
+    /* capture the controller's configuration, for when the peripheral is about
+       to be power-gated or handed to another world. the FIFOs' contents aren't
+       saved, so wait for the transmitter to drain first if that matters */
+    pub fn suspend(&self) -> SavedState
+    {
+        SavedState
+        {
+            txctrl: self.read_reg(REG_TXCTRL) & REG_TXCTRL_MASK,
+            rxctrl: self.read_reg(REG_RXCTRL) & REG_RXCTRL_MASK,
+            ie: self.read_reg(REG_IE) & REG_IE_MASK,
+            div: self.read_reg(REG_DIV) & REG_DIV_MASK
+        }
+    }
+
+    /* restore a configuration captured by suspend(). the divisor is set before
+       the transmitter and receiver are enabled, and interrupts come last */
+    pub fn resume(&self, state: SavedState)
+    {
+        self.write_reg(REG_DIV, state.div);
+        self.write_reg(REG_TXCTRL, state.txctrl);
+        self.write_reg(REG_RXCTRL, state.rxctrl);
+        self.write_reg(REG_IE, state.ie);
+    }
+
+    /* like read_byte() but check up to LOOP_MAX times for a byte to arrive */
+    pub(crate) fn wait_for_byte(&self) -> Result<u8, Fault>
+    {
+        self.read_byte_timeout(&mut Polls::new(LOOP_MAX))
+    }
+
+    /* return true if every byte has left the tx FIFO for the shifter.
+       only meaningful while the tx watermark is 1 */
+    pub(crate) fn is_tx_drained(&self) -> bool
+    {
+        self.read_reg(REG_IP) & REG_IP_TXWM != 0
+    }
+
+    /* return which interrupts are pending. this includes those that aren't
+       enabled, as the pending bits follow the FIFO levels regardless */
+    pub fn ip(&self) -> IrqPending
+    {
+        let ip = self.read_reg(REG_IP);
+        IrqPending { txwm: ip & REG_IP_TXWM != 0, rxwm: ip & REG_IP_RXWM != 0 }
+    }
+
+    /* return true if the tx FIFO is empty. the last byte taken from it may
+       still be on the wire, so allow a character's time before cutting power
+       or resetting */
+    pub fn is_tx_idle(&self) -> bool
+    {
+        self.watermark_pending(REG_TXCTRL, REG_TXCTRL_TXCNT_SHIFT, 1, REG_IP_TXWM)
+    }
+
+    /* return true if at least one received byte is waiting to be read,
+       without removing it from the rx FIFO as read_byte() would */
+    pub fn is_rx_ready(&self) -> bool
+    {
+        self.watermark_pending(REG_RXCTRL, REG_RXCTRL_RXCNT_SHIFT, 0, REG_IP_RXWM)
+    }
+
+    /* the status registers can't say how full the FIFOs are, but the watermark
+       pending bits can: txwm is pending while the tx FIFO holds fewer than txcnt
+       bytes, and rxwm while the rx FIFO holds more than rxcnt. so briefly set a
+       watermark to level, with its irq masked, and return its pending bit. the
+       watermark and irq enable are restored before returning */
+    fn watermark_pending(&self, ctrl_reg: usize, shift: u32, level: u32, bit: u32) -> bool
+    {
+        let ctrl = self.read_reg(ctrl_reg);
+        let cnt = math::with_watermark(ctrl, shift, level);
+        if ctrl == cnt
+        {
+            return self.read_reg(REG_IP) & bit != 0
+        }
+
+        /* the IE and IP registers share a layout */
+        let ie = self.read_reg(REG_IE);
+        self.write_reg(REG_IE, ie & !bit);
+        self.write_reg(ctrl_reg, cnt);
+        let pending = self.read_reg(REG_IP) & bit != 0;
+        self.write_reg(ctrl_reg, ctrl);
+        self.write_reg(REG_IE, ie);
+        pending
+    }
+
+    /* return true if the tx FIFO is full, so data can't be sent */
+    pub fn is_tx_full(&self) -> bool
+    {
+        let val = self.read_reg(REG_TXDATA);
+        val & REG_TXDATA_FULL != 0
     }
 }
 
 #[cfg(test)]
 mod tests
 {
+    use super::*;
+    use core::cell::Cell;
+
+    /* a register block in plain memory for the driver to talk to. it has
+       none of the hardware's side effects: reads return the last value
+       written, so tests set up RXDATA and inspect TXDATA directly. it starts
+       with nothing received and nothing left to send */
+    pub(crate) struct FakeRegisters
+    {
+        cells: [Cell<u32>; UART::REGISTER_COUNT]
+    }
+
+    impl FakeRegisters
+    {
+        pub(crate) fn new() -> Self
+        {
+            let regs = FakeRegisters { cells: Default::default() };
+            regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+            regs.set(REG_IP, REG_IP_TXWM);
+            regs
+        }
+
+        pub(crate) fn uart(&self) -> UART
+        {
+            UART::new(self.base(), REG_TOTAL_SIZE).unwrap()
+        }
+
+        pub(crate) fn base(&self) -> usize
+        {
+            self.cells.as_ptr() as usize
+        }
+
+        pub(crate) fn get(&self, reg: usize) -> u32
+        {
+            self.cells[reg / 4].get()
+        }
+
+        pub(crate) fn set(&self, reg: usize, val: u32)
+        {
+            self.cells[reg / 4].set(val)
+        }
+
+        /* make a byte available to read */
+        pub(crate) fn receive(&self, byte: u8)
+        {
+            self.set(REG_RXDATA, byte as u32);
+        }
+    }
+
+    /* a controller that receives a scripted sequence of bytes, one per read of
+       RXDATA, and records everything transmitted. for tests of interactive
+       code, which needs the rx FIFO to empty as it's read */
+    pub(crate) struct Script
+    {
+        input: std::sync::Mutex<std::collections::VecDeque<u8>>,
+        output: std::sync::Mutex<Vec<u8>>,
+        regs: [core::sync::atomic::AtomicU32; REG_TOTAL_SIZE / 4]
+    }
+
+    impl Script
+    {
+        /* a standard UART that will receive input. the script is leaked, as a
+           bus must live for the rest of the program */
+        pub(crate) fn uart(input: &[u8]) -> (&'static Script, UART)
+        {
+            let script: &'static Script = Box::leak(Box::new(Script
+            {
+                input: std::sync::Mutex::new(input.iter().copied().collect()),
+                output: std::sync::Mutex::new(Vec::new()),
+                regs: Default::default()
+            }));
+            (script, UART::on_bus(script))
+        }
+
+        /* queue more bytes to receive */
+        pub(crate) fn receive(&self, input: &[u8])
+        {
+            self.input.lock().unwrap().extend(input);
+        }
+
+        /* take everything transmitted so far */
+        pub(crate) fn sent(&self) -> Vec<u8>
+        {
+            core::mem::take(&mut *self.output.lock().unwrap())
+        }
+    }
+
+    impl Bus for Script
+    {
+        fn read(&self, reg: usize) -> u32
+        {
+            match reg
+            {
+                REG_TXDATA => 0,
+                REG_RXDATA => self.input.lock().unwrap().pop_front().map(|b| b as u32).unwrap_or(REG_RXDATA_EMPTY),
+                _ => self.regs[reg / 4].load(Ordering::Relaxed)
+            }
+        }
+
+        fn write(&self, reg: usize, val: u32)
+        {
+            match reg
+            {
+                REG_TXDATA => self.output.lock().unwrap().push(val as u8),
+                _ => self.regs[reg / 4].store(val, Ordering::Relaxed)
+            }
+        }
+    }
+
+    /* a controller modelled on chapter 13 of the FU540 manual, for tests that
+       depend on the hardware's limits. each direction has an 8-entry FIFO:
+       reading TXDATA returns the full flag, and a write while full is ignored;
+       each read of RXDATA dequeues a byte or returns the empty flag. txwm is
+       pending while the tx FIFO holds strictly fewer entries than txcnt, and
+       rxwm while the rx FIFO holds strictly more than rxcnt.
+
+       the line only moves when the test says so. shift() spends a character
+       time on it, sending the byte at the head of the tx FIFO and delivering
+       the next byte waiting to arrive. set_pace() makes that happen every so
+       many register accesses, as if the line ran alongside the driver, for
+       code that busy-waits on the flags. then a byte taken from the tx FIFO
+       spends that many accesses in the shifter, and is lost if txen is
+       cleared before it's out */
+    pub(crate) struct Fifos
+    {
+        state: std::sync::Mutex<FifoState>
+    }
+
+    /* entries in each of the model's FIFOs */
+    const FIFO_ENTRIES: usize = FIFO_DEPTH as usize;
+
+    #[derive(Default)]
+    struct FifoState
+    {
+        tx: std::collections::VecDeque<u8>,         /* tx FIFO, head first */
+        rx: std::collections::VecDeque<u8>,         /* rx FIFO, head first */
+        arriving: std::collections::VecDeque<u8>,   /* bytes still on the line */
+        sent: Vec<u8>,                              /* bytes gone down the line */
+        txctrl: u32,
+        rxctrl: u32,
+        ie: u32,
+        div: u32,
+        overruns: usize,    /* bytes that arrived to a full rx FIFO */
+        pace: usize,        /* register accesses per character time, or 0 if only shift() moves the line */
+        accesses: usize,    /* since the last character time */
+        shifting: usize     /* register accesses until the last byte sent is wholly on the line */
+    }
+
+    impl FifoState
+    {
+        fn shift(&mut self)
+        {
+            if self.txctrl & REG_TXCTRL_TXEN != 0
+            {
+                if let Some(byte) = self.tx.pop_front()
+                {
+                    self.sent.push(byte);
+                    self.shifting = self.pace;
+                }
+            }
+
+            /* with the receiver off, the line is ignored and the byte is lost */
+            if let Some(byte) = self.arriving.pop_front()
+            {
+                match (self.rxctrl & REG_RXCTRL_RXEN != 0, self.rx.len() < FIFO_ENTRIES)
+                {
+                    (true, true) => self.rx.push_back(byte),
+                    (true, false) => self.overruns += 1,
+                    (false, _) => ()
+                }
+            }
+        }
+
+        fn ip(&self) -> u32
+        {
+            let txcnt = (self.txctrl >> REG_TXCTRL_TXCNT_SHIFT) & REG_CNT_FIELD;
+            let rxcnt = (self.rxctrl >> REG_RXCTRL_RXCNT_SHIFT) & REG_CNT_FIELD;
+
+            let mut ip = 0;
+            if self.tx.len() < txcnt as usize
+            {
+                ip |= REG_IP_TXWM;
+            }
+            if self.rx.len() > rxcnt as usize
+            {
+                ip |= REG_IP_RXWM;
+            }
+            ip
+        }
+
+        /* count a register access, moving the line on if it's time */
+        fn access(&mut self)
+        {
+            if self.pace > 0
+            {
+                self.shifting = self.shifting.saturating_sub(1);
+                self.accesses += 1;
+                if self.accesses == self.pace
+                {
+                    self.accesses = 0;
+                    self.shift();
+                }
+            }
+        }
+    }
+
+    impl Fifos
+    {
+        /* a standard UART on an idle line. the model is leaked, as a bus must
+           live for the rest of the program */
+        pub(crate) fn uart() -> (&'static Fifos, UART)
+        {
+            let fifos: &'static Fifos = Box::leak(Box::new(Fifos { state: Default::default() }));
+            (fifos, UART::on_bus(fifos))
+        }
+
+        /* queue bytes to arrive, one per character time */
+        pub(crate) fn arrive(&self, input: &[u8])
+        {
+            self.state.lock().unwrap().arriving.extend(input);
+        }
+
+        /* let count character times pass on the line */
+        pub(crate) fn shift(&self, count: usize)
+        {
+            let mut state = self.state.lock().unwrap();
+            for _ in 0..count
+            {
+                state.shift();
+            }
+        }
+
+        /* pass a character time every accesses register accesses, or only on
+           shift() if zero */
+        pub(crate) fn set_pace(&self, accesses: usize)
+        {
+            let mut state = self.state.lock().unwrap();
+            state.pace = accesses;
+            state.accesses = 0;
+        }
+
+        /* take everything sent down the line so far */
+        pub(crate) fn sent(&self) -> Vec<u8>
+        {
+            core::mem::take(&mut self.state.lock().unwrap().sent)
+        }
+
+        /* (tx, rx) FIFO occupancy */
+        pub(crate) fn levels(&self) -> (usize, usize)
+        {
+            let state = self.state.lock().unwrap();
+            (state.tx.len(), state.rx.len())
+        }
+
+        /* bytes lost because the rx FIFO was full when they arrived */
+        pub(crate) fn overruns(&self) -> usize
+        {
+            self.state.lock().unwrap().overruns
+        }
+    }
+
+    impl Bus for Fifos
+    {
+        fn read(&self, reg: usize) -> u32
+        {
+            let mut state = self.state.lock().unwrap();
+            state.access();
+            match reg
+            {
+                REG_TXDATA => match state.tx.len() < FIFO_ENTRIES
+                {
+                    true => 0,
+                    false => REG_TXDATA_FULL
+                },
+                REG_RXDATA => state.rx.pop_front().map(|b| b as u32).unwrap_or(REG_RXDATA_EMPTY),
+                REG_TXCTRL => state.txctrl,
+                REG_RXCTRL => state.rxctrl,
+                REG_IE => state.ie,
+                REG_IP => state.ip(),
+                REG_DIV => state.div,
+                _ => 0
+            }
+        }
+
+        fn write(&self, reg: usize, val: u32)
+        {
+            let mut state = self.state.lock().unwrap();
+            state.access();
+            match reg
+            {
+                REG_TXDATA if state.tx.len() < FIFO_ENTRIES => state.tx.push_back(val as u8),
+                REG_TXCTRL =>
+                {
+                    /* the byte in the shifter is cut off */
+                    if val & REG_TXCTRL_TXEN == 0 && state.shifting > 0
+                    {
+                        state.sent.pop();
+                        state.shifting = 0;
+                    }
+                    state.txctrl = val & REG_TXCTRL_MASK;
+                },
+                REG_RXCTRL => state.rxctrl = val & REG_RXCTRL_MASK,
+                REG_IE => state.ie = val & REG_IE_MASK,
+                REG_DIV => state.div = val & REG_DIV_MASK,
+                _ => ()     /* rxdata and ip are read-only, and a full tx FIFO drops the write */
+            }
+        }
+    }
+
     #[test]
     fn it_works()
     {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn wide_physical_addresses()
+    {
+        let regs = FakeRegisters::new();
+        let base = regs.cells.as_ptr() as u64;
+        assert!(UART::from_phys(base, 1 << 40).is_ok());
+        assert!(matches!(UART::from_phys(base, 4), Err(Fault::SizeTooSmall)));
+        assert!(matches!(UART::from_phys(u64::MAX - 8, 0x1000), Err(Fault::BadAddress)));
+    }
+
+    #[test]
+    fn blocking_byte_io()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.send_byte_blocking(b'!');
+        assert_eq!(regs.get(REG_TXDATA), b'!' as u32);
+
+        regs.receive(b'?');
+        assert_eq!(uart.read_byte_blocking(), b'?');
+    }
+
+    #[test]
+    fn shutdown_leaves_controller_inert()
+    {
+        let regs = FakeRegisters::new();
+        let mut uart = regs.uart();
+        uart.enable_rx_watermark_irq(true);
+        regs.set(REG_IP, REG_IP_TXWM);
+
+        uart.shutdown();
+        assert_eq!(regs.get(REG_IE), 0);
+        assert_eq!(regs.get(REG_TXCTRL) & REG_TXCTRL_TXEN, 0);
+        assert_eq!(regs.get(REG_RXCTRL) & REG_RXCTRL_RXEN, 0);
+
+        /* with the transmitter off, there's nothing to wait for */
+        regs.set(REG_IP, 0);
+        uart.shutdown();
+    }
+
+    #[test]
+    fn shutdown_lets_the_last_byte_out()
+    {
+        /* a character time of 11 bits, each 4 bus cycles long */
+        let (fifos, mut uart) = Fifos::uart();
+        uart.set_baud(115_200, 115_200 * 4);
+        uart.write_reg(REG_TXCTRL, uart.read_reg(REG_TXCTRL) | REG_TXCTRL_NSTOP);
+        fifos.set_pace(11 * 4);
+
+        uart.send_byte_blocking(b'!');
+        uart.shutdown();
+        assert_eq!(fifos.sent(), b"!");
+    }
+
+    #[test]
+    fn suspend_and_resume()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.set_baud(115_200, 115_200 * 42);
+        uart.set_rx_watermark(WatermarkLevel::L3);
+        uart.enable_tx_watermark_irq(true);
+        let state = uart.suspend();
+
+        /* as if the power domain had been switched off */
+        for reg in [REG_TXCTRL, REG_RXCTRL, REG_IE, REG_DIV]
+        {
+            regs.set(reg, 0);
+        }
+
+        uart.resume(state);
+        assert_eq!(uart.suspend(), state);
+        assert_eq!(regs.get(REG_DIV), 41);
+        assert_eq!(regs.get(REG_IE), REG_IE_TXWM);
+        assert_eq!(regs.get(REG_RXCTRL), 3 << REG_RXCTRL_RXCNT_SHIFT | REG_RXCTRL_RXEN);
+    }
+
+    #[test]
+    fn attach_leaves_registers_alone()
+    {
+        let regs = FakeRegisters::new();
+        regs.set(REG_TXCTRL, 0x0002_0001);
+        regs.set(REG_RXCTRL, 0x0004_0001);
+
+        let uart = UART::attach(regs.cells.as_ptr() as usize);
+        assert_eq!(regs.get(REG_TXCTRL), 0x0002_0001);
+        assert_eq!(regs.get(REG_RXCTRL), 0x0004_0001);
+        assert_eq!(uart.base_addr(), regs.cells.as_ptr() as usize);
+    }
+
+    #[test]
+    fn reset_to_power_on_state()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.enable_rx_watermark_irq(true);
+        uart.set_baud(115_200, 115_200 * 8);
+        uart.reset();
+
+        assert_eq!(regs.get(REG_TXCTRL), 0);
+        assert_eq!(regs.get(REG_RXCTRL), 0);
+        assert_eq!(regs.get(REG_IE), 0);
+        assert_eq!(regs.get(REG_DIV), 7);
+    }
+
+    #[test]
+    fn status_queries()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.enable_tx_watermark_irq(true);
+        uart.set_rx_watermark(WatermarkLevel::L5);
+
+        assert!(!uart.is_tx_full());
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+        assert!(uart.is_tx_full());
+
+        regs.set(REG_IP, REG_IP_TXWM | REG_IP_RXWM);
+        assert_eq!(uart.ip(), IrqPending::BOTH);
+        assert!(uart.is_tx_idle());
+        assert!(uart.is_rx_ready());
+        regs.set(REG_IP, REG_IP_TXWM);
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+        assert!(!uart.is_rx_ready());
+
+        /* the watermarks and irq enables are put back */
+        assert_eq!(uart.rx_watermark(), WatermarkLevel::L5);
+        assert_eq!(uart.tx_watermark(), DEFAULT_TX_WATERMARK);
+        assert_eq!(regs.get(REG_IE), REG_IE_TXWM);
+    }
+
+    #[test]
+    fn variant_divisors()
+    {
+        let regs = FakeRegisters::new();
+        let base = regs.cells.as_ptr() as usize;
+
+        UART::new(base, REG_TOTAL_SIZE).unwrap().set_baud(115_200, 115_200 * 100);
+        assert_eq!(regs.get(REG_DIV), 99);
+
+        let whole = Variant { divisor_offset: 0, ..Variant::SIFIVE };
+        UART::new_with_variant(base, REG_TOTAL_SIZE, whole).unwrap().set_baud(115_200, 115_200 * 100);
+        assert_eq!(regs.get(REG_DIV), 100);
+
+        let halved = Variant::SIFIVE.with_quirks(Quirks::HALF_RATE_CLOCK);
+        UART::new_with_variant(base, REG_TOTAL_SIZE, halved).unwrap().set_baud_from_source(115_200, 115_200 * 100);
+        assert_eq!(regs.get(REG_DIV), 49);
+    }
+
+    #[test]
+    fn limits_match_hardware()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        assert_eq!(uart.register_count() * 4, uart.size());
+        assert_eq!(uart.fifo_depth(), UART::FIFO_DEPTH);
+        assert_eq!(uart.min_baud(500_000_000), 7629);
+
+        /* the largest divisor fills the register */
+        uart.try_set_divisor(uart.max_divisor()).unwrap();
+        assert_eq!(regs.get(REG_DIV), REG_DIV_MASK);
+
+        let shallow = Variant { fifo_depth: 4, ..Variant::K210_UARTHS };
+        let uart = UART::new_with_variant(regs.base(), REG_TOTAL_SIZE, shallow).unwrap();
+        assert_eq!(uart.fifo_depth(), 4);
+        assert_eq!(uart.min_baud(65536 * 300), 300);
+    }
+
+    #[test]
+    fn rebase_keeps_configuration()
+    {
+        let (old, new) = (FakeRegisters::new(), FakeRegisters::new());
+        let mut uart = old.uart();
+        let new_base = new.cells.as_ptr() as usize;
+
+        unsafe { uart.set_base(new_base) };
+        assert_eq!(uart.base_addr(), new_base);
+        assert_eq!(new.get(REG_TXCTRL), 0);
+
+        uart.send_byte(b'x').unwrap();
+        assert_eq!(new.get(REG_TXDATA), b'x' as u32);
+        assert_eq!(old.get(REG_TXDATA), 0);
+    }
+
+    #[test]
+    fn probe_rejects_plain_memory()
+    {
+        let regs = FakeRegisters::new();
+        regs.set(REG_RXDATA, 0);
+        regs.set(REG_DIV, 1234);
+
+        /* every bit of RAM can be written, unlike the divisor register */
+        assert!(!UART::probe(regs.cells.as_ptr() as usize));
+        assert_eq!(regs.get(REG_DIV), 1234);
+    }
+
+    #[test]
+    fn fifos_hold_eight_bytes()
+    {
+        let (fifos, uart) = Fifos::uart();
+        for byte in b"01234567"
+        {
+            uart.send_byte(*byte).unwrap();
+        }
+        assert!(uart.is_tx_full());
+        assert!(uart.send_byte(b'8').is_err());
+        fifos.shift(1);
+        uart.send_byte(b'8').unwrap();
+        fifos.shift(10);
+        assert_eq!(fifos.sent(), b"012345678");
+
+        /* the ninth byte to arrive before any are read is lost */
+        fifos.arrive(b"abcdefghi");
+        fifos.shift(9);
+        assert_eq!((fifos.levels(), fifos.overruns()), ((0, 8), 1));
+        let mut got = Vec::new();
+        while let Ok(byte) = uart.read_byte()
+        {
+            got.push(byte);
+        }
+        assert_eq!(got, b"abcdefgh");
+
+        /* with the line running alongside, the blocking path drains the FIFO itself */
+        fifos.set_pace(3);
+        for byte in b"the quick brown fox"
+        {
+            uart.send_byte_blocking(*byte);
+        }
+        fifos.set_pace(0);
+        fifos.shift(8);
+        assert_eq!(fifos.sent(), b"the quick brown fox");
+    }
+
+    #[test]
+    fn fifo_watermarks_are_strict()
+    {
+        let (fifos, uart) = Fifos::uart();
+        uart.set_tx_watermark(WatermarkLevel::L2);
+        uart.set_rx_watermark(WatermarkLevel::L1);
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+
+        uart.send_byte(b'a').unwrap();
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+        uart.send_byte(b'b').unwrap();
+        assert_eq!(uart.ip(), IrqPending::NONE);
+
+        fifos.arrive(b"xy");
+        fifos.shift(1);
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+        fifos.shift(1);
+        assert_eq!(uart.ip(), IrqPending::BOTH);
+        uart.read_byte().unwrap();
+        assert_eq!(uart.ip(), IrqPending::TXWM);
+    }
 }
@@ -0,0 +1,245 @@
+/* dmesg-style log kept in RAM until it can be sent
+ *
+ * LogRing records lines in a ring buffer, overwriting the oldest lines once
+ * it's full, so logging works before the UART is set up and from contexts
+ * that mustn't wait for the transmitter, such as interrupt handlers. When
+ * it's convenient, flush_log() sends the backlog out of the UART, eg:
+ *
+ *   static LOG: LogRing<4096> = LogRing::new();
+ *   ...
+ *   LOG.log_str("timer: calibrated");
+ *   LOG.logf("hart %u up\n", &[hartid.into()]);
+ *   ...
+ *   uart.flush_log(&LOG)?;
+ *
+ * Logging never waits: a line logged while another context holds the ring,
+ * eg from an interrupt that arrived mid-line, is dropped and counted.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::{UART, Fault};
+use super::printf::{self, Arg};
+
+/* longest line logf() formats. longer lines are cut short */
+const LINE_MAX: usize = 128;
+
+/* bytes taken from the ring at a time while flushing, so loggers are only
+   kept out briefly, and not while the UART is sending */
+const FLUSH_CHUNK: usize = 32;
+
+struct Ring<const N: usize>
+{
+    data: [u8; N],
+    head: usize,    /* oldest byte */
+    len: usize
+}
+
+/* a log of up to N bytes of lines, each stored with a trailing LF */
+pub struct LogRing<const N: usize>
+{
+    ring: UnsafeCell<Ring<N>>,
+    busy: AtomicBool,       /* the ring is in use */
+    dropped: AtomicUsize    /* lines lost because the ring was busy */
+}
+
+/* the ring is only touched while holding busy */
+unsafe impl<const N: usize> Sync for LogRing<N> {}
+
+impl<const N: usize> Default for LogRing<N>
+{
+    fn default() -> Self
+    {
+        LogRing::new()
+    }
+}
+
+impl<const N: usize> LogRing<N>
+{
+    pub const fn new() -> Self
+    {
+        LogRing
+        {
+            ring: UnsafeCell::new(Ring { data: [0; N], head: 0, len: 0 }),
+            busy: AtomicBool::new(false),
+            dropped: AtomicUsize::new(0)
+        }
+    }
+
+    /* record line, adding a LF if it doesn't end with one. the oldest lines are
+       discarded to make room, and a line too long for the ring is cut short.
+       returns false if the line was dropped because the ring was busy */
+    pub fn log(&self, line: &[u8]) -> bool
+    {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = &line[..line.len().min(N.saturating_sub(1))];
+        if N == 0
+        {
+            return true
+        }
+
+        let logged = self.with_ring(|ring|
+        {
+            /* discard whole lines until there's room */
+            while N - ring.len < line.len() + 1
+            {
+                loop
+                {
+                    let byte = ring.data[ring.head];
+                    ring.head = (ring.head + 1) % N;
+                    ring.len -= 1;
+                    if byte == b'\n'
+                    {
+                        break
+                    }
+                }
+            }
+
+            for &byte in line.iter().chain(b"\n")
+            {
+                ring.data[(ring.head + ring.len) % N] = byte;
+                ring.len += 1;
+            }
+        });
+
+        if logged.is_none()
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        logged.is_some()
+    }
+
+    /* record a string, as log() */
+    pub fn log_str(&self, line: &str) -> bool
+    {
+        self.log(line.as_bytes())
+    }
+
+    /* format a line with printf() conversions and record it, as log() */
+    pub fn logf(&self, fmt: &str, args: &[Arg]) -> bool
+    {
+        let mut line = [0u8; LINE_MAX];
+        let mut len = 0;
+        let _ = printf::format(&mut |byte|
+        {
+            match len < LINE_MAX
+            {
+                true => { line[len] = byte; len += 1; Ok(()) },
+                false => Err(Fault::FrameTooLong)
+            }
+        }, fmt, args);
+
+        self.log(&line[..len])
+    }
+
+    /* number of lines dropped because the ring was busy */
+    pub fn dropped(&self) -> usize
+    {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /* remove up to buf's length of the oldest logged bytes into buf, returning
+       how many. returns zero if the log is empty or busy */
+    pub fn take(&self, buf: &mut [u8]) -> usize
+    {
+        self.with_ring(|ring|
+        {
+            let count = ring.len.min(buf.len());
+            for slot in buf[..count].iter_mut()
+            {
+                *slot = ring.data[ring.head];
+                ring.head = (ring.head + 1) % N;
+            }
+            ring.len -= count;
+            count
+        })
+        .unwrap_or(0)
+    }
+
+    /* run f on the ring unless another context is using it */
+    fn with_ring<R, F: FnOnce(&mut Ring<N>) -> R>(&self, f: F) -> Option<R>
+    {
+        if self.busy.swap(true, Ordering::Acquire)
+        {
+            return None
+        }
+
+        /* busy was clear, so nothing else holds the ring */
+        let result = f(unsafe { &mut *self.ring.get() });
+        self.busy.store(false, Ordering::Release);
+        Some(result)
+    }
+}
+
+impl UART
+{
+    /* send everything in log, oldest first, with each LF sent as CRLF, and
+       return how many bytes were taken from the log. bytes taken from the log
+       but not sent when the UART fails are lost */
+    pub fn flush_log<const N: usize>(&self, log: &LogRing<N>) -> Result<usize, Fault>
+    {
+        let mut flushed = 0;
+        let mut chunk = [0u8; FLUSH_CHUNK];
+        loop
+        {
+            let count = log.take(&mut chunk);
+            if count == 0
+            {
+                return Ok(flushed)
+            }
+
+            for &byte in &chunk[..count]
+            {
+                if byte == b'\n'
+                {
+                    self.send_byte(b'\r')?;
+                }
+                self.send_byte(byte)?;
+            }
+            flushed += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    #[test]
+    fn keeps_latest_lines()
+    {
+        let log: LogRing<16> = LogRing::new();
+        assert!(log.log_str("one"));
+        assert!(log.log(b"two\n"));
+        assert!(log.logf("%s %u!", &["three".into(), 3u8.into()]));
+
+        let (script, uart) = Script::uart(b"");
+        assert!(matches!(uart.flush_log(&log), Ok(13)));
+        assert_eq!(script.sent(), b"two\r\nthree 3!\r\n");
+        assert!(matches!(uart.flush_log(&log), Ok(0)));
+
+        /* too long for the ring */
+        log.log_str("a line much longer than the ring");
+        assert!(matches!(uart.flush_log(&log), Ok(16)));
+        assert_eq!(script.sent(), b"a line much lon\r\n");
+    }
+
+    #[test]
+    fn never_waits_for_a_busy_ring()
+    {
+        let log: LogRing<16> = LogRing::new();
+        log.with_ring(|_|
+        {
+            assert!(!log.log_str("interrupted"));
+            assert_eq!(log.take(&mut [0u8; 4]), 0);
+        });
+        assert_eq!(log.dropped(), 1);
+        assert_eq!(log.take(&mut [0u8; 4]), 0);
+    }
+}
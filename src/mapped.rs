@@ -0,0 +1,119 @@
+/* reach a UART from Linux userspace through /dev/mem or UIO
+ *
+ * The driver only needs the registers to be mapped somewhere it can reach,
+ * so on a booted system, such as a HiFive Unmatched running Linux, the same
+ * code can drive the real controller from a process. This is handy for
+ * trying out register sequences without reflashing firmware. Needs root, or
+ * access to the UIO device, and the UART mustn't be in use by the kernel.
+ *
+ * The mapping is made with attach(), so nothing is written to the
+ * controller until the caller does so.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::Deref;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use super::{UART, REG_TOTAL_SIZE};
+
+/* a UART whose registers are mapped into this process. unmapped when dropped */
+pub struct MappedUart
+{
+    uart: UART,
+    mapping: *mut libc::c_void,
+    len: usize
+}
+
+impl MappedUart
+{
+    /* map the controller at physical address phys through /dev/mem */
+    pub fn dev_mem(phys: u64) -> io::Result<Self>
+    {
+        let file = OpenOptions::new().read(true).write(true).custom_flags(libc::O_SYNC).open("/dev/mem")?;
+        MappedUart::map(&file, phys)
+    }
+
+    /* map the first memory region of a UIO device, eg /dev/uio0, which the
+       kernel's uio_pdrv_genirq driver can provide for a sifive,uart0 node */
+    pub fn uio<P: AsRef<Path>>(device: P) -> io::Result<Self>
+    {
+        let file = OpenOptions::new().read(true).write(true).open(device)?;
+        MappedUart::map(&file, 0)
+    }
+
+    /* map the registers at offset within file. the mapping starts on a page
+       boundary, so the registers needn't */
+    fn map(file: &File, offset: u64) -> io::Result<Self>
+    {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let start = offset & !(page - 1);
+        let skip = (offset - start) as usize;
+        let len = skip + REG_TOTAL_SIZE;
+
+        let mapping = unsafe
+        {
+            libc::mmap(core::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED, file.as_raw_fd(), start as libc::off_t)
+        };
+        if mapping == libc::MAP_FAILED
+        {
+            return Err(io::Error::last_os_error())
+        }
+
+        let uart = UART::attach(mapping as usize + skip);
+        Ok(MappedUart { uart, mapping, len })
+    }
+}
+
+impl Deref for MappedUart
+{
+    type Target = UART;
+
+    fn deref(&self) -> &UART
+    {
+        &self.uart
+    }
+}
+
+impl Drop for MappedUart
+{
+    fn drop(&mut self)
+    {
+        unsafe { libc::munmap(self.mapping, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use crate::REG_DIV;
+
+    #[test]
+    fn maps_a_device_file()
+    {
+        /* an ordinary file stands in for a UIO device */
+        let path = std::env::temp_dir().join(format!("mmio_sifive_uart-{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[0; 4096]).unwrap();
+
+        {
+            let uart = MappedUart::uio(&path).unwrap();
+            uart.set_baud(115_200, 115_200 * 0x1235);
+        }
+
+        let mut contents = Vec::new();
+        let mut file = File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(REG_DIV as u64)).unwrap();
+        file.take(4).read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, 0x1234u32.to_ne_bytes());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
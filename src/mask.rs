@@ -0,0 +1,74 @@
+/* critical sections free of UART interrupts
+ *
+ * Changing the configuration can take several register writes, and the
+ * interrupt handler mustn't run part way through. mask_all() disables both
+ * of the UART's interrupt sources and returns an IrqGuard, which puts back
+ * whichever were enabled when it's dropped, eg:
+ *
+ *   {
+ *       let _masked = uart.mask_all();
+ *       uart.set_baud(rate, bus_freq);
+ *       uart.set_rx_watermark(level);
+ *   }   // interrupts enabled again here
+ *
+ * Guards nest: an inner guard restores the outer guard's masked state.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, REG_IE, REG_IE_MASK};
+
+/* restores the UART's interrupt enables when dropped */
+#[must_use = "interrupts are unmasked again as soon as the guard is dropped"]
+pub struct IrqGuard<'a>
+{
+    uart: &'a UART,
+    ie: u32     /* the enables to restore */
+}
+
+impl UART
+{
+    /* disable the tx and rx watermark interrupts until the returned guard is dropped */
+    pub fn mask_all(&self) -> IrqGuard<'_>
+    {
+        let ie = self.read_reg(REG_IE) & REG_IE_MASK;
+        self.write_reg(REG_IE, 0);
+        IrqGuard { uart: self, ie }
+    }
+}
+
+impl Drop for IrqGuard<'_>
+{
+    fn drop(&mut self)
+    {
+        self.uart.write_reg(REG_IE, self.ie);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::REG_IE_RXWM;
+
+    #[test]
+    fn guard_restores_enables()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        uart.enable_rx_watermark_irq(true);
+        {
+            let _outer = uart.mask_all();
+            assert_eq!(regs.get(REG_IE), 0);
+            {
+                let _inner = uart.mask_all();
+                uart.enable_tx_watermark_irq(true);
+            }
+            assert_eq!(regs.get(REG_IE), 0);
+        }
+        assert_eq!(regs.get(REG_IE), REG_IE_RXWM);
+    }
+}
@@ -0,0 +1,206 @@
+/* the arithmetic behind the register values, free of any MMIO
+ *
+ * Working out a divisor from a baud rate, the rate a divisor really gives,
+ * how far that is from the rate asked for, where a watermark sits in a
+ * control register, and where a ring buffer's indices point are all done
+ * by these functions, so they can be tested exhaustively on their own,
+ * eg before picking a bus clock:
+ *
+ *   let ppm = baud_error_ppm(115200, 500_000_000, 1);   // Some(60)
+ *
+ * divisor_offset is the variant's: 1 for SiFive's controller, whose manual
+ * gives the baud rate as bus_freq / (div + 1), and 0 for any clone that
+ * divides by the register's value itself.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{REG_CNT_FIELD, REG_DIV_MASK};
+
+/* the divisor giving the nearest rate at or above baud from bus_freq, both
+   in Hz, or None if baud is zero or too fast for bus_freq to reach. it isn't
+   checked against the register */
+pub const fn divisor(baud: u32, bus_freq: u32, divisor_offset: u32) -> Option<u32>
+{
+    match bus_freq.checked_div(baud)
+    {
+        Some(div) if div > divisor_offset => Some(div - divisor_offset),
+        _ => None
+    }
+}
+
+/* the divisor set_baud() programs: divisor()'s, the slowest the register
+   can hold if baud is zero or too slow for it, or 1 if it's too fast */
+pub(crate) const fn clamped_divisor(baud: u32, bus_freq: u32, divisor_offset: u32) -> u32
+{
+    match divisor(baud, bus_freq, divisor_offset)
+    {
+        Some(div) if div <= REG_DIV_MASK => div,
+        None if baud > 0 => 1,
+        _ => REG_DIV_MASK
+    }
+}
+
+/* the baud rate divisor gives from bus_freq in Hz, or None if it stops the clock */
+pub const fn baud_from_divisor(divisor: u32, bus_freq: u32, divisor_offset: u32) -> Option<u32>
+{
+    bus_freq.checked_div(divisor.saturating_add(divisor_offset))
+}
+
+/* the baud rate actually achieved when asking for baud, or None if it can't be set */
+pub const fn achieved_baud(baud: u32, bus_freq: u32, divisor_offset: u32) -> Option<u32>
+{
+    match divisor(baud, bus_freq, divisor_offset)
+    {
+        Some(div) => baud_from_divisor(div, bus_freq, divisor_offset),
+        None => None
+    }
+}
+
+/* how far the achieved rate is from baud, in parts per million. the divisor
+   rounds down, so this is never negative. the far end of a line tolerates an
+   error of around 20000 ppm, or 2%, before bytes are garbled */
+pub const fn baud_error_ppm(baud: u32, bus_freq: u32, divisor_offset: u32) -> Option<u32>
+{
+    match achieved_baud(baud, bus_freq, divisor_offset)
+    {
+        Some(actual) => Some(((actual - baud) as u64 * 1_000_000 / baud as u64) as u32),
+        None => None
+    }
+}
+
+/* ctrl, a txctrl or rxctrl value, with its watermark field at shift set to level */
+pub(crate) const fn with_watermark(ctrl: u32, shift: u32, level: u32) -> u32
+{
+    ctrl & !(REG_CNT_FIELD << shift) | (level & REG_CNT_FIELD) << shift
+}
+
+/* the watermark level held in ctrl's field at shift */
+pub(crate) const fn watermark(ctrl: u32, shift: u32) -> u32
+{
+    ctrl >> shift & REG_CNT_FIELD
+}
+
+/* bytes held by a ring of capacity slots, whose head and tail indices count
+   from 0 to 2 * capacity - 1 */
+pub(crate) const fn ring_len(head: usize, tail: usize, capacity: usize) -> usize
+{
+    (tail + 2 * capacity - head) % (2 * capacity)
+}
+
+/* a ring index moved on by count, wrapping at 2 * capacity */
+pub(crate) const fn ring_advance(index: usize, count: usize, capacity: usize) -> usize
+{
+    (index + count) % (2 * capacity)
+}
+
+/* the slot a ring index refers to */
+pub(crate) const fn ring_slot(index: usize, capacity: usize) -> usize
+{
+    index % capacity
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{REG_TXCTRL_TXCNT_SHIFT, REG_TXCTRL_TXEN, REG_TXCTRL_NSTOP};
+
+    #[test]
+    fn divisors_round_down()
+    {
+        assert_eq!(divisor(115200, 500_000_000, 0), Some(4340));
+        assert_eq!(divisor(115200, 500_000_000, 1), Some(4339));
+        assert_eq!(divisor(0, 500_000_000, 0), None);
+        assert_eq!(divisor(115200, 1000, 1), None);
+        assert_eq!(divisor(115200, 115200, 1), None);
+        assert_eq!(divisor(115200, 115200, 0), Some(1));
+        assert_eq!(baud_from_divisor(4340, 500_000_000, 0), Some(115207));
+        assert_eq!(baud_from_divisor(0, 500_000_000, 0), None);
+        assert_eq!(baud_from_divisor(u32::MAX, 500_000_000, 1), Some(0));
+        assert_eq!(clamped_divisor(115200, 500_000_000, 1), 4339);
+        assert_eq!(clamped_divisor(300, 500_000_000, 1), REG_DIV_MASK);
+        assert_eq!(clamped_divisor(0, 500_000_000, 1), REG_DIV_MASK);
+        assert_eq!(clamped_divisor(115200, 1000, 1), 1);
+
+        /* asking for the rate any divisor the register can hold gives gets exactly that rate */
+        for offset in 0..=1
+        {
+            for div in 1..=REG_DIV_MASK
+            {
+                let baud = baud_from_divisor(div, 500_000_000, offset).unwrap();
+                assert_eq!(achieved_baud(baud, 500_000_000, offset), Some(baud));
+            }
+        }
+    }
+
+    #[test]
+    fn errors_are_bounded()
+    {
+        assert_eq!(baud_error_ppm(115200, 500_000_000, 0), Some(60));
+        assert_eq!(baud_error_ppm(115200, 115200 * 16, 0), Some(0));
+        assert_eq!(baud_error_ppm(115200, 1000, 0), None);
+        assert_eq!(baud_error_ppm(0, 1000, 0), None);
+
+        /* a bus slower than the rate asked for can't reach it, whatever the offset */
+        for offset in 0..=1
+        {
+            assert_eq!(achieved_baud(115200, 1000, offset), None);
+            assert_eq!(baud_error_ppm(115200, 1000, offset), None);
+            assert_eq!(baud_error_ppm(115200, 115199, offset), None);
+        }
+
+        /* the achieved rate is at or above the one asked for, and off by no more
+           than one step of the divisor */
+        for offset in 0..=1
+        {
+            for baud in 1..=250_000
+            {
+                let bus_freq = 16_000_000;
+                let div = divisor(baud, bus_freq, offset).unwrap() + offset;
+                let ppm = baud_error_ppm(baud, bus_freq, offset).unwrap();
+                assert!(achieved_baud(baud, bus_freq, offset).unwrap() >= baud);
+                assert!(ppm <= 1_000_000 / div, "{} baud is {} ppm off", baud, ppm);
+            }
+        }
+    }
+
+    #[test]
+    fn watermarks_leave_other_bits_alone()
+    {
+        let others = [0, REG_TXCTRL_TXEN, REG_TXCTRL_NSTOP, u32::MAX];
+        for ctrl in others
+        {
+            for level in 0..=REG_CNT_FIELD
+            {
+                let set = with_watermark(ctrl, REG_TXCTRL_TXCNT_SHIFT, level);
+                assert_eq!(watermark(set, REG_TXCTRL_TXCNT_SHIFT), level);
+                assert_eq!(set & !(REG_CNT_FIELD << REG_TXCTRL_TXCNT_SHIFT),
+                           ctrl & !(REG_CNT_FIELD << REG_TXCTRL_TXCNT_SHIFT));
+            }
+        }
+        assert_eq!(with_watermark(0, REG_TXCTRL_TXCNT_SHIFT, 9), 1 << REG_TXCTRL_TXCNT_SHIFT);
+    }
+
+    /* every head and length of rings up to 9 slots, against a plain count */
+    #[test]
+    fn ring_indices_wrap()
+    {
+        for capacity in 1..=9
+        {
+            for head in 0..2 * capacity
+            {
+                for len in 0..=capacity
+                {
+                    let tail = ring_advance(head, len, capacity);
+                    assert!(tail < 2 * capacity);
+                    assert_eq!(ring_len(head, tail, capacity), len);
+                    assert_eq!(ring_slot(tail, capacity), (ring_slot(head, capacity) + len) % capacity);
+                    assert_eq!(ring_len(ring_advance(head, len, capacity), tail, capacity), 0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+/* a UART whose registers are held in memory, for examples and doctests
+ *
+ * UART::in_memory() returns a standard UART, initialized as new() does, on
+ * a simulated register block rather than one at a fixed address such as
+ * 0x10010000, so code showing how to use the driver can actually run on
+ * the host, and check what it did:
+ *
+ *   let (regs, uart) = UART::in_memory();
+ *   regs.receive(b"hi");
+ *   assert_eq!(uart.read_byte_blocking(), b'h');
+ *   uart.send_byte_blocking(b'!');
+ *   assert_eq!(regs.sent(), b"!");
+ *
+ * The registers behave as HostBus's do: the transmitter never fills up,
+ * received bytes are delivered through RXDATA while the receiver is
+ * enabled, and the interrupt pending bits follow the watermarks. Nothing
+ * arrives or raises interrupts unless the caller makes it.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use super::bus::{self, Bus};
+use super::math::watermark;
+use super::{UART, FIFO_DEPTH, REG_TXCTRL_TXEN, REG_TXCTRL_TXCNT_SHIFT, REG_RXCTRL_RXEN,
+            REG_RXCTRL_RXCNT_SHIFT, REG_IP_TXWM, REG_IP_RXWM, REG_RXDATA_EMPTY};
+
+/* the simulated registers and the bytes received but not yet read */
+#[derive(Default)]
+pub(crate) struct State
+{
+    txctrl: u32,
+    rxctrl: u32,
+    ie: u32,
+    div: u32,
+    pub(crate) rx: VecDeque<u8>
+}
+
+impl State
+{
+    fn ip(&self) -> u32
+    {
+        /* the tx FIFO is always empty, so txwm is pending for any non-zero watermark */
+        let txcnt = watermark(self.txctrl, REG_TXCTRL_TXCNT_SHIFT);
+        let rxcnt = watermark(self.rxctrl, REG_RXCTRL_RXCNT_SHIFT);
+
+        let mut ip = 0;
+        if txcnt > 0
+        {
+            ip |= REG_IP_TXWM;
+        }
+        if self.rx.len().min(FIFO_DEPTH as usize) > rxcnt as usize
+        {
+            ip |= REG_IP_RXWM;
+        }
+        ip
+    }
+
+    pub(crate) fn read(&mut self, reg: usize) -> u32
+    {
+        match reg
+        {
+            bus::TXDATA => 0,
+            bus::RXDATA => match (self.rxctrl & REG_RXCTRL_RXEN, self.rx.pop_front())
+            {
+                (0, Some(byte)) =>
+                {
+                    /* the receiver is off, so leave the byte for later */
+                    self.rx.push_front(byte);
+                    REG_RXDATA_EMPTY
+                },
+                (_, Some(byte)) => byte as u32,
+                (_, None) => REG_RXDATA_EMPTY
+            },
+            bus::TXCTRL => self.txctrl,
+            bus::RXCTRL => self.rxctrl,
+            bus::IE => self.ie,
+            bus::IP => self.ip(),
+            bus::DIV => self.div,
+            _ => 0
+        }
+    }
+
+    /* write a register, returning the byte transmitted, if any */
+    pub(crate) fn write(&mut self, reg: usize, val: u32) -> Option<u8>
+    {
+        match reg
+        {
+            bus::TXDATA if self.txctrl & REG_TXCTRL_TXEN != 0 => return Some(val as u8),
+            bus::TXCTRL => self.txctrl = val,
+            bus::RXCTRL => self.rxctrl = val,
+            bus::IE => self.ie = val,
+            bus::DIV => self.div = val,
+            _ => ()
+        }
+        None
+    }
+}
+
+/* registers held in memory, keeping everything transmitted */
+#[derive(Default)]
+pub struct MemoryBus
+{
+    state: Mutex<State>,
+    sent: Mutex<Vec<u8>>
+}
+
+impl MemoryBus
+{
+    pub fn new() -> Self
+    {
+        MemoryBus::default()
+    }
+
+    /* queue bytes to be read, as if they had arrived down the line */
+    pub fn receive(&self, input: &[u8])
+    {
+        self.state.lock().unwrap().rx.extend(input);
+    }
+
+    /* take everything transmitted so far */
+    pub fn sent(&self) -> Vec<u8>
+    {
+        core::mem::take(&mut self.sent.lock().unwrap())
+    }
+
+    /* create a standard UART, initialized as new() does, on this bus. the bus
+       is leaked, as the UART may be used for the rest of the program */
+    pub fn into_uart(self) -> (&'static MemoryBus, UART)
+    {
+        let bus: &'static MemoryBus = Box::leak(Box::new(self));
+        (bus, UART::on_bus(bus))
+    }
+}
+
+impl Bus for MemoryBus
+{
+    fn read(&self, reg: usize) -> u32
+    {
+        self.state.lock().unwrap().read(reg)
+    }
+
+    fn write(&self, reg: usize, val: u32)
+    {
+        let sent = self.state.lock().unwrap().write(reg, val);
+        if let Some(byte) = sent
+        {
+            self.sent.lock().unwrap().push(byte);
+        }
+    }
+}
+
+impl UART
+{
+    /* a standard UART on a fresh MemoryBus, returned with it so what's
+       received can be supplied and what's sent checked, eg:
+
+         let (regs, uart) = UART::in_memory();
+         regs.receive(b"hi");
+         assert!(matches!(uart.read_byte(), Ok(b'h')));
+         uart.send_str("ok").unwrap();
+         assert_eq!(regs.sent(), b"ok");
+
+       examples/in_memory.rs runs this */
+    pub fn in_memory() -> (&'static MemoryBus, UART)
+    {
+        MemoryBus::new().into_uart()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Fault;
+
+    /* the examples above, also run by examples/in_memory.rs */
+    #[test]
+    fn examples_run()
+    {
+        let (regs, uart) = UART::in_memory();
+        assert!(matches!(uart.read_byte(), Err(Fault::DataNotReady)));
+
+        regs.receive(b"hi");
+        assert!(matches!(uart.read_byte(), Ok(b'h')));
+        assert_eq!(uart.read_byte_blocking(), b'i');
+        uart.send_str("ok").unwrap();
+        uart.send_byte_blocking(b'!');
+        assert_eq!(regs.sent(), b"ok!");
+    }
+
+    #[test]
+    fn holds_bytes_while_disabled()
+    {
+        let (regs, mut uart) = UART::in_memory();
+        regs.receive(b"abc");
+        assert!(matches!(uart.read_byte(), Ok(b'a')));
+        assert!(uart.ip().txwm && !uart.ip().rxwm);
+
+        /* nothing is sent or received until the controller is set up again */
+        uart.shutdown();
+        assert!(uart.send_byte(b'x').is_ok());
+        let uart = UART::on_bus(regs);
+        uart.send_str("yz").unwrap();
+        assert_eq!(regs.sent(), b"yz");
+        assert!(matches!(uart.read_byte(), Ok(b'b')));
+        assert!(matches!(uart.read_byte(), Ok(b'c')));
+    }
+}
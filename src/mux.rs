@@ -0,0 +1,229 @@
+/* virtual channels sharing one UART
+ *
+ * Mux lets a single port carry several independent streams at once, such
+ * as a console, a log and binary telemetry. Each write on a channel is
+ * sent as a COBS frame holding:
+ *   channel number (u8), data, CRC16 of the channel number and data (big endian)
+ * and received frames are checked and sorted into a ring buffer for each
+ * channel. Frames that fail their CRC, or are for a channel that doesn't
+ * exist, are dropped and counted.
+ *
+ * Each channel has a handle that reads and writes it, and handles to
+ * different channels can be used side by side, eg:
+ *
+ *   let mut frame = [0u8; mux::FRAME_MAX];
+ *   let mux: Mux<3, 256> = Mux::new(&uart, &mut frame);
+ *   let (console, log) = (mux.channel(0), mux.channel(1));
+ *   log.write(b"booting\n")?;
+ *   let count = console.read(&mut line);
+ *
+ * Received bytes are only taken from the UART when a channel is read or
+ * poll() is called, so call one often enough that the rx FIFO doesn't
+ * overflow. A Mux must only be used from one context.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::{Cell, RefCell};
+use super::{UART, Fault};
+use super::cobs::{Encoder, Decoder};
+use super::crc::crc16;
+use super::ring::RingBuffer;
+
+/* most data sent in one frame. longer writes are split across frames */
+pub const PAYLOAD_MAX: usize = 255;
+
+/* the largest decoded frame: the channel number, data and CRC. a receive buffer
+   this big takes any frame a Mux sends */
+pub const FRAME_MAX: usize = 1 + PAYLOAD_MAX + 2;
+
+/* CHANNELS channels, each buffering up to RX received bytes */
+pub struct Mux<'a, const CHANNELS: usize, const RX: usize>
+{
+    uart: &'a UART,
+    decoder: RefCell<Decoder<'a>>,
+    rx: [RingBuffer<RX>; CHANNELS],
+    dropped: Cell<usize>    /* frames discarded, and frames that didn't fit a channel's ring */
+}
+
+impl<'a, const CHANNELS: usize, const RX: usize> Mux<'a, CHANNELS, RX>
+{
+    /* multiplex channels over uart, decoding frames in frame, which should be
+       FRAME_MAX bytes long to take any frame */
+    pub fn new(uart: &'a UART, frame: &'a mut [u8]) -> Self
+    {
+        Mux
+        {
+            uart,
+            decoder: RefCell::new(Decoder::new(frame)),
+            rx: core::array::from_fn(|_| RingBuffer::new()),
+            dropped: Cell::new(0)
+        }
+    }
+
+    /* return the handle for channel number id, which must be less than CHANNELS */
+    pub fn channel(&self, id: u8) -> Channel<'_, 'a, CHANNELS, RX>
+    {
+        assert!((id as usize) < CHANNELS, "no such channel");
+        Channel { mux: self, id }
+    }
+
+    /* number of frames dropped as corrupt, too long, for no known channel, or
+       because their channel's ring buffer was full */
+    pub fn dropped(&self) -> usize
+    {
+        self.dropped.get()
+    }
+
+    /* sort everything waiting in the rx FIFO into the channels' ring buffers,
+       returning how many frames were received */
+    pub fn poll(&self) -> usize
+    {
+        let mut decoder = self.decoder.borrow_mut();
+        let mut received = 0;
+
+        while let Ok(byte) = self.uart.read_byte()
+        {
+            match decoder.push(byte)
+            {
+                Ok(Some(_)) => match self.deliver(decoder.frame())
+                {
+                    true => received += 1,
+                    false => self.dropped.set(self.dropped.get() + 1)
+                },
+                Ok(None) => (),
+                Err(_) => self.dropped.set(self.dropped.get() + 1)
+            }
+        }
+
+        received
+    }
+
+    /* check a frame and copy its data into its channel's ring, if it all fits */
+    fn deliver(&self, frame: &[u8]) -> bool
+    {
+        let (body, crc) = match frame.len()
+        {
+            0..=2 => return false,
+            len => frame.split_at(len - 2)
+        };
+
+        if crc16(0, body) != u16::from_be_bytes([crc[0], crc[1]])
+        {
+            return false
+        }
+
+        match self.rx.get(body[0] as usize)
+        {
+            Some(ring) if ring.free() >= body.len() - 1 => ring.push_from(&body[1..]) == body.len() - 1,
+            _ => false
+        }
+    }
+
+    fn send(&self, id: u8, data: &[u8]) -> Result<(), Fault>
+    {
+        for chunk in data.chunks(PAYLOAD_MAX)
+        {
+            let mut frame = [0u8; FRAME_MAX];
+            frame[0] = id;
+            frame[1..=chunk.len()].copy_from_slice(chunk);
+            let crc = crc16(0, &frame[..=chunk.len()]);
+            frame[chunk.len() + 1..chunk.len() + 3].copy_from_slice(&crc.to_be_bytes());
+
+            for byte in Encoder::new(&frame[..chunk.len() + 3])
+            {
+                self.uart.send_byte(byte)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/* reads and writes one of a Mux's channels */
+#[derive(Clone, Copy)]
+pub struct Channel<'m, 'a, const CHANNELS: usize, const RX: usize>
+{
+    mux: &'m Mux<'a, CHANNELS, RX>,
+    id: u8
+}
+
+impl<const CHANNELS: usize, const RX: usize> Channel<'_, '_, CHANNELS, RX>
+{
+    pub fn id(&self) -> u8
+    {
+        self.id
+    }
+
+    /* send data on this channel, split into frames of up to PAYLOAD_MAX bytes */
+    pub fn write(&self, data: &[u8]) -> Result<(), Fault>
+    {
+        self.mux.send(self.id, data)
+    }
+
+    /* copy bytes received on this channel into buf, returning how many. returns
+       immediately, with zero if nothing's waiting */
+    pub fn read(&self, buf: &mut [u8]) -> usize
+    {
+        self.mux.poll();
+        self.mux.rx[self.id as usize].pop_into(buf)
+    }
+}
+
+impl<const CHANNELS: usize, const RX: usize> core::fmt::Write for Channel<'_, '_, CHANNELS, RX>
+{
+    fn write_str(&mut self, string: &str) -> core::fmt::Result
+    {
+        self.write(string.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    #[test]
+    fn channels_are_independent()
+    {
+        let (script, uart) = Script::uart(b"");
+        let mut frame = [0u8; FRAME_MAX];
+        let mux: Mux<2, 8> = Mux::new(&uart, &mut frame);
+        let (console, log) = (mux.channel(0), mux.channel(1));
+
+        log.write(b"log").unwrap();
+        console.write(b"hi").unwrap();
+        let sent = script.sent();
+
+        /* loop what was sent back, with a corrupted copy of the first frame */
+        let mut corrupt = sent.clone();
+        corrupt[2] ^= 1;
+        script.receive(&corrupt[..corrupt.iter().position(|&b| b == 0).unwrap() + 1]);
+        script.receive(&sent);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(console.read(&mut buf), 2);
+        assert_eq!(&buf[..2], b"hi");
+        assert_eq!(log.read(&mut buf), 3);
+        assert_eq!(&buf[..3], b"log");
+        assert_eq!(mux.dropped(), 1);
+    }
+
+    #[test]
+    fn long_writes_are_split()
+    {
+        let (script, uart) = Script::uart(b"");
+        let mut frame = [0u8; FRAME_MAX];
+        let mux: Mux<1, 512> = Mux::new(&uart, &mut frame);
+        mux.channel(0).write(&[7u8; 300]).unwrap();
+
+        let sent = script.sent();
+        assert_eq!(sent.iter().filter(|&&b| b == 0).count(), 2);
+        script.receive(&sent);
+        assert_eq!(mux.poll(), 2);
+        assert_eq!(mux.channel(0).read(&mut [0u8; 512]), 300);
+    }
+}
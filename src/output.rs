@@ -0,0 +1,160 @@
+/* sending strings and runs of bytes
+ *
+ * Console code sending more than a byte at a time needs to know how far
+ * it got if the UART stops accepting data, so these report how many bytes
+ * went out before any fault. The number formatters use printf(), which
+ * needs only a small stack buffer, for early boot code that can't use
+ * core::fmt yet.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, REG_TXDATA};
+
+/* a send that stopped early, and how many bytes went out before it did */
+#[derive(Debug)]
+pub struct Partial
+{
+    pub sent: usize,
+    pub fault: Fault
+}
+
+impl UART
+{
+    /* send each byte of data, returning how many were sent, which is all of them
+       unless a byte times out waiting for room in the tx FIFO */
+    pub fn send_bytes(&self, data: &[u8]) -> Result<usize, Partial>
+    {
+        self.send_iter(data.iter().copied())
+    }
+
+    /* queue as much of data as fits in the tx FIFO without waiting, returning
+       how many bytes were queued, as io::Write::write() does. fails with
+       TxNotEmpty if data isn't empty but none of it fits */
+    pub fn write(&self, data: &[u8]) -> Result<usize, Fault>
+    {
+        let mut count = 0;
+        for &byte in data
+        {
+            if self.is_tx_full()
+            {
+                break
+            }
+
+            self.write_reg(REG_TXDATA, byte as u32);
+            count += 1;
+        }
+
+        match count
+        {
+            0 if !data.is_empty() => Err(Fault::TxNotEmpty),
+            count => Ok(count)
+        }
+    }
+
+    /* send bytes as they're generated, eg by an encoder, without buffering them
+       first. returns how many were sent, as send_bytes() */
+    pub fn send_iter<I: IntoIterator<Item = u8>>(&self, bytes: I) -> Result<usize, Partial>
+    {
+        let mut sent = 0;
+        for byte in bytes
+        {
+            self.send_byte(byte).map_err(|fault| Partial { sent, fault })?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /* send a string's UTF-8 bytes, as send_bytes() */
+    pub fn send_str(&self, string: &str) -> Result<usize, Partial>
+    {
+        self.send_bytes(string.as_bytes())
+    }
+
+    /* send value as 16 lowercase hex digits, zero-padded and without a prefix */
+    pub fn send_hex_u64(&self, value: u64) -> Result<usize, Partial>
+    {
+        self.printf("%016x", &[value.into()])
+    }
+
+    /* send value in decimal, without padding */
+    pub fn send_dec_u32(&self, value: u32) -> Result<usize, Partial>
+    {
+        self.printf("%u", &[value.into()])
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::{FakeRegisters, Script};
+    use crate::{REG_TXDATA, REG_TXDATA_FULL};
+
+    #[test]
+    fn formats_numbers()
+    {
+        let (script, uart) = Script::uart(b"");
+        assert!(matches!(uart.send_hex_u64(0xdead_beef_0000_0123), Ok(16)));
+        assert_eq!(script.sent(), b"deadbeef00000123");
+
+        for (value, digits) in [(0, &b"0"[..]), (u32::MAX, b"4294967295"), (1200, b"1200")]
+        {
+            assert!(matches!(uart.send_dec_u32(value), Ok(len) if len == digits.len()));
+            assert_eq!(script.sent(), digits);
+        }
+
+        let regs = FakeRegisters::new();
+        assert!(matches!(regs.uart().send_dec_u32(42), Ok(2)));
+        assert_eq!(regs.get(REG_TXDATA), b'2' as u32);
+    }
+
+    #[test]
+    fn sends_generated_bytes()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        assert!(matches!(uart.send_iter((b'a'..=b'z').filter(|b| b & 1 == 0)), Ok(13)));
+        assert_eq!(regs.get(REG_TXDATA), b'z' as u32);
+    }
+
+    #[test]
+    fn writes_what_fits()
+    {
+        struct Writer<'a>(&'a UART);
+
+        impl std::io::Write for Writer<'_>
+        {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+            {
+                self.0.write(buf).map_err(|_| std::io::ErrorKind::WouldBlock.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        assert!(matches!(uart.write(b""), Ok(0)));
+        std::io::Write::write_all(&mut Writer(&uart), b"io").unwrap();
+        assert_eq!(regs.get(REG_TXDATA), b'o' as u32);
+
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+        assert!(matches!(uart.write(b"x"), Err(Fault::TxNotEmpty)));
+    }
+
+    #[test]
+    fn reports_progress()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        assert!(matches!(uart.send_str("hello"), Ok(5)));
+        assert_eq!(regs.get(REG_TXDATA), b'o' as u32);
+
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+        assert!(matches!(uart.send_bytes(b"abc"), Err(Partial { sent: 0, fault: Fault::TxNotEmpty })));
+    }
+}
@@ -0,0 +1,427 @@
+/* length-prefixed, CRC-protected packets on top of the UART
+ *
+ * Each packet on the wire is:
+ *   sync (0xa5 0x5a), length (u16, little endian), payload, CRC16 (big endian)
+ * where the CRC covers the length and payload. The receiver hunts for the
+ * sync pattern, so after a corrupted packet or line noise it locks back on
+ * to the next good packet without any help from the sender.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::crc::crc16;
+use super::timeout::{Timeout, Forever};
+
+/* marks the start of every packet */
+pub const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+/* largest payload the two-byte length field can describe */
+pub const MAX_PAYLOAD: usize = u16::MAX as usize;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State
+{
+    Hunt,           /* looking for the first sync byte */
+    Sync,           /* seen the first sync byte */
+    Length(u8),     /* seen the low byte of the length */
+    LengthLow,      /* waiting for the low byte of the length */
+    Payload,
+    Crc(u8),        /* seen the high byte of the CRC */
+    CrcHigh,        /* waiting for the high byte of the CRC */
+    Complete        /* buf[..len] holds a verified payload */
+}
+
+/* reassemble packets one byte at a time into a caller-supplied buffer */
+pub struct PacketReceiver<'a>
+{
+    buf: &'a mut [u8],
+    state: State,
+    len: usize,     /* payload length announced by the header */
+    pos: usize,     /* payload bytes received so far */
+    start: usize    /* where the payload begins in buf. only non-zero while rescanning */
+}
+
+/* header bytes after the first sync byte: the second one, then the length */
+const HEADER: usize = 3;
+
+/* the bytes a failed packet used up after its first sync byte: the rest of
+   its header, the payload held at the start of buf, then as much of its CRC
+   as arrived. a real packet may have started anywhere among them */
+struct Consumed
+{
+    header: [u8; HEADER],
+    payload: usize,
+    crc: [u8; 2],
+    crc_len: usize
+}
+
+impl Consumed
+{
+    fn len(&self) -> usize
+    {
+        HEADER + self.payload + self.crc_len
+    }
+
+    /* return the byte at index, given the buffer holding the payload */
+    fn get(&self, buf: &[u8], index: usize) -> u8
+    {
+        match index
+        {
+            i if i < HEADER => self.header[i],
+            i if i < HEADER + self.payload => buf[i - HEADER],
+            i => self.crc[i - HEADER - self.payload]
+        }
+    }
+}
+
+impl<'a> PacketReceiver<'a>
+{
+    pub fn new(buf: &'a mut [u8]) -> Self
+    {
+        PacketReceiver { buf, state: State::Hunt, len: 0, pos: 0, start: 0 }
+    }
+
+    /* feed a received byte into the receiver. returns the payload length when
+       a packet passes its CRC check, which can then be fetched with payload().
+       fails with BadFrame on a CRC mismatch and FrameTooLong if the packet
+       won't fit in the buffer. either way, the sync bytes were a false start,
+       so the bytes read since them are scanned again for the next packet. if
+       those hold a whole packet, it's returned instead of the fault, and any
+       bytes after it among them are dropped */
+    pub fn push(&mut self, byte: u8) -> Result<Option<usize>, Fault>
+    {
+        let before = self.state;
+        match self.step(byte, None)
+        {
+            Err(fault) =>
+            {
+                let [low, high] = (self.len as u16).to_le_bytes();
+                let consumed = match before
+                {
+                    State::Crc(crc) => Consumed { header: [SYNC[1], low, high], payload: self.len, crc: [crc, byte], crc_len: 2 },
+                    _ => Consumed { header: [SYNC[1], low, high], payload: 0, crc: [0; 2], crc_len: 0 }
+                };
+                match self.rescan(&consumed)
+                {
+                    Some(len) => Ok(Some(len)),
+                    None => Err(fault)
+                }
+            },
+            result => result
+        }
+    }
+
+    /* run the bytes a failed packet used up back through the receiver,
+       returning a packet's length if one turns up whole among them. a false
+       start found while rescanning is skipped over in the same way. payload
+       bytes aren't copied while rescanning, as they're already in buf */
+    fn rescan(&mut self, consumed: &Consumed) -> Option<usize>
+    {
+        let (mut index, mut sync) = (0, 0);
+        self.state = State::Hunt;
+        while index < consumed.len()
+        {
+            match self.step(consumed.get(self.buf, index), Some(index))
+            {
+                Ok(Some(len)) =>
+                {
+                    self.settle(consumed);
+                    return Some(len)
+                },
+                Ok(None) if self.state == State::Sync => sync = index,
+                Ok(None) => (),
+                Err(_) => index = sync
+            }
+            index += 1;
+        }
+
+        self.settle(consumed);
+        None
+    }
+
+    /* move the payload found while rescanning to the start of buf, where the
+       rest of it will arrive. every byte moves down, so none is overwritten
+       before it's copied */
+    fn settle(&mut self, consumed: &Consumed)
+    {
+        let count = match self.state
+        {
+            State::Payload => self.pos,
+            State::CrcHigh | State::Crc(_) | State::Complete => self.len,
+            _ => 0
+        };
+        for i in 0..count
+        {
+            self.buf[i] = consumed.get(self.buf, self.start + HEADER + i);
+        }
+        self.start = 0;
+    }
+
+    /* move the state machine on by a byte. while rescanning, at is the byte's
+       index among the consumed bytes, and payload bytes are left where they are */
+    fn step(&mut self, byte: u8, at: Option<usize>) -> Result<Option<usize>, Fault>
+    {
+        self.state = match (self.state, byte)
+        {
+            (State::Hunt, b) | (State::Complete, b) if b == SYNC[0] => State::Sync,
+            (State::Hunt, _) | (State::Complete, _) => State::Hunt,
+
+            (State::Sync, b) if b == SYNC[1] => State::LengthLow,
+            (State::Sync, b) if b == SYNC[0] => State::Sync,
+            (State::Sync, _) => State::Hunt,
+
+            (State::LengthLow, low) => State::Length(low),
+            (State::Length(low), high) =>
+            {
+                self.len = u16::from_le_bytes([low, high]) as usize;
+                self.pos = 0;
+                self.start = match at
+                {
+                    Some(at) => at + 1 - HEADER,
+                    None => 0
+                };
+                if self.len > self.buf.len()
+                {
+                    self.state = State::Hunt;
+                    return Err(Fault::FrameTooLong)
+                }
+                match self.len
+                {
+                    0 => State::CrcHigh,
+                    _ => State::Payload
+                }
+            },
+
+            (State::Payload, b) =>
+            {
+                if at.is_none()
+                {
+                    self.buf[self.pos] = b;
+                }
+                self.pos += 1;
+                match self.pos == self.len
+                {
+                    true => State::CrcHigh,
+                    false => State::Payload
+                }
+            },
+
+            (State::CrcHigh, high) => State::Crc(high),
+            (State::Crc(high), low) =>
+            {
+                if u16::from_be_bytes([high, low]) != checksum(&self.buf[self.start..self.start + self.len])
+                {
+                    self.state = State::Hunt;
+                    return Err(Fault::BadFrame)
+                }
+
+                self.state = State::Complete;
+                return Ok(Some(self.len))
+            }
+        };
+
+        Ok(None)
+    }
+
+    /* return the most recently verified payload, or an empty slice if a packet
+       is still being received */
+    pub fn payload(&self) -> &[u8]
+    {
+        match self.state
+        {
+            State::Complete => &self.buf[..self.len],
+            _ => &[]
+        }
+    }
+
+    /* abandon the packet in progress and hunt for the next one */
+    pub fn reset(&mut self)
+    {
+        self.state = State::Hunt;
+        self.start = 0;
+    }
+}
+
+/* CRC over a packet's length field and payload */
+fn checksum(payload: &[u8]) -> u16
+{
+    crc16(crc16(0, &(payload.len() as u16).to_le_bytes()), payload)
+}
+
+impl UART
+{
+    /* transmit the given payload as a single packet */
+    pub fn send_packet(&self, payload: &[u8]) -> Result<(), Fault>
+    {
+        if payload.len() > MAX_PAYLOAD
+        {
+            return Err(Fault::FrameTooLong)
+        }
+
+        let header = (payload.len() as u16).to_le_bytes();
+        let crc = checksum(payload).to_be_bytes();
+
+        for &byte in SYNC.iter().chain(header.iter()).chain(payload).chain(crc.iter())
+        {
+            self.send_byte(byte)?;
+        }
+
+        Ok(())
+    }
+
+    /* receive a packet's payload into buf, returning its length. gives up
+       with DataNotReady if nothing at all arrives, but once a byte has, waits
+       as long as it takes for a packet, however slow the line. use
+       read_packet_timeout() to bound that too, or a PacketReceiver directly
+       to receive packets across multiple calls */
+    pub fn read_packet(&self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        let first = self.wait_for_byte()?;
+        self.read_rest_of_packet(buf, first, &mut Forever)
+    }
+
+    /* receive a packet's payload into buf, returning its length, waiting for
+       each byte until timeout expires. a Deadline bounds the whole packet.
+       gives up with DataNotReady, discarding any partial packet */
+    pub fn read_packet_timeout<T: Timeout>(&self, buf: &mut [u8], timeout: &mut T) -> Result<usize, Fault>
+    {
+        let first = self.read_byte_timeout(timeout)?;
+        self.read_rest_of_packet(buf, first, timeout)
+    }
+
+    /* hunt for a packet starting with first, waiting for each later byte
+       until timeout expires */
+    fn read_rest_of_packet<T: Timeout>(&self, buf: &mut [u8], first: u8, timeout: &mut T) -> Result<usize, Fault>
+    {
+        let mut receiver = PacketReceiver::new(buf);
+        let mut byte = first;
+        loop
+        {
+            if let Some(len) = receiver.push(byte)?
+            {
+                return Ok(len)
+            }
+            byte = self.read_byte_timeout(timeout)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Fifos;
+    use crate::timeout::Polls;
+    use crate::LOOP_MAX;
+
+    fn packet(payload: &[u8]) -> Vec<u8>
+    {
+        let mut bytes = SYNC.to_vec();
+        bytes.extend(&(payload.len() as u16).to_le_bytes());
+        bytes.extend(payload);
+        bytes.extend(&checksum(payload).to_be_bytes());
+        bytes
+    }
+
+    /* feed bytes to a receiver, collecting the payloads and errors that come out */
+    fn feed(bytes: &[u8]) -> (Vec<Vec<u8>>, usize)
+    {
+        let mut buf = [0u8; 16];
+        let mut receiver = PacketReceiver::new(&mut buf);
+        let (mut payloads, mut errors) = (vec![], 0);
+        for &byte in bytes
+        {
+            match receiver.push(byte)
+            {
+                Ok(Some(_)) => payloads.push(receiver.payload().to_vec()),
+                Ok(None) => (),
+                Err(_) => errors += 1
+            }
+        }
+        (payloads, errors)
+    }
+
+    #[test]
+    fn receives_back_to_back_packets()
+    {
+        let mut bytes = packet(b"hello");
+        bytes.extend(packet(b""));
+        bytes.extend(packet(&SYNC));
+
+        let (payloads, errors) = feed(&bytes);
+        assert_eq!(errors, 0);
+        assert_eq!(payloads, [b"hello".to_vec(), vec![], SYNC.to_vec()]);
+    }
+
+    #[test]
+    fn resyncs_after_noise_and_corruption()
+    {
+        let mut corrupt = packet(b"broken");
+        corrupt[5] ^= 0x10;
+
+        let mut bytes = vec![0x00, SYNC[0], 0x13, SYNC[0]];
+        bytes.extend(corrupt);
+        bytes.extend([0xff, 0xa5]);
+        bytes.extend(packet(b"good"));
+
+        let (payloads, errors) = feed(&bytes);
+        assert_eq!(errors, 1);
+        assert_eq!(payloads, [b"good".to_vec()]);
+    }
+
+    #[test]
+    fn rescans_after_false_starts()
+    {
+        /* a false header announcing a length too long for the buffer swallows
+           the real packet's sync bytes */
+        let mut bytes = vec![SYNC[0], SYNC[1], SYNC[0], SYNC[1]];
+        bytes.extend(&packet(b"ok")[2..]);
+        assert_eq!(feed(&bytes), (vec![b"ok".to_vec()], 1));
+
+        /* one announcing a short length ends partway through the real packet */
+        let mut bytes = vec![SYNC[0], SYNC[1], 5, 0];
+        bytes.extend(packet(b"hi"));
+        assert_eq!(feed(&bytes), (vec![b"hi".to_vec()], 1));
+
+        /* and one announcing a longer length swallows the whole of it, along
+           with another false start */
+        let mut bytes = vec![SYNC[0], SYNC[1], 14, 0, SYNC[0], SYNC[1], 9, 0];
+        bytes.extend(packet(b"hi"));
+        bytes.extend([0; 4]);
+        bytes.extend(packet(b"next"));
+        assert_eq!(feed(&bytes), (vec![b"hi".to_vec(), b"next".to_vec()], 0));
+    }
+
+    /* bytes trickling in far slower than LOOP_MAX polls apart */
+    #[test]
+    fn waits_for_slow_packets()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let mut buf = [0u8; 8];
+        assert!(matches!(uart.read_packet(&mut buf), Err(Fault::DataNotReady)));
+
+        fifos.arrive(&packet(b"slow"));
+        fifos.shift(1);
+        fifos.set_pace(LOOP_MAX * 3);
+        assert_eq!(uart.read_packet(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"slow");
+
+        fifos.arrive(&packet(b"slow"));
+        assert!(matches!(uart.read_packet_timeout(&mut buf, &mut Polls::new(LOOP_MAX)), Err(Fault::DataNotReady)));
+        assert_eq!(uart.read_packet_timeout(&mut buf, &mut Polls::new(LOOP_MAX * 4)).unwrap(), 4);
+    }
+
+    #[test]
+    fn rejects_oversized_packets()
+    {
+        let mut bytes = packet(&[7; 20]);
+        bytes.extend(packet(b"ok"));
+
+        let (payloads, errors) = feed(&bytes);
+        assert_eq!(errors, 1);
+        assert_eq!(payloads, [b"ok".to_vec()]);
+    }
+}
@@ -0,0 +1,175 @@
+/* throttle long output so a person can read it
+ *
+ * At 115200 baud a memory dump scrolls off the terminal faster than anyone
+ * can read it. Pager sends output on a UART and, after every page of lines,
+ * shows --More-- and waits for a key: space shows the next page, enter one
+ * more line, and q or ctrl-C stops the output with Fault::Cancelled. It can
+ * also honor XOFF (ctrl-S) and XON (ctrl-Q) from the terminal, eg:
+ *
+ *   let mut pager = Pager::new(&uart, 24);
+ *   pager.set_flow_control(true);
+ *   writeln!(pager, "...")?;
+ *
+ * Keys are only looked for while paused or, with flow control on, between
+ * bytes, when anything other than XOFF and XON is discarded.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::output::Partial;
+
+const CTRL_C: u8 = 0x03;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+const MORE: &[u8] = b"--More--";
+const UNMORE: &[u8] = b"\r        \r";   /* rub out MORE */
+
+pub struct Pager<'a>
+{
+    uart: &'a UART,
+    page: usize,            /* lines per page, or 0 to never pause */
+    lines: usize,           /* lines sent since the last pause */
+    flow_control: bool
+}
+
+impl<'a> Pager<'a>
+{
+    /* page output sent on uart every page lines. a page of 0 never pauses */
+    pub const fn new(uart: &'a UART, page: usize) -> Self
+    {
+        Pager { uart, page, lines: 0, flow_control: false }
+    }
+
+    /* pause while the terminal has sent XOFF, until it sends XON */
+    pub fn set_flow_control(&mut self, enable: bool)
+    {
+        self.flow_control = enable;
+    }
+
+    /* start a fresh page, eg after the user has typed a command */
+    pub fn reset(&mut self)
+    {
+        self.lines = 0;
+    }
+
+    /* send each byte of data, pausing as needed, and return how many were sent.
+       fails with Cancelled if the user quits at a pause */
+    pub fn send_bytes(&mut self, data: &[u8]) -> Result<usize, Partial>
+    {
+        for (sent, &byte) in data.iter().enumerate()
+        {
+            self.send_byte(byte).map_err(|fault| Partial { sent, fault })?;
+        }
+
+        Ok(data.len())
+    }
+
+    /* send a string's UTF-8 bytes, as send_bytes() */
+    pub fn send_str(&mut self, string: &str) -> Result<usize, Partial>
+    {
+        self.send_bytes(string.as_bytes())
+    }
+
+    fn send_byte(&mut self, byte: u8) -> Result<(), Fault>
+    {
+        /* pause before the line after a full page, so output that ends on a
+           page boundary doesn't wait for a key */
+        if self.page > 0 && self.lines >= self.page
+        {
+            self.more()?;
+        }
+
+        if self.flow_control
+        {
+            self.check_xoff();
+        }
+
+        self.uart.send_byte(byte)?;
+        if byte == b'\n'
+        {
+            self.lines += 1;
+        }
+
+        Ok(())
+    }
+
+    /* show MORE and wait for a key */
+    fn more(&mut self) -> Result<(), Fault>
+    {
+        self.uart.send_bytes(MORE).map_err(|partial| partial.fault)?;
+        let key = self.uart.read_byte_blocking();
+        self.uart.send_bytes(UNMORE).map_err(|partial| partial.fault)?;
+
+        self.lines = match key
+        {
+            b'q' | b'Q' | CTRL_C => return Err(Fault::Cancelled),
+            b'\r' | b'\n' => self.page - 1,
+            _ => 0
+        };
+        Ok(())
+    }
+
+    /* if XOFF has arrived, wait for XON */
+    fn check_xoff(&self)
+    {
+        if !matches!(self.uart.read_byte(), Ok(XOFF))
+        {
+            return
+        }
+
+        while !matches!(self.uart.read_byte(), Ok(XON))
+        {
+            self.uart.relax();
+        }
+    }
+}
+
+impl core::fmt::Write for Pager<'_>
+{
+    fn write_str(&mut self, string: &str) -> core::fmt::Result
+    {
+        self.send_str(string).map(|_| ()).map_err(|_| core::fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    #[test]
+    fn pauses_every_page()
+    {
+        let (script, uart) = Script::uart(b" \r ");
+        let mut pager = Pager::new(&uart, 2);
+        assert!(matches!(pager.send_str("1\n2\n3\n4\n5\n6\n"), Ok(12)));
+        assert_eq!(script.sent(), b"1\n2\n--More--\r        \r3\n4\n--More--\r        \r5\n--More--\r        \r6\n");
+
+        script.receive(b"q");
+        assert!(matches!(pager.send_str("7\n8\n"), Err(Partial { sent: 2, fault: Fault::Cancelled })));
+
+        /* a new page after a reset */
+        script.sent();
+        pager.reset();
+        assert!(matches!(pager.send_str("8\n"), Ok(2)));
+        assert_eq!(script.sent(), b"8\n");
+    }
+
+    #[test]
+    fn honors_xoff()
+    {
+        let (script, uart) = Script::uart(b"\x13x\x11");
+        let mut pager = Pager::new(&uart, 0);
+        pager.set_flow_control(true);
+        assert!(matches!(pager.send_str("ok"), Ok(2)));
+        assert_eq!(script.sent(), b"ok");
+
+        /* everything up to XON was read before the first byte went out */
+        assert!(uart.read_byte().is_err());
+    }
+}
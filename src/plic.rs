@@ -0,0 +1,210 @@
+/* routing the UART's interrupt through a RISC-V PLIC
+ *
+ * Getting a UART interrupt to a hart takes work in two places: the UART's
+ * own interrupt enable bits, and the platform-level interrupt controller's
+ * priority, enable and threshold registers for the right source and
+ * context. InterruptController describes the PLIC operations this needs,
+ * matching the hooks PLIC crates provide, so any of them can be adapted
+ * in a few lines. Plic implements it for a standard memory-mapped PLIC,
+ * as found in SiFive's SoCs and QEMU's virt machine, eg:
+ *
+ *   let plic = Plic::new(0x0c00_0000);
+ *   uart.route_irq(&plic, info.irq, 3, HART1_S_CONTEXT);
+ *   ...
+ *   fn external_irq()
+ *   {
+ *       if let Some(other) = UART.service_plic(&plic, HART1_S_CONTEXT, info.irq, &BUFFERS)
+ *       {
+ *           handle_other(other);
+ *           plic.complete(HART1_S_CONTEXT, other);
+ *       }
+ *   }
+ *
+ * A context is a hart's privilege mode as numbered by the SoC: on the
+ * FU540 and FU740, hart 0 has context 0 and hart n has contexts 2n - 1
+ * for M-mode and 2n for S-mode. QEMU's virt numbers them 2n and 2n + 1.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::ptr::{read_volatile, write_volatile};
+use super::UART;
+use super::buffered::Buffers;
+
+/* offsets of a standard PLIC's registers from its base */
+const PLIC_PRIORITY: usize = 0x0;              /* 4 bytes per source */
+const PLIC_ENABLE: usize = 0x2000;             /* 0x80 bytes per context, a bit per source */
+const PLIC_ENABLE_STRIDE: usize = 0x80;
+const PLIC_CONTEXT: usize = 0x20_0000;         /* 0x1000 bytes per context */
+const PLIC_CONTEXT_STRIDE: usize = 0x1000;
+const PLIC_THRESHOLD: usize = 0x0;             /* within a context's block */
+const PLIC_CLAIM: usize = 0x4;                 /* within a context's block, also used to complete */
+
+/* the interrupt controller operations needed to route and service the UART's interrupt */
+pub trait InterruptController
+{
+    /* set source's priority. zero never interrupts */
+    fn set_priority(&self, source: u32, priority: u32);
+
+    /* allow or stop source interrupting context */
+    fn enable(&self, context: usize, source: u32, enable: bool);
+
+    /* only interrupt context for sources with a priority above threshold */
+    fn set_threshold(&self, context: usize, threshold: u32);
+
+    /* take the highest priority pending source for context, if any */
+    fn claim(&self, context: usize) -> Option<u32>;
+
+    /* tell the controller a claimed source has been handled */
+    fn complete(&self, context: usize, source: u32);
+}
+
+/* a memory-mapped PLIC with the standard register layout */
+pub struct Plic
+{
+    base_addr: usize
+}
+
+impl Plic
+{
+    /* the PLIC at base_addr, which must be mapped and left to this object */
+    pub const fn new(base_addr: usize) -> Self
+    {
+        Plic { base_addr }
+    }
+
+    fn read(&self, offset: usize) -> u32
+    {
+        unsafe { read_volatile((self.base_addr + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32)
+    {
+        unsafe { write_volatile((self.base_addr + offset) as *mut u32, value) }
+    }
+
+    fn context(context: usize, reg: usize) -> usize
+    {
+        PLIC_CONTEXT + context * PLIC_CONTEXT_STRIDE + reg
+    }
+}
+
+impl InterruptController for Plic
+{
+    fn set_priority(&self, source: u32, priority: u32)
+    {
+        self.write(PLIC_PRIORITY + source as usize * 4, priority);
+    }
+
+    fn enable(&self, context: usize, source: u32, enable: bool)
+    {
+        let offset = PLIC_ENABLE + context * PLIC_ENABLE_STRIDE + (source as usize / 32) * 4;
+        let bit = 1 << (source % 32);
+        let bits = self.read(offset);
+        match enable
+        {
+            true => self.write(offset, bits | bit),
+            false => self.write(offset, bits & !bit)
+        }
+    }
+
+    fn set_threshold(&self, context: usize, threshold: u32)
+    {
+        self.write(Plic::context(context, PLIC_THRESHOLD), threshold);
+    }
+
+    fn claim(&self, context: usize) -> Option<u32>
+    {
+        match self.read(Plic::context(context, PLIC_CLAIM))
+        {
+            0 => None,
+            source => Some(source)
+        }
+    }
+
+    fn complete(&self, context: usize, source: u32)
+    {
+        self.write(Plic::context(context, PLIC_CLAIM), source);
+    }
+}
+
+impl UART
+{
+    /* route this UART's interrupt, which is plic's source number source, to
+       context at priority. the context's threshold is left alone, so it must
+       be below priority. the rx watermark interrupt is enabled; the tx
+       watermark interrupt is left to the buffered drivers, which enable it as
+       they need it */
+    pub fn route_irq<P: InterruptController>(&self, plic: &P, source: u32, priority: u32, context: usize)
+    {
+        plic.set_priority(source, priority);
+        plic.enable(context, source, true);
+        self.enable_rx_watermark_irq(true);
+    }
+
+    /* stop this UART interrupting context, undoing route_irq() */
+    pub fn unroute_irq<P: InterruptController>(&self, plic: &P, source: u32, context: usize)
+    {
+        self.enable_rx_watermark_irq(false);
+        self.enable_tx_watermark_irq(false);
+        plic.enable(context, source, false);
+    }
+
+    /* claim context's next interrupt from plic. if it's this UART's source,
+       service it with handle_interrupt() and complete it, returning None. any
+       other source claimed is returned for the caller to handle and complete */
+    pub fn service_plic<P, const RX: usize, const TX: usize>(&self, plic: &P, context: usize, source: u32,
+                                                             buffers: &Buffers<RX, TX>) -> Option<u32>
+        where P: InterruptController
+    {
+        match plic.claim(context)?
+        {
+            claimed if claimed == source =>
+            {
+                self.handle_interrupt(buffers);
+                plic.complete(context, source);
+                None
+            },
+            other => Some(other)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+    use crate::{REG_IE, REG_IE_RXWM};
+
+    #[test]
+    fn routes_and_services_irq()
+    {
+        let mut regs = vec![0u32; (PLIC_CONTEXT + 3 * PLIC_CONTEXT_STRIDE) / 4];
+        let base = regs.as_mut_ptr() as usize;
+        let plic = Plic::new(base);
+        let word = |offset: usize| unsafe { read_volatile((base + offset) as *const u32) };
+
+        let (_, uart) = Script::uart(b"ab");
+        uart.route_irq(&plic, 39, 3, 2);
+        assert_eq!(word(39 * 4), 3);
+        assert_eq!(word(PLIC_ENABLE + 2 * PLIC_ENABLE_STRIDE + 4), 1 << 7);
+        assert_ne!(uart.read_reg(REG_IE) & REG_IE_RXWM, 0);
+
+        /* in plain memory, the claim register reads back whatever was last
+           completed: nothing pending, then another source, then the UART's */
+        let buffers: Buffers<8, 8> = Buffers::new();
+        assert_eq!(uart.service_plic(&plic, 2, 39, &buffers), None);
+        plic.complete(2, 7);
+        assert_eq!(uart.service_plic(&plic, 2, 39, &buffers), Some(7));
+        plic.complete(2, 39);
+        assert_eq!(uart.service_plic(&plic, 2, 39, &buffers), None);
+        assert_eq!(buffers.rx.len(), 2);
+
+        uart.unroute_irq(&plic, 39, 2);
+        assert_eq!(word(PLIC_ENABLE + 2 * PLIC_ENABLE_STRIDE + 4), 0);
+        assert_eq!(uart.read_reg(REG_IE), 0);
+    }
+}
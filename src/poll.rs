@@ -0,0 +1,281 @@
+/* buffered I/O for main-loop firmware without interrupts
+ *
+ * Firmware built as a single super-loop, with no PLIC set up, can still
+ * use a set of Buffers to send and receive more than the hardware FIFOs
+ * hold. Polled does the interrupt handler's job when asked: one call to
+ * poll() each time round the loop checks the controller and moves bytes
+ * between it and the buffers, and reports what changed, eg:
+ *
+ *   static BUFFERS: Buffers<64, 256> = Buffers::new();
+ *   let mut port = Polled::new(&uart, &BUFFERS)?;
+ *   loop
+ *   {
+ *       let events = port.poll();
+ *       if events.received > 0 { handle_input(&mut port); }
+ *       if events.sent > 0 { queue_more_output(&mut port); }
+ *       do_other_work();
+ *   }
+ *
+ * With nothing waiting in either direction, poll() costs one read of the
+ * interrupt pending register. The buffers must not also be serviced by an
+ * interrupt handler, and poll() must be called often enough that the rx
+ * FIFO doesn't overflow between calls.
+ *
+ * Systems that can't or won't take PLIC interrupts can instead call tick()
+ * from a periodic timer. The tick period must be no longer than
+ * max_tick_us() at the baud rate, the time the hardware rx FIFO takes to
+ * fill, and the rx buffer must hold bytes_per_tick() for every tick that can
+ * pass before the rest of the system reads it. Ticks that find the rx FIFO
+ * still holding bytes after taking a FIFO's worth ran late, and may have
+ * lost input: late_ticks() counts them, and Buffers::overruns() counts
+ * bytes lost because the rx buffer was full.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, WatermarkLevel};
+use super::buffered::Buffers;
+use super::handler::UartEventHandler;
+
+/* what a call to poll() did */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Events
+{
+    pub received: usize,    /* bytes added to the rx buffer */
+    pub sent: usize         /* bytes handed to the controller, freeing room in the tx buffer */
+}
+
+impl Events
+{
+    /* return true if anything changed */
+    pub fn any(&self) -> bool
+    {
+        self.received > 0 || self.sent > 0
+    }
+}
+
+/* most bytes that can arrive in tick_us microseconds at baud */
+pub fn bytes_per_tick(baud: u32, tick_us: u32) -> usize
+{
+    (tick_us as u64 * baud as u64).div_ceil(UART::BITS_PER_CHAR as u64 * 1_000_000) as usize
+}
+
+/* longest tick period, in microseconds, before a full FIFO's worth of bytes
+   can arrive at baud and further input is lost */
+pub fn max_tick_us(baud: u32) -> u32
+{
+    (UART::FIFO_DEPTH as u64 * UART::BITS_PER_CHAR as u64 * 1_000_000 / baud.max(1) as u64) as u32
+}
+
+pub struct Polled<'a, const RX: usize, const TX: usize>
+{
+    uart: &'a UART,
+    buffers: &'a Buffers<RX, TX>,
+    late_ticks: usize   /* ticks that found more than a FIFO's worth of input */
+}
+
+impl<'a, const RX: usize, const TX: usize> Polled<'a, RX, TX>
+{
+    /* poll uart, moving bytes through buffers. the rx watermark is set to zero
+       so the controller reports a single received byte as pending. the buffers
+       can only be used by one driver; AlreadyClaimed is returned if they
+       already have been */
+    pub fn new(uart: &'a UART, buffers: &'a Buffers<RX, TX>) -> Result<Self, Fault>
+    {
+        buffers.claim()?;
+        uart.set_rx_watermark(WatermarkLevel::L0);
+        Ok(Polled { uart, buffers, late_ticks: 0 })
+    }
+
+    /* move received bytes into the rx buffer, and queued bytes to the
+       controller, and return how many of each. does at most one hardware FIFO's
+       worth in each direction */
+    pub fn poll(&mut self) -> Events
+    {
+        let mut events = Events::default();
+        if self.uart.ip().rxwm
+        {
+            events.received = self.uart.pump_rx(self.buffers);
+        }
+        if !self.buffers.tx.is_empty()
+        {
+            events.sent = self.uart.pump_tx(self.buffers);
+        }
+        events
+    }
+
+    /* call from a periodic timer to move bytes as poll() does, and count the
+       tick as late if bytes are still waiting in the rx FIFO afterwards */
+    pub fn tick(&mut self) -> Events
+    {
+        let events = self.poll();
+        if events.received > 0 && self.uart.is_rx_ready()
+        {
+            self.late_ticks += 1;
+        }
+        events
+    }
+
+    /* number of ticks that found more input than the rx FIFO holds, so may
+       have lost some because the tick period is too long */
+    pub fn late_ticks(&self) -> usize
+    {
+        self.late_ticks
+    }
+
+    /* poll(), then tell handler what changed */
+    pub fn poll_with<H: UartEventHandler>(&mut self, handler: &mut H) -> Events
+    {
+        let events = self.poll();
+        if events.received > 0
+        {
+            handler.rx_ready(self.available());
+        }
+        if events.sent > 0
+        {
+            handler.tx_space(self.free());
+        }
+        events
+    }
+
+    /* queue as much of data as fits for poll() to send, returning how many bytes
+       were queued */
+    pub fn write(&mut self, data: &[u8]) -> usize
+    {
+        self.buffers.tx.push_from(data)
+    }
+
+    /* take as many bytes received by poll() as fit in buf, returning how many */
+    pub fn read(&mut self, buf: &mut [u8]) -> usize
+    {
+        self.buffers.rx.pop_into(buf)
+    }
+
+    /* room left for bytes to queue */
+    pub fn free(&self) -> usize
+    {
+        self.buffers.tx.free()
+    }
+
+    /* number of received bytes waiting to be read */
+    pub fn available(&self) -> usize
+    {
+        self.buffers.rx.len()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use crate::bus::{self, Bus};
+    use crate::tests::FakeRegisters;
+    use crate::{REG_IP, REG_IP_RXWM, REG_RXCTRL_RXEN, REG_RXDATA, REG_RXDATA_EMPTY, REG_TXDATA};
+
+    #[test]
+    fn poll_pumps_buffers()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<4, 16> = Buffers::new();
+        let mut port = Polled::new(&uart, &buffers).unwrap();
+        assert!(matches!(uart.split(&buffers), Err(Fault::AlreadyClaimed)));
+        assert_eq!(uart.rx_watermark(), WatermarkLevel::L0);
+        assert!(!port.poll().any());
+
+        /* the fake never empties, so a poll reads one FIFO's worth */
+        regs.receive(b'p');
+        assert!(!port.poll().any());
+        regs.set(REG_IP, REG_IP_RXWM);
+        assert_eq!(port.poll(), Events { received: 4, sent: 0 });
+        regs.set(REG_IP, 0);
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(port.read(&mut buf), 4);
+        assert_eq!(&buf[..4], b"pppp");
+
+        assert_eq!(port.write(b"0123456789"), 10);
+        assert_eq!(port.free(), 6);
+        assert_eq!(port.poll(), Events { received: 0, sent: 8 });
+        assert_eq!(port.poll(), Events { received: 0, sent: 2 });
+        assert_eq!(regs.get(REG_TXDATA), b'9' as u32);
+        assert_eq!(port.available(), 0);
+    }
+
+    /* a controller whose IP reports rx watermark zero while input is waiting */
+    struct Line(Mutex<VecDeque<u8>>);
+
+    impl Bus for Line
+    {
+        fn read(&self, reg: usize) -> u32
+        {
+            let mut input = self.0.lock().unwrap();
+            match reg
+            {
+                bus::RXDATA => input.pop_front().map(|b| b as u32).unwrap_or(bus::FIFO_FLAG),
+                bus::IP if !input.is_empty() => REG_IP_RXWM,
+                bus::RXCTRL => REG_RXCTRL_RXEN,
+                _ => 0
+            }
+        }
+
+        fn write(&self, _: usize, _: u32) {}
+    }
+
+    #[test]
+    fn ticks_keep_up()
+    {
+        assert_eq!(bytes_per_tick(115200, 1000), 12);
+        assert_eq!(bytes_per_tick(9600, 1000), 1);
+        assert_eq!(max_tick_us(115200), 694);
+
+        let line: &'static Line = Box::leak(Box::new(Line(Mutex::new(b"0123456789".iter().copied().collect()))));
+        let uart = UART::on_bus(line);
+        let buffers: Buffers<16, 16> = Buffers::new();
+        let mut port = Polled::new(&uart, &buffers).unwrap();
+
+        assert_eq!(port.tick(), Events { received: 8, sent: 0 });
+        assert_eq!(port.late_ticks(), 1);
+        assert_eq!(port.tick(), Events { received: 2, sent: 0 });
+        assert_eq!(port.late_ticks(), 1);
+
+        line.0.lock().unwrap().extend(b"ab");
+        port.tick();
+        assert_eq!(port.late_ticks(), 1);
+        assert_eq!(port.available(), 12);
+    }
+
+    #[test]
+    fn handler_hears_events()
+    {
+        #[derive(Default)]
+        struct Counts { rx: usize, tx: usize }
+
+        impl UartEventHandler for Counts
+        {
+            fn rx_ready(&mut self, available: usize) { self.rx += available; }
+            fn tx_space(&mut self, free: usize) { self.tx += free; }
+        }
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<4, 16> = Buffers::new();
+        let mut port = Polled::new(&uart, &buffers).unwrap();
+        let mut counts = Counts::default();
+
+        port.write(b"abc");
+        regs.receive(b'p');
+        regs.set(REG_IP, REG_IP_RXWM);
+        port.poll_with(&mut counts);
+        assert_eq!((counts.rx, counts.tx), (4, 16));
+
+        regs.set(REG_IP, 0);
+        port.poll_with(&mut counts);
+        assert_eq!((counts.rx, counts.tx), (4, 16));
+    }
+}
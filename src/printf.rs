@@ -0,0 +1,292 @@
+/* printf-style formatting without core::fmt
+ *
+ * core::fmt's machinery costs several KB of code, which matters on parts
+ * such as the FE310 with little flash to spare. This formatter understands
+ * the handful of conversions console messages need, and the convenience
+ * printing methods, such as send_hex_u64(), are built on it:
+ *
+ *   %d %i   signed decimal          %u      unsigned decimal
+ *   %x %X   lowercase or uppercase hex, without a prefix
+ *   %s      string                  %c      character
+ *   %%      a percent sign
+ *
+ * A width may follow the %, padding with spaces on the left, or with zeroes
+ * if it starts with 0, or on the right if it starts with -. Arguments are
+ * passed as a slice of Arg, eg:
+ *
+ *   uart.printf("%-8s %08x\r\n", &["pc".into(), pc.into()])?;
+ *
+ * or with the uart_printf! macro, which converts each argument:
+ *
+ *   uart_printf!(uart, "%-8s %08x\r\n", "pc", pc)?;
+ *
+ * An argument that doesn't suit its conversion, or a missing one, is
+ * printed as ?, rather than failing.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::output::Partial;
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+const UPPER_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/* an argument to be formatted */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg<'a>
+{
+    Str(&'a str),
+    Char(char),
+    Int(i64),
+    Uint(u64)
+}
+
+impl<'a> From<&'a str> for Arg<'a>
+{
+    fn from(string: &'a str) -> Self
+    {
+        Arg::Str(string)
+    }
+}
+
+impl From<char> for Arg<'_>
+{
+    fn from(c: char) -> Self
+    {
+        Arg::Char(c)
+    }
+}
+
+macro_rules! from_int
+{
+    ($variant:ident, $wide:ty, $($int:ty),*) =>
+    {
+        $(
+            impl From<$int> for Arg<'_>
+            {
+                fn from(value: $int) -> Self
+                {
+                    Arg::$variant(value as $wide)
+                }
+            }
+        )*
+    }
+}
+
+from_int!(Int, i64, i8, i16, i32, i64, isize);
+from_int!(Uint, u64, u8, u16, u32, u64, usize);
+
+/* how a conversion's output is padded to its width */
+#[derive(Clone, Copy)]
+struct Spec
+{
+    width: usize,
+    zero: bool,     /* pad numbers with zeroes after any sign */
+    left: bool      /* pad on the right */
+}
+
+/* format args according to fmt, writing each byte of the result to out */
+pub fn format<O: FnMut(u8) -> Result<(), Fault>>(out: &mut O, fmt: &str, args: &[Arg]) -> Result<(), Fault>
+{
+    let mut args = args.iter();
+    let mut fmt = fmt.bytes();
+
+    while let Some(byte) = fmt.next()
+    {
+        if byte != b'%'
+        {
+            out(byte)?;
+            continue
+        }
+
+        let mut spec = Spec { width: 0, zero: false, left: false };
+        let mut conversion = fmt.next();
+        loop
+        {
+            match conversion
+            {
+                Some(b'-') if spec.width == 0 => spec.left = true,
+                Some(b'0') if spec.width == 0 => spec.zero = true,
+                Some(digit @ b'0'..=b'9') => spec.width = spec.width.saturating_mul(10).saturating_add((digit - b'0') as usize),
+                _ => break
+            }
+            conversion = fmt.next();
+        }
+
+        match (conversion, args.next())
+        {
+            (Some(b'%'), _) => out(b'%')?,
+            (Some(b'd') | Some(b'i'), Some(&Arg::Int(value))) => number(out, value.unsigned_abs(), value < 0, 10, DIGITS, spec)?,
+            (Some(b'd') | Some(b'i') | Some(b'u'), Some(&Arg::Uint(value))) => number(out, value, false, 10, DIGITS, spec)?,
+            (Some(b'x'), Some(&Arg::Uint(value))) => number(out, value, false, 16, DIGITS, spec)?,
+            (Some(b'X'), Some(&Arg::Uint(value))) => number(out, value, false, 16, UPPER_DIGITS, spec)?,
+
+            /* negative numbers are printed in hex as their two's complement */
+            (Some(b'x'), Some(&Arg::Int(value))) => number(out, value as u64, false, 16, DIGITS, spec)?,
+            (Some(b'X'), Some(&Arg::Int(value))) => number(out, value as u64, false, 16, UPPER_DIGITS, spec)?,
+            (Some(b'u'), Some(&Arg::Int(value))) if value >= 0 => number(out, value as u64, false, 10, DIGITS, spec)?,
+
+            (Some(b's'), Some(&Arg::Str(string))) => padded(out, string.as_bytes(), spec)?,
+            (Some(b'c'), Some(&Arg::Char(c))) =>
+            {
+                let mut encoded = [0u8; 4];
+                padded(out, c.encode_utf8(&mut encoded).as_bytes(), spec)?
+            },
+
+            (None, _) => break,
+            _ => out(b'?')?
+        }
+    }
+
+    Ok(())
+}
+
+/* write value in the given radix, with a leading minus sign if negative */
+fn number<O: FnMut(u8) -> Result<(), Fault>>(out: &mut O, mut value: u64, negative: bool, radix: u64, digits: &[u8; 16], spec: Spec) -> Result<(), Fault>
+{
+    /* enough for u64::MAX in decimal, or a minus sign and i64::MIN */
+    let mut buf = [0u8; 20];
+    let mut start = buf.len();
+    loop
+    {
+        start -= 1;
+        buf[start] = digits[(value % radix) as usize];
+        value /= radix;
+        if value == 0
+        {
+            break
+        }
+    }
+
+    let len = buf.len() - start + negative as usize;
+    let padding = spec.width.saturating_sub(len);
+
+    if !spec.left && !spec.zero
+    {
+        repeat(out, b' ', padding)?;
+    }
+    if negative
+    {
+        out(b'-')?;
+    }
+    if !spec.left && spec.zero
+    {
+        repeat(out, b'0', padding)?;
+    }
+    for &digit in &buf[start..]
+    {
+        out(digit)?;
+    }
+    if spec.left
+    {
+        repeat(out, b' ', padding)?;
+    }
+
+    Ok(())
+}
+
+/* write bytes padded with spaces to the width, counting characters rather than bytes */
+fn padded<O: FnMut(u8) -> Result<(), Fault>>(out: &mut O, bytes: &[u8], spec: Spec) -> Result<(), Fault>
+{
+    let chars = bytes.iter().filter(|&&byte| byte & 0xc0 != 0x80).count();
+    let padding = spec.width.saturating_sub(chars);
+
+    if !spec.left
+    {
+        repeat(out, b' ', padding)?;
+    }
+    for &byte in bytes
+    {
+        out(byte)?;
+    }
+    if spec.left
+    {
+        repeat(out, b' ', padding)?;
+    }
+
+    Ok(())
+}
+
+fn repeat<O: FnMut(u8) -> Result<(), Fault>>(out: &mut O, byte: u8, count: usize) -> Result<(), Fault>
+{
+    for _ in 0..count
+    {
+        out(byte)?;
+    }
+
+    Ok(())
+}
+
+impl UART
+{
+    /* send args formatted according to fmt, returning how many bytes were sent,
+       as send_bytes() */
+    pub fn printf(&self, fmt: &str, args: &[Arg]) -> Result<usize, Partial>
+    {
+        let mut sent = 0;
+        format(&mut |byte| { self.send_byte(byte)?; sent += 1; Ok(()) }, fmt, args)
+            .map(|_| sent)
+            .map_err(|fault| Partial { sent, fault })
+    }
+}
+
+/* uart_printf!(uart, fmt, args...) calls uart.printf(fmt, &[args...]), converting
+   each argument into an Arg */
+#[macro_export]
+macro_rules! uart_printf
+{
+    ($uart:expr, $fmt:expr $(, $arg:expr)* $(,)?) =>
+    {
+        $uart.printf($fmt, &[$($crate::printf::Arg::from($arg)),*])
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn sprintf(fmt: &str, args: &[Arg]) -> String
+    {
+        let mut text = vec![];
+        format(&mut |byte| { text.push(byte); Ok(()) }, fmt, args).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn conversions()
+    {
+        assert_eq!(sprintf("%d %i %u %x %X %%", &[(-42).into(), 7i32.into(), 42u8.into(), 0xbeefu32.into(), 0xbeefu32.into()]),
+                   "-42 7 42 beef BEEF %");
+        assert_eq!(sprintf("%d %u", &[i64::MIN.into(), u64::MAX.into()]), "-9223372036854775808 18446744073709551615");
+        assert_eq!(sprintf("%s=%c", &["key".into(), 'é'.into()]), "key=é");
+        assert_eq!(sprintf("%x", &[(-1i8).into()]), "ffffffffffffffff");
+    }
+
+    #[test]
+    fn widths()
+    {
+        assert_eq!(sprintf("[%5d|%-5d|%05d|%08x]", &[(-12).into(), 12.into(), (-12).into(), 0xabcu32.into()]),
+                   "[  -12|12   |-0012|00000abc]");
+        assert_eq!(sprintf("[%4s|%-4s|%2s|%3c]", &["ab".into(), "é".into(), "long".into(), 'x'.into()]),
+                   "[  ab|é   |long|  x]");
+    }
+
+    #[test]
+    fn mismatches_are_marked()
+    {
+        assert_eq!(sprintf("%d %s %u %q", &["x".into(), 1u8.into(), (-1).into()]), "? ? ? ?");
+        assert_eq!(sprintf("trailing %", &[]), "trailing ");
+    }
+
+    #[test]
+    fn prints_to_uart()
+    {
+        let (script, uart) = crate::tests::Script::uart(b"");
+        assert!(matches!(uart_printf!(uart, "%s %03u\r\n", "pc", 7u32), Ok(8)));
+        assert_eq!(script.sent(), b"pc 007\r\n");
+    }
+}
@@ -0,0 +1,446 @@
+/* readline-style line editing with history, in fixed-size buffers
+ *
+ * Readline edits a line of up to LINE bytes at a terminal that understands
+ * ANSI escape sequences, and remembers the last HIST lines entered, for
+ * on-target debug shells. The arrow keys move the cursor and step through
+ * history, and the usual emacs-style control keys work too:
+ *
+ *   ctrl-A, home       start of line       ctrl-E, end     end of line
+ *   ctrl-B, left       back a character    ctrl-F, right   forward a character
+ *   ctrl-P, up         previous line       ctrl-N, down    next line
+ *   backspace, DEL     rub out backwards   delete          rub out forwards
+ *   ctrl-U             kill to start       ctrl-K          kill to end
+ *
+ * Nothing is allocated: the line, history and the line being edited before
+ * stepping into history are all arrays inside the Readline, eg:
+ *
+ *   let mut readline: Readline<80, 8> = Readline::new();
+ *   loop
+ *   {
+ *       uart.send_str("> ")?;
+ *       let line = readline.read_line(&uart)?;
+ *       ...
+ *   }
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::timeout::{Timeout, Forever};
+
+const CTRL_A: u8 = 0x01;
+const CTRL_B: u8 = 0x02;
+const CTRL_E: u8 = 0x05;
+const CTRL_F: u8 = 0x06;
+const BEL: u8 = 0x07;
+const BS: u8 = 0x08;
+const CTRL_K: u8 = 0x0b;
+const CTRL_N: u8 = 0x0e;
+const CTRL_P: u8 = 0x10;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/* where we are in an escape sequence */
+#[derive(Clone, Copy)]
+enum Escape
+{
+    None,
+    Started,        /* ESC received */
+    Csi(u32),       /* ESC [ and any numeric parameter so far */
+    Ss3             /* ESC O */
+}
+
+/* editing actions, from control keys or escape sequences */
+#[derive(Clone, Copy)]
+enum Action
+{
+    Home,
+    End,
+    Left,
+    Right,
+    Up,
+    Down,
+    Backspace,
+    Delete,
+    KillToStart,
+    KillToEnd
+}
+
+pub struct Readline<const LINE: usize, const HIST: usize>
+{
+    line: [u8; LINE],
+    len: usize,
+    cursor: usize,
+    done: bool,             /* the line was returned, so start afresh on the next call */
+    after_cr: bool,         /* the last line ended with CR, so a following LF is part of it */
+    escape: Escape,
+
+    history: [[u8; LINE]; HIST],
+    history_lens: [usize; HIST],
+    history_count: usize,   /* entries in use */
+    history_next: usize,    /* slot the next entry goes in */
+    back: usize,            /* how far back in history the line came from, 0 if it's new */
+    saved: [u8; LINE],      /* the new line, while stepping through history */
+    saved_len: usize
+}
+
+impl<const LINE: usize, const HIST: usize> Default for Readline<LINE, HIST>
+{
+    fn default() -> Self
+    {
+        Readline::new()
+    }
+}
+
+impl<const LINE: usize, const HIST: usize> Readline<LINE, HIST>
+{
+    pub const fn new() -> Self
+    {
+        Readline
+        {
+            line: [0; LINE],
+            len: 0,
+            cursor: 0,
+            done: false,
+            after_cr: false,
+            escape: Escape::None,
+            history: [[0; LINE]; HIST],
+            history_lens: [0; HIST],
+            history_count: 0,
+            history_next: 0,
+            back: 0,
+            saved: [0; LINE],
+            saved_len: 0
+        }
+    }
+
+    /* edit a line, waiting as long as it takes for each key, and return it
+       without its line ending once entered */
+    pub fn read_line(&mut self, uart: &UART) -> Result<&[u8], Fault>
+    {
+        self.read_line_timeout(uart, &mut Forever)
+    }
+
+    /* like read_line() but waits until timeout expires for each key, failing
+       with DataNotReady if one doesn't arrive. call again to carry on editing */
+    pub fn read_line_timeout<T: Timeout>(&mut self, uart: &UART, timeout: &mut T) -> Result<&[u8], Fault>
+    {
+        if self.done
+        {
+            self.done = false;
+            self.len = 0;
+            self.cursor = 0;
+            self.back = 0;
+        }
+
+        loop
+        {
+            let byte = uart.read_byte_timeout(timeout)?;
+            if self.key(uart, byte)?
+            {
+                self.done = true;
+                return Ok(&self.line[..self.len])
+            }
+        }
+    }
+
+    /* a line entered back entries ago, 1 being the most recent */
+    pub fn history(&self, back: usize) -> Option<&[u8]>
+    {
+        match back
+        {
+            0 => None,
+            _ if back > self.history_count => None,
+            _ =>
+            {
+                let slot = (self.history_next + HIST - back) % HIST;
+                Some(&self.history[slot][..self.history_lens[slot]])
+            }
+        }
+    }
+
+    /* act on a received byte, returning true once the line is entered */
+    fn key(&mut self, uart: &UART, byte: u8) -> Result<bool, Fault>
+    {
+        let after_cr = self.after_cr;
+        self.after_cr = false;
+
+        match (self.escape, byte)
+        {
+            (Escape::None, ESC) => self.escape = Escape::Started,
+            (Escape::Started, b'[') => self.escape = Escape::Csi(0),
+            (Escape::Started, b'O') => self.escape = Escape::Ss3,
+            (Escape::Csi(param), b'0'..=b'9') =>
+                self.escape = Escape::Csi(param.saturating_mul(10).saturating_add((byte - b'0') as u32)),
+
+            (Escape::Csi(param), _) =>
+            {
+                self.escape = Escape::None;
+                let action = match (byte, param)
+                {
+                    (b'~', 1) | (b'~', 7) => Some(Action::Home),
+                    (b'~', 4) | (b'~', 8) => Some(Action::End),
+                    (b'~', 3) => Some(Action::Delete),
+                    _ => Readline::<LINE, HIST>::final_byte(byte)
+                };
+                if let Some(action) = action
+                {
+                    self.act(uart, action)?;
+                }
+            },
+
+            (Escape::Ss3, _) =>
+            {
+                self.escape = Escape::None;
+                if let Some(action) = Readline::<LINE, HIST>::final_byte(byte)
+                {
+                    self.act(uart, action)?;
+                }
+            },
+
+            /* an unrecognized sequence is dropped */
+            (Escape::Started, _) => self.escape = Escape::None,
+
+            (Escape::None, b'\n') if after_cr => (),
+            (Escape::None, b'\r') | (Escape::None, b'\n') =>
+            {
+                self.after_cr = byte == b'\r';
+                self.out(uart, b"\r\n")?;
+                self.remember();
+                return Ok(true)
+            },
+
+            (Escape::None, CTRL_A) => self.act(uart, Action::Home)?,
+            (Escape::None, CTRL_E) => self.act(uart, Action::End)?,
+            (Escape::None, CTRL_B) => self.act(uart, Action::Left)?,
+            (Escape::None, CTRL_F) => self.act(uart, Action::Right)?,
+            (Escape::None, CTRL_P) => self.act(uart, Action::Up)?,
+            (Escape::None, CTRL_N) => self.act(uart, Action::Down)?,
+            (Escape::None, BS) | (Escape::None, DEL) => self.act(uart, Action::Backspace)?,
+            (Escape::None, CTRL_U) => self.act(uart, Action::KillToStart)?,
+            (Escape::None, CTRL_K) => self.act(uart, Action::KillToEnd)?,
+            (Escape::None, 0..=0x1f) => (),
+            (Escape::None, _) => self.insert(uart, byte)?
+        }
+
+        Ok(false)
+    }
+
+    /* the action for the last byte of a cursor key's escape sequence */
+    fn final_byte(byte: u8) -> Option<Action>
+    {
+        match byte
+        {
+            b'A' => Some(Action::Up),
+            b'B' => Some(Action::Down),
+            b'C' => Some(Action::Right),
+            b'D' => Some(Action::Left),
+            b'H' => Some(Action::Home),
+            b'F' => Some(Action::End),
+            _ => None
+        }
+    }
+
+    fn act(&mut self, uart: &UART, action: Action) -> Result<(), Fault>
+    {
+        match action
+        {
+            Action::Home =>
+            {
+                self.left(uart, self.cursor)?;
+                self.cursor = 0;
+            },
+            Action::End =>
+            {
+                self.out(uart, &self.line[self.cursor..self.len])?;
+                self.cursor = self.len;
+            },
+            Action::Left if self.cursor > 0 =>
+            {
+                self.left(uart, 1)?;
+                self.cursor -= 1;
+            },
+            Action::Right if self.cursor < self.len =>
+            {
+                self.out(uart, &self.line[self.cursor..self.cursor + 1])?;
+                self.cursor += 1;
+            },
+            Action::Backspace if self.cursor > 0 =>
+            {
+                self.line.copy_within(self.cursor..self.len, self.cursor - 1);
+                self.len -= 1;
+                self.cursor -= 1;
+                self.left(uart, 1)?;
+                self.redraw(uart)?;
+            },
+            Action::Delete if self.cursor < self.len =>
+            {
+                self.line.copy_within(self.cursor + 1..self.len, self.cursor);
+                self.len -= 1;
+                self.redraw(uart)?;
+            },
+            Action::KillToStart =>
+            {
+                self.line.copy_within(self.cursor..self.len, 0);
+                self.len -= self.cursor;
+                self.left(uart, self.cursor)?;
+                self.cursor = 0;
+                self.redraw(uart)?;
+            },
+            Action::KillToEnd =>
+            {
+                self.len = self.cursor;
+                self.out(uart, b"\x1b[K")?;
+            },
+            Action::Up if self.back < self.history_count =>
+            {
+                if self.back == 0
+                {
+                    self.saved = self.line;
+                    self.saved_len = self.len;
+                }
+                self.back += 1;
+                self.recall(uart)?;
+            },
+            Action::Down if self.back > 0 =>
+            {
+                self.back -= 1;
+                self.recall(uart)?;
+            },
+
+            /* nowhere to go */
+            _ => self.out(uart, &[BEL])?
+        }
+
+        Ok(())
+    }
+
+    /* put a byte in the line at the cursor */
+    fn insert(&mut self, uart: &UART, byte: u8) -> Result<(), Fault>
+    {
+        if self.len == LINE
+        {
+            return self.out(uart, &[BEL])
+        }
+
+        self.line.copy_within(self.cursor..self.len, self.cursor + 1);
+        self.line[self.cursor] = byte;
+        self.len += 1;
+        self.cursor += 1;
+
+        /* rewrite the rest of the line, shifted along, and put the cursor back */
+        self.out(uart, &self.line[self.cursor - 1..self.len])?;
+        self.left(uart, self.len - self.cursor)
+    }
+
+    /* replace the line with the entry back steps into history, or the saved new line */
+    fn recall(&mut self, uart: &UART) -> Result<(), Fault>
+    {
+        match self.history(self.back)
+        {
+            Some(entry) =>
+            {
+                let len = entry.len();
+                let mut line = [0; LINE];
+                line[..len].copy_from_slice(entry);
+                self.line = line;
+                self.len = len;
+            },
+            None =>
+            {
+                self.line = self.saved;
+                self.len = self.saved_len;
+            }
+        }
+
+        self.left(uart, self.cursor)?;
+        self.cursor = 0;
+        self.redraw(uart)?;
+        self.act(uart, Action::End)
+    }
+
+    /* add the entered line to history, unless it's empty or repeats the last one */
+    fn remember(&mut self)
+    {
+        if HIST == 0 || self.len == 0 || self.history(1) == Some(&self.line[..self.len])
+        {
+            return
+        }
+
+        let slot = self.history_next;
+        self.history[slot] = self.line;
+        self.history_lens[slot] = self.len;
+        self.history_next = (slot + 1) % HIST;
+        self.history_count = (self.history_count + 1).min(HIST);
+    }
+
+    /* rewrite the line from the cursor onwards, clear anything left over beyond
+       its end, and put the cursor back */
+    fn redraw(&self, uart: &UART) -> Result<(), Fault>
+    {
+        self.out(uart, &self.line[self.cursor..self.len])?;
+        self.out(uart, b"\x1b[K")?;
+        self.left(uart, self.len - self.cursor)
+    }
+
+    /* move the terminal's cursor back count columns */
+    fn left(&self, uart: &UART, count: usize) -> Result<(), Fault>
+    {
+        if count > 0
+        {
+            self.out(uart, b"\x1b[")?;
+            uart.send_dec_u32(count as u32).map_err(|partial| partial.fault)?;
+            self.out(uart, b"D")?;
+        }
+        Ok(())
+    }
+
+    fn out(&self, uart: &UART, bytes: &[u8]) -> Result<(), Fault>
+    {
+        uart.send_bytes(bytes).map(|_| ()).map_err(|partial| partial.fault)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    #[test]
+    fn moves_cursor_and_edits()
+    {
+        let (script, uart) = Script::uart(b"ac\x1b[Db\x01\x1b[3~X\x05!\r");
+        let mut readline: Readline<8, 2> = Readline::new();
+        assert_eq!(readline.read_line(&uart).unwrap(), b"Xbc!");
+
+        let echoed = script.sent();
+        assert!(echoed.starts_with(b"ac\x1b[1Dbc\x1b[1D"));
+        assert!(echoed.ends_with(b"!\r\n"));
+    }
+
+    #[test]
+    fn steps_through_history()
+    {
+        let (script, uart) = Script::uart(b"one\rtwo\rtwo\r\nthr\x1bOA\x1b[A\r");
+        let mut readline: Readline<8, 2> = Readline::new();
+        assert_eq!(readline.read_line(&uart).unwrap(), b"one");
+        assert_eq!(readline.read_line(&uart).unwrap(), b"two");
+        assert_eq!(readline.read_line(&uart).unwrap(), b"two");
+        assert_eq!(readline.history(1), Some(&b"two"[..]));
+        assert_eq!(readline.history(2), Some(&b"one"[..]));
+        assert_eq!(readline.read_line(&uart).unwrap(), b"one");
+        script.sent();
+
+        /* only two entries are kept, and down returns to the new line */
+        script.receive(b"new\x10\x10\x10\x0e\x0e\r");
+        assert_eq!(readline.read_line(&uart).unwrap(), b"new");
+        assert!(script.sent().contains(&BEL));
+        assert_eq!(readline.history(1), Some(&b"new"[..]));
+        assert_eq!(readline.history(2), Some(&b"one"[..]));
+        assert_eq!(readline.history(3), None);
+    }
+}
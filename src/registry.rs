@@ -0,0 +1,149 @@
+/* keep track of all the UARTs on a board
+ *
+ * A registry owns every UART declared to it, either by hand or as found
+ * by probing, and hands each one out to at most one claimant at a time.
+ * Claims are released when dropped. Claiming only needs a shared
+ * reference, so a registry can be filled in during boot and then shared
+ * by the rest of the kernel.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::{UART, Fault};
+
+/* a registry with room for up to N UARTs */
+pub struct Registry<const N: usize>
+{
+    uarts: [Option<UART>; N],
+    claimed: [AtomicBool; N],
+    count: usize
+}
+
+/* exclusive access to a registered UART, released on drop */
+pub struct Claim<'a>
+{
+    uart: &'a UART,
+    index: usize,
+    flag: &'a AtomicBool
+}
+
+impl<const N: usize> Default for Registry<N>
+{
+    fn default() -> Self
+    {
+        Registry::new()
+    }
+}
+
+impl<const N: usize> Registry<N>
+{
+    pub const fn new() -> Self
+    {
+        Registry { uarts: [const { None }; N], claimed: [const { AtomicBool::new(false) }; N], count: 0 }
+    }
+
+    /* add a UART to the registry, returning its index, or fail with
+       RegistryFull if there's no room */
+    pub fn declare(&mut self, uart: UART) -> Result<usize, Fault>
+    {
+        if self.count == N
+        {
+            return Err(Fault::RegistryFull)
+        }
+
+        let index = self.count;
+        self.uarts[index] = Some(uart);
+        self.count += 1;
+        Ok(index)
+    }
+
+    /* number of UARTs declared */
+    pub fn len(&self) -> usize
+    {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.count == 0
+    }
+
+    /* take exclusive use of the UART at the given index. fails with NoSuchDevice
+       if there isn't one, or AlreadyClaimed if someone else has it */
+    pub fn claim(&self, index: usize) -> Result<Claim<'_>, Fault>
+    {
+        let uart = self.uarts.get(index).and_then(|u| u.as_ref()).ok_or(Fault::NoSuchDevice)?;
+        let flag = &self.claimed[index];
+
+        if flag.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err()
+        {
+            return Err(Fault::AlreadyClaimed)
+        }
+
+        Ok(Claim { uart, index, flag })
+    }
+
+    /* return true if the UART at the given index is currently claimed */
+    pub fn is_claimed(&self, index: usize) -> bool
+    {
+        self.claimed.get(index).map(|f| f.load(Ordering::Relaxed)).unwrap_or(false)
+    }
+}
+
+impl Claim<'_>
+{
+    /* index of the claimed UART in its registry */
+    pub fn index(&self) -> usize
+    {
+        self.index
+    }
+}
+
+impl Deref for Claim<'_>
+{
+    type Target = UART;
+
+    fn deref(&self) -> &UART
+    {
+        self.uart
+    }
+}
+
+impl Drop for Claim<'_>
+{
+    fn drop(&mut self)
+    {
+        self.flag.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+
+    #[test]
+    fn claims_are_exclusive()
+    {
+        let (regs0, regs1) = (FakeRegisters::new(), FakeRegisters::new());
+        let mut registry: Registry<2> = Registry::new();
+        assert_eq!(registry.declare(regs0.uart()).unwrap(), 0);
+        assert_eq!(registry.declare(regs1.uart()).unwrap(), 1);
+        assert!(matches!(registry.declare(regs1.uart()), Err(Fault::RegistryFull)));
+
+        let claim = registry.claim(1).unwrap();
+        assert_eq!(claim.index(), 1);
+        assert!(registry.is_claimed(1));
+        assert!(matches!(registry.claim(1), Err(Fault::AlreadyClaimed)));
+        assert!(matches!(registry.claim(2), Err(Fault::NoSuchDevice)));
+
+        drop(claim);
+        assert!(!registry.is_claimed(1));
+        assert!(registry.claim(1).is_ok());
+    }
+}
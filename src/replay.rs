@@ -0,0 +1,394 @@
+/* recording register traffic, and checking later runs against it
+ *
+ * Recorder is a Bus that passes each access on to the controller and
+ * appends it to a log in a fixed-size buffer. Replayer is a Bus that plays
+ * the controller's side of a log back: reads return the recorded values,
+ * and every access is checked against the log, panicking at the first that
+ * differs. Recording a scenario on hardware and replaying it in a test
+ * verifies that a change to the driver makes exactly the same register
+ * accesses, in the same order, with the same values, eg:
+ *
+ *   static RECORDER: Recorder<4096> = Recorder::mmio(UART0_BASE);
+ *   let uart = UART::attach_bus(&RECORDER);
+ *   greet(&uart);
+ *   dump(&console, RECORDER.log());
+ *   ...
+ *   let replayer: &'static Replayer = Box::leak(Box::new(Replayer::new(GREETING)?));
+ *   greet(&UART::attach_bus(replayer));
+ *   replayer.finish();
+ *
+ * Each access is logged as a byte holding the register and whether it was
+ * written, followed by its value in LEB128, so most take two bytes. A
+ * recording that fills its buffer stops, and overflowed() says so.
+ * Accesses from other harts or interrupt handlers are logged in the order
+ * they reach the recorder.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::UnsafeCell;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::Fault;
+use super::bus::Bus;
+
+/* the header byte holds the register's word offset, and this flag for writes */
+const WRITE_FLAG: u8 = 1 << 3;
+const REG_FIELD: u8 = 0x7;
+
+/* registers in the block, and the most bytes an access takes in the log */
+const REG_COUNT: usize = super::REG_TOTAL_SIZE / 4;
+const MAX_ENCODED: usize = 6;
+
+/* a register access: the register's byte offset, and the value read or written */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access
+{
+    Read(usize, u32),
+    Write(usize, u32)
+}
+
+impl Access
+{
+    /* encode into out, returning the number of bytes used, up to MAX_ENCODED */
+    fn encode(&self, out: &mut [u8; MAX_ENCODED]) -> usize
+    {
+        let (header, mut value) = match *self
+        {
+            Access::Read(reg, value) => ((reg / 4) as u8, value),
+            Access::Write(reg, value) => ((reg / 4) as u8 | WRITE_FLAG, value)
+        };
+
+        out[0] = header;
+        let mut len = 1;
+        loop
+        {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            match value
+            {
+                0 => { out[len] = byte; return len + 1 },
+                _ => out[len] = byte | 0x80
+            }
+            len += 1;
+        }
+    }
+
+    /* decode the access at the start of log, returning it and the number of
+       bytes it took, or BadFrame if it's malformed or cut short */
+    fn decode(log: &[u8]) -> Result<(Access, usize), Fault>
+    {
+        let header = *log.first().ok_or(Fault::BadFrame)?;
+        let reg = (header & REG_FIELD) as usize;
+        if header & !(REG_FIELD | WRITE_FLAG) != 0 || reg >= REG_COUNT
+        {
+            return Err(Fault::BadFrame)
+        }
+
+        let mut value: u32 = 0;
+        for (index, byte) in log[1..].iter().take(MAX_ENCODED - 1).enumerate()
+        {
+            value |= ((byte & 0x7f) as u32) << (7 * index);
+            if byte & 0x80 == 0
+            {
+                let access = match header & WRITE_FLAG
+                {
+                    0 => Access::Read(reg * 4, value),
+                    _ => Access::Write(reg * 4, value)
+                };
+                return Ok((access, index + 2))
+            }
+        }
+
+        Err(Fault::BadFrame)
+    }
+}
+
+/* iterate over the accesses in a log, stopping at its end or at anything malformed */
+pub fn accesses(log: &[u8]) -> Accesses<'_>
+{
+    Accesses { log }
+}
+
+pub struct Accesses<'a>
+{
+    log: &'a [u8]
+}
+
+impl Iterator for Accesses<'_>
+{
+    type Item = Access;
+
+    fn next(&mut self) -> Option<Access>
+    {
+        let (access, len) = Access::decode(self.log).ok()?;
+        self.log = &self.log[len..];
+        Some(access)
+    }
+}
+
+/* where the recorder sends accesses on to */
+enum Target
+{
+    Mmio(usize),
+    Bus(&'static dyn Bus)
+}
+
+/* a Bus that logs up to N bytes of accesses to the registers at a base
+   address, or on another bus */
+pub struct Recorder<const N: usize>
+{
+    target: Target,
+    log: UnsafeCell<[u8; N]>,
+    len: AtomicUsize,           /* bytes of the log reserved so far */
+    overflowed: AtomicBool      /* an access didn't fit and recording stopped */
+}
+
+/* each access reserves its own part of the log before writing to it */
+unsafe impl<const N: usize> Sync for Recorder<N> {}
+
+impl<const N: usize> Recorder<N>
+{
+    /* record accesses to the memory-mapped registers at base. the caller must
+       ensure base is the address of a SiFive UART, as with UART::new() */
+    pub const fn mmio(base: usize) -> Self
+    {
+        Recorder::with_target(Target::Mmio(base))
+    }
+
+    /* record accesses to bus, eg a simulated controller */
+    pub const fn on(bus: &'static dyn Bus) -> Self
+    {
+        Recorder::with_target(Target::Bus(bus))
+    }
+
+    const fn with_target(target: Target) -> Self
+    {
+        Recorder { target, log: UnsafeCell::new([0; N]), len: AtomicUsize::new(0), overflowed: AtomicBool::new(false) }
+    }
+
+    /* the log so far. call once the scenario is over, as accesses still in
+       progress may not have been written out yet */
+    pub fn log(&self) -> &[u8]
+    {
+        let len = self.len.load(Ordering::Acquire).min(N);
+        unsafe { core::slice::from_raw_parts(self.log.get() as *const u8, len) }
+    }
+
+    /* return true if the log filled up and accesses went unrecorded */
+    pub fn overflowed(&self) -> bool
+    {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
+    /* forget everything recorded, to start a new scenario */
+    pub fn clear(&self)
+    {
+        self.len.store(0, Ordering::Release);
+        self.overflowed.store(false, Ordering::Relaxed);
+    }
+
+    fn record(&self, access: Access)
+    {
+        if self.overflowed()
+        {
+            return
+        }
+
+        let mut encoded = [0u8; MAX_ENCODED];
+        let count = access.encode(&mut encoded);
+        let start = self.len.fetch_add(count, Ordering::AcqRel);
+        match start + count <= N
+        {
+            true => unsafe { core::ptr::copy_nonoverlapping(encoded.as_ptr(), (self.log.get() as *mut u8).add(start), count) },
+            false =>
+            {
+                self.len.fetch_sub(count, Ordering::AcqRel);
+                self.overflowed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<const N: usize> Bus for Recorder<N>
+{
+    fn read(&self, reg: usize) -> u32
+    {
+        let value = match self.target
+        {
+            Target::Mmio(base) => unsafe { read_volatile((base + reg) as *const u32) },
+            Target::Bus(bus) => bus.read(reg)
+        };
+        self.record(Access::Read(reg, value));
+        value
+    }
+
+    fn write(&self, reg: usize, val: u32)
+    {
+        match self.target
+        {
+            Target::Mmio(base) => unsafe { write_volatile((base + reg) as *mut u32, val) },
+            Target::Bus(bus) => bus.write(reg, val)
+        }
+        self.record(Access::Write(reg, val));
+    }
+}
+
+/* a Bus that replays a recorded log, panicking if the driver's accesses differ */
+pub struct Replayer<'a>
+{
+    log: &'a [u8],
+    next: AtomicUsize,      /* offset of the next access in the log */
+    count: AtomicUsize      /* accesses replayed so far */
+}
+
+impl<'a> Replayer<'a>
+{
+    /* replay log, or fail with BadFrame if it's malformed */
+    pub fn new(log: &'a [u8]) -> Result<Self, Fault>
+    {
+        let mut offset = 0;
+        while offset < log.len()
+        {
+            offset += Access::decode(&log[offset..])?.1;
+        }
+
+        Ok(Replayer { log, next: AtomicUsize::new(0), count: AtomicUsize::new(0) })
+    }
+
+    /* number of bytes of the log not yet replayed */
+    pub fn remaining(&self) -> usize
+    {
+        self.log.len() - self.next.load(Ordering::Acquire)
+    }
+
+    /* panic unless the whole log has been replayed */
+    pub fn finish(&self)
+    {
+        let count = self.count.load(Ordering::Relaxed);
+        if let Some(access) = accesses(&self.log[self.next.load(Ordering::Acquire)..]).next()
+        {
+            panic!("register access {} never made: expected {:?}", count, access);
+        }
+    }
+
+    /* check the driver's access against the next in the log, and return the
+       logged value */
+    fn replay(&self, made: Access) -> u32
+    {
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        let offset = self.next.load(Ordering::Acquire);
+        let (expected, len) = match Access::decode(&self.log[offset..])
+        {
+            Ok(next) => next,
+            Err(_) => panic!("register access {} beyond end of log: {:?}", count, made)
+        };
+
+        /* reads are checked by register, as the value comes from the log */
+        match (expected, made)
+        {
+            (Access::Read(reg, value), Access::Read(made_reg, _)) if reg == made_reg =>
+            {
+                self.next.store(offset + len, Ordering::Release);
+                value
+            },
+            (Access::Write(..), Access::Write(..)) if expected == made =>
+            {
+                self.next.store(offset + len, Ordering::Release);
+                0
+            },
+            _ => panic!("register access {} differs: expected {:?}, made {:?}", count, expected, made)
+        }
+    }
+}
+
+impl Bus for Replayer<'_>
+{
+    fn read(&self, reg: usize) -> u32
+    {
+        self.replay(Access::Read(reg, 0))
+    }
+
+    fn write(&self, reg: usize, val: u32)
+    {
+        self.replay(Access::Write(reg, val));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::UART;
+    use crate::bus::Bus;
+    use crate::tests::Script;
+
+    /* a scenario to record: set up, say hello, and read a reply */
+    fn scenario(uart: &UART) -> Vec<u8>
+    {
+        uart.set_baud(115_200, 115_200 * 300);
+        uart.send_str("hello").unwrap();
+        let mut reply = Vec::new();
+        while let Ok(byte) = uart.read_byte()
+        {
+            reply.push(byte);
+        }
+        reply
+    }
+
+    fn record() -> &'static [u8]
+    {
+        let (script, _) = Script::uart(b"hi");
+        script.write(crate::REG_IP, crate::REG_IP_TXWM);
+        let recorder: &'static Recorder<256> = Box::leak(Box::new(Recorder::on(script)));
+        assert_eq!(scenario(&UART::attach_bus(recorder)), b"hi");
+        assert!(!recorder.overflowed());
+        recorder.log()
+    }
+
+    #[test]
+    fn replays_recording()
+    {
+        let log = record();
+        assert_eq!(accesses(log).find(|access| matches!(access, Access::Write(..))), Some(Access::Write(crate::REG_DIV, 299)));
+        assert_eq!(accesses(log).filter(|access| matches!(access, Access::Write(crate::REG_TXDATA, _))).count(), 5);
+        assert!(log.len() < accesses(log).count() * 3);
+
+        let replayer: &'static Replayer = Box::leak(Box::new(Replayer::new(log).unwrap()));
+        assert_eq!(scenario(&UART::attach_bus(replayer)), b"hi");
+        assert_eq!(replayer.remaining(), 0);
+        replayer.finish();
+
+        assert!(matches!(Replayer::new(&log[..log.len() - 1]), Err(Fault::BadFrame)));
+        assert!(matches!(Replayer::new(&[0x07, 0]), Err(Fault::BadFrame)));
+    }
+
+    #[test]
+    #[should_panic(expected = "differs")]
+    fn catches_changed_accesses()
+    {
+        let replayer: &'static Replayer = Box::leak(Box::new(Replayer::new(record()).unwrap()));
+        let uart = UART::attach_bus(replayer);
+        uart.set_baud(115_200, 115_200 * 300);
+        uart.send_str("jello").unwrap();
+    }
+
+    #[test]
+    fn stops_when_full()
+    {
+        let (script, _) = Script::uart(b"");
+        script.write(crate::REG_IP, crate::REG_IP_TXWM);
+        let recorder: &'static Recorder<16> = Box::leak(Box::new(Recorder::on(script)));
+        let uart = UART::attach_bus(recorder);
+        uart.send_str("0123456789").unwrap();
+        assert!(recorder.overflowed());
+        assert_eq!(accesses(recorder.log()).count(), 8);
+
+        recorder.clear();
+        uart.set_baud(115_200, 115_200 * 1000);
+        let writes: Vec<Access> = accesses(recorder.log()).filter(|access| matches!(access, Access::Write(..))).collect();
+        assert_eq!(writes, [Access::Write(crate::REG_DIV, 999)]);
+    }
+}
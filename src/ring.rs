@@ -0,0 +1,310 @@
+/* lock-free single-producer, single-consumer byte ring buffer
+ *
+ * Used to hand bytes between interrupt handlers and the rest of the
+ * system without locks: for received data the interrupt handler is the
+ * producer, and for data to transmit it's the consumer. Either side may be
+ * interrupted by the other at any point. Only one context may push and
+ * only one may pop at any one time: each end is claimed for the length of
+ * a call, and a call that finds its end already claimed by another context
+ * backs off as if the buffer were full or empty, rather than racing it.
+ *
+ * The buffer is private to the crate. Buffers and the mux hold them, and
+ * the handles that read and write through them are each handed out once.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::math::{ring_len, ring_advance, ring_slot};
+
+/* a ring buffer holding up to N bytes.
+
+   head and tail count from 0 to 2N - 1 so that a full buffer (tail is N
+   ahead of head) can be told apart from an empty one (tail equals head)
+   without wasting a slot, and without N having to be a power of two */
+pub(crate) struct RingBuffer<const N: usize>
+{
+    data: UnsafeCell<[u8; N]>,
+    head: AtomicUsize,      /* next byte to pop, only moved by the consumer */
+    tail: AtomicUsize,      /* next slot to fill, only moved by the producer */
+    pushing: AtomicBool,    /* a context is the producer */
+    popping: AtomicBool     /* a context is the consumer */
+}
+
+/* the producer and consumer only ever touch disjoint slots, and there's at
+   most one of each at a time */
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> Default for RingBuffer<N>
+{
+    fn default() -> Self
+    {
+        RingBuffer::new()
+    }
+}
+
+impl<const N: usize> RingBuffer<N>
+{
+    pub const fn new() -> Self
+    {
+        RingBuffer
+        {
+            data: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            pushing: AtomicBool::new(false),
+            popping: AtomicBool::new(false)
+        }
+    }
+
+    pub const fn capacity(&self) -> usize
+    {
+        N
+    }
+
+    /* number of bytes waiting to be popped */
+    pub fn len(&self) -> usize
+    {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        ring_len(head, tail, N)
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool
+    {
+        self.len() == N
+    }
+
+    /* number of bytes that can be pushed before the buffer is full */
+    pub fn free(&self) -> usize
+    {
+        N - self.len()
+    }
+
+    /* producer: add a byte, or return false if the buffer is full */
+    pub fn push(&self, byte: u8) -> bool
+    {
+        self.as_producer(false, || self.push_claimed(byte))
+    }
+
+    /* consumer: remove the oldest byte, if there is one */
+    pub fn pop(&self) -> Option<u8>
+    {
+        self.as_consumer(None, || self.pop_claimed())
+    }
+
+    /* consumer: borrow the oldest bytes in place, as many as are contiguous in
+       memory. this is empty only if the buffer is.
+
+       safety: the caller must be the only context that pops from the buffer,
+       and must not pop while the slice is in use, or the producer could
+       overwrite the bytes it covers */
+    pub unsafe fn peek(&self) -> &[u8]
+    {
+        let head = self.head.load(Ordering::Relaxed);
+        let start = ring_slot(head, N);
+        let count = self.len().min(N - start);
+        core::slice::from_raw_parts((self.data.get() as *const u8).add(start), count)
+    }
+
+    /* consumer: remove up to count of the oldest bytes without reading them */
+    pub fn consume(&self, count: usize)
+    {
+        self.as_consumer((), ||
+        {
+            let head = self.head.load(Ordering::Relaxed);
+            let count = count.min(self.len());
+            self.head.store(ring_advance(head, count, N), Ordering::Release);
+        })
+    }
+
+    /* producer: add as many bytes from data as will fit, returning how many */
+    pub fn push_from(&self, data: &[u8]) -> usize
+    {
+        self.as_producer(0, || data.iter().take_while(|&&byte| self.push_claimed(byte)).count())
+    }
+
+    /* consumer: remove as many bytes as will fit in buf, returning how many */
+    pub fn pop_into(&self, buf: &mut [u8]) -> usize
+    {
+        self.as_consumer(0, ||
+        {
+            let mut count = 0;
+            for slot in buf.iter_mut()
+            {
+                match self.pop_claimed()
+                {
+                    Some(byte) => *slot = byte,
+                    None => break
+                }
+                count += 1;
+            }
+
+            count
+        })
+    }
+
+    /* run f as the producer, or return busy if another context already is */
+    fn as_producer<R>(&self, busy: R, f: impl FnOnce() -> R) -> R
+    {
+        if self.pushing.swap(true, Ordering::Acquire)
+        {
+            return busy
+        }
+
+        let result = f();
+        self.pushing.store(false, Ordering::Release);
+        result
+    }
+
+    /* run f as the consumer, or return busy if another context already is */
+    fn as_consumer<R>(&self, busy: R, f: impl FnOnce() -> R) -> R
+    {
+        if self.popping.swap(true, Ordering::Acquire)
+        {
+            return busy
+        }
+
+        let result = f();
+        self.popping.store(false, Ordering::Release);
+        result
+    }
+
+    /* push, having claimed the producer's end */
+    fn push_claimed(&self, byte: u8) -> bool
+    {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if ring_len(head, tail, N) == N
+        {
+            return false
+        }
+
+        unsafe { (self.data.get() as *mut u8).add(ring_slot(tail, N)).write(byte) };
+        self.tail.store(ring_advance(tail, 1, N), Ordering::Release);
+        true
+    }
+
+    /* pop, having claimed the consumer's end */
+    fn pop_claimed(&self) -> Option<u8>
+    {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail
+        {
+            return None
+        }
+
+        let byte = unsafe { (self.data.get() as *const u8).add(ring_slot(head, N)).read() };
+        self.head.store(ring_advance(head, 1, N), Ordering::Release);
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn fills_and_drains_in_order()
+    {
+        let ring: RingBuffer<3> = RingBuffer::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.push_from(b"abcd"), 3);
+        assert!(ring.is_full());
+        assert!(!ring.push(b'x'));
+
+        assert_eq!(ring.pop(), Some(b'a'));
+        assert!(ring.push(b'd'));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(ring.pop_into(&mut buf), 3);
+        assert_eq!(&buf[..3], b"bcd");
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn indices_wrap()
+    {
+        let ring: RingBuffer<5> = RingBuffer::new();
+        for i in 0..100u8
+        {
+            assert!(ring.push(i));
+            assert!(ring.push(i.wrapping_mul(3)));
+            assert_eq!(ring.len(), 2);
+            assert_eq!(ring.pop(), Some(i));
+            assert_eq!(ring.pop(), Some(i.wrapping_mul(3)));
+        }
+    }
+
+    #[test]
+    fn peek_stops_at_wrap()
+    {
+        let ring: RingBuffer<4> = RingBuffer::new();
+        ring.push_from(b"abc");
+        ring.consume(2);
+        ring.push_from(b"def");
+
+        assert_eq!(unsafe { ring.peek() }, b"cd");
+        ring.consume(2);
+        assert_eq!(unsafe { ring.peek() }, b"ef");
+        ring.consume(9);
+        assert!(ring.is_empty());
+        assert_eq!(unsafe { ring.peek() }, b"");
+    }
+
+    #[test]
+    fn claimed_ends_back_off()
+    {
+        let ring: RingBuffer<4> = RingBuffer::new();
+        ring.push_from(b"ab");
+
+        /* as if another context were part way through a push or pop */
+        ring.pushing.store(true, Ordering::Relaxed);
+        assert!(!ring.push(b'c'));
+        assert_eq!(ring.pop(), Some(b'a'));
+        ring.pushing.store(false, Ordering::Relaxed);
+
+        ring.popping.store(true, Ordering::Relaxed);
+        assert_eq!(ring.pop_into(&mut [0u8; 4]), 0);
+        assert_eq!(ring.push_from(b"cd"), 2);
+        ring.popping.store(false, Ordering::Relaxed);
+        assert_eq!(ring.len(), 3);
+    }
+
+    #[test]
+    fn concurrent_producer_and_consumer()
+    {
+        static RING: RingBuffer<7> = RingBuffer::new();
+        const COUNT: usize = 10_000;
+
+        let producer = std::thread::spawn(||
+        {
+            for i in 0..COUNT
+            {
+                while !RING.push(i as u8) { std::thread::yield_now() }
+            }
+        });
+
+        for i in 0..COUNT
+        {
+            let byte = loop
+            {
+                if let Some(byte) = RING.pop() { break byte }
+                std::thread::yield_now();
+            };
+            assert_eq!(byte, i as u8);
+        }
+
+        producer.join().unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+/* blocking reads and writes that sleep the calling task under an RTOS
+ *
+ * BlockingUart drives the interrupt-driven AsyncUart from ordinary blocking
+ * code. When it must wait, it calls the Notifier's block(), which should
+ * put the calling task to sleep, eg on a FreeRTOS task notification or a
+ * kernel wait queue. The interrupt handler calls UART::handle_interrupt()
+ * as usual, which calls the Notifier's notify() to wake the task.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use super::{UART, Fault};
+use super::asynch::AsyncUart;
+use super::buffered::Buffers;
+
+/* how a task sleeps and is woken. notify() may be called from an interrupt
+   handler, and a notify() that arrives before block() must make that
+   block() return straight away, as a binary semaphore or task notification
+   does, or the wake-up will be lost */
+pub trait Notifier: Sync
+{
+    /* sleep until notify() is called */
+    fn block(&self);
+
+    /* wake the sleeping task */
+    fn notify(&self);
+}
+
+/* a Waker that calls a Notifier's notify() */
+struct NotifyWaker<N>(PhantomData<N>);
+
+impl<N: Notifier + 'static> NotifyWaker<N>
+{
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(Self::clone, Self::wake, Self::wake, Self::drop);
+
+    fn waker(notifier: &'static N) -> Waker
+    {
+        let raw = RawWaker::new(notifier as *const N as *const (), &Self::VTABLE);
+        unsafe { Waker::from_raw(raw) }
+    }
+
+    unsafe fn clone(data: *const ()) -> RawWaker
+    {
+        RawWaker::new(data, &Self::VTABLE)
+    }
+
+    unsafe fn wake(data: *const ())
+    {
+        (*(data as *const N)).notify();
+    }
+
+    unsafe fn drop(_: *const ()) {}
+}
+
+pub struct BlockingUart<'a, N: Notifier + 'static, const RX: usize, const TX: usize>
+{
+    serial: AsyncUart<'a, RX, TX>,
+    notifier: &'static N
+}
+
+impl<'a, N: Notifier + 'static, const RX: usize, const TX: usize> BlockingUart<'a, N, RX, TX>
+{
+    /* the buffers must be the same ones passed to handle_interrupt() by the
+       interrupt handler. AlreadyClaimed is returned if another driver uses them */
+    pub fn new(uart: &'a UART, buffers: &'a Buffers<RX, TX>, notifier: &'static N) -> Result<Self, Fault>
+    {
+        Ok(BlockingUart { serial: AsyncUart::new(uart, buffers)?, notifier })
+    }
+
+    /* sleep until at least one byte arrives, then return as many as are waiting,
+       up to the size of buf */
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        let notifier = self.notifier;
+        block_on(notifier, self.serial.read(buf))
+    }
+
+    /* sleep until a byte arrives, then return it */
+    pub fn read_byte(&mut self) -> Result<u8, Fault>
+    {
+        let mut byte = [0u8];
+        self.read(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /* sleep until there's room in the tx buffer, then queue as much of data as
+       fits, returning how many bytes were queued */
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, Fault>
+    {
+        let notifier = self.notifier;
+        block_on(notifier, self.serial.write(data))
+    }
+
+    /* queue all of data, sleeping whenever the tx buffer is full */
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Fault>
+    {
+        let notifier = self.notifier;
+        block_on(notifier, self.serial.write_all(data))
+    }
+
+    /* sleep until every queued byte has left the hardware tx FIFO */
+    pub fn flush(&mut self) -> Result<(), Fault>
+    {
+        let notifier = self.notifier;
+        block_on(notifier, self.serial.flush())
+    }
+}
+
+/* poll future to completion, blocking on the notifier between polls */
+fn block_on<N: Notifier + 'static, F: Future>(notifier: &'static N, future: F) -> F::Output
+{
+    let waker = NotifyWaker::waker(notifier);
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop
+    {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx)
+        {
+            return output
+        }
+
+        notifier.block();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use crate::tests::FakeRegisters;
+
+    static BUFFERS: Buffers<8, 8> = Buffers::new();
+
+    /* stands in for an RTOS: blocking runs the "interrupt handler", which
+       receives a byte and wakes the task */
+    struct Rtos
+    {
+        blocks: AtomicUsize,
+        notifies: AtomicUsize
+    }
+
+    impl Notifier for Rtos
+    {
+        fn block(&self)
+        {
+            self.blocks.fetch_add(1, Ordering::SeqCst);
+            BUFFERS.rx.push(b'n');
+            BUFFERS.rx_waker.wake();
+        }
+
+        fn notify(&self)
+        {
+            self.notifies.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    static RTOS: Rtos = Rtos { blocks: AtomicUsize::new(0), notifies: AtomicUsize::new(0) };
+
+    #[test]
+    fn read_sleeps_until_notified()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut serial = BlockingUart::new(&uart, &BUFFERS, &RTOS).unwrap();
+
+        assert_eq!(serial.read_byte().unwrap(), b'n');
+        assert_eq!(RTOS.blocks.load(Ordering::SeqCst), 1);
+        assert_eq!(RTOS.notifies.load(Ordering::SeqCst), 1);
+        assert!(serial.write_all(b"no wait").is_ok());
+    }
+}
@@ -0,0 +1,65 @@
+/* common interface for serial port drivers
+ *
+ * This crate follows the same API as mmio_16550_uart. This trait captures
+ * that API so a kernel can decide at boot, eg from the device tree, which
+ * driver to use, and then keep the result as a &dyn SerialController
+ * without caring which controller is underneath. The trait is deliberately
+ * object-safe: no generics, no Self-returning methods.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+
+pub trait SerialController
+{
+    /* transmit a byte, or fail if the controller won't accept it */
+    fn send_byte(&self, to_send: u8) -> Result<(), Fault>;
+
+    /* return a received byte, or fail with DataNotReady if there isn't one */
+    fn read_byte(&self) -> Result<u8, Fault>;
+
+    /* size of the controller's MMIO space in bytes */
+    fn size(&self) -> usize;
+}
+
+impl SerialController for UART
+{
+    fn send_byte(&self, to_send: u8) -> Result<(), Fault>
+    {
+        UART::send_byte(self, to_send)
+    }
+
+    fn read_byte(&self) -> Result<u8, Fault>
+    {
+        UART::read_byte(self)
+    }
+
+    fn size(&self) -> usize
+    {
+        UART::size(self)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::{REG_TXDATA, REG_TOTAL_SIZE};
+
+    #[test]
+    fn usable_as_trait_object()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let console: &dyn SerialController = &uart;
+
+        console.send_byte(b'!').unwrap();
+        assert_eq!(regs.get(REG_TXDATA), b'!' as u32);
+        assert!(matches!(console.read_byte(), Err(Fault::DataNotReady)));
+        assert_eq!(console.size(), REG_TOTAL_SIZE);
+    }
+}
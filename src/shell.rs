@@ -0,0 +1,171 @@
+/* a tiny command shell for debug monitors
+ *
+ * Shell reads lines with Readline, splits each into whitespace-separated
+ * arguments, and calls the handler of the command named by the first. The
+ * commands are a static table, so nothing is allocated, eg:
+ *
+ *   static COMMANDS: &[Command] = &[
+ *       Command { name: "peek", help: "peek <addr>: read a word", handler: peek },
+ *       Command { name: "boot", help: "boot: carry on booting", handler: boot }
+ *   ];
+ *
+ *   let mut shell: Shell<80, 4> = Shell::new("> ", COMMANDS);
+ *   shell.run(&uart)?;
+ *
+ * help lists the commands. Handlers are passed the arguments after the
+ * command's name, and a handler returning Fault::Cancelled leaves run(), eg
+ * for a boot command. Other faults are reported and the shell carries on.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::fmt::Write;
+use super::{UART, Fault};
+use super::readline::Readline;
+
+/* most arguments a command can be given, including its name */
+const MAX_ARGS: usize = 16;
+
+pub type Handler = fn(&UART, &[&str]) -> Result<(), Fault>;
+
+#[derive(Clone, Copy)]
+pub struct Command
+{
+    pub name: &'static str,
+    pub help: &'static str,     /* one line, shown by help */
+    pub handler: Handler
+}
+
+/* a shell with lines of up to LINE bytes and HIST lines of history */
+pub struct Shell<const LINE: usize, const HIST: usize>
+{
+    prompt: &'static str,
+    commands: &'static [Command],
+    readline: Readline<LINE, HIST>
+}
+
+impl<const LINE: usize, const HIST: usize> Shell<LINE, HIST>
+{
+    pub const fn new(prompt: &'static str, commands: &'static [Command]) -> Self
+    {
+        Shell { prompt, commands, readline: Readline::new() }
+    }
+
+    /* prompt for and run commands until one returns Cancelled, or the UART fails */
+    pub fn run(&mut self, uart: &UART) -> Result<(), Fault>
+    {
+        loop
+        {
+            uart.send_str(self.prompt).map_err(|partial| partial.fault)?;
+            let line = self.readline.read_line(uart)?;
+            match dispatch(uart, self.commands, line)
+            {
+                Err(Fault::Cancelled) => return Ok(()),
+                Err(fault) => { let _ = writeln!(Console(uart), "error: {:?}\r", fault); },
+                Ok(()) => ()
+            }
+        }
+    }
+}
+
+/* split line into arguments and run the command it names. an empty line does
+   nothing. problems with the line itself are reported rather than returned */
+pub fn dispatch(uart: &UART, commands: &[Command], line: &[u8]) -> Result<(), Fault>
+{
+    let mut console = Console(uart);
+    let line = match core::str::from_utf8(line)
+    {
+        Ok(line) => line,
+        Err(_) => { let _ = console.write_str("not valid UTF-8\r\n"); return Ok(()) }
+    };
+
+    let mut args = [""; MAX_ARGS];
+    let mut count = 0;
+    for arg in line.split_ascii_whitespace()
+    {
+        if count == MAX_ARGS
+        {
+            let _ = console.write_str("too many arguments\r\n");
+            return Ok(())
+        }
+
+        args[count] = arg;
+        count += 1;
+    }
+
+    let (name, args) = match args[..count].split_first()
+    {
+        Some((name, args)) => (*name, args),
+        None => return Ok(())
+    };
+
+    if name == "help"
+    {
+        for command in commands
+        {
+            let _ = writeln!(console, "{:<10} {}\r", command.name, command.help);
+        }
+        return Ok(())
+    }
+
+    match commands.iter().find(|command| command.name == name)
+    {
+        Some(command) => (command.handler)(uart, args),
+        None =>
+        {
+            let _ = writeln!(console, "unknown command: {} (try help)\r", name);
+            Ok(())
+        }
+    }
+}
+
+/* formatted output to the UART, for messages to the user */
+struct Console<'a>(&'a UART);
+
+impl Write for Console<'_>
+{
+    fn write_str(&mut self, string: &str) -> core::fmt::Result
+    {
+        self.0.send_str(string).map(|_| ()).map_err(|_| core::fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    fn echo(uart: &UART, args: &[&str]) -> Result<(), Fault>
+    {
+        uart.send_str(&args.join("+")).map(|_| ()).map_err(|partial| partial.fault)
+    }
+
+    static COMMANDS: &[Command] = &[
+        Command { name: "echo", help: "echo <args>: join args", handler: echo },
+        Command { name: "fail", help: "fail: time out", handler: |_, _| Err(Fault::TimedOut) },
+        Command { name: "exit", help: "exit: leave", handler: |_, _| Err(Fault::Cancelled) }
+    ];
+
+    #[test]
+    fn dispatches_commands()
+    {
+        let (script, uart) = Script::uart(b"  echo a  b\r\rnope\rfail\rexit\r");
+        let mut shell: Shell<32, 2> = Shell::new("> ", COMMANDS);
+        assert!(shell.run(&uart).is_ok());
+
+        let sent = String::from_utf8(script.sent()).unwrap();
+        assert_eq!(sent, ">   echo a  b\r\na+b> \r\n> nope\r\nunknown command: nope (try help)\r\n\
+                          > fail\r\nerror: TimedOut\r\n> exit\r\n");
+    }
+
+    #[test]
+    fn lists_commands()
+    {
+        let (script, uart) = Script::uart(b"");
+        assert!(dispatch(&uart, COMMANDS, b"help").is_ok());
+        assert_eq!(String::from_utf8(script.sent()).unwrap().lines().count(), 3);
+    }
+}
@@ -0,0 +1,338 @@
+/* SLIP framing on top of the UART, as described by RFC 1055
+ *
+ * Frames are delimited by END bytes. END and ESC bytes within a frame
+ * are replaced by two-byte escape sequences. The encoder and decoder
+ * below are streaming: they never need the whole frame in one place.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::timeout::{Timeout, Forever};
+
+/* SLIP's special characters */
+pub const END:     u8 = 0xc0; /* end of frame */
+pub const ESC:     u8 = 0xdb; /* start of an escape sequence */
+pub const ESC_END: u8 = 0xdc; /* ESC ESC_END means END within the frame */
+pub const ESC_ESC: u8 = 0xdd; /* ESC ESC_ESC means ESC within the frame */
+
+/* iterate over the bytes of a SLIP-encoded frame. a leading END is
+   emitted to flush out any line noise received by the other side
+   before the frame, as suggested by the RFC */
+pub struct Encoder<'a>
+{
+    data: &'a [u8],
+    started: bool,
+    pending: Option<u8>, /* second half of an escape sequence */
+    finished: bool
+}
+
+impl<'a> Encoder<'a>
+{
+    pub fn new(data: &'a [u8]) -> Self
+    {
+        Encoder { data, started: false, pending: None, finished: false }
+    }
+}
+
+impl Iterator for Encoder<'_>
+{
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8>
+    {
+        if !self.started
+        {
+            self.started = true;
+            return Some(END)
+        }
+
+        if let Some(byte) = self.pending.take()
+        {
+            return Some(byte)
+        }
+
+        match self.data.split_first()
+        {
+            Some((&byte, rest)) =>
+            {
+                self.data = rest;
+                match byte
+                {
+                    END => { self.pending = Some(ESC_END); Some(ESC) },
+                    ESC => { self.pending = Some(ESC_ESC); Some(ESC) },
+                    _ => Some(byte)
+                }
+            },
+            None if !self.finished =>
+            {
+                self.finished = true;
+                Some(END)
+            },
+            None => None
+        }
+    }
+}
+
+/* reassemble SLIP frames one byte at a time into a caller-supplied buffer */
+pub struct Decoder<'a>
+{
+    buf: &'a mut [u8],
+    len: usize,
+    escaped: bool,  /* last byte was ESC */
+    discard: bool,  /* frame is broken: skip bytes until the next END */
+    complete: bool  /* buf[..len] holds a finished frame */
+}
+
+impl<'a> Decoder<'a>
+{
+    pub fn new(buf: &'a mut [u8]) -> Self
+    {
+        Decoder { buf, len: 0, escaped: false, discard: false, complete: false }
+    }
+
+    /* feed a received byte into the decoder. returns the length of the frame
+       when it's complete, which can then be fetched with frame(). a frame that
+       overflows the buffer or contains a bad escape is reported once and
+       then dropped up to the next END. empty frames are silently ignored */
+    pub fn push(&mut self, byte: u8) -> Result<Option<usize>, Fault>
+    {
+        if self.complete
+        {
+            self.len = 0;
+            self.complete = false;
+        }
+
+        if byte == END
+        {
+            let broken = self.discard;
+            self.discard = false;
+            self.escaped = false;
+
+            if broken || self.len == 0
+            {
+                self.len = 0;
+                return Ok(None)
+            }
+
+            self.complete = true;
+            return Ok(Some(self.len))
+        }
+
+        if self.discard
+        {
+            return Ok(None)
+        }
+
+        let byte = match (self.escaped, byte)
+        {
+            (false, ESC) =>
+            {
+                self.escaped = true;
+                return Ok(None)
+            },
+            (false, b) => b,
+            (true, ESC_END) => END,
+            (true, ESC_ESC) => ESC,
+            (true, _) => return Err(self.drop_frame(Fault::BadEscape))
+        };
+        self.escaped = false;
+
+        if self.len >= self.buf.len()
+        {
+            return Err(self.drop_frame(Fault::FrameTooLong))
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        Ok(None)
+    }
+
+    /* return the most recently completed frame, or an empty slice if a frame
+       is still being received */
+    pub fn frame(&self) -> &[u8]
+    {
+        match self.complete
+        {
+            true => &self.buf[..self.len],
+            false => &[]
+        }
+    }
+
+    /* abandon the frame in progress and wait for the next one */
+    pub fn reset(&mut self)
+    {
+        self.len = 0;
+        self.escaped = false;
+        self.discard = false;
+        self.complete = false;
+    }
+
+    fn drop_frame(&mut self, reason: Fault) -> Fault
+    {
+        self.len = 0;
+        self.escaped = false;
+        self.discard = true;
+        reason
+    }
+}
+
+impl UART
+{
+    /* transmit the given data as a single SLIP frame */
+    pub fn send_slip_frame(&self, data: &[u8]) -> Result<(), Fault>
+    {
+        for byte in Encoder::new(data)
+        {
+            self.send_byte(byte)?;
+        }
+
+        Ok(())
+    }
+
+    /* receive a SLIP frame into buf, returning the frame's length. gives up
+       with DataNotReady if nothing at all arrives, but once a byte has, waits
+       as long as it takes for the rest of the frame, however slow the line.
+       use read_slip_frame_timeout() to bound that too, or a Decoder directly
+       to receive frames across multiple calls */
+    pub fn read_slip_frame(&self, buf: &mut [u8]) -> Result<usize, Fault>
+    {
+        let first = self.wait_for_byte()?;
+        self.read_rest_of_slip_frame(buf, first, &mut Forever)
+    }
+
+    /* receive a SLIP frame into buf, returning the frame's length, waiting
+       for each byte until timeout expires. a Deadline bounds the whole frame.
+       gives up with DataNotReady, discarding any partial frame */
+    pub fn read_slip_frame_timeout<T: Timeout>(&self, buf: &mut [u8], timeout: &mut T) -> Result<usize, Fault>
+    {
+        let first = self.read_byte_timeout(timeout)?;
+        self.read_rest_of_slip_frame(buf, first, timeout)
+    }
+
+    /* decode a frame starting with first, waiting for each later byte until
+       timeout expires. a frame too long for buf, or with a bad escape, is read
+       and dropped up to its END before the fault is returned, so the next read
+       starts with the frame after it rather than partway through this one */
+    fn read_rest_of_slip_frame<T: Timeout>(&self, buf: &mut [u8], first: u8, timeout: &mut T) -> Result<usize, Fault>
+    {
+        let mut decoder = Decoder::new(buf);
+        let mut byte = first;
+        loop
+        {
+            match decoder.push(byte)
+            {
+                Ok(Some(len)) => return Ok(len),
+                Ok(None) => (),
+                Err(fault) => loop
+                {
+                    match self.read_byte_timeout(timeout)
+                    {
+                        Ok(END) | Err(_) => return Err(fault),
+                        Ok(_) => ()
+                    }
+                }
+            }
+            byte = self.read_byte_timeout(timeout)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::{Fifos, Script};
+    use crate::timeout::Polls;
+    use crate::LOOP_MAX;
+
+    #[test]
+    fn encode_escapes_specials()
+    {
+        let encoded: Vec<u8> = Encoder::new(&[1, END, 2, ESC, 3]).collect();
+        assert_eq!(encoded, [END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, 3, END]);
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let data = [END, ESC, 0, 0xff, ESC_END, ESC_ESC];
+        let mut buf = [0u8; 16];
+        let mut decoder = Decoder::new(&mut buf);
+        let mut len = None;
+        for byte in Encoder::new(&data)
+        {
+            if let Some(l) = decoder.push(byte).unwrap()
+            {
+                len = Some(l);
+            }
+        }
+
+        assert_eq!(len, Some(data.len()));
+        assert_eq!(decoder.frame(), &data);
+    }
+
+    #[test]
+    fn overflow_drops_frame()
+    {
+        let mut buf = [0u8; 2];
+        let mut decoder = Decoder::new(&mut buf);
+        assert!(decoder.push(1).unwrap().is_none());
+        assert!(decoder.push(2).unwrap().is_none());
+        assert!(matches!(decoder.push(3), Err(Fault::FrameTooLong)));
+        assert!(decoder.push(4).unwrap().is_none());
+        assert_eq!(decoder.push(END).unwrap(), None);
+
+        /* next frame decodes normally */
+        assert!(decoder.push(5).unwrap().is_none());
+        assert_eq!(decoder.push(END).unwrap(), Some(1));
+        assert_eq!(decoder.frame(), &[5]);
+    }
+
+    #[test]
+    fn bad_escape_drops_frame()
+    {
+        let mut buf = [0u8; 8];
+        let mut decoder = Decoder::new(&mut buf);
+        decoder.push(ESC).unwrap();
+        assert!(matches!(decoder.push(0x42), Err(Fault::BadEscape)));
+        assert_eq!(decoder.push(END).unwrap(), None);
+    }
+
+    /* bytes trickling in far slower than LOOP_MAX polls apart */
+    #[test]
+    fn waits_for_slow_frames()
+    {
+        let (fifos, uart) = Fifos::uart();
+        let mut buf = [0u8; 8];
+        assert!(matches!(uart.read_slip_frame(&mut buf), Err(Fault::DataNotReady)));
+
+        let frame: Vec<u8> = Encoder::new(b"slow").collect();
+        fifos.arrive(&frame);
+        fifos.shift(1);
+        fifos.set_pace(LOOP_MAX * 3);
+        assert_eq!(uart.read_slip_frame(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"slow");
+
+        fifos.arrive(&frame);
+        assert!(matches!(uart.read_slip_frame_timeout(&mut buf, &mut Polls::new(LOOP_MAX)), Err(Fault::DataNotReady)));
+        assert_eq!(uart.read_slip_frame_timeout(&mut buf, &mut Polls::new(LOOP_MAX * 4)).unwrap(), 4);
+    }
+
+    #[test]
+    fn resyncs_after_broken_frames()
+    {
+        let mut input: Vec<u8> = Encoder::new(b"far too long").collect();
+        input.extend([ESC, 0x42, 1, 2, END]);
+        input.extend(Encoder::new(b"ok"));
+        let (_, uart) = Script::uart(&input);
+
+        let mut buf = [0u8; 4];
+        assert!(matches!(uart.read_slip_frame(&mut buf), Err(Fault::FrameTooLong)));
+        assert!(matches!(uart.read_slip_frame(&mut buf), Err(Fault::BadEscape)));
+        assert_eq!(uart.read_slip_frame(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ok");
+    }
+}
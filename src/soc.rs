@@ -0,0 +1,104 @@
+/* where the UARTs live on SiFive's system-on-chips
+ *
+ * Base addresses and PLIC interrupt numbers are fixed in silicon and come
+ * from each chip's manual. The peripheral clock is set by software, so the
+ * defaults here are what the usual boot firmware leaves it at; systems
+ * that reprogram their PLLs must supply their own value to set_baud().
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::variant::{Variant, Quirks};
+
+/* a UART's location in a chip's physical memory map */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartInfo
+{
+    pub base: usize,    /* physical address of the registers */
+    pub size: usize,    /* size of the MMIO region in bytes */
+    pub irq: u32        /* PLIC interrupt source number */
+}
+
+/* description of a chip's UARTs */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Soc
+{
+    pub name: &'static str,
+    pub uart0: UartInfo,
+    pub uart1: UartInfo,
+    pub clock: u32,     /* default frequency in Hz of the clock driving the UARTs */
+    pub quirks: Quirks  /* how the chip's UARTs differ from the common design */
+}
+
+impl Soc
+{
+    /* the controller variant used for this chip's UARTs */
+    pub const fn variant(&self) -> Variant
+    {
+        Variant::SIFIVE.with_quirks(self.quirks)
+    }
+}
+
+/* each UART is given a 4KiB page */
+const REGION_SIZE: usize = 0x1000;
+
+/* FU540-C000, as used in the HiFive Unleashed. the UARTs are driven by tlclk,
+   which is half coreclk. 500MHz assumes coreclk has been set to 1GHz by the
+   first-stage bootloader */
+pub const FU540_C000: Soc = Soc
+{
+    name: "FU540-C000",
+    uart0: UartInfo { base: 0x1001_0000, size: REGION_SIZE, irq: 4 },
+    uart1: UartInfo { base: 0x1001_1000, size: REGION_SIZE, irq: 5 },
+    clock: 500_000_000,
+    quirks: Quirks::HALF_RATE_CLOCK
+};
+
+/* FU740-C000, as used in the HiFive Unmatched. the UARTs are driven by pclk,
+   which is half hfpclkpll. 130MHz assumes U-Boot SPL's 260MHz hfpclkpll */
+pub const FU740_C000: Soc = Soc
+{
+    name: "FU740-C000",
+    uart0: UartInfo { base: 0x1001_0000, size: REGION_SIZE, irq: 39 },
+    uart1: UartInfo { base: 0x1001_1000, size: REGION_SIZE, irq: 40 },
+    clock: 130_000_000,
+    quirks: Quirks::HALF_RATE_CLOCK
+};
+
+/* FE310-G002, as used in the HiFive1 Rev B. the UARTs are driven by tlclk,
+   which is the same as coreclk. 16MHz assumes the core is running directly
+   from the board's crystal, before any PLL is configured */
+pub const FE310_G002: Soc = Soc
+{
+    name: "FE310-G002",
+    uart0: UartInfo { base: 0x1001_3000, size: REGION_SIZE, irq: 3 },
+    uart1: UartInfo { base: 0x1002_3000, size: REGION_SIZE, irq: 4 },
+    clock: 16_000_000,
+    quirks: Quirks::NONE
+};
+
+impl UART
+{
+    /* create a UART object for a chip's known UART. like new(), this doesn't
+       change the baud rate */
+    pub fn from_info(info: &UartInfo) -> Result<Self, Fault>
+    {
+        UART::new(info.base, info.size)
+    }
+
+    /* like from_info() but with the chip's quirks */
+    pub fn from_soc_info(soc: &Soc, info: &UartInfo) -> Result<Self, Fault>
+    {
+        UART::new_with_variant(info.base, info.size, soc.variant())
+    }
+
+    pub fn fu540_uart0() -> Result<Self, Fault> { UART::from_soc_info(&FU540_C000, &FU540_C000.uart0) }
+    pub fn fu540_uart1() -> Result<Self, Fault> { UART::from_soc_info(&FU540_C000, &FU540_C000.uart1) }
+    pub fn fu740_uart0() -> Result<Self, Fault> { UART::from_soc_info(&FU740_C000, &FU740_C000.uart0) }
+    pub fn fu740_uart1() -> Result<Self, Fault> { UART::from_soc_info(&FU740_C000, &FU740_C000.uart1) }
+    pub fn fe310_uart0() -> Result<Self, Fault> { UART::from_soc_info(&FE310_G002, &FE310_G002.uart0) }
+    pub fn fe310_uart1() -> Result<Self, Fault> { UART::from_soc_info(&FE310_G002, &FE310_G002.uart1) }
+}
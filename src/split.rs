@@ -0,0 +1,139 @@
+/* separate transmit and receive halves, for RTIC and similar frameworks
+ *
+ * RTIC hands each task the resources it owns, so a UART shared between a
+ * task that sends and a task that receives is better split in two: Tx
+ * queues bytes for sending and Rx takes received bytes, each touching only
+ * its own side of a set of Buffers. Neither needs a lock, as the ring
+ * buffers are single-producer, single-consumer. The PLIC task, or whatever
+ * handles the UART's interrupt, calls on_uart_interrupt() to move bytes
+ * between the buffers and the hardware, eg:
+ *
+ *   static BUFFERS: Buffers<64, 256> = Buffers::new();
+ *   ...
+ *   let (tx, rx) = UART_REF.split(&BUFFERS)?;
+ *   ...
+ *   #[task(binds = UART0)] fn uart0(_: uart0::Context) { on_uart_interrupt(UART_REF, &BUFFERS); }
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, WatermarkLevel};
+use super::buffered::Buffers;
+
+/* the sending half of a split UART */
+pub struct Tx<'a, const RX: usize, const TX: usize>
+{
+    uart: &'a UART,
+    buffers: &'a Buffers<RX, TX>
+}
+
+/* the receiving half of a split UART */
+pub struct Rx<'a, const RX: usize, const TX: usize>
+{
+    buffers: &'a Buffers<RX, TX>
+}
+
+impl UART
+{
+    /* split into halves that send and receive through buffers. the rx watermark
+       is set to zero and its interrupt enabled, so every byte received is moved
+       into the buffers promptly. a set of buffers can only be split, or used by
+       any other driver, once; after that, AlreadyClaimed is returned */
+    pub fn split<'a, const RX: usize, const TX: usize>(&'a self, buffers: &'a Buffers<RX, TX>) -> Result<(Tx<'a, RX, TX>, Rx<'a, RX, TX>), Fault>
+    {
+        buffers.claim()?;
+        self.set_rx_watermark(WatermarkLevel::L0);
+        self.enable_rx_watermark_irq(true);
+        Ok((Tx { uart: self, buffers }, Rx { buffers }))
+    }
+}
+
+/* service the UART's interrupt on behalf of a split UART's halves */
+pub fn on_uart_interrupt<const RX: usize, const TX: usize>(uart: &UART, buffers: &Buffers<RX, TX>)
+{
+    uart.handle_interrupt(buffers);
+}
+
+impl<const RX: usize, const TX: usize> Tx<'_, RX, TX>
+{
+    /* queue as much of data as fits, returning how many bytes were queued */
+    pub fn write(&mut self, data: &[u8]) -> usize
+    {
+        self.uart.queue_tx(self.buffers, data)
+    }
+
+    /* room left for bytes to queue */
+    pub fn free(&self) -> usize
+    {
+        self.buffers.tx.free()
+    }
+
+    /* return true once every queued byte has been handed to the hardware */
+    pub fn is_empty(&self) -> bool
+    {
+        self.buffers.tx.is_empty()
+    }
+}
+
+impl<const RX: usize, const TX: usize> Rx<'_, RX, TX>
+{
+    /* take as many received bytes as fit in buf, returning how many */
+    pub fn read(&mut self, buf: &mut [u8]) -> usize
+    {
+        self.buffers.rx.pop_into(buf)
+    }
+
+    /* take the oldest received byte, if any */
+    pub fn read_byte(&mut self) -> Option<u8>
+    {
+        self.buffers.rx.pop()
+    }
+
+    /* number of received bytes waiting to be read */
+    pub fn len(&self) -> usize
+    {
+        self.buffers.rx.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.buffers.rx.is_empty()
+    }
+
+    /* received bytes dropped so far because they weren't read in time */
+    pub fn overruns(&self) -> usize
+    {
+        self.buffers.overruns()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::{REG_RXDATA, REG_RXDATA_EMPTY, REG_TXDATA};
+
+    #[test]
+    fn halves_share_interrupt()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let buffers: Buffers<8, 8> = Buffers::new();
+        let (mut tx, mut rx) = uart.split(&buffers).unwrap();
+        assert!(matches!(uart.split(&buffers), Err(Fault::AlreadyClaimed)));
+
+        assert_eq!(tx.write(b"tx"), 2);
+        assert_eq!(tx.free(), 6);
+        regs.receive(b'r');
+        on_uart_interrupt(&uart, &buffers);
+        regs.set(REG_RXDATA, REG_RXDATA_EMPTY);
+
+        assert!(tx.is_empty());
+        assert_eq!(regs.get(REG_TXDATA), b'x' as u32);
+        assert_eq!(rx.len(), 8);
+        assert_eq!(rx.read_byte(), Some(b'r'));
+    }
+}
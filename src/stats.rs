@@ -0,0 +1,137 @@
+/* counters and timings from the interrupt-driven drivers
+ *
+ * Buffers keeps count of the interrupts it has handled and the bytes it
+ * has had to drop, and, when the handler is timed, how long servicing
+ * took. Calling handle_interrupt_timed() instead of handle_interrupt()
+ * stamps the handler's entry and the point it finished moving bytes with
+ * a caller-provided clock, so the cost of console interrupts to other
+ * real-time work can be measured, eg:
+ *
+ *   fn uart_irq() { UART.handle_interrupt_timed(&BUFFERS, &mut MCycle); }
+ *   ...
+ *   let stats = BUFFERS.stats();
+ *   log!("uart irqs: {} worst: {} cycles", stats.interrupts, stats.max_service);
+ *
+ * Comparing entry stamps with the time the interrupt was raised, eg by a
+ * timer that triggers a known transmission, gives the latency.
+ * Timestamps are kept in a usize, so wrap on 32-bit harts.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::UART;
+use super::buffered::Buffers;
+use super::timeout::Clock;
+
+/* a snapshot of a set of Buffers' counters */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats
+{
+    pub interrupts: usize,      /* calls to the interrupt handler */
+    pub spurious: usize,        /* calls with no enabled interrupt pending */
+    pub overruns: usize,        /* received bytes dropped because the rx buffer was full */
+    pub throttled: usize,       /* received bytes dropped by the rate limit */
+    pub timed: usize,           /* interrupts handled by handle_interrupt_timed() */
+    pub last_entry: usize,      /* clock when the last timed handler was entered */
+    pub last_done: usize,       /* clock when the last timed handler finished */
+    pub max_service: usize,     /* longest a timed handler took, in clock ticks */
+    pub total_service: usize    /* ticks spent in timed handlers, for the average */
+}
+
+/* updated by the interrupt handler */
+pub(crate) struct IrqCounters
+{
+    pub(crate) interrupts: AtomicUsize,
+    pub(crate) spurious: AtomicUsize,
+    timed: AtomicUsize,
+    last_entry: AtomicUsize,
+    last_done: AtomicUsize,
+    max_service: AtomicUsize,
+    total_service: AtomicUsize
+}
+
+impl IrqCounters
+{
+    pub(crate) const fn new() -> Self
+    {
+        IrqCounters
+        {
+            interrupts: AtomicUsize::new(0),
+            spurious: AtomicUsize::new(0),
+            timed: AtomicUsize::new(0),
+            last_entry: AtomicUsize::new(0),
+            last_done: AtomicUsize::new(0),
+            max_service: AtomicUsize::new(0),
+            total_service: AtomicUsize::new(0)
+        }
+    }
+
+    fn record(&self, entry: u64, done: u64)
+    {
+        let service = done.wrapping_sub(entry) as usize;
+        self.timed.fetch_add(1, Ordering::Relaxed);
+        self.last_entry.store(entry as usize, Ordering::Relaxed);
+        self.last_done.store(done as usize, Ordering::Relaxed);
+        self.max_service.fetch_max(service, Ordering::Relaxed);
+        self.total_service.fetch_add(service, Ordering::Relaxed);
+    }
+}
+
+impl<const RX: usize, const TX: usize> Buffers<RX, TX>
+{
+    /* return the counters so far */
+    pub fn stats(&self) -> Stats
+    {
+        let irqs = &self.irqs;
+        Stats
+        {
+            interrupts: irqs.interrupts.load(Ordering::Relaxed),
+            spurious: irqs.spurious.load(Ordering::Relaxed),
+            overruns: self.overruns(),
+            throttled: self.throttled(),
+            timed: irqs.timed.load(Ordering::Relaxed),
+            last_entry: irqs.last_entry.load(Ordering::Relaxed),
+            last_done: irqs.last_done.load(Ordering::Relaxed),
+            max_service: irqs.max_service.load(Ordering::Relaxed),
+            total_service: irqs.total_service.load(Ordering::Relaxed)
+        }
+    }
+}
+
+impl UART
+{
+    /* handle_interrupt(), recording when it was entered and when it finished
+       using clock */
+    pub fn handle_interrupt_timed<C: Clock, const RX: usize, const TX: usize>(&self, buffers: &Buffers<RX, TX>, clock: &mut C)
+    {
+        let entry = clock.now();
+        self.handle_interrupt(buffers);
+        buffers.irqs.record(entry, clock.now());
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    #[test]
+    fn times_the_handler()
+    {
+        let (_, uart) = Script::uart(b"0123456789");
+        let buffers: Buffers<4, 4> = Buffers::new();
+        let mut time = 0;
+        let mut clock = || { time += 3; time };
+
+        uart.handle_interrupt(&buffers);
+        uart.handle_interrupt_timed(&buffers, &mut clock);
+        let stats = buffers.stats();
+        assert_eq!((stats.interrupts, stats.timed, stats.overruns), (2, 1, 6));
+        assert_eq!((stats.last_entry, stats.last_done, stats.max_service), (3, 6, 3));
+        assert_eq!(stats.total_service, 3);
+    }
+}
@@ -0,0 +1,117 @@
+/* catching misuse of the driver during development
+ *
+ * Some mistakes don't fail, they just misbehave quietly: reading with the
+ * receiver disabled always finds the rx FIFO empty, a watermark beyond a
+ * variant's shallower FIFO is clamped to one that fires at a different
+ * level, and changing the divisor while bytes are still being sent
+ * garbles them. With the strict feature enabled, debug builds panic where
+ * the mistake is made, saying what it was, eg:
+ *
+ *   mmio_sifive_uart: misuse: divisor changed while transmitting
+ *
+ * The checks that read registers only do so in strict debug builds, so
+ * release builds, and builds without the feature, access the controller
+ * exactly as before.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, REG_RXCTRL, REG_RXCTRL_RXEN, REG_TXCTRL, REG_TXCTRL_TXEN, REG_TXCTRL_TXCNT_SHIFT,
+            REG_IP, REG_IP_TXWM};
+use super::math::watermark;
+
+/* true if misuse is caught */
+const STRICT: bool = cfg!(all(feature = "strict", debug_assertions));
+
+/* panic in a strict debug build unless ok */
+#[inline]
+pub(crate) fn check(ok: bool, what: &'static str)
+{
+    if STRICT && !ok
+    {
+        misuse(what);
+    }
+}
+
+#[cold]
+fn misuse(what: &'static str) -> !
+{
+    panic!("mmio_sifive_uart: misuse: {}", what)
+}
+
+impl UART
+{
+    /* called when a read finds the rx FIFO empty, which is all it ever will
+       with the receiver disabled */
+    #[inline]
+    pub(crate) fn check_rx_enabled(&self)
+    {
+        if STRICT && self.read_reg(REG_RXCTRL) & REG_RXCTRL_RXEN == 0
+        {
+            misuse("read with the receiver disabled");
+        }
+    }
+
+    /* called before the divisor is changed. txwm is pending while the tx FIFO
+       holds fewer than txcnt bytes, so with it clear and txcnt above zero, there
+       are bytes still to send at the old rate */
+    #[inline]
+    pub(crate) fn check_tx_quiet(&self)
+    {
+        if !STRICT
+        {
+            return
+        }
+
+        let ctrl = self.read_reg(REG_TXCTRL);
+        let txcnt = watermark(ctrl, REG_TXCTRL_TXCNT_SHIFT);
+        if ctrl & REG_TXCTRL_TXEN != 0 && txcnt > 0 && self.read_reg(REG_IP) & REG_IP_TXWM == 0
+        {
+            misuse("divisor changed while transmitting");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{UART, Variant, WatermarkLevel, REG_TOTAL_SIZE};
+    use crate::tests::{Fifos, FakeRegisters};
+
+    #[test]
+    #[cfg_attr(all(feature = "strict", debug_assertions), should_panic(expected = "receiver disabled"))]
+    fn catches_reads_with_receiver_off()
+    {
+        let (_, mut uart) = Fifos::uart();
+        assert!(uart.read_byte().is_err());
+        uart.shutdown();
+        assert!(uart.read_byte().is_err());
+    }
+
+    #[test]
+    #[cfg_attr(all(feature = "strict", debug_assertions), should_panic(expected = "while transmitting"))]
+    fn catches_divisor_changes_mid_transmission()
+    {
+        let (fifos, uart) = Fifos::uart();
+        uart.set_baud(115_200, 115_200 * 100);
+        uart.send_byte(b'x').unwrap();
+        fifos.shift(1);
+        uart.set_baud(9600, 9600 * 100);
+        uart.send_byte(b'y').unwrap();
+        uart.set_baud(115_200, 115_200 * 100);
+    }
+
+    #[test]
+    #[cfg_attr(all(feature = "strict", debug_assertions), should_panic(expected = "watermark out of range"))]
+    fn catches_watermarks_beyond_fifo()
+    {
+        let regs = FakeRegisters::new();
+        let shallow = Variant { fifo_depth: 4, ..Variant::SIFIVE };
+        let uart = UART::new_with_variant(regs.base(), REG_TOTAL_SIZE, shallow).unwrap();
+        uart.set_tx_watermark(WatermarkLevel::L3);
+        uart.set_rx_watermark(WatermarkLevel::L7);
+        assert_eq!(uart.rx_watermark(), WatermarkLevel::L3);
+    }
+}
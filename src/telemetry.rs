@@ -0,0 +1,160 @@
+/* stream numeric samples for graphing on the host
+ *
+ * During bring-up it helps to watch sensor readings and control loop
+ * values as graphs rather than scrolling numbers. Telemetry sends each
+ * sample either as a row of CSV, with a header row naming the columns
+ * sent before the first, for logging to a file or a serial plotter, or
+ * as teleplot lines, one per value, eg:
+ *
+ *   let mut plot = Telemetry::new(&uart, Style::Teleplot, &["temp", "fan"]);
+ *   loop
+ *   {
+ *       plot.sample_at(millis(), &[read_temp(), fan_rpm()])?;
+ *   }
+ *
+ * Values are integers, formatted with printf() rather than core::fmt to
+ * keep the overhead down: send fixed-point values, eg millidegrees.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault};
+use super::printf::Arg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style
+{
+    Csv,        /* name,name CRLF then value,value CRLF per sample */
+    Teleplot    /* >name:value LF, or >name:time:value LF, per value */
+}
+
+pub struct Telemetry<'a>
+{
+    uart: &'a UART,
+    style: Style,
+    names: &'a [&'a str],
+    header_sent: bool
+}
+
+impl<'a> Telemetry<'a>
+{
+    /* send samples of the values named by names, in that order */
+    pub fn new(uart: &'a UART, style: Style, names: &'a [&'a str]) -> Self
+    {
+        Telemetry { uart, style, names, header_sent: false }
+    }
+
+    /* send a sample. values are matched to names in order, and any beyond the
+       last name are ignored */
+    pub fn sample(&mut self, values: &[i64]) -> Result<(), Fault>
+    {
+        self.send(None, values)
+    }
+
+    /* send a sample taken at time, in whatever units the host expects, eg
+       milliseconds for teleplot. CSV gets a leading time column, as long as
+       the first sample sent was timestamped */
+    pub fn sample_at(&mut self, time: u64, values: &[i64]) -> Result<(), Fault>
+    {
+        self.send(Some(time), values)
+    }
+
+    /* send the CSV header again before the next sample, eg after the host reconnects */
+    pub fn restart(&mut self)
+    {
+        self.header_sent = false;
+    }
+
+    fn send(&mut self, time: Option<u64>, values: &[i64]) -> Result<(), Fault>
+    {
+        let values = values.iter().zip(self.names);
+        match self.style
+        {
+            Style::Csv =>
+            {
+                if !self.header_sent
+                {
+                    if time.is_some()
+                    {
+                        self.print("time,", &[])?;
+                    }
+                    self.row(self.names.iter().map(|&name| name.into()), "%s")?;
+                    self.header_sent = true;
+                }
+
+                if let Some(time) = time
+                {
+                    self.print("%u,", &[time.into()])?;
+                }
+                self.row(values.map(|(&value, _)| value.into()), "%d")
+            },
+
+            Style::Teleplot =>
+            {
+                for (&value, &name) in values
+                {
+                    match time
+                    {
+                        Some(time) => self.print(">%s:%u:%d\n", &[name.into(), time.into(), value.into()])?,
+                        None => self.print(">%s:%d\n", &[name.into(), value.into()])?
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /* send a CSV row of items, each formatted with conversion */
+    fn row<'b, I: Iterator<Item = Arg<'b>>>(&self, items: I, conversion: &str) -> Result<(), Fault>
+    {
+        for (i, item) in items.enumerate()
+        {
+            if i > 0
+            {
+                self.print(",", &[])?;
+            }
+            self.print(conversion, &[item])?;
+        }
+        self.print("\r\n", &[])
+    }
+
+    fn print(&self, fmt: &str, args: &[Arg]) -> Result<(), Fault>
+    {
+        self.uart.printf(fmt, args).map(|_| ()).map_err(|partial| partial.fault)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    #[test]
+    fn csv_rows()
+    {
+        let (script, uart) = Script::uart(b"");
+        let mut csv = Telemetry::new(&uart, Style::Csv, &["x", "y"]);
+        csv.sample_at(10, &[1, -2]).unwrap();
+        csv.sample_at(20, &[3, 4, 5]).unwrap();
+        assert_eq!(script.sent(), b"time,x,y\r\n10,1,-2\r\n20,3,4\r\n");
+
+        let mut csv = Telemetry::new(&uart, Style::Csv, &["x"]);
+        csv.sample(&[7]).unwrap();
+        csv.restart();
+        csv.sample(&[8]).unwrap();
+        assert_eq!(script.sent(), b"x\r\n7\r\nx\r\n8\r\n");
+    }
+
+    #[test]
+    fn teleplot_lines()
+    {
+        let (script, uart) = Script::uart(b"");
+        let mut plot = Telemetry::new(&uart, Style::Teleplot, &["temp", "fan"]);
+        plot.sample(&[21, 900]).unwrap();
+        plot.sample_at(1500, &[-3]).unwrap();
+        assert_eq!(script.sent(), b">temp:21\n>fan:900\n>temp:1500:-3\n");
+    }
+}
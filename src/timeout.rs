@@ -0,0 +1,482 @@
+/* bounding how long blocking operations wait
+ *
+ * By default, blocking operations give up after checking the hardware a
+ * fixed number of times, which needs no timer but means the time allowed
+ * depends on the CPU's speed. Passing a Timeout to the _timeout() variants
+ * of those operations gives the wait a precise meaning instead: Ticks
+ * measures it against any free-running counter. With the embedded-hal
+ * feature, any embedded_hal::delay::DelayNs can be used, and with the riscv
+ * feature, the hart's mcycle counter or the CLINT's mtime.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, REG_TXDATA};
+
+/* decides when a blocking operation has waited long enough */
+pub trait Timeout
+{
+    /* called once as an operation begins waiting */
+    fn start(&mut self);
+
+    /* called each time the operation finds the hardware isn't ready. may pause
+       before returning. returns true once the time allowed has passed */
+    fn expired(&mut self) -> bool;
+}
+
+/* give up after checking the hardware a number of times. this is the
+   no-dependency default used by send_byte() and friends */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Polls
+{
+    limit: usize,
+    remaining: usize
+}
+
+impl Polls
+{
+    pub const fn new(limit: usize) -> Self
+    {
+        Polls { limit, remaining: limit }
+    }
+}
+
+impl Timeout for Polls
+{
+    fn start(&mut self)
+    {
+        self.remaining = self.limit;
+    }
+
+    fn expired(&mut self) -> bool
+    {
+        match self.remaining
+        {
+            0 => true,
+            _ =>
+            {
+                self.remaining -= 1;
+                false
+            }
+        }
+    }
+}
+
+/* never give up */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Forever;
+
+impl Timeout for Forever
+{
+    fn start(&mut self) {}
+
+    fn expired(&mut self) -> bool
+    {
+        false
+    }
+}
+
+/* a free-running counter that timeouts can be measured against */
+pub trait Clock
+{
+    fn now(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> Clock for F
+{
+    fn now(&mut self) -> u64
+    {
+        self()
+    }
+}
+
+/* give up once a clock has advanced by a number of ticks */
+pub struct Ticks<C: Clock>
+{
+    clock: C,
+    ticks: u64,
+    started: u64
+}
+
+impl<C: Clock> Ticks<C>
+{
+    pub fn new(clock: C, ticks: u64) -> Self
+    {
+        Ticks { clock, ticks, started: 0 }
+    }
+
+    /* return the clock */
+    pub fn release(self) -> C
+    {
+        self.clock
+    }
+}
+
+impl<C: Clock> Timeout for Ticks<C>
+{
+    fn start(&mut self)
+    {
+        self.started = self.clock.now();
+    }
+
+    fn expired(&mut self) -> bool
+    {
+        self.clock.now().wrapping_sub(self.started) >= self.ticks
+    }
+}
+
+/* give up once a clock reaches an absolute tick value. unlike the other
+   timeouts, this isn't restarted as each operation begins, so a series of
+   operations using it share a single time budget. the clock may wrap */
+pub struct Deadline<C: Clock>
+{
+    clock: C,
+    deadline: u64
+}
+
+impl<C: Clock> Deadline<C>
+{
+    pub fn new(deadline: u64, clock: C) -> Self
+    {
+        Deadline { clock, deadline }
+    }
+
+    /* return the clock */
+    pub fn release(self) -> C
+    {
+        self.clock
+    }
+}
+
+impl<C: Clock> Timeout for Deadline<C>
+{
+    fn start(&mut self) {}
+
+    fn expired(&mut self) -> bool
+    {
+        self.clock.now().wrapping_sub(self.deadline) as i64 >= 0
+    }
+}
+
+/* wait up to a number of microseconds, pausing for one between checks */
+#[cfg(feature = "embedded-hal")]
+pub struct Delay<D: embedded_hal::delay::DelayNs>
+{
+    delay: D,
+    limit_us: u32,
+    waited_us: u32
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<D: embedded_hal::delay::DelayNs> Delay<D>
+{
+    pub fn new(delay: D, limit_us: u32) -> Self
+    {
+        Delay { delay, limit_us, waited_us: 0 }
+    }
+
+    /* return the delay provider */
+    pub fn release(self) -> D
+    {
+        self.delay
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<D: embedded_hal::delay::DelayNs> Timeout for Delay<D>
+{
+    fn start(&mut self)
+    {
+        self.waited_us = 0;
+    }
+
+    fn expired(&mut self) -> bool
+    {
+        if self.waited_us >= self.limit_us
+        {
+            return true
+        }
+
+        self.delay.delay_us(1);
+        self.waited_us += 1;
+        false
+    }
+}
+
+/* clocks available to M-mode firmware before any HAL timer is set up */
+#[cfg(feature = "riscv")]
+pub mod riscv
+{
+    use core::ptr::read_volatile;
+    use super::Clock;
+
+    /* the current hart's cycle counter, which runs at the core clock */
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct MCycle;
+
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    impl Clock for MCycle
+    {
+        #[cfg(target_arch = "riscv64")]
+        fn now(&mut self) -> u64
+        {
+            let cycles: u64;
+            unsafe { core::arch::asm!("csrr {0}, mcycle", out(reg) cycles) };
+            cycles
+        }
+
+        #[cfg(target_arch = "riscv32")]
+        fn now(&mut self) -> u64
+        {
+            /* re-read if the low half wrapped between reading the halves */
+            loop
+            {
+                let (hi, lo, hi_again): (u32, u32, u32);
+                unsafe
+                {
+                    core::arch::asm!("csrr {0}, mcycleh", "csrr {1}, mcycle", "csrr {2}, mcycleh",
+                                     out(reg) hi, out(reg) lo, out(reg) hi_again)
+                };
+                if hi == hi_again
+                {
+                    return (hi as u64) << 32 | lo as u64
+                }
+            }
+        }
+    }
+
+    /* offset of the mtime register from the base of a SiFive CLINT, which is
+       at 0x0200_0000 on the FU540, FU740 and FE310 */
+    pub const CLINT_MTIME: usize = 0xbff8;
+
+    /* the CLINT's machine timer, shared by all harts and running at the
+       board's real-time clock rate rather than the core clock */
+    #[derive(Debug, Clone, Copy)]
+    pub struct Mtime
+    {
+        addr: usize
+    }
+
+    impl Mtime
+    {
+        /* safety: clint_base must be the base address of a mapped SiFive CLINT */
+        pub const unsafe fn new(clint_base: usize) -> Self
+        {
+            Mtime { addr: clint_base + CLINT_MTIME }
+        }
+    }
+
+    impl Clock for Mtime
+    {
+        #[cfg(target_pointer_width = "64")]
+        fn now(&mut self) -> u64
+        {
+            unsafe { read_volatile(self.addr as *const u64) }
+        }
+
+        #[cfg(not(target_pointer_width = "64"))]
+        fn now(&mut self) -> u64
+        {
+            /* re-read if the low half wrapped between reading the halves */
+            loop
+            {
+                let hi = unsafe { read_volatile((self.addr + 4) as *const u32) };
+                let lo = unsafe { read_volatile(self.addr as *const u32) };
+                if hi == unsafe { read_volatile((self.addr + 4) as *const u32) }
+                {
+                    return (hi as u64) << 32 | lo as u64
+                }
+            }
+        }
+    }
+}
+
+impl UART
+{
+    /* send a byte, waiting until timeout expires for room in the tx FIFO.
+       fails with TxNotEmpty if there's still no room */
+    pub fn send_byte_timeout<T: Timeout>(&self, to_send: u8, timeout: &mut T) -> Result<(), Fault>
+    {
+        self.step();
+        timeout.start();
+        while self.is_tx_full()
+        {
+            self.relax();
+            if timeout.expired()
+            {
+                return Err(Fault::TxNotEmpty)
+            }
+        }
+
+        self.write_reg(REG_TXDATA, to_send as u32);
+        Ok(())
+    }
+
+    /* read a byte, waiting until timeout expires for one to arrive.
+       fails with DataNotReady if none does */
+    pub fn read_byte_timeout<T: Timeout>(&self, timeout: &mut T) -> Result<u8, Fault>
+    {
+        self.step();
+        timeout.start();
+        loop
+        {
+            if let Ok(byte) = self.read_byte()
+            {
+                return Ok(byte)
+            }
+
+            self.relax();
+            if timeout.expired()
+            {
+                return Err(Fault::DataNotReady)
+            }
+        }
+    }
+
+    /* fill buf with received bytes, waiting until timeout expires for each. with
+       a Deadline, the whole read shares one time budget. fails with DataNotReady
+       if buf couldn't be filled in time */
+    pub fn read_exact_timeout<T: Timeout>(&self, buf: &mut [u8], timeout: &mut T) -> Result<(), Fault>
+    {
+        for slot in buf.iter_mut()
+        {
+            *slot = self.read_byte_timeout(timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /* send all of data, waiting until timeout expires for room for each byte.
+       fails with TxNotEmpty if it couldn't all be sent in time */
+    pub fn send_all_timeout<T: Timeout>(&self, data: &[u8], timeout: &mut T) -> Result<(), Fault>
+    {
+        for &byte in data
+        {
+            self.send_byte_timeout(byte, timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /* fill buf before now() reaches deadline */
+    pub fn read_exact_before<F: FnMut() -> u64>(&self, buf: &mut [u8], deadline: u64, now: F) -> Result<(), Fault>
+    {
+        self.read_exact_timeout(buf, &mut Deadline::new(deadline, now))
+    }
+
+    /* send all of data before now() reaches deadline */
+    pub fn send_all_before<F: FnMut() -> u64>(&self, data: &[u8], deadline: u64, now: F) -> Result<(), Fault>
+    {
+        self.send_all_timeout(data, &mut Deadline::new(deadline, now))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+    use crate::REG_TXDATA_FULL;
+
+    #[test]
+    fn send_gives_up_when_fifo_stays_full()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+
+        let mut polls = Polls::new(3);
+        assert!(matches!(uart.send_byte_timeout(b'x', &mut polls), Err(Fault::TxNotEmpty)));
+        assert!(matches!(uart.send_byte(b'x'), Err(Fault::TxNotEmpty)));
+        assert!(matches!(uart.read_byte_timeout(&mut polls), Err(Fault::DataNotReady)));
+
+        regs.set(REG_TXDATA, 0);
+        assert!(uart.send_byte_timeout(b'x', &mut polls).is_ok());
+        assert_eq!(regs.get(REG_TXDATA), b'x' as u32);
+    }
+
+    #[test]
+    fn waits_call_yield_hook()
+    {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static YIELDS: AtomicUsize = AtomicUsize::new(0);
+
+        let regs = FakeRegisters::new();
+        let mut uart = regs.uart();
+        uart.set_yield_hook(Some(|| { YIELDS.fetch_add(1, Ordering::Relaxed); }));
+        regs.set(REG_TXDATA, REG_TXDATA_FULL);
+        assert!(uart.send_byte_timeout(b'y', &mut Polls::new(3)).is_err());
+        assert_eq!(YIELDS.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn bulk_writes_feed_watchdog()
+    {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static FEEDS: AtomicUsize = AtomicUsize::new(0);
+
+        let regs = FakeRegisters::new();
+        let mut uart = regs.uart();
+        uart.set_watchdog_hook(Some(|| { FEEDS.fetch_add(1, Ordering::Relaxed); }), 100);
+        assert!(uart.send_all_timeout(&[0u8; 1000], &mut Polls::new(1)).is_ok());
+        assert_eq!(FEEDS.load(Ordering::Relaxed), 10);
+    }
+
+    #[test]
+    fn ticks_measure_a_clock()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut now = 0u64;
+        let mut ticks = Ticks::new(|| { now += 10; now }, 100);
+        assert!(uart.read_byte_timeout(&mut ticks).is_err());
+        assert_eq!(now, 110);
+    }
+
+    #[test]
+    fn deadline_is_shared()
+    {
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut now = u64::MAX - 5;
+        let mut buf = [0u8; 4];
+        assert!(matches!(uart.read_exact_before(&mut buf, 2, || { now = now.wrapping_add(1); now }), Err(Fault::DataNotReady)));
+        assert_eq!(now, 2);
+
+        regs.receive(b'D');
+        assert!(uart.read_exact_before(&mut buf, 1, || 0).is_ok());
+        assert!(uart.send_all_before(b"ok", 1, || 0).is_ok());
+        assert_eq!(&buf, b"DDDD");
+    }
+
+    #[cfg(feature = "riscv")]
+    #[test]
+    fn mtime_reads_the_clint()
+    {
+        let clint = vec![0u64; (riscv::CLINT_MTIME + 8) / 8];
+        let addr = clint.as_ptr() as usize;
+        unsafe { *((addr + riscv::CLINT_MTIME) as *mut u64) = 0x1_0000_0002 };
+        let mut mtime = unsafe { riscv::Mtime::new(addr) };
+        assert_eq!(mtime.now(), 0x1_0000_0002);
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn delay_counts_microseconds()
+    {
+        struct Clock(u32);
+        impl embedded_hal::delay::DelayNs for Clock
+        {
+            fn delay_ns(&mut self, ns: u32) { self.0 += ns }
+        }
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        let mut delay = Delay::new(Clock(0), 250);
+        assert!(uart.read_byte_timeout(&mut delay).is_err());
+        assert_eq!(delay.release().0, 250_000);
+    }
+}
@@ -0,0 +1,90 @@
+/* choosing FIFO watermarks from the baud rate and interrupt latency
+ *
+ * The watermarks trade interrupt rate against the risk of losing data. A
+ * high rx watermark means fewer interrupts, but less room left in the rx
+ * FIFO for bytes that arrive before the handler runs; a low tx watermark
+ * means fewer interrupts, but less queued to send while the handler is
+ * held up, so the line goes idle. Given the longest the system can take
+ * to respond to an interrupt, recommended_watermarks() picks the levels
+ * that interrupt least often without overflowing or running dry, eg:
+ *
+ *   let (tx, rx) = uart.apply_recommended_watermarks(115200, 200, BUS_HZ);
+ *
+ * If the latency is longer than the FIFOs can cover at that rate, the
+ * safest levels are returned, but data may still be lost: use the
+ * buffered drivers with a shorter latency, or a slower rate.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Variant, WatermarkLevel};
+use super::math::achieved_baud;
+
+/* (tx, rx) watermarks for SiFive's controller running at baud, set from
+   bus_freq in Hz, when interrupts may take up to max_latency_us microseconds
+   to be serviced */
+pub fn recommended_watermarks(baud: u32, max_latency_us: u32, bus_freq: u32) -> (WatermarkLevel, WatermarkLevel)
+{
+    Variant::SIFIVE.recommended_watermarks(baud, max_latency_us, bus_freq)
+}
+
+impl Variant
+{
+    /* as recommended_watermarks(), for this variant's FIFO depth and watermark range */
+    pub fn recommended_watermarks(&self, baud: u32, max_latency_us: u32, bus_freq: u32) -> (WatermarkLevel, WatermarkLevel)
+    {
+        /* the rate actually achieved by the divisor set for baud */
+        let actual = achieved_baud(baud.max(1), bus_freq, self.divisor_offset).unwrap_or(bus_freq) as u64;
+
+        /* bytes that can cross the line before the handler responds */
+        let chars = (max_latency_us as u64 * actual).div_ceil(UART::BITS_PER_CHAR as u64 * 1_000_000);
+
+        /* the rx interrupt fires with rx + 1 bytes waiting. leave room for the
+           bytes that arrive while it waits to be serviced */
+        let rx = (self.fifo_depth as u64).saturating_sub(1 + chars) as u32;
+
+        /* the tx interrupt fires with tx - 1 bytes left to send. make sure they
+           keep the line busy until it's serviced */
+        let tx = chars.saturating_add(1).min(self.watermark_max().get() as u64) as u32;
+
+        (WatermarkLevel::saturating(tx), self.clamp_watermark(WatermarkLevel::saturating(rx)))
+    }
+}
+
+impl UART
+{
+    /* work out the recommended watermarks for this controller and set them,
+       returning them as (tx, rx) */
+    pub fn apply_recommended_watermarks(&self, baud: u32, max_latency_us: u32, bus_freq: u32) -> (WatermarkLevel, WatermarkLevel)
+    {
+        let (tx, rx) = self.variant.recommended_watermarks(baud, max_latency_us, bus_freq);
+        self.set_tx_watermark(tx);
+        self.set_rx_watermark(rx);
+        (tx, rx)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::FakeRegisters;
+
+    #[test]
+    fn watermarks_follow_latency()
+    {
+        /* at 115200, a byte takes about 87us */
+        assert_eq!(recommended_watermarks(115200, 0, 500_000_000), (WatermarkLevel::L1, WatermarkLevel::L7));
+        assert_eq!(recommended_watermarks(115200, 100, 500_000_000), (WatermarkLevel::L3, WatermarkLevel::L5));
+        assert_eq!(recommended_watermarks(115200, 300, 500_000_000), (WatermarkLevel::L5, WatermarkLevel::L3));
+        assert_eq!(recommended_watermarks(115200, 10_000, 500_000_000), (WatermarkLevel::L7, WatermarkLevel::L0));
+        assert_eq!(recommended_watermarks(9600, 300, 500_000_000), (WatermarkLevel::L2, WatermarkLevel::L6));
+
+        let regs = FakeRegisters::new();
+        let uart = regs.uart();
+        assert_eq!(uart.apply_recommended_watermarks(115200, 100, 500_000_000), (WatermarkLevel::L3, WatermarkLevel::L5));
+        assert_eq!((uart.tx_watermark(), uart.rx_watermark()), (WatermarkLevel::L3, WatermarkLevel::L5));
+    }
+}
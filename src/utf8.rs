@@ -0,0 +1,157 @@
+/* sending and receiving characters rather than bytes
+ *
+ * Characters outside ASCII take two to four bytes in UTF-8, so code that
+ * reads and writes a byte at a time mangles them. send_char() encodes a
+ * character in full, and read_char() assembles one from the bytes that
+ * arrive. Anything that isn't valid UTF-8, such as a stray continuation
+ * byte, an overlong encoding or a surrogate, is read as U+FFFD, the
+ * replacement character, one for each maximal invalid run as the Unicode
+ * standard recommends, eg:
+ *
+ *   let mut decoder = Utf8Decoder::new();
+ *   loop
+ *   {
+ *       let c = decoder.read_char(&uart)?;
+ *       uart.send_char(c.to_ascii_uppercase())?;
+ *   }
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, LOOP_MAX};
+use super::output::Partial;
+use super::timeout::{Timeout, Polls};
+
+const REPLACEMENT: char = char::REPLACEMENT_CHARACTER;
+
+/* assembles received bytes into characters. an invalid sequence is only found
+   to be invalid when the byte after it arrives, so that byte is kept here for
+   the next character rather than lost */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Utf8Decoder
+{
+    pending: Option<u8>
+}
+
+impl Utf8Decoder
+{
+    pub const fn new() -> Self
+    {
+        Utf8Decoder { pending: None }
+    }
+
+    /* read a character, checking up to LOOP_MAX times for each byte to arrive */
+    pub fn read_char(&mut self, uart: &UART) -> Result<char, Fault>
+    {
+        self.read_char_timeout(uart, &mut Polls::new(LOOP_MAX))
+    }
+
+    /* read a character, waiting until timeout expires for each byte. fails with
+       DataNotReady if the first doesn't arrive. a sequence cut short by the
+       timeout is read as the replacement character */
+    pub fn read_char_timeout<T: Timeout>(&mut self, uart: &UART, timeout: &mut T) -> Result<char, Fault>
+    {
+        let lead = match self.pending.take()
+        {
+            Some(byte) => byte,
+            None => uart.read_byte_timeout(timeout)?
+        };
+
+        let (len, mut code) = match lead
+        {
+            0x00..=0x7f => return Ok(lead as char),
+            0xc2..=0xdf => (2, lead as u32 & 0x1f),
+            0xe0..=0xef => (3, lead as u32 & 0x0f),
+            0xf0..=0xf4 => (4, lead as u32 & 0x07),
+
+            /* continuation bytes, and leads of overlong or out-of-range sequences */
+            _ => return Ok(REPLACEMENT)
+        };
+
+        for i in 1..len
+        {
+            let byte = match uart.read_byte_timeout(timeout)
+            {
+                Ok(byte) => byte,
+                Err(_) => return Ok(REPLACEMENT)
+            };
+
+            /* the second byte is restricted further, to rule out overlong
+               encodings, surrogates and code points beyond U+10FFFF */
+            let valid = match (i, lead)
+            {
+                (1, 0xe0) => (0xa0..=0xbf).contains(&byte),
+                (1, 0xed) => (0x80..=0x9f).contains(&byte),
+                (1, 0xf0) => (0x90..=0xbf).contains(&byte),
+                (1, 0xf4) => (0x80..=0x8f).contains(&byte),
+                _ => byte & 0xc0 == 0x80
+            };
+
+            if !valid
+            {
+                self.pending = Some(byte);
+                return Ok(REPLACEMENT)
+            }
+
+            code = code << 6 | (byte & 0x3f) as u32;
+        }
+
+        Ok(char::from_u32(code).unwrap_or(REPLACEMENT))
+    }
+}
+
+impl UART
+{
+    /* read a character as Utf8Decoder::read_char() does. a byte that shows a
+       sequence to be invalid is lost along with it, so keep a Utf8Decoder to
+       read a stream of characters */
+    pub fn read_char(&self) -> Result<char, Fault>
+    {
+        Utf8Decoder::new().read_char(self)
+    }
+
+    /* send a character's UTF-8 encoding, returning how many bytes were sent */
+    pub fn send_char(&self, c: char) -> Result<usize, Partial>
+    {
+        let mut encoded = [0u8; 4];
+        self.send_bytes(c.encode_utf8(&mut encoded).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tests::Script;
+
+    fn decode(input: &[u8]) -> String
+    {
+        let (_, uart) = Script::uart(input);
+        let mut decoder = Utf8Decoder::new();
+        core::iter::from_fn(|| decoder.read_char_timeout(&uart, &mut Polls::new(1)).ok()).collect()
+    }
+
+    #[test]
+    fn round_trips_characters()
+    {
+        let (script, uart) = Script::uart(b"");
+        for c in ['a', 'é', '€', '🦀']
+        {
+            assert_eq!(uart.send_char(c).unwrap(), c.len_utf8());
+        }
+
+        let sent = script.sent();
+        assert_eq!(sent, "aé€🦀".as_bytes());
+        assert_eq!(decode(&sent), "aé€🦀");
+    }
+
+    #[test]
+    fn replaces_invalid_input()
+    {
+        /* stray continuation, overlong, surrogate, truncated then ASCII, and beyond U+10FFFF */
+        assert_eq!(decode(b"\x80a\xc0\xaf\xed\xa0\x80\xe2\x82z\xf4\x90\x80\x80"), "\u{fffd}a\u{fffd}\u{fffd}\u{fffd}\u{fffd}\u{fffd}\u{fffd}z\u{fffd}\u{fffd}\u{fffd}\u{fffd}");
+        assert_eq!(decode(b"\xe2\x82"), "\u{fffd}");
+    }
+}
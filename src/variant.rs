@@ -0,0 +1,145 @@
+/* controllers that clone SiFive's UART with minor differences
+ *
+ * Several non-SiFive chips, such as the Kendryte K210 with its UARTHS,
+ * reuse this register layout. Rather than forking the driver, each known
+ * deviation is described here and passed to UART::new_with_variant().
+ * Differences that are simply on or off, such as silicon errata needing a
+ * workaround, are collected as Quirks.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::ops::BitOr;
+use super::watermark::{WatermarkLevel, FIFO_DEPTH};
+
+/* a set of quirks, each a single bit */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks(u32);
+
+impl Quirks
+{
+    pub const NONE: Quirks = Quirks(0);
+
+    /* the UART's clock is half the rate of its source, so a source frequency
+       passed to UART::set_baud_from_source() is halved first. true of the
+       FU540, whose tlclk is coreclk / 2, and the FU740, whose pclk is
+       hfpclkpll / 2, but not the FE310, whose tlclk is coreclk itself */
+    pub const HALF_RATE_CLOCK: Quirks = Quirks(1 << 0);
+
+    /* return true if every quirk in other is also in self */
+    pub const fn contains(self, other: Quirks) -> bool
+    {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Quirks) -> Quirks
+    {
+        Quirks(self.0 | other.0)
+    }
+
+    pub const fn is_empty(self) -> bool
+    {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Quirks
+{
+    type Output = Quirks;
+
+    fn bitor(self, other: Quirks) -> Quirks
+    {
+        self.union(other)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variant
+{
+    pub name: &'static str,
+    pub fifo_depth: u32,        /* entries in each of the tx and rx FIFOs */
+    pub watermark_bits: u32,    /* width of the txcnt and rxcnt fields */
+    pub divisor_offset: u32,    /* subtracted from bus_freq / baud to get the divisor */
+    pub quirks: Quirks          /* on-or-off differences and errata workarounds */
+}
+
+impl Variant
+{
+    /* SiFive's own controller, as found in the FU540, FU740 and FE310 */
+    pub const SIFIVE: Variant = Variant
+    {
+        name: "SiFive UART",
+        fifo_depth: FIFO_DEPTH,
+        watermark_bits: 3,
+        divisor_offset: 1,
+        quirks: Quirks::NONE
+    };
+
+    /* Kendryte K210's high-speed UART, which programs its divisor as SiFive's
+       does, and so is the same controller but for its name */
+    pub const K210_UARTHS: Variant = Variant
+    {
+        name: "Kendryte K210 UARTHS",
+        fifo_depth: 8,
+        watermark_bits: 3,
+        divisor_offset: 1,
+        quirks: Quirks::NONE
+    };
+
+    /* this variant with extra quirks, eg Variant::SIFIVE.with_quirks(Quirks::HALF_RATE_CLOCK) */
+    pub const fn with_quirks(self, quirks: Quirks) -> Variant
+    {
+        Variant { quirks: self.quirks.union(quirks), ..self }
+    }
+
+    /* highest watermark level the controller can usefully be set to */
+    pub const fn watermark_max(&self) -> WatermarkLevel
+    {
+        let field_max = (1 << self.watermark_bits) - 1;
+        match self.fifo_depth - 1 < field_max
+        {
+            true => WatermarkLevel::saturating(self.fifo_depth - 1),
+            false => WatermarkLevel::saturating(field_max)
+        }
+    }
+
+    /* limit a watermark level to what the controller supports */
+    pub const fn clamp_watermark(&self, level: WatermarkLevel) -> WatermarkLevel
+    {
+        level.min(self.watermark_max())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn watermarks_fit_fifo_and_field()
+    {
+        assert_eq!(Variant::SIFIVE.watermark_max(), WatermarkLevel::L7);
+        assert_eq!(Variant::SIFIVE.clamp_watermark(WatermarkLevel::L7), WatermarkLevel::L7);
+
+        let shallow = Variant { fifo_depth: 4, ..Variant::SIFIVE };
+        assert_eq!(shallow.clamp_watermark(WatermarkLevel::L6), WatermarkLevel::L3);
+
+        let narrow = Variant { watermark_bits: 2, fifo_depth: 16, ..Variant::SIFIVE };
+        assert_eq!(narrow.watermark_max(), WatermarkLevel::L3);
+
+        let deep = Variant { watermark_bits: 4, fifo_depth: 16, ..Variant::SIFIVE };
+        assert_eq!(deep.watermark_max(), WatermarkLevel::MAX);
+    }
+
+    #[test]
+    fn quirks_combine()
+    {
+        assert!(Variant::SIFIVE.quirks.is_empty());
+        let halved = Variant::SIFIVE.with_quirks(Quirks::HALF_RATE_CLOCK);
+        assert!(halved.quirks.contains(Quirks::HALF_RATE_CLOCK));
+        assert!(halved.quirks.contains(Quirks::NONE));
+        assert_eq!(Quirks::NONE | Quirks::HALF_RATE_CLOCK, halved.quirks);
+    }
+}
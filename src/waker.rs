@@ -0,0 +1,153 @@
+/* a waker slot shared between a task and an interrupt handler
+ *
+ * A task registers its waker before sleeping; the interrupt handler wakes
+ * it when there's work to do. This follows the same lock-free protocol as
+ * futures' AtomicWaker: a wake that races with a registration is never
+ * lost, it either wakes the newly registered waker or the registering
+ * task is woken immediately.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::Waker;
+
+const WAITING: usize = 0;       /* idle: the slot may be registered or woken */
+const REGISTERING: usize = 1;   /* a task is storing its waker */
+const WAKING: usize = 2;        /* a wake is in progress, or arrived mid-registration */
+
+pub struct AtomicWaker
+{
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>
+}
+
+/* access to the waker is serialized by the state machine */
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl Default for AtomicWaker
+{
+    fn default() -> Self
+    {
+        AtomicWaker::new()
+    }
+}
+
+impl AtomicWaker
+{
+    pub const fn new() -> Self
+    {
+        AtomicWaker { state: AtomicUsize::new(WAITING), waker: UnsafeCell::new(None) }
+    }
+
+    /* store the waker to call on the next wake(). only one task may register
+       at a time, which is always true for a driver with a single reader or writer */
+    pub fn register(&self, waker: &Waker)
+    {
+        match self.state.compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) =>
+            {
+                unsafe
+                {
+                    let slot = &mut *self.waker.get();
+                    match slot
+                    {
+                        Some(old) if old.will_wake(waker) => (),
+                        _ => *slot = Some(waker.clone())
+                    }
+                }
+
+                /* if a wake arrived while we were busy, it's up to us to deliver it */
+                if self.state.compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire).is_err()
+                {
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker
+                    {
+                        waker.wake();
+                    }
+                }
+            },
+
+            /* a wake is in progress: make sure the task polls again */
+            Err(WAKING) => waker.wake_by_ref(),
+
+            /* another registration is in progress, which is a misuse */
+            Err(_) => ()
+        }
+    }
+
+    /* wake the registered task, if any */
+    pub fn wake(&self)
+    {
+        if let Some(waker) = self.take()
+        {
+            waker.wake();
+        }
+    }
+
+    /* remove and return the registered waker, if any */
+    pub fn take(&self) -> Option<Waker>
+    {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel)
+        {
+            WAITING =>
+            {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            },
+
+            /* a registration in progress will see WAKING and wake itself */
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests
+{
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::task::Wake;
+
+    /* a waker that counts how many times it's been woken */
+    pub(crate) struct Counter(pub(crate) AtomicUsize);
+
+    impl Wake for Counter
+    {
+        fn wake(self: Arc<Self>)
+        {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub(crate) fn counting_waker() -> (Arc<Counter>, Waker)
+    {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        (counter.clone(), Waker::from(counter))
+    }
+
+    #[test]
+    fn wakes_registered_task_once()
+    {
+        let slot = AtomicWaker::new();
+        let (counter, waker) = counting_waker();
+
+        slot.wake();
+        slot.register(&waker);
+        slot.wake();
+        slot.wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        slot.register(&waker);
+        assert!(slot.take().is_some());
+        assert!(slot.take().is_none());
+    }
+}
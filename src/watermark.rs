@@ -0,0 +1,132 @@
+/* FIFO watermark levels
+ *
+ * Each FIFO holds FIFO_DEPTH bytes, and its interrupt watermark is a level
+ * from 0 to FIFO_DEPTH - 1, which is also all the 3-bit txcnt and rxcnt
+ * fields can hold. WatermarkLevel has exactly those values, so a level
+ * beyond the FIFO can't be passed to the configuration methods, eg:
+ *
+ *   uart.set_rx_watermark(WatermarkLevel::L6);
+ *
+ * Levels worked out at run time are converted with new(), which fails for
+ * those out of range, or saturating(), which picks the nearest one. Clones
+ * with shallower FIFOs are limited further by their Variant.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use core::convert::TryFrom;
+use core::fmt;
+use super::{Fault, REG_CNT_FIELD};
+
+/* entries in each of the tx and rx FIFOs of SiFive's controller */
+pub const FIFO_DEPTH: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(u8)]
+pub enum WatermarkLevel
+{
+    #[default]
+    L0, L1, L2, L3, L4, L5, L6, L7
+}
+
+/* the highest level must fill the FIFO's last entry and the register field */
+const _: () = assert!(WatermarkLevel::MAX as u32 == FIFO_DEPTH - 1 && WatermarkLevel::MAX as u32 == REG_CNT_FIELD);
+
+impl WatermarkLevel
+{
+    pub const MIN: WatermarkLevel = WatermarkLevel::L0;
+    pub const MAX: WatermarkLevel = WatermarkLevel::L7;
+
+    /* the level for level, or None if it's beyond the FIFO */
+    pub const fn new(level: u32) -> Option<WatermarkLevel>
+    {
+        match level
+        {
+            0 => Some(WatermarkLevel::L0),
+            1 => Some(WatermarkLevel::L1),
+            2 => Some(WatermarkLevel::L2),
+            3 => Some(WatermarkLevel::L3),
+            4 => Some(WatermarkLevel::L4),
+            5 => Some(WatermarkLevel::L5),
+            6 => Some(WatermarkLevel::L6),
+            7 => Some(WatermarkLevel::L7),
+            _ => None
+        }
+    }
+
+    /* the level for level, or MAX if it's beyond the FIFO */
+    pub const fn saturating(level: u32) -> WatermarkLevel
+    {
+        match WatermarkLevel::new(level)
+        {
+            Some(level) => level,
+            None => WatermarkLevel::MAX
+        }
+    }
+
+    pub const fn get(self) -> u32
+    {
+        self as u32
+    }
+
+    /* the lower of this level and other */
+    pub const fn min(self, other: WatermarkLevel) -> WatermarkLevel
+    {
+        match self as u8 <= other as u8
+        {
+            true => self,
+            false => other
+        }
+    }
+}
+
+impl TryFrom<u32> for WatermarkLevel
+{
+    type Error = Fault;
+
+    /* fails with OutOfRange for a level beyond the FIFO */
+    fn try_from(level: u32) -> Result<Self, Fault>
+    {
+        WatermarkLevel::new(level).ok_or(Fault::OutOfRange)
+    }
+}
+
+impl From<WatermarkLevel> for u32
+{
+    fn from(level: WatermarkLevel) -> u32
+    {
+        level.get()
+    }
+}
+
+/* the level as a number, eg 6 */
+impl fmt::Display for WatermarkLevel
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.get())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn levels_stop_at_fifo_depth()
+    {
+        for level in 0..FIFO_DEPTH
+        {
+            assert_eq!(WatermarkLevel::new(level).map(WatermarkLevel::get), Some(level));
+            assert_eq!(WatermarkLevel::saturating(level).get(), level);
+        }
+        assert_eq!(WatermarkLevel::new(FIFO_DEPTH), None);
+        assert!(matches!(WatermarkLevel::try_from(u32::MAX), Err(Fault::OutOfRange)));
+        assert_eq!(WatermarkLevel::saturating(9), WatermarkLevel::MAX);
+        assert_eq!(WatermarkLevel::L6.min(WatermarkLevel::L2), WatermarkLevel::L2);
+        assert_eq!(format!("rx@{}", WatermarkLevel::L6), "rx@6");
+    }
+}
@@ -0,0 +1,389 @@
+/* XMODEM receiver, for downloading images over the UART
+ *
+ * Supports XMODEM-CRC and XMODEM-1K, falling back to the original
+ * checksum protocol if the sender doesn't respond to CRC requests.
+ * Received blocks are handed to a caller-supplied sink as they arrive,
+ * so the image never has to fit in a buffer owned by this crate.
+ *
+ * Timeouts are counted in polls of the receive register, as elsewhere
+ * in this crate, so they scale with the speed of the CPU core.
+ *
+ * (c) Chris Williams, 2021.
+ *
+ * See README and LICENSE for usage and copying.
+ */
+
+use super::{UART, Fault, Polls};
+use super::crc::crc16;
+
+/* protocol control bytes */
+const SOH: u8 = 0x01; /* start of 128-byte block */
+const STX: u8 = 0x02; /* start of 1024-byte block */
+const EOT: u8 = 0x04; /* end of transmission */
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18; /* cancel transfer */
+const CRC_REQUEST: u8 = b'C';
+
+const BLOCK_SIZE: usize = 128;
+const BLOCK_SIZE_1K: usize = 1024;
+
+/* number of times to ask for CRC mode before falling back to checksums */
+const CRC_ATTEMPTS: usize = 3;
+
+/* defaults: roughly a second or so of polling on a typical core, and
+   the conventional ten retries */
+const DEFAULT_TIMEOUT: usize = 1_000_000;
+const DEFAULT_RETRIES: usize = 10;
+
+/* receives the contents of each valid block, in order. return an error
+   to cancel the transfer */
+pub trait Sink
+{
+    fn write(&mut self, data: &[u8]) -> Result<(), Fault>;
+}
+
+impl<F> Sink for F where F: FnMut(&[u8]) -> Result<(), Fault>
+{
+    fn write(&mut self, data: &[u8]) -> Result<(), Fault>
+    {
+        self(data)
+    }
+}
+
+/* the two things the receiver needs from the serial link */
+trait Link
+{
+    fn send(&mut self, byte: u8) -> Result<(), Fault>;
+
+    /* wait up to the given number of polls for a byte */
+    fn recv(&mut self, polls: usize) -> Result<u8, Fault>;
+}
+
+impl Link for &UART
+{
+    fn send(&mut self, byte: u8) -> Result<(), Fault>
+    {
+        self.send_byte(byte)
+    }
+
+    fn recv(&mut self, polls: usize) -> Result<u8, Fault>
+    {
+        self.read_byte_timeout(&mut Polls::new(polls))
+    }
+}
+
+pub struct Receiver
+{
+    timeout: usize, /* polls to wait for each byte */
+    retries: usize  /* consecutive errors tolerated before giving up */
+}
+
+impl Default for Receiver
+{
+    fn default() -> Self
+    {
+        Receiver::new()
+    }
+}
+
+impl Receiver
+{
+    pub fn new() -> Self
+    {
+        Receiver { timeout: DEFAULT_TIMEOUT, retries: DEFAULT_RETRIES }
+    }
+
+    /* set the number of polls to wait for each byte before timing out */
+    pub fn with_timeout(self, polls: usize) -> Self
+    {
+        Receiver { timeout: polls, ..self }
+    }
+
+    /* set the number of consecutive timeouts or bad blocks tolerated */
+    pub fn with_retries(self, retries: usize) -> Self
+    {
+        Receiver { retries, ..self }
+    }
+
+    /* receive a file from the sender on the other end of the UART, writing
+       each block to the sink. returns the number of bytes received, which
+       includes any padding the sender added to the final block. fails with
+       Cancelled if either side aborts, TooManyErrors if the link is too
+       unreliable, or BadFrame if the sender skips a block */
+    pub fn receive<S: Sink>(&self, uart: &UART, sink: &mut S) -> Result<usize, Fault>
+    {
+        self.run(&mut &*uart, sink)
+    }
+
+    fn run<L: Link, S: Sink>(&self, link: &mut L, sink: &mut S) -> Result<usize, Fault>
+    {
+        let mut block = [0u8; BLOCK_SIZE_1K];
+        let mut crc_mode = true;
+        let mut started = false;
+        let mut expected: u8 = 1;
+        let mut errors = 0;
+        let mut received = 0;
+
+        loop
+        {
+            if errors > self.retries
+            {
+                cancel(link);
+                return Err(Fault::TooManyErrors)
+            }
+
+            /* keep soliciting the sender until the first block arrives */
+            if !started
+            {
+                if errors >= CRC_ATTEMPTS
+                {
+                    crc_mode = false;
+                }
+                link.send(if crc_mode { CRC_REQUEST } else { NAK })?;
+            }
+
+            let size = match link.recv(self.timeout)
+            {
+                Ok(SOH) => BLOCK_SIZE,
+                Ok(STX) => BLOCK_SIZE_1K,
+                Ok(EOT) =>
+                {
+                    link.send(ACK)?;
+                    return Ok(received)
+                },
+                Ok(CAN) =>
+                {
+                    /* two in a row are needed to cancel, to avoid noise aborting the transfer */
+                    if let Ok(CAN) = link.recv(self.timeout)
+                    {
+                        return Err(Fault::Cancelled)
+                    }
+                    continue
+                },
+                /* line noise is as much a failed attempt as silence, so a noisy
+                   line with no sender on it can't keep the receiver going forever */
+                Ok(_) | Err(_) =>
+                {
+                    errors += 1;
+                    if started
+                    {
+                        link.send(NAK)?;
+                    }
+                    continue
+                }
+            };
+
+            started = true;
+            let data = &mut block[..size];
+            /* a block that times out part-way is treated the same as a corrupt one */
+            match self.read_block(link, data, crc_mode).ok().flatten()
+            {
+                Some(number) if number == expected =>
+                {
+                    if let Err(e) = sink.write(data)
+                    {
+                        cancel(link);
+                        return Err(e)
+                    }
+
+                    link.send(ACK)?;
+                    expected = expected.wrapping_add(1);
+                    received += size;
+                    errors = 0;
+                },
+
+                /* our previous ACK must have been lost: acknowledge the resend */
+                Some(number) if number == expected.wrapping_sub(1) => link.send(ACK)?,
+
+                Some(_) =>
+                {
+                    cancel(link);
+                    return Err(Fault::BadFrame)
+                },
+
+                None =>
+                {
+                    self.purge(link);
+                    link.send(NAK)?;
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    /* read the rest of a block after its header byte. returns the block number
+       if it arrived intact, or None if it was corrupted */
+    fn read_block<L: Link>(&self, link: &mut L, data: &mut [u8], crc_mode: bool) -> Result<Option<u8>, Fault>
+    {
+        let number = link.recv(self.timeout)?;
+        let inverse = link.recv(self.timeout)?;
+
+        for byte in data.iter_mut()
+        {
+            *byte = link.recv(self.timeout)?;
+        }
+
+        let mut check = [0u8; 2];
+        let check = match crc_mode
+        {
+            true => &mut check[..],
+            false => &mut check[..1]
+        };
+
+        for byte in check.iter_mut()
+        {
+            *byte = link.recv(self.timeout)?;
+        }
+
+        Ok(block_valid(number, inverse, data, check).then_some(number))
+    }
+
+    /* drain the line until it goes quiet so we're in sync for the resend */
+    fn purge<L: Link>(&self, link: &mut L)
+    {
+        while link.recv(self.timeout).is_ok() {}
+    }
+}
+
+/* returns true if a block's number, contents and checksum are consistent.
+   check is either a big-endian CRC16 or a one-byte arithmetic checksum */
+fn block_valid(number: u8, inverse: u8, data: &[u8], check: &[u8]) -> bool
+{
+    if number != !inverse
+    {
+        return false
+    }
+
+    match check
+    {
+        [hi, lo] => crc16(0, data) == u16::from_be_bytes([*hi, *lo]),
+        [sum] => data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) == *sum,
+        _ => false
+    }
+}
+
+/* best-effort abort of the transfer */
+fn cancel<L: Link>(link: &mut L)
+{
+    let _ = link.send(CAN);
+    let _ = link.send(CAN);
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::VecDeque;
+
+    /* a sender that has already queued up everything it will say.
+       None represents a pause in which the line is quiet */
+    struct Script
+    {
+        incoming: VecDeque<Option<u8>>,
+        sent: Vec<u8>
+    }
+
+    impl Link for Script
+    {
+        fn send(&mut self, byte: u8) -> Result<(), Fault>
+        {
+            self.sent.push(byte);
+            Ok(())
+        }
+
+        fn recv(&mut self, _polls: usize) -> Result<u8, Fault>
+        {
+            self.incoming.pop_front().flatten().ok_or(Fault::DataNotReady)
+        }
+    }
+
+    fn crc_block(number: u8, fill: u8) -> Vec<u8>
+    {
+        let data = [fill; BLOCK_SIZE];
+        let mut block = vec![SOH, number, !number];
+        block.extend(&data);
+        block.extend(&crc16(0, &data).to_be_bytes());
+        block
+    }
+
+    /* run the receiver against bursts of data from the sender,
+       with the line going quiet after each burst */
+    fn run(bursts: &[Vec<u8>]) -> (Result<usize, Fault>, Vec<u8>, Vec<u8>)
+    {
+        let incoming = bursts.iter()
+                        .flat_map(|burst| burst.iter().map(|&b| Some(b)).chain([None]))
+                        .collect();
+        let mut link = Script { incoming, sent: vec![] };
+        let mut image = vec![];
+        let result = Receiver::new().with_retries(3).run(&mut link, &mut |data: &[u8]|
+        {
+            image.extend_from_slice(data);
+            Ok(())
+        });
+        (result, link.sent, image)
+    }
+
+    #[test]
+    fn receives_crc_blocks()
+    {
+        let mut incoming = crc_block(1, 0xaa);
+        incoming.extend(crc_block(2, 0x55));
+        incoming.push(EOT);
+
+        let (result, sent, image) = run(&[incoming]);
+        assert_eq!(result.unwrap(), 2 * BLOCK_SIZE);
+        assert_eq!(sent, [CRC_REQUEST, ACK, ACK, ACK]);
+        assert_eq!(&image[..BLOCK_SIZE], &[0xaa; BLOCK_SIZE][..]);
+        assert_eq!(&image[BLOCK_SIZE..], &[0x55; BLOCK_SIZE][..]);
+    }
+
+    #[test]
+    fn ignores_duplicate_and_rejects_corrupt_blocks()
+    {
+        let mut corrupt = crc_block(2, 0x11);
+        corrupt[10] ^= 1;
+
+        let mut incoming = crc_block(1, 0x01);
+        incoming.extend(crc_block(1, 0x01));
+        incoming.extend(corrupt);
+
+        let mut resend = crc_block(2, 0x11);
+        resend.push(EOT);
+
+        let (result, sent, image) = run(&[incoming, resend]);
+        assert_eq!(result.unwrap(), 2 * BLOCK_SIZE);
+        assert_eq!(sent, [CRC_REQUEST, ACK, ACK, NAK, ACK, ACK]);
+        assert_eq!(image.len(), 2 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn sender_cancel_and_silence()
+    {
+        let (result, _, _) = run(&[vec![CAN, CAN]]);
+        assert!(matches!(result, Err(Fault::Cancelled)));
+
+        /* no sender at all: crc requests, then checksum requests, then give up */
+        let (result, sent, _) = run(&[]);
+        assert!(matches!(result, Err(Fault::TooManyErrors)));
+        assert_eq!(&sent[..5], &[CRC_REQUEST, CRC_REQUEST, CRC_REQUEST, NAK, CAN]);
+
+        /* and noise is no better than silence */
+        let mut incoming = vec![0x55; 8];
+        incoming.extend(crc_block(1, 0x01));
+        incoming.push(EOT);
+        let (result, sent, _) = run(&[incoming]);
+        assert!(matches!(result, Err(Fault::TooManyErrors)));
+        assert_eq!(sent, [CRC_REQUEST, CRC_REQUEST, CRC_REQUEST, NAK, CAN, CAN]);
+    }
+
+    #[test]
+    fn checksum_blocks_validate()
+    {
+        let data = [3u8; BLOCK_SIZE];
+        let sum = (3 * BLOCK_SIZE) as u8;
+        assert!(block_valid(7, !7, &data, &[sum]));
+        assert!(!block_valid(7, !7, &data, &[sum.wrapping_add(1)]));
+        assert!(!block_valid(7, 7, &data, &[sum]));
+    }
+}